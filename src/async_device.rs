@@ -0,0 +1,191 @@
+// 异步块设备, 给基于 executor 的内核用: 驱动发出请求后返回 future, 中断到来时唤醒
+// 和同步的 BlockDevice 可以互相包装, 缓存和盘上格式的逻辑两边共用
+use crate::block_device::BlockDevice;
+use crate::error::IOError;
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, sync::Arc};
+use core::any::Any;
+use core::future::Future;
+use core::ops::Range;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll, Waker};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// 异步块设备请求返回的 future, 借用请求的缓冲区直到完成
+pub type BlockFuture<'a> = Pin<Box<dyn Future<Output = Result<(), IOError>> + Send + 'a>>;
+
+/// BlockDevice 的异步版本, 各方法的约定和 BlockDevice 相同
+pub trait AsyncBlockDevice: Send + Sync + Any {
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> BlockFuture<'a>;
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> BlockFuture<'a>;
+    // 默认逐块等待 read_block, 支持多块传输的驱动可以覆盖成一次请求
+    fn read_blocks<'a>(
+        &'a self,
+        start_block: usize,
+        count: usize,
+        buf: &'a mut [u8],
+    ) -> BlockFuture<'a> {
+        Box::pin(async move {
+            if count == 0 {
+                return Ok(());
+            }
+            if buf.len() < count || !buf.len().is_multiple_of(count) {
+                return Err(IOError::NotEnoughBuffer);
+            }
+            let block_size = buf.len() / count;
+            for (i, block) in buf.chunks_mut(block_size).enumerate() {
+                self.read_block(start_block + i, block).await?;
+            }
+            Ok(())
+        })
+    }
+    // 默认逐块等待 write_block
+    fn write_blocks<'a>(
+        &'a self,
+        start_block: usize,
+        count: usize,
+        buf: &'a [u8],
+    ) -> BlockFuture<'a> {
+        Box::pin(async move {
+            if count == 0 {
+                return Ok(());
+            }
+            if buf.len() < count || !buf.len().is_multiple_of(count) {
+                return Err(IOError::NotEnoughBuffer);
+            }
+            let block_size = buf.len() / count;
+            for (i, block) in buf.chunks(block_size).enumerate() {
+                self.write_block(start_block + i, block).await?;
+            }
+            Ok(())
+        })
+    }
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+    fn discard(&self, _blocks: Range<usize>) -> BlockFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        None
+    }
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// 把同步块设备当作异步块设备用, 每个请求在第一次 poll 时同步完成
+pub struct AsyncAdapter {
+    inner: Arc<dyn BlockDevice>,
+}
+
+impl AsyncAdapter {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        Self { inner }
+    }
+    pub fn inner(&self) -> Arc<dyn BlockDevice> {
+        Arc::clone(&self.inner)
+    }
+}
+
+impl AsyncBlockDevice for AsyncAdapter {
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self.inner.read_block(block_id, buf) })
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self.inner.write_block(block_id, buf) })
+    }
+    fn read_blocks<'a>(
+        &'a self,
+        start_block: usize,
+        count: usize,
+        buf: &'a mut [u8],
+    ) -> BlockFuture<'a> {
+        Box::pin(async move { self.inner.read_blocks(start_block, count, buf) })
+    }
+    fn write_blocks<'a>(
+        &'a self,
+        start_block: usize,
+        count: usize,
+        buf: &'a [u8],
+    ) -> BlockFuture<'a> {
+        Box::pin(async move { self.inner.write_blocks(start_block, count, buf) })
+    }
+    fn flush(&self) -> BlockFuture<'_> {
+        Box::pin(async move { self.inner.flush() })
+    }
+    fn discard(&self, blocks: Range<usize>) -> BlockFuture<'_> {
+        Box::pin(async move { self.inner.discard(blocks) })
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 把异步块设备当作同步块设备用, 每个请求用 block_on 忙等到完成
+/// 挂载, 分配簇和写回缓存这些同步路径经过这里
+pub struct BlockingAdapter {
+    inner: Arc<dyn AsyncBlockDevice>,
+}
+
+impl BlockingAdapter {
+    pub fn new(inner: Arc<dyn AsyncBlockDevice>) -> Self {
+        Self { inner }
+    }
+    pub fn inner(&self) -> Arc<dyn AsyncBlockDevice> {
+        Arc::clone(&self.inner)
+    }
+}
+
+impl BlockDevice for BlockingAdapter {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        block_on(self.inner.read_block(block_id, buf))
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        block_on(self.inner.write_block(block_id, buf))
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        block_on(self.inner.read_blocks(start_block, count, buf))
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        block_on(self.inner.write_blocks(start_block, count, buf))
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        block_on(self.inner.flush())
+    }
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        block_on(self.inner.discard(blocks))
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 在当前上下文中忙等 future 完成, 不需要 executor
+/// waker 什么也不做, future 每次被 poll 时都要自己检查请求是否已经完成
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        core::hint::spin_loop();
+    }
+}
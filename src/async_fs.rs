@@ -0,0 +1,362 @@
+// VFile 的异步读写, 簇链查找, 缓存和目录项的处理和同步路径共用, 只有块设备请求换成 await
+// 所有锁都在 await 之前释放, 返回的 future 可以在线程间移动
+use super::{
+    AsyncBlockDevice, AsyncLoad, CacheLoad, FSError, RunFileSystem, ShortDirectoryEntry, VFile,
+};
+use crate::cluster_walk::{
+    root_segments, ChainWalk, ClusterTransfer, ContiguousRun, CountClusters, Segment,
+};
+use crate::data::FIXED_ROOT_CLUSTER;
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec};
+use spin::RwLock;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+#[derive(Copy, Clone)]
+enum Cache {
+    Fat,
+    Root,
+    Cluster,
+}
+
+// 在对应的缓存上执行 f, 返回前释放所有锁
+fn with_cache<V>(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    cache: Cache,
+    f: impl FnOnce(&mut dyn AsyncLoad) -> V,
+) -> V {
+    let runfs = fs.read();
+    match cache {
+        Cache::Fat => f(runfs.fat_manager_modify().sector_cache()),
+        Cache::Root => f(runfs.data_manager_modify().root_cache()),
+        Cache::Cluster => f(runfs.data_manager_modify().cluster_cache()),
+    }
+}
+
+// 把 id 加载进缓存, 队列满时先异步写回要换出的缓存
+async fn load(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    cache: Cache,
+    id: usize,
+) -> Result<(), FSError> {
+    loop {
+        let step = with_cache(fs, cache, |c| c.prepare_load(id));
+        match step {
+            CacheLoad::Cached => return Ok(()),
+            CacheLoad::WriteBack {
+                block_id,
+                count,
+                data,
+                pin,
+            } => {
+                // 写回结束前缓存留在队列中, 别的任务 sync 时会等这次写回, 失败时恢复修改标记
+                let result = device.write_blocks(block_id, count, &data).await;
+                pin.finish(result.is_ok());
+                result?;
+            }
+            CacheLoad::Read {
+                block_id,
+                count,
+                len,
+            } => {
+                let mut data = vec![0u8; len];
+                device.read_blocks(block_id, count, &mut data).await?;
+                with_cache(fs, cache, |c| c.finish_load(id, data))?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// 加载 (cluster_id, offset) 所在的簇或固定根目录区扇区
+async fn load_data(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    cluster_id: usize,
+    offset: usize,
+) -> Result<(), FSError> {
+    let root_sector = {
+        let runfs = fs.read();
+        let data_manager = runfs.data_manager_read();
+        data_manager
+            .is_fixed_root(cluster_id)
+            .then(|| data_manager.root_position(offset).0)
+    };
+    match root_sector {
+        Some(sector_id) => load(fs, device, Cache::Root, sector_id).await,
+        None => load(fs, device, Cache::Cluster, cluster_id).await,
+    }
+}
+
+// 先加载表项所在的 FAT 扇区, 再走同步路径读表项, 扇区在缓存中时不会碰块设备
+async fn next_cluster(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    cluster_id: usize,
+) -> Result<Option<usize>, FSError> {
+    let (sector_id, next_sector) = fs.read().fat_manager_read().entry_sectors(cluster_id);
+    load(fs, device, Cache::Fat, sector_id).await?;
+    if let Some(next_sector) = next_sector {
+        load(fs, device, Cache::Fat, next_sector).await?;
+    }
+    fs.read().fat_manager_modify().next_cluster(cluster_id)
+}
+
+// FATManager::walk 的异步版本
+async fn walk<W: ChainWalk>(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    mut walk: W,
+) -> Result<W::Output, FSError> {
+    while let Some(cluster_id) = walk.pending() {
+        let next = next_cluster(fs, device, cluster_id).await?;
+        walk.advance(next);
+    }
+    Ok(walk.finish())
+}
+
+// 和 DataManager::read_bytes 相同, 加载之后到读之前可能被别的任务换出, 换出了就重新加载
+async fn read_bytes(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    cluster_id: usize,
+    offset: usize,
+    buf: &mut [u8],
+) -> Result<(), FSError> {
+    while !fs
+        .read()
+        .data_manager_read()
+        .read_cached(cluster_id, offset, buf)
+    {
+        load_data(fs, device, cluster_id, offset).await?;
+    }
+    Ok(())
+}
+
+// 和 DataManager::write_bytes 相同
+async fn write_bytes(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    cluster_id: usize,
+    offset: usize,
+    buf: &[u8],
+) -> Result<(), FSError> {
+    while !fs
+        .read()
+        .data_manager_read()
+        .write_cached(cluster_id, offset, buf)
+    {
+        load_data(fs, device, cluster_id, offset).await?;
+    }
+    Ok(())
+}
+
+// 和 ClusterCacheManager::read_clusters 相同, 已缓存的簇从缓存复制, 其余相邻的簇合并成一次请求
+async fn read_clusters(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    first_cluster: usize,
+    buf: &mut [u8],
+) -> Result<(), FSError> {
+    let cluster_size = fs.read().bpb().cluster_size();
+    let count = buf.len() / cluster_size;
+    let mut i = 0;
+    while i < count {
+        let run = {
+            let runfs = fs.read();
+            let mut data_manager = runfs.data_manager_modify();
+            let cluster_cache = data_manager.cluster_cache();
+            let run = cluster_cache.uncached_run(first_cluster, i, count);
+            if run.is_none() {
+                let dst = &mut buf[i * cluster_size..(i + 1) * cluster_size];
+                cluster_cache.read_cached(first_cluster + i, 0, dst);
+            }
+            run
+        };
+        let Some(run) = run else {
+            i += 1;
+            continue;
+        };
+        let dst = &mut buf[i * cluster_size..run.end * cluster_size];
+        device.read_blocks(run.block_id, run.blocks, dst).await?;
+        // 等待期间被别的任务加载进缓存的簇以缓存为准
+        let runfs = fs.read();
+        let mut data_manager = runfs.data_manager_modify();
+        for j in i..run.end {
+            let dst = &mut buf[j * cluster_size..(j + 1) * cluster_size];
+            data_manager
+                .cluster_cache()
+                .read_cached(first_cluster + j, 0, dst);
+        }
+        i = run.end;
+    }
+    Ok(())
+}
+
+// 和 ClusterCacheManager::write_clusters 相同, 直接写设备之后同步等待期间进了缓存的簇
+async fn write_clusters(
+    fs: &Arc<RwLock<RunFileSystem>>,
+    device: &dyn AsyncBlockDevice,
+    first_cluster: usize,
+    buf: &[u8],
+) -> Result<(), FSError> {
+    let cluster_size = fs.read().bpb().cluster_size();
+    let count = buf.len() / cluster_size;
+    let mut i = 0;
+    while i < count {
+        let run = {
+            let runfs = fs.read();
+            let mut data_manager = runfs.data_manager_modify();
+            let cluster_cache = data_manager.cluster_cache();
+            let run = cluster_cache.uncached_run(first_cluster, i, count);
+            if run.is_none() {
+                let src = &buf[i * cluster_size..(i + 1) * cluster_size];
+                cluster_cache.write_cached(first_cluster + i, 0, src);
+            }
+            run
+        };
+        let Some(run) = run else {
+            i += 1;
+            continue;
+        };
+        let src = &buf[i * cluster_size..run.end * cluster_size];
+        device.write_blocks(run.block_id, run.blocks, src).await?;
+        fs.read()
+            .data_manager_modify()
+            .cluster_cache()
+            .refresh_cached(first_cluster + i, src);
+        i = run.end;
+    }
+    Ok(())
+}
+
+// 文件自己的短目录项, 先加载所在的簇或扇区
+async fn short_dirent(
+    file: &VFile,
+    device: &dyn AsyncBlockDevice,
+) -> Result<ShortDirectoryEntry, FSError> {
+    if !file.is_root() {
+        let (cluster_id, offset) = file.short_pos();
+        load_data(&file.fs(), device, cluster_id, offset).await?;
+    }
+    file.short_dirent()
+}
+
+/// VFile::read_at 的异步版本, 和 ShortDirectoryEntry::read_at 走同样的步骤
+pub(crate) async fn read_at(file: &VFile, offset: usize, buf: &mut [u8]) -> Result<usize, FSError> {
+    let fs = file.fs();
+    let device = fs.read().async_device();
+    let device = device.as_ref();
+    let entry = short_dirent(file, device).await?;
+    let fixed_root_size = entry.fixed_root_size(&fs.read());
+    if let Some(root_size) = fixed_root_size {
+        // 固定根目录区按扇区加载
+        let sector_size = fs.read().bpb().bytes_per_sector() as usize;
+        let offset_end_pos = (offset + buf.len()).min(root_size);
+        for (root_offset, range) in root_segments(offset, offset_end_pos, sector_size) {
+            read_bytes(
+                &fs,
+                device,
+                FIXED_ROOT_CLUSTER,
+                root_offset,
+                &mut buf[range],
+            )
+            .await?;
+        }
+        return Ok(offset_end_pos.saturating_sub(offset));
+    }
+    let cluster_size = fs.read().bpb().cluster_size();
+    let first_cluster = entry.first_cluster() as usize;
+    let size = if entry.is_dir() {
+        cluster_size * walk(&fs, device, CountClusters::new(first_cluster)).await?
+    } else {
+        entry.size().unwrap_or(0) as usize
+    };
+    let offset_end_pos = (offset + buf.len()).min(size);
+    let mut transfer = ClusterTransfer::new(first_cluster, offset, offset_end_pos, cluster_size);
+    while let Some(segment) = transfer.segment() {
+        match segment {
+            Segment::Next(cluster_id) => {
+                let next = next_cluster(&fs, device, cluster_id).await?;
+                transfer.set_next(next);
+            }
+            Segment::Whole { cluster, max } => {
+                let (count, next) = walk(&fs, device, ContiguousRun::new(cluster, max)).await?;
+                let range = transfer.buf_range(count * cluster_size);
+                read_clusters(&fs, device, cluster, &mut buf[range]).await?;
+                transfer.advance_run(count, next);
+            }
+            Segment::Partial {
+                cluster,
+                offset,
+                len,
+            } => {
+                let range = transfer.buf_range(len);
+                read_bytes(&fs, device, cluster, offset, &mut buf[range]).await?;
+                transfer.advance(len);
+            }
+        }
+    }
+    Ok(transfer.done())
+}
+
+/// VFile::write_at 的异步版本, 扩容要分配簇, 仍然走同步路径
+pub(crate) async fn write_at(file: &VFile, offset: usize, buf: &[u8]) -> Result<usize, FSError> {
+    file.adjust_capacity(offset + buf.len())?;
+    let fs = file.fs();
+    let device = fs.read().async_device();
+    let device = device.as_ref();
+    let entry = short_dirent(file, device).await?;
+    let fixed_root_size = entry.fixed_root_size(&fs.read());
+    if let Some(root_size) = fixed_root_size {
+        let sector_size = fs.read().bpb().bytes_per_sector() as usize;
+        let offset_end_pos = (offset + buf.len()).min(root_size);
+        for (root_offset, range) in root_segments(offset, offset_end_pos, sector_size) {
+            write_bytes(&fs, device, FIXED_ROOT_CLUSTER, root_offset, &buf[range]).await?;
+        }
+        return Ok(offset_end_pos.saturating_sub(offset));
+    }
+    let cluster_size = fs.read().bpb().cluster_size();
+    let first_cluster = entry.first_cluster() as usize;
+    let capacity = cluster_size * walk(&fs, device, CountClusters::new(first_cluster)).await?;
+    let offset_end_pos = (offset + buf.len()).min(capacity);
+    let mut transfer = ClusterTransfer::new(first_cluster, offset, offset_end_pos, cluster_size);
+    while let Some(segment) = transfer.segment() {
+        match segment {
+            Segment::Next(cluster_id) => {
+                let next = next_cluster(&fs, device, cluster_id).await?;
+                transfer.set_next(next);
+            }
+            Segment::Whole { cluster, max } => {
+                let (count, next) = walk(&fs, device, ContiguousRun::new(cluster, max)).await?;
+                let range = transfer.buf_range(count * cluster_size);
+                write_clusters(&fs, device, cluster, &buf[range]).await?;
+                transfer.advance_run(count, next);
+            }
+            Segment::Partial {
+                cluster,
+                offset,
+                len,
+            } => {
+                let range = transfer.buf_range(len);
+                write_bytes(&fs, device, cluster, offset, &buf[range]).await?;
+                transfer.advance(len);
+            }
+        }
+    }
+    let write_size = transfer.done();
+    if file.is_file() {
+        let (cluster_id, dirent_offset) = file.short_pos();
+        load_data(&fs, device, cluster_id, dirent_offset).await?;
+        fs.read().data_manager_modify().modify_short_dirent(
+            cluster_id,
+            dirent_offset,
+            |short_entry: &mut ShortDirectoryEntry| {
+                short_entry.grow_size((offset + write_size) as u32)
+            },
+        )?;
+    }
+    Ok(write_size)
+}
@@ -1,11 +1,79 @@
 use crate::error::IOError;
 use core::any::Any;
+use core::ops::Range;
 
 pub trait BlockDevice: Send + Sync + Any {
-    // TODO: read, write 要返回结果 Result
     // read_block中, 如果 block 长度大于 buf, 必须确保 buf 顺利读到 block 前 n 个的
     //数据, 前n个数据不会被覆盖或者读取失败, 错误在Result中返回处理
     fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError>;
     // write_block中, 如果 block 长度大于 buf, 必须确保 buf 不会写进 block,直接返回error
     fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError>;
+    // 从 start_block 开始连续读 count 个块, buf 平均分成 count 份, 每份是一个块
+    // 默认逐块调用 read_block, 支持多块传输的驱动(SD 卡, virtio 等)可以覆盖成一次请求
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        read_blocks_each(self, start_block, count, buf)
+    }
+    // 从 start_block 开始连续写 count 个块, buf 的划分和 read_blocks 相同
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        write_blocks_each(self, start_block, count, buf)
+    }
+    // 把设备自己的易失写缓存刷到存储介质, 没有写缓存的设备不用实现
+    fn flush(&self) -> Result<(), IOError> {
+        Ok(())
+    }
+    // 通知设备这些块中的数据不再需要(TRIM), 只是提示, 之后读到的内容不确定
+    fn discard(&self, _blocks: Range<usize>) -> Result<(), IOError> {
+        Ok(())
+    }
+    // 每块的字节数, None 表示设备不知道, 由文件系统的扇区大小决定
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+    // 设备的总块数, None 表示设备不知道
+    fn num_blocks(&self) -> Option<usize> {
+        None
+    }
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+// read_blocks 的默认实现, 逐块调用 read_block, 覆盖 read_blocks 的设备处理不了的情况可以退回到这里
+pub(crate) fn read_blocks_each<D: BlockDevice + ?Sized>(
+    device: &D,
+    start_block: usize,
+    count: usize,
+    buf: &mut [u8],
+) -> Result<(), IOError> {
+    if count == 0 {
+        return Ok(());
+    }
+    if buf.len() < count || !buf.len().is_multiple_of(count) {
+        return Err(IOError::NotEnoughBuffer);
+    }
+    let block_size = buf.len() / count;
+    for (i, block) in buf.chunks_mut(block_size).enumerate() {
+        device.read_block(start_block + i, block)?;
+    }
+    Ok(())
+}
+
+// write_blocks 的默认实现, 逐块调用 write_block
+pub(crate) fn write_blocks_each<D: BlockDevice + ?Sized>(
+    device: &D,
+    start_block: usize,
+    count: usize,
+    buf: &[u8],
+) -> Result<(), IOError> {
+    if count == 0 {
+        return Ok(());
+    }
+    if buf.len() < count || !buf.len().is_multiple_of(count) {
+        return Err(IOError::NotEnoughBuffer);
+    }
+    let block_size = buf.len() / count;
+    for (i, block) in buf.chunks(block_size).enumerate() {
+        device.write_block(start_block + i, block)?;
+    }
+    Ok(())
 }
@@ -1,13 +1,23 @@
 // 对 BootSector, BPB抽象,文件系统重要信息管理.
 use super::BlockDevice;
-use crate::config::MAX_CLUS_SZ;
+use crate::config::{MAX_CLUS_SZ, MAX_SEC_SZ};
 use crate::dir_entry::DIRENT_SZ;
 use crate::error::FSError;
+use crate::fat::{FatType, FAT32_MAX_CLUSTERS};
+use crate::mkfs::{FormatOptions, BACKUP_BOOT_SECTOR, FSINFO_SECTOR};
+use crate::sector_cache::CacheGeometry;
 use crate::START_CLUS_ID;
 #[cfg(not(feature = "std"))]
-use alloc::{slice, str, sync::Arc};
+use alloc::{slice, sync::Arc, vec, vec::Vec};
 #[cfg(feature = "std")]
-use std::{str, sync::Arc, slice};
+use std::{slice, sync::Arc};
+
+/// 挂载时使用的是主副本还是备份副本, 只有 FAT32 有备份启动扇区和备份 FSInfo
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectorCopy {
+    Primary,
+    Backup,
+}
 
 // BPB 79 Byte
 #[repr(C, packed(1))]
@@ -38,23 +48,118 @@ pub struct BiosParameterBlock {
     fs_type_label: [u8; 8], // 文件系统类型名, 如果是FAT32就是FAT32的ascii码
 }
 
+// FAT12/16 的扩展 BPB 26 Byte, 紧跟在 total_sectors_32 之后, 与 FAT32 的扩展 BPB 重叠
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+struct ExtendedBiosParameterBlock16 {
+    drive_number: u8,
+    reserved: u8,
+    boot_signature: u8,
+    volume_id: u32,
+    volume_label: [u8; 11],
+    fs_type_label: [u8; 8],
+}
+
 impl BiosParameterBlock {
-    const FAT32_MAX_CLUSTERS: u32 = 0x0FFF_FFF4;
+    const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+    const BIOS_DRIVE_NUMBER: u8 = 0x80;
+    const ACTIVE_FAT_MASK: u16 = 0x000F;
+    const NO_FAT_MIRRORING: u16 = 0x0080;
 
-    // RunFS 最先判断是否是 FAT32 类型文件系统
-    fn validate_fat32(&self) -> Result<(), FSError> {
-        if self.root_entries != 0
-            || self.total_sectors_16 != 0
-            || self.sectors_per_fat_16 != 0
-            || self.fs_version != 0
-            || str::from_utf8(&self.fs_type_label).unwrap() != "FAT32   "
-        {
-            // println!("Unsupported filesystem: Not FAT32");
-            return Err(FSError::CorruptedFileSystem);
+    /// 格式化时按计算好的几何参数生成 BPB
+    /// FAT32 根目录固定在第一个数据簇, FAT12/16 根目录在 FAT 表之后的固定区域
+    pub(crate) fn for_format(
+        options: &FormatOptions,
+        sectors_per_cluster: u8,
+        fats_sectors: u32,
+    ) -> Self {
+        let mut bpb = Self {
+            bytes_per_sector: options.bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: options.reserved_sectors,
+            fats_number: options.fats_number,
+            media: options.media,
+            hidden_sectors: options.hidden_sectors,
+            sectors_per_track: 32,
+            heads: 64,
+            ..Self::default()
+        };
+        match options.fat_type {
+            FatType::Fat32 => {
+                let mut dummy2 = [0u8; 15];
+                dummy2[12] = Self::BIOS_DRIVE_NUMBER;
+                dummy2[14] = Self::EXTENDED_BOOT_SIGNATURE;
+                bpb.total_sectors_32 = options.total_sectors;
+                bpb.fats_sectors = fats_sectors;
+                bpb.root_dir_cluster = START_CLUS_ID as u32;
+                bpb.fsinfo_sector = FSINFO_SECTOR;
+                bpb.backup_boot_sector = BACKUP_BOOT_SECTOR;
+                bpb.dummy2 = dummy2;
+                bpb.volumn_id = options.volume_id;
+                bpb.volume_label = options.volume_label;
+                bpb.fs_type_label = *b"FAT32   ";
+            }
+            FatType::Fat12 | FatType::Fat16 => {
+                bpb.root_entries = options.root_entries;
+                // 总扇区数放得下 16 位时必须写在 total_sectors_16
+                match u16::try_from(options.total_sectors) {
+                    Ok(n) => bpb.total_sectors_16 = n,
+                    Err(_) => bpb.total_sectors_32 = options.total_sectors,
+                }
+                bpb.sectors_per_fat_16 = fats_sectors as u16;
+                bpb.set_extended_bpb16(ExtendedBiosParameterBlock16 {
+                    drive_number: Self::BIOS_DRIVE_NUMBER,
+                    boot_signature: Self::EXTENDED_BOOT_SIGNATURE,
+                    volume_id: options.volume_id,
+                    volume_label: options.volume_label,
+                    fs_type_label: if options.fat_type == FatType::Fat12 {
+                        *b"FAT12   "
+                    } else {
+                        *b"FAT16   "
+                    },
+                    ..ExtendedBiosParameterBlock16::default()
+                });
+            }
+        }
+        bpb
+    }
+    fn extended_bpb16(&self) -> ExtendedBiosParameterBlock16 {
+        unsafe {
+            core::ptr::read_unaligned(
+                core::ptr::addr_of!(self.fats_sectors) as *const ExtendedBiosParameterBlock16
+            )
+        }
+    }
+    fn set_extended_bpb16(&mut self, extended_bpb: ExtendedBiosParameterBlock16) {
+        unsafe {
+            core::ptr::write_unaligned(
+                core::ptr::addr_of_mut!(self.fats_sectors) as *mut ExtendedBiosParameterBlock16,
+                extended_bpb,
+            )
+        }
+    }
+
+    // FAT 类型由簇数决定, 再检查 FAT32 专有的字段是否与类型一致, 类型名只是参考不做检查
+    fn validate_fat_type(&self) -> Result<(), FSError> {
+        match self.fat_type() {
+            FatType::Fat32 => {
+                if self.total_sectors_16 != 0
+                    || self.sectors_per_fat_16 != 0
+                    || self.fs_version != 0
+                {
+                    // println!("Unsupported filesystem: Not FAT32");
+                    return Err(FSError::CorruptedFileSystem);
+                }
+            }
+            FatType::Fat12 | FatType::Fat16 => {
+                if self.sectors_per_fat_16 == 0 {
+                    return Err(FSError::CorruptedFileSystem);
+                }
+            }
         }
         Ok(())
     }
-    // 本项目实现的 FAT32 文件系统扇区的字节数只支持范围在 512-4096 字节中二的整指数倍
+    // 本项目实现的 FAT32 文件系统扇区的字节数只支持范围在 512-4096 字节中二的整指数倍, 同时不能超过 MAX_SEC_SZ
     fn validate_bytes_per_sector(&self) -> Result<(), FSError> {
         if self.bytes_per_sector.count_ones() != 1 {
             // println!("invalid bytes_per_sector value in BPB: expected a power of two");
@@ -64,6 +169,10 @@ impl BiosParameterBlock {
             // println!("invalid bytes_per_sector value in BPB: expected value in range [512, 4096]");
             return Err(FSError::CorruptedFileSystem);
         }
+        // 打开 small-cache 时上限更低
+        if usize::from(self.bytes_per_sector) > MAX_SEC_SZ {
+            return Err(FSError::CorruptedFileSystem);
+        }
         Ok(())
     }
     // 本项目实现的 FAT32 文件系统簇的扇区数只支持二的整指数倍
@@ -84,7 +193,7 @@ impl BiosParameterBlock {
         }
         Ok(())
     }
-    // RunFS 实现的 FAT32 文件系统簇的字节数必须小于32KB
+    // 簇的字节数不能超过 MAX_CLUS_SZ
     fn validate_bytes_per_cluster(&self) -> Result<(), FSError> {
         let bytes_per_cluster: usize =
             usize::from(self.sectors_per_cluster) * usize::from(self.bytes_per_sector);
@@ -102,6 +211,10 @@ impl BiosParameterBlock {
             // println!("invalid reserved_sectors value in BPB");
             return Err(FSError::CorruptedFileSystem);
         }
+        // FAT12/16 没有备份启动扇区和 FSInfo
+        if self.fat_type() != FatType::Fat32 {
+            return Ok(());
+        }
         if self.backup_boot_sector >= self.reserved_sectors {
             // println!("Invalid BPB: expected backup boot-sector to be in the reserved region");
             return Err(FSError::CorruptedFileSystem);
@@ -120,19 +233,34 @@ impl BiosParameterBlock {
         }
         Ok(())
     }
-    // RunFS 实现的 FAT32 文件系统 FAT 表数必须为1或2
+    // 关闭镜像时活动 FAT 表必须存在, 要先确定 FAT 类型
+    fn validate_active_fat(&self) -> Result<(), FSError> {
+        if self.active_fat() >= self.fats_number {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(())
+    }
+    // FAT32 根目录在簇链中, root_entries 必须为 0; FAT12/16 根目录区必须占满整数个扇区
     fn validate_root_entries(&self) -> Result<(), FSError> {
-        if self.fats_number == 0 || self.fats_number > 2 {
-            // println!("invalid fats value in BPB: {}", self.fats_number);
+        let root_dir_bytes = u32::from(self.root_entries) * (DIRENT_SZ as u32);
+        let invalid = match self.fat_type() {
+            FatType::Fat32 => self.root_entries != 0,
+            FatType::Fat12 | FatType::Fat16 => {
+                self.root_entries == 0
+                    || !root_dir_bytes.is_multiple_of(u32::from(self.bytes_per_sector))
+            }
+        };
+        if invalid {
+            // println!("invalid root_entries value in BPB: {}", self.root_entries);
             return Err(FSError::CorruptedFileSystem);
         }
         Ok(())
     }
     fn validate_total_sectors(&self) -> Result<(), FSError> {
-        let total_sectors = self.total_sectors_32();
+        let total_sectors = self.total_sectors();
         let first_data_sector = self.first_data_sector();
-        if self.total_sectors_32 == 0 {
-            // println!("Invalid BPB (total_sectors_32 should be non-zero)");
+        if total_sectors == 0 {
+            // println!("Invalid BPB (total_sectors should be non-zero)");
             return Err(FSError::CorruptedFileSystem);
         }
         if total_sectors <= first_data_sector {
@@ -142,7 +270,7 @@ impl BiosParameterBlock {
         Ok(())
     }
     fn validate_fats_sectors(&self) -> Result<(), FSError> {
-        if self.fats_sectors == 0 {
+        if self.fats_sectors() == 0 {
             // println!("Invalid sectors_per_fat_32 value in FAT32 BPB: expected non-zero value");
             return Err(FSError::CorruptedFileSystem);
         }
@@ -150,38 +278,64 @@ impl BiosParameterBlock {
     }
     fn validate_total_clusters(&self) -> Result<(), FSError> {
         let total_clusters = self.total_clusters();
-        if total_clusters > Self::FAT32_MAX_CLUSTERS {
+        if total_clusters > FAT32_MAX_CLUSTERS {
             // println!("Invalid BPB: too many clusters {}", total_clusters);
             return Err(FSError::CorruptedFileSystem);
         }
         let total_fat_entries =
-            self.fats_sectors() * u32::from(self.bytes_per_sector) * 8 / (DIRENT_SZ as u32);
-        let usable_fat_entries: u32 = total_fat_entries - u32::try_from(START_CLUS_ID).unwrap();
-        if usable_fat_entries < total_clusters {
+            u64::from(self.fats_sectors()) * u64::from(self.bytes_per_sector) * 8
+                / u64::from(self.fat_type().bits_per_entry());
+        let usable_fat_entries = total_fat_entries.saturating_sub(START_CLUS_ID as u64);
+        if usable_fat_entries < u64::from(total_clusters) {
             // println!(
             //     "FAT is too small (allows allocation of {} clusters) compared to the total number of clusters ({})",
             //     usable_fat_entries, total_clusters
             // );
+            return Err(FSError::CorruptedFileSystem);
         }
         Ok(())
     }
-    // 验证文件系统是否是合法的FAT32类型
+    // 验证文件系统是否是合法的 FAT12/16/32 类型, 簇数依赖的字段要先检查
     #[must_use]
     pub(crate) fn validate(&self) -> Result<(), FSError> {
-        self.validate_fat32()?;
         self.validate_bytes_per_sector()?;
         self.validate_sectors_per_cluster()?;
         self.validate_bytes_per_cluster()?;
-        self.validate_reserved_sectors()?;
         self.validate_fats()?;
-        self.validate_root_entries()?;
-        self.validate_total_sectors()?;
         self.validate_fats_sectors()?;
+        self.validate_total_sectors()?;
+        self.validate_fat_type()?;
+        self.validate_active_fat()?;
+        self.validate_reserved_sectors()?;
+        self.validate_root_entries()?;
         self.validate_total_clusters()?;
         Ok(())
     }
+    pub fn fat_type(&self) -> FatType {
+        FatType::from_clusters(self.total_clusters())
+    }
     pub fn volumn_id(&self) -> u32 {
-        self.volumn_id
+        match self.fat_type() {
+            FatType::Fat32 => self.volumn_id,
+            FatType::Fat12 | FatType::Fat16 => self.extended_bpb16().volume_id,
+        }
+    }
+    /// 卷名, 不足 11 字节用空格填充, 没有卷名时为 NO NAME
+    pub fn volume_label(&self) -> [u8; 11] {
+        match self.fat_type() {
+            FatType::Fat32 => self.volume_label,
+            FatType::Fat12 | FatType::Fat16 => self.extended_bpb16().volume_label,
+        }
+    }
+    pub(crate) fn set_volume_label(&mut self, volume_label: [u8; 11]) {
+        match self.fat_type() {
+            FatType::Fat32 => self.volume_label = volume_label,
+            FatType::Fat12 | FatType::Fat16 => {
+                let mut extended_bpb = self.extended_bpb16();
+                extended_bpb.volume_label = volume_label;
+                self.set_extended_bpb16(extended_bpb);
+            }
+        }
     }
     pub fn bytes_per_sector(&self) -> u16 {
         self.bytes_per_sector
@@ -189,14 +343,41 @@ impl BiosParameterBlock {
     pub fn sectors_per_cluster(&self) -> u8 {
         self.sectors_per_cluster
     }
+    pub fn fats_number(&self) -> u8 {
+        self.fats_number
+    }
+    /// 每个 FAT 表的扇区数, FAT12/16 记录在 sectors_per_fat_16
     pub fn fats_sectors(&self) -> u32 {
-        self.fats_sectors
+        if self.sectors_per_fat_16 != 0 {
+            u32::from(self.sectors_per_fat_16)
+        } else {
+            self.fats_sectors
+        }
     }
     pub fn total_sectors_32(&self) -> u32 {
         self.total_sectors_32
     }
+    /// 总扇区数, 放得下 16 位时记录在 total_sectors_16
+    pub fn total_sectors(&self) -> u32 {
+        if self.total_sectors_16 != 0 {
+            u32::from(self.total_sectors_16)
+        } else {
+            self.total_sectors_32
+        }
+    }
+    /// 文件系统之前的隐藏扇区数, 即所在分区在整个磁盘中的起始扇区
+    pub fn hidden_sectors(&self) -> u32 {
+        self.hidden_sectors
+    }
+    /// FAT12/16 的根目录不在簇链中, 返回 0
     pub fn root_dir_cluster(&self) -> u32 {
-        self.root_dir_cluster
+        match self.fat_type() {
+            FatType::Fat32 => self.root_dir_cluster,
+            FatType::Fat12 | FatType::Fat16 => 0,
+        }
+    }
+    pub fn root_entries(&self) -> u32 {
+        u32::from(self.root_entries)
     }
     pub fn reserved_sectors(&self) -> u32 {
         u32::from(self.reserved_sectors)
@@ -205,13 +386,59 @@ impl BiosParameterBlock {
         self.reserved_sectors()
     }
     pub fn first_backup_fats_sector(&self) -> u32 {
-        self.first_fats_sector() + self.fats_sectors
+        self.first_fats_sector() + self.fats_sectors()
+    }
+    /// 第 fat 个 FAT 表(从 0 开始)的起始扇区
+    pub fn fat_start_sector(&self, fat: u8) -> u32 {
+        self.first_fats_sector() + u32::from(fat) * self.fats_sectors()
+    }
+    pub fn extended_flags(&self) -> u16 {
+        match self.fat_type() {
+            FatType::Fat32 => self.extended_flags,
+            FatType::Fat12 | FatType::Fat16 => 0,
+        }
+    }
+    /// extended_flags 第 7 位为 0 时所有 FAT 表互为镜像, FAT12/16 总是镜像
+    pub fn is_fat_mirrored(&self) -> bool {
+        self.extended_flags() & Self::NO_FAT_MIRRORING == 0
+    }
+    /// 读写使用的 FAT 表, 只有关闭镜像时 extended_flags 第 0-3 位才有效, 否则是第一个 FAT 表
+    pub fn active_fat(&self) -> u8 {
+        if self.is_fat_mirrored() {
+            0
+        } else {
+            (self.extended_flags() & Self::ACTIVE_FAT_MASK) as u8
+        }
+    }
+    /// 修改时要写入的 FAT 表序号
+    pub fn written_fats(&self) -> core::ops::Range<u8> {
+        if self.is_fat_mirrored() {
+            0..self.fats_number
+        } else {
+            self.active_fat()..self.active_fat() + 1
+        }
+    }
+    /// Some(n) 关闭镜像并只使用第 n 个 FAT 表, None 打开镜像, 只用于 FAT32
+    pub(crate) fn set_active_fat(&mut self, active_fat: Option<u8>) {
+        let flags = self.extended_flags & !(Self::NO_FAT_MIRRORING | Self::ACTIVE_FAT_MASK);
+        self.extended_flags = match active_fat {
+            Some(n) => flags | Self::NO_FAT_MIRRORING | (u16::from(n) & Self::ACTIVE_FAT_MASK),
+            None => flags,
+        };
     }
     // FAT32 读出来的没有用
     pub fn root_dir_sectors(&self) -> u32 {
         let root_dir_bytes = u32::from(self.root_entries) * (DIRENT_SZ as u32);
         (root_dir_bytes + u32::from(self.bytes_per_sector) - 1) / u32::from(self.bytes_per_sector)
     }
+    /// FAT12/16 固定根目录区的第一个扇区, 紧跟在 FAT 表之后
+    pub fn first_root_dir_sector(&self) -> u32 {
+        self.reserved_sectors() + self.sectors_per_all_fats()
+    }
+    /// FAT12/16 固定根目录区的字节数
+    pub fn root_dir_size(&self) -> usize {
+        usize::from(self.root_entries) * DIRENT_SZ
+    }
     pub fn sectors_per_all_fats(&self) -> u32 {
         u32::from(self.fats_number) * self.fats_sectors()
     }
@@ -221,7 +448,7 @@ impl BiosParameterBlock {
         self.reserved_sectors() + fat_sectors + root_dir_sectors
     }
     pub fn total_clusters(&self) -> u32 {
-        let total_sectors = self.total_sectors_32();
+        let total_sectors = self.total_sectors();
         let first_data_sector = self.first_data_sector();
         let data_sectors = total_sectors - first_data_sector;
         data_sectors / u32::from(self.sectors_per_cluster)
@@ -229,22 +456,47 @@ impl BiosParameterBlock {
     pub fn cluster_size(&self) -> usize {
         usize::from(self.sectors_per_cluster) * usize::from(self.bytes_per_sector)
     }
+    pub(crate) fn cache_geometry(&self) -> CacheGeometry {
+        CacheGeometry {
+            bytes_per_sector: usize::from(self.bytes_per_sector),
+            sectors_per_cluster: usize::from(self.sectors_per_cluster),
+            first_data_sector: self.first_data_sector() as usize,
+            total_clusters: self.total_clusters() as usize,
+        }
+    }
+    /// 只有 FAT32 有 FSInfo 扇区
     pub fn fsinfo_sector(&self) -> u32 {
         u32::from(self.fsinfo_sector)
     }
+    /// 只有 FAT32 有备份启动扇区
     pub fn backup_boot_sector(&self) -> u32 {
         u32::from(self.backup_boot_sector)
     }
+    /// 调整 FAT32 卷大小后的 BPB, 根目录簇可能在缩小时被搬走
+    pub(crate) fn resized(
+        &self,
+        total_sectors: u32,
+        fats_sectors: u32,
+        root_dir_cluster: u32,
+    ) -> Self {
+        Self {
+            total_sectors_16: 0,
+            total_sectors_32: total_sectors,
+            fats_sectors,
+            root_dir_cluster,
+            ..*self
+        }
+    }
 }
 
-// RunFS 全程不会改变这个起始扇区,也不能改变起始扇区,因为不具备创建文件系统,扩容等功能
+// 格式化时创建, 挂载后只有调整卷大小时才会改写
 #[repr(C, packed(1))]
 #[derive(Debug, Copy, Clone)]
 pub struct BootSector {
     bootjmp: [u8; 3],
     oem_name: [u8; 8],
     pub(crate) bpb: BiosParameterBlock,
-    boot_code: [u8; 420],
+    boot_code: [u8; BootSector::BOOT_CODE_LEN],
     boot_sig: [u8; 2],
 }
 
@@ -261,24 +513,93 @@ impl Default for BootSector {
 }
 
 impl BootSector {
-    #[allow(unused)]
-    fn set_bootjump(&mut self, data: &[u8]) {
-        self.bootjmp = data.try_into().expect("slice with incorrect length");
+    const JUMP_INSTRUCTION: [u8; 3] = [0xEB, 0x58, 0x90];
+    // FAT12/16 的 BPB 更短, 启动代码从 0x3E 开始
+    const JUMP_INSTRUCTION_16: [u8; 3] = [0xEB, 0x3C, 0x90];
+    const OEM_NAME: [u8; 8] = *b"RUNFS   ";
+    const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+    // FAT32 引导代码区在启动扇区中的偏移, 跳转指令要跳到这里面
+    const BOOT_CODE_START: usize = 3 + 8 + core::mem::size_of::<BiosParameterBlock>();
+    /// 引导代码区长度
+    pub const BOOT_CODE_LEN: usize = 420;
+    /// 链式加载的文件起始簇号在引导代码区中的偏移, 这时引导代码最长只能到这里
+    pub const BOOT_FILE_CLUSTER_OFFSET: usize = Self::BOOT_CODE_LEN - 4;
+
+    /// 格式化时生成启动扇区, 启动代码全部为 0
+    pub(crate) fn for_format(bpb: BiosParameterBlock) -> Self {
+        Self {
+            bootjmp: if bpb.fat_type() == FatType::Fat32 {
+                Self::JUMP_INSTRUCTION
+            } else {
+                Self::JUMP_INSTRUCTION_16
+            },
+            oem_name: Self::OEM_NAME,
+            bpb,
+            boot_sig: Self::SIGNATURE,
+            ..Self::default()
+        }
+    }
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const BootSector) as *const u8,
+                core::mem::size_of::<BootSector>(),
+            )
+        }
+    }
+    pub fn bootjump(&self) -> [u8; 3] {
+        self.bootjmp
+    }
+    pub fn oem_name(&self) -> [u8; 8] {
+        self.oem_name
+    }
+    pub fn boot_code(&self) -> &[u8] {
+        &self.boot_code
+    }
+    /// 设置跳转指令, 只接受跳进 FAT32 引导代码区的 EB xx 90 短跳转或 E9 xx xx 近跳转
+    pub fn set_bootjump(&mut self, jump: [u8; 3]) -> Result<(), FSError> {
+        let target = match jump[0] {
+            0xEB => 2 + i32::from(jump[1] as i8),
+            0xE9 => 3 + i32::from(i16::from_le_bytes([jump[1], jump[2]])),
+            _ => return Err(FSError::InvalidInput),
+        };
+        let code_start = Self::BOOT_CODE_START as i32;
+        if target < code_start || target >= code_start + Self::BOOT_CODE_LEN as i32 {
+            return Err(FSError::InvalidInput);
+        }
+        self.bootjmp = jump;
+        Ok(())
+    }
+    pub fn set_oem_name(&mut self, oem_name: [u8; 8]) {
+        self.oem_name = oem_name;
     }
-    #[allow(unused)]
-    fn set_oem_name(&mut self, data: &[u8]) {
-        self.oem_name = data.try_into().expect("slice with incorrect length");
+    /// 写入引导代码, 不足 BOOT_CODE_LEN 的部分补 0
+    pub fn set_boot_code(&mut self, code: &[u8]) -> Result<(), FSError> {
+        if code.len() > Self::BOOT_CODE_LEN {
+            return Err(FSError::InvalidInput);
+        }
+        self.boot_code = [0; Self::BOOT_CODE_LEN];
+        self.boot_code[..code.len()].copy_from_slice(code);
+        Ok(())
     }
-    #[allow(unused)]
-    fn set_boot_code(&mut self, data: &[u8]) {
-        self.boot_code = data.try_into().expect("slice with incorrect length");
+    /// 引导代码区最后 4 Byte 记录的要链式加载的文件的起始簇号
+    pub fn boot_file_cluster(&self) -> u32 {
+        let offset = Self::BOOT_FILE_CLUSTER_OFFSET;
+        u32::from_le_bytes(self.boot_code[offset..offset + 4].try_into().unwrap())
     }
-    #[allow(unused)]
-    fn set_boot_sig(&mut self, data: &[u8]) {
-        self.boot_sig = data.try_into().expect("slice with incorrect length");
+    pub fn set_boot_file_cluster(&mut self, cluster: u32) {
+        let offset = Self::BOOT_FILE_CLUSTER_OFFSET;
+        self.boot_code[offset..offset + 4].copy_from_slice(&cluster.to_le_bytes());
     }
     // 直接通过块设备读取获得启动扇区, 只用于 RunFileSystem 创建
-    pub fn directly_new(block_device: Arc<dyn BlockDevice>) -> Self {
+    pub fn directly_new(block_device: Arc<dyn BlockDevice>) -> Result<Self, FSError> {
+        Self::directly_new_at(block_device, 0)
+    }
+    /// 读取位于 sector_id 扇区的启动扇区, 用于分区中的文件系统
+    pub fn directly_new_at(
+        block_device: Arc<dyn BlockDevice>,
+        sector_id: usize,
+    ) -> Result<Self, FSError> {
         let boot_sector = BootSector::default();
         // 调试没问题,能够获取 512 Byte 准确数据
         let sector_slice = unsafe {
@@ -287,11 +608,44 @@ impl BootSector {
                 core::mem::size_of::<BootSector>(),
             )
         };
-        block_device.read_block(0, sector_slice).unwrap();
-        boot_sector
+        block_device.read_block(sector_id, sector_slice)?;
+        Ok(boot_sector)
+    }
+    /// 读取并校验 start_sector 处的启动扇区, 损坏时改用 FAT32 的备份启动扇区
+    /// 主启动扇区中记录的备份位置不一定可信, 先试它再试默认的第 6 扇区, 都不可用时返回主启动扇区的错误
+    pub(crate) fn read_valid(
+        block_device: &Arc<dyn BlockDevice>,
+        start_sector: usize,
+    ) -> Result<(Self, SectorCopy), FSError> {
+        let primary = Self::directly_new_at(Arc::clone(block_device), start_sector)?;
+        let err = match primary.validate() {
+            Ok(()) => return Ok((primary, SectorCopy::Primary)),
+            Err(e) => e,
+        };
+        log::warn!("boot sector not valid: {:?}, trying backup", err);
+        let mut backup_sectors = vec![BACKUP_BOOT_SECTOR];
+        // 保留区一般只有 32 个扇区, 更大的值多半是损坏的数据
+        let hint = primary.bpb.backup_boot_sector;
+        if hint != 0 && hint < 32 && hint != BACKUP_BOOT_SECTOR {
+            backup_sectors.insert(0, hint);
+        }
+        for backup_sector in backup_sectors {
+            let backup = Self::directly_new_at(
+                Arc::clone(block_device),
+                start_sector + usize::from(backup_sector),
+            )?;
+            // 备份中记录的位置要和实际读到它的位置一致
+            if backup.validate().is_ok()
+                && backup.bpb.fat_type() == FatType::Fat32
+                && backup.bpb.backup_boot_sector == backup_sector
+            {
+                return Ok((backup, SectorCopy::Backup));
+            }
+        }
+        Err(err)
     }
     pub(crate) fn validate(&self) -> Result<(), FSError> {
-        if self.boot_sig != [0x55, 0xAA] {
+        if self.boot_sig != Self::SIGNATURE {
             // println!(
             //     "Invalid boot sector signature: expected [0x55, 0xAA] but got {:?}",
             //     self.boot_sig
@@ -307,4 +661,22 @@ impl BootSector {
         self.bpb.validate()?;
         Ok(())
     }
+    /// 写回位于 start_sector 的启动扇区, FAT32 同时写备份启动扇区, 扇区中启动扇区之后的部分保持不变
+    pub(crate) fn write_back(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        start_sector: usize,
+    ) -> Result<(), FSError> {
+        let mut sector_ids = vec![start_sector];
+        if self.bpb.fat_type() == FatType::Fat32 {
+            sector_ids.push(start_sector + self.bpb.backup_boot_sector() as usize);
+        }
+        let mut sector: Vec<u8> = vec![0; usize::from(self.bpb.bytes_per_sector())];
+        for sector_id in sector_ids {
+            block_device.read_block(sector_id, &mut sector)?;
+            sector[..core::mem::size_of::<BootSector>()].copy_from_slice(self.as_bytes());
+            block_device.write_block(sector_id, &sector)?;
+        }
+        Ok(())
+    }
 }
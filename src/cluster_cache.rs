@@ -1,56 +1,71 @@
-/// 簇缓存层，扇区的进一步抽象，用于 FAT32 的数据区
-use super::{BiosParameterBlock, BlockDevice, START_CLUS_ID};
-use crate::config::{DATACLU_CACHE_SZ, MAX_CLUS_SZ};
+/// 簇缓存层，扇区的进一步抽象，用于 FAT 的数据区和 exFAT 的簇堆
+use super::{
+    AsyncLoad, BlockDevice, CacheGeometry, CacheLoad, IOError, WriteBackPin, WriteBackState,
+    START_CLUS_ID,
+};
+use crate::config::DATACLU_CACHE_SZ;
 #[cfg(not(feature = "std"))]
 use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::any::Any;
 use spin::RwLock;
 #[cfg(feature = "std")]
 use std::{collections::VecDeque, sync::Arc};
 
 pub struct ClusterCache {
     cache: Vec<u8>,
-    cluster_id: usize, // cluster_id 是数据区的簇号, 一般从 2 开始标号
+    cluster_id: usize,   // cluster_id 是数据区的簇号, 一般从 2 开始标号
+    start_sector: usize, // 文件系统所在分区的起始扇区
     modified: bool,
-    bpb: Arc<BiosParameterBlock>,
+    write_back: Arc<WriteBackState>,
+    geometry: CacheGeometry,
     block_dev: Arc<dyn BlockDevice>, // Arc + dyn 实现 BlockDevice Trait 的动态分发
 }
 
 impl ClusterCache {
     pub fn new(
         cluster_id: usize,
+        start_sector: usize,
         block_dev: Arc<dyn BlockDevice>,
-        bpb: Arc<BiosParameterBlock>,
-    ) -> Self {
-        let total_clusters: usize = bpb.total_clusters().try_into().unwrap();
+        geometry: CacheGeometry,
+    ) -> Result<Self, IOError> {
+        let total_clusters: usize = geometry.total_clusters;
         let end_cluster_id: usize = total_clusters + START_CLUS_ID;
         assert!(
             cluster_id >= START_CLUS_ID && cluster_id <= end_cluster_id,
             "cluster id {} not in data range ",
             cluster_id
         );
-        let sectors_per_cluster: usize = bpb.sectors_per_cluster().try_into().unwrap();
-        let data_start_sector: usize = bpb.first_data_sector().try_into().unwrap();
-        let sector_size: usize = bpb.bytes_per_sector().try_into().unwrap();
-        let mut cache: Vec<u8> = vec![0; MAX_CLUS_SZ];
-        let block_id = (cluster_id - START_CLUS_ID) * sectors_per_cluster + data_start_sector;
-        let cluster_size: usize = bpb.cluster_size().try_into().unwrap();
-        for (i, id) in (block_id..(block_id + sectors_per_cluster)).enumerate() {
-            block_dev
-                .read_block(id, &mut cache[(i * sector_size)..((i + 1) * sector_size)])
-                .unwrap();
-        }
-        // 先占后缩,适配尽可能宽的簇大小范围,同时避免空间不够用
-        cache.resize_with(cluster_size, Default::default);
-        cache.shrink_to(cluster_size);
-        assert!(
-            cache.capacity() == cluster_size,
-            "cluster cache len cannot be shrink to proper size"
-        );
+        // 缓存按卷的实际簇大小分配, 整簇一次读入
+        let mut cache: Vec<u8> = vec![0; geometry.cluster_size()];
+        block_dev.read_blocks(
+            geometry.cluster_sector(cluster_id) + start_sector,
+            geometry.sectors_per_cluster,
+            &mut cache,
+        )?;
+        Ok(Self::from_data(
+            cluster_id,
+            start_sector,
+            block_dev,
+            geometry,
+            cache,
+        ))
+    }
+    /// 用已经读到的簇内容创建, 异步加载时使用
+    pub fn from_data(
+        cluster_id: usize,
+        start_sector: usize,
+        block_dev: Arc<dyn BlockDevice>,
+        geometry: CacheGeometry,
+        cache: Vec<u8>,
+    ) -> Self {
+        assert_eq!(cache.len(), geometry.cluster_size());
         Self {
             cache,
             cluster_id,
+            start_sector,
             modified: false,
-            bpb,
+            write_back: Arc::new(WriteBackState::default()),
+            geometry,
             block_dev,
         }
     }
@@ -68,7 +83,7 @@ impl ClusterCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        let cluster_size: usize = self.bpb.cluster_size().try_into().unwrap();
+        let cluster_size: usize = self.geometry.cluster_size();
         assert!(offset + type_size <= cluster_size);
         unsafe {
             &*((&self.cache[offset..offset + type_size]).as_ptr() as *const _ as usize as *const T)
@@ -80,7 +95,7 @@ impl ClusterCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        let cluster_size = self.bpb.cluster_size() as usize;
+        let cluster_size = self.geometry.cluster_size();
         assert!(
             offset + type_size <= cluster_size,
             "offset: {}, type_size: {}",
@@ -102,75 +117,271 @@ impl ClusterCache {
     fn set_modify(&mut self) {
         self.modified = true
     }
-    fn sync(&mut self) {
+    // 和 BlockCache 一样, 等异步写回的旧快照落盘
+    fn wait_write_back(&mut self) {
+        if self.write_back.wait() {
+            self.modified = true;
+        }
+    }
+    // 写回失败时仍然保留修改标记
+    fn sync(&mut self) -> Result<(), IOError> {
+        self.wait_write_back();
         if self.modified {
-            let sector_size: usize = self.bpb.bytes_per_sector().try_into().unwrap();
-            let sectors_per_cluster: usize = self.bpb.sectors_per_cluster().try_into().unwrap();
-            let data_start_sector: usize = self.bpb.first_data_sector().try_into().unwrap();
+            self.block_dev.write_blocks(
+                self.geometry.cluster_sector(self.cluster_id) + self.start_sector,
+                self.geometry.sectors_per_cluster,
+                &self.cache,
+            )?;
             self.modified = false;
-            let block_id =
-                (self.cluster_id - START_CLUS_ID) * sectors_per_cluster + data_start_sector;
-            for (i, id) in (block_id..(block_id + sectors_per_cluster)).enumerate() {
-                self.block_dev
-                    .write_block(id, &self.cache[(i * sector_size)..((i + 1) * sector_size)])
-                    .unwrap();
-            }
         }
+        Ok(())
     }
 }
 
 impl Drop for ClusterCache {
     fn drop(&mut self) {
-        self.sync()
+        if let Err(e) = self.sync() {
+            log::error!("cluster {} lost on write back: {:?}", self.cluster_id, e);
+        }
     }
 }
 
+/// 不在缓存中的相邻簇合并成的一次块设备请求, end 是这一段之后的簇序号
+pub(crate) struct DeviceRun {
+    pub block_id: usize,
+    pub blocks: usize,
+    pub end: usize,
+}
+
 pub struct ClusterCacheManager {
-    bpb: Arc<BiosParameterBlock>,
+    geometry: CacheGeometry,
+    start_sector: usize,
     block_device: Arc<dyn BlockDevice>,
     queue: VecDeque<(usize, Arc<RwLock<ClusterCache>>)>,
 }
 
 impl ClusterCacheManager {
-    pub fn new(bpb: Arc<BiosParameterBlock>, block_device: Arc<dyn BlockDevice>) -> Self {
+    pub fn new(
+        geometry: CacheGeometry,
+        start_sector: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
         Self {
-            bpb,
+            geometry,
+            start_sector,
             block_device,
             queue: VecDeque::new(),
         }
     }
-    pub fn get_cache(&mut self, cluster_id: usize) -> Arc<RwLock<ClusterCache>> {
+    pub fn get_cache(&mut self, cluster_id: usize) -> Result<Arc<RwLock<ClusterCache>>, IOError> {
         if let Some(pair) = self.queue.iter().find(|pair| pair.0 == cluster_id) {
-            Arc::clone(&pair.1)
+            Ok(Arc::clone(&pair.1))
         } else {
-            // substitute
-            if self.queue.len() == DATACLU_CACHE_SZ {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of SectorCache!");
-                }
-            }
+            self.evict()?;
             // load cluster into mem and push back
             let cluster_cache = Arc::new(RwLock::new(ClusterCache::new(
                 cluster_id,
+                self.start_sector,
                 Arc::clone(&self.block_device),
-                Arc::clone(&self.bpb),
-            )));
+                self.geometry,
+            )?));
             self.queue
                 .push_back((cluster_id, Arc::clone(&cluster_cache)));
-            cluster_cache
+            Ok(cluster_cache)
         }
     }
-    // pub fn data_cache_sync_all(&mut self) {
-    //     for (_, cache) in self.queue.iter() {
-    //         cache.write().sync();
-    //     }
-    // }
+    // 队列满时换出最早的没有被使用的缓存
+    fn evict(&mut self) -> Result<(), IOError> {
+        if self.queue.len() == DATACLU_CACHE_SZ {
+            // from front to tail
+            if let Some((idx, _)) = self
+                .queue
+                .iter()
+                .enumerate()
+                .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+            {
+                // 先写回, 失败时缓存留在队列中, 修改不会丢
+                self.queue[idx].1.write().sync()?;
+                self.queue.drain(idx..=idx);
+            } else {
+                panic!("Run out of SectorCache!");
+            }
+        }
+        Ok(())
+    }
+    fn cached(&self, cluster_id: usize) -> Option<Arc<RwLock<ClusterCache>>> {
+        self.queue
+            .iter()
+            .find(|pair| pair.0 == cluster_id)
+            .map(|pair| Arc::clone(&pair.1))
+    }
+    /// 读从 first_cluster 开始物理上连续的 buf.len() / 簇大小 个簇
+    /// 已经缓存的簇从缓存中读, 其余相邻的簇合并成一次 read_blocks, 读到的簇不进缓存
+    pub fn read_clusters(&mut self, first_cluster: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        let cluster_size = self.geometry.cluster_size();
+        let count = buf.len() / cluster_size;
+        let mut i = 0;
+        while i < count {
+            match self.uncached_run(first_cluster, i, count) {
+                None => {
+                    let dst = &mut buf[i * cluster_size..(i + 1) * cluster_size];
+                    self.read_cached(first_cluster + i, 0, dst);
+                    i += 1;
+                }
+                Some(run) => {
+                    let dst = &mut buf[i * cluster_size..run.end * cluster_size];
+                    self.block_device
+                        .read_blocks(run.block_id, run.blocks, dst)?;
+                    i = run.end;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 写从 first_cluster 开始物理上连续的 buf.len() / 簇大小 个簇, 缓存和 read_clusters 相同处理
+    pub fn write_clusters(&mut self, first_cluster: usize, buf: &[u8]) -> Result<(), IOError> {
+        let cluster_size = self.geometry.cluster_size();
+        let count = buf.len() / cluster_size;
+        let mut i = 0;
+        while i < count {
+            match self.uncached_run(first_cluster, i, count) {
+                None => {
+                    let src = &buf[i * cluster_size..(i + 1) * cluster_size];
+                    self.write_cached(first_cluster + i, 0, src);
+                    i += 1;
+                }
+                Some(run) => {
+                    let src = &buf[i * cluster_size..run.end * cluster_size];
+                    self.block_device
+                        .write_blocks(run.block_id, run.blocks, src)?;
+                    i = run.end;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// read_clusters/write_clusters 从第 i 个簇开始不在缓存中的一段, 第 i 个簇在缓存中时返回 None
+    pub(crate) fn uncached_run(
+        &self,
+        first_cluster: usize,
+        i: usize,
+        count: usize,
+    ) -> Option<DeviceRun> {
+        if self.is_cached(first_cluster + i) {
+            return None;
+        }
+        let mut end = i + 1;
+        while end < count && !self.is_cached(first_cluster + end) {
+            end += 1;
+        }
+        Some(DeviceRun {
+            block_id: self.cluster_block(first_cluster + i),
+            blocks: (end - i) * self.geometry.sectors_per_cluster,
+            end,
+        })
+    }
+    /// 不看缓存, 直接从块设备读从 first_cluster 开始物理上连续的整簇
+    pub(crate) fn read_clusters_uncached(
+        &self,
+        first_cluster: usize,
+        buf: &mut [u8],
+    ) -> Result<(), IOError> {
+        let count = buf.len() / self.geometry.cluster_size();
+        self.block_device.read_blocks(
+            self.cluster_block(first_cluster),
+            count * self.geometry.sectors_per_cluster,
+            &mut buf[..count * self.geometry.cluster_size()],
+        )
+    }
+    /// 簇的第一个块号, 已经加上分区起始扇区
+    pub(crate) fn cluster_block(&self, cluster_id: usize) -> usize {
+        self.geometry.cluster_sector(cluster_id) + self.start_sector
+    }
+    pub(crate) fn is_cached(&self, cluster_id: usize) -> bool {
+        self.cached(cluster_id).is_some()
+    }
+    /// 簇在缓存中时从 offset 处读满 buf, 不在时返回 false
+    pub(crate) fn read_cached(&self, cluster_id: usize, offset: usize, buf: &mut [u8]) -> bool {
+        self.cached(cluster_id).is_some_and(|cache| {
+            buf.copy_from_slice(&cache.read().cache[offset..offset + buf.len()]);
+            true
+        })
+    }
+    /// 簇在缓存中时把 buf 写到 offset 处并标记修改, 不在时返回 false
+    pub(crate) fn write_cached(&self, cluster_id: usize, offset: usize, buf: &[u8]) -> bool {
+        self.cached(cluster_id).is_some_and(|cache| {
+            let mut cache = cache.write();
+            cache.cache[offset..offset + buf.len()].copy_from_slice(buf);
+            cache.set_modify();
+            true
+        })
+    }
+    /// 绕过缓存直接写设备之后, 让已缓存的簇和设备上的内容一致, 不改变修改标记
+    pub(crate) fn refresh_cached(&self, first_cluster: usize, buf: &[u8]) {
+        let cluster_size = self.geometry.cluster_size();
+        for (i, data) in buf.chunks_exact(cluster_size).enumerate() {
+            if let Some(cache) = self.cached(first_cluster + i) {
+                cache.write().cache.copy_from_slice(data);
+            }
+        }
+    }
+    /// 写回所有修改过的簇, 缓存仍然保留
+    pub fn data_cache_sync_all(&mut self) -> Result<(), IOError> {
+        for (_, cache) in self.queue.iter() {
+            cache.write().sync()?;
+        }
+        Ok(())
+    }
+}
+
+impl AsyncLoad for ClusterCacheManager {
+    fn prepare_load(&mut self, cluster_id: usize) -> CacheLoad {
+        if self.queue.iter().any(|pair| pair.0 == cluster_id) {
+            return CacheLoad::Cached;
+        }
+        if self.queue.len() == DATACLU_CACHE_SZ {
+            if let Some(idx) = self
+                .queue
+                .iter()
+                .position(|pair| Arc::strong_count(&pair.1) == 1)
+            {
+                let mut cache = self.queue[idx].1.write();
+                cache.wait_write_back();
+                if cache.modified {
+                    cache.modified = false;
+                    let pin = Arc::clone(&self.queue[idx].1) as Arc<dyn Any + Send + Sync>;
+                    return CacheLoad::WriteBack {
+                        block_id: self.geometry.cluster_sector(cache.cluster_id)
+                            + self.start_sector,
+                        count: self.geometry.sectors_per_cluster,
+                        data: cache.cache.clone(),
+                        pin: WriteBackPin::new(pin, &cache.write_back),
+                    };
+                }
+                drop(cache);
+                self.queue.remove(idx);
+            }
+        }
+        CacheLoad::Read {
+            block_id: self.cluster_block(cluster_id),
+            count: self.geometry.sectors_per_cluster,
+            len: self.geometry.cluster_size(),
+        }
+    }
+    fn finish_load(&mut self, cluster_id: usize, data: Vec<u8>) -> Result<(), IOError> {
+        if self.queue.iter().any(|pair| pair.0 == cluster_id) {
+            return Ok(());
+        }
+        self.evict()?;
+        let cluster_cache = ClusterCache::from_data(
+            cluster_id,
+            self.start_sector,
+            Arc::clone(&self.block_device),
+            self.geometry,
+            data,
+        );
+        self.queue
+            .push_back((cluster_id, Arc::new(RwLock::new(cluster_cache))));
+        Ok(())
+    }
 }
@@ -0,0 +1,212 @@
+// 沿簇链走和按偏移读写文件的计算, 需要查 FAT 表项或读写数据时停下交给调用方
+// 同步路径直接查缓存, 异步路径先等待缓存加载, 两边走的步骤相同
+use core::ops::Range;
+
+/// 一步一步沿簇链走, pending 给出要查表项的簇, 查到的下一个簇交给 advance
+pub(crate) trait ChainWalk {
+    type Output;
+    /// 下一个要查表项的簇, None 表示已经走完
+    fn pending(&self) -> Option<usize>;
+    fn advance(&mut self, next: Option<usize>);
+    fn finish(self) -> Self::Output;
+}
+
+/// 簇链上第 index 个簇, 链不够长时为 None
+pub(crate) struct SearchCluster {
+    cluster: Option<usize>,
+    remaining: usize,
+}
+
+impl SearchCluster {
+    pub fn new(start_cluster: usize, index: usize) -> Self {
+        Self {
+            cluster: Some(start_cluster),
+            remaining: index,
+        }
+    }
+}
+
+impl ChainWalk for SearchCluster {
+    type Output = Option<usize>;
+    fn pending(&self) -> Option<usize> {
+        self.cluster.filter(|_| self.remaining > 0)
+    }
+    fn advance(&mut self, next: Option<usize>) {
+        self.cluster = next;
+        self.remaining -= 1;
+    }
+    fn finish(self) -> Option<usize> {
+        self.cluster
+    }
+}
+
+/// 簇链的长度
+pub(crate) struct CountClusters {
+    cluster: Option<usize>,
+    num: usize,
+}
+
+impl CountClusters {
+    pub fn new(start_cluster: usize) -> Self {
+        Self {
+            cluster: Some(start_cluster),
+            num: 1,
+        }
+    }
+}
+
+impl ChainWalk for CountClusters {
+    type Output = usize;
+    fn pending(&self) -> Option<usize> {
+        self.cluster
+    }
+    fn advance(&mut self, next: Option<usize>) {
+        if next.is_some() {
+            self.num += 1;
+        }
+        self.cluster = next;
+    }
+    fn finish(self) -> usize {
+        self.num
+    }
+}
+
+/// 从 cluster_id 开始物理上也连续的簇数(不超过 max)和之后的下一个簇
+pub(crate) struct ContiguousRun {
+    last: usize,
+    count: usize,
+    max: usize,
+    next: Option<usize>,
+    done: bool,
+}
+
+impl ContiguousRun {
+    pub fn new(cluster_id: usize, max: usize) -> Self {
+        Self {
+            last: cluster_id,
+            count: 1,
+            max,
+            next: None,
+            done: false,
+        }
+    }
+}
+
+impl ChainWalk for ContiguousRun {
+    type Output = (usize, Option<usize>);
+    fn pending(&self) -> Option<usize> {
+        (!self.done).then_some(self.last)
+    }
+    fn advance(&mut self, next: Option<usize>) {
+        self.next = next;
+        if self.count < self.max && next == Some(self.last + 1) {
+            self.count += 1;
+            self.last += 1;
+        } else {
+            self.done = true;
+        }
+    }
+    fn finish(self) -> (usize, Option<usize>) {
+        (self.count, self.next)
+    }
+}
+
+/// ClusterTransfer 的下一步
+pub(crate) enum Segment {
+    /// 要先查 cluster 的下一个簇, 交给 ClusterTransfer::set_next
+    Next(usize),
+    /// 从簇边界开始最多 max 个整簇, 用 ContiguousRun 合并物理上连续的簇后交给 advance_run
+    Whole { cluster: usize, max: usize },
+    /// cluster 中 offset 处的 len 字节, 读写完交给 advance
+    Partial {
+        cluster: usize,
+        offset: usize,
+        len: usize,
+    },
+}
+
+/// 按偏移读写簇链上 [offset, end) 的数据, 先沿簇链走到 offset 所在的簇
+pub(crate) struct ClusterTransfer {
+    cluster_size: usize,
+    start: usize,
+    end: usize,
+    offset: usize,
+    cluster: Option<usize>,
+    skip: usize, // 读写之前还要沿簇链前进的簇数
+}
+
+impl ClusterTransfer {
+    pub fn new(first_cluster: usize, offset: usize, end: usize, cluster_size: usize) -> Self {
+        Self {
+            cluster_size,
+            start: offset,
+            end,
+            offset,
+            cluster: Some(first_cluster),
+            skip: offset / cluster_size,
+        }
+    }
+    /// 读写完或者簇链提前结束时返回 None
+    pub fn segment(&self) -> Option<Segment> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let cluster = self.cluster?;
+        if self.skip > 0 {
+            return Some(Segment::Next(cluster));
+        }
+        let whole_clusters = (self.end - self.offset) / self.cluster_size;
+        if self.offset.is_multiple_of(self.cluster_size) && whole_clusters > 0 {
+            return Some(Segment::Whole {
+                cluster,
+                max: whole_clusters,
+            });
+        }
+        let offset = self.offset % self.cluster_size;
+        Some(Segment::Partial {
+            cluster,
+            offset,
+            len: (self.cluster_size - offset).min(self.end - self.offset),
+        })
+    }
+    /// 接下来 len 字节在调用方缓冲区中的位置
+    pub fn buf_range(&self, len: usize) -> Range<usize> {
+        self.offset - self.start..self.offset - self.start + len
+    }
+    pub fn set_next(&mut self, next: Option<usize>) {
+        self.cluster = next;
+        self.skip -= 1;
+    }
+    /// Whole 读写完 count 个簇, next 是 ContiguousRun 给出的下一个簇
+    pub fn advance_run(&mut self, count: usize, next: Option<usize>) {
+        self.offset += count * self.cluster_size;
+        self.cluster = next;
+    }
+    /// Partial 读写完, 下一段从下一个簇开始
+    pub fn advance(&mut self, len: usize) {
+        self.offset += len;
+        self.skip = 1;
+    }
+    /// 已经读写的长度
+    pub fn done(&self) -> usize {
+        self.offset - self.start
+    }
+}
+
+/// 固定根目录区中 [offset, end) 按扇区分段, 给出每段的偏移和在调用方缓冲区中的位置
+pub(crate) fn root_segments(
+    offset: usize,
+    end: usize,
+    sector_size: usize,
+) -> impl Iterator<Item = (usize, Range<usize>)> {
+    let mut current = offset;
+    core::iter::from_fn(move || {
+        if current >= end {
+            return None;
+        }
+        let len = (sector_size - current % sector_size).min(end - current);
+        let segment = (current, current - offset..current - offset + len);
+        current += len;
+        Some(segment)
+    })
+}
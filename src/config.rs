@@ -1,7 +1,16 @@
 /// 可以自行调整的变量
 
-pub const MAX_SEC_SZ: usize = 512;      // 限制最大扇区512Byte, 太大了单片机受不了
+// 能挂载的最大扇区和簇, 缓存按挂载的卷实际的扇区和簇大小分配, 这里只是上限
+// 默认覆盖 4Kn 硬盘和 FAT 允许的最大簇(4096 * 128), 内存很小的单片机可以打开 small-cache feature
+#[cfg(not(feature = "small-cache"))]
+pub const MAX_SEC_SZ: usize = 4096;
+#[cfg(not(feature = "small-cache"))]
+pub const MAX_CLUS_SZ: usize = 4096 * 128;
+#[cfg(feature = "small-cache")]
+pub const MAX_SEC_SZ: usize = 512; // 限制最大扇区512Byte, 太大了单片机受不了
+#[cfg(feature = "small-cache")]
 pub const MAX_CLUS_SZ: usize = 512 * 64; // 限制最大簇32KB, 太大了单片机受不了
 
 pub const INFOSEC_CACHE_SZ: usize = 4; // 扇区缓冲区长度
 pub const DATACLU_CACHE_SZ: usize = 2; // 簇缓冲区长度
+pub const BATCH_SECTORS: usize = 64; // 格式化, 调整大小时清零或搬移扇区一次请求的最大扇区数
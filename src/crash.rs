@@ -0,0 +1,150 @@
+// 断电测试: 在内存盘上回放一串文件系统操作, 依次在每一次写时断电, 检查镜像还能挂载并且没有一致性错误
+use super::{CheckReport, FSError, FaultyDevice, FileAttributes, RamDisk, RunFileSystem, VFile};
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::RwLock;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// 断电测试中回放的操作, 路径都是从根目录开始的绝对路径
+#[derive(Clone, Debug)]
+pub enum CrashOp {
+    CreateDir(String),
+    CreateFile(String),
+    /// 从 offset 处写入 data
+    Write {
+        path: String,
+        offset: usize,
+        data: Vec<u8>,
+    },
+    Delete(String),
+    /// 写回缓存并刷新块设备
+    Sync,
+}
+
+/// 断电测试失败的原因
+#[derive(Debug)]
+pub enum PowerCutFailure {
+    /// 不断电时回放操作就失败了
+    Replay(FSError),
+    /// 在第 cut_at 次写时断电后挂载或检查出错, cut_at 等于总写次数表示没有断电
+    Mount { cut_at: usize, error: FSError },
+    /// 断电后一致性检查发现错误
+    Check { cut_at: usize, report: CheckReport },
+}
+
+/// 断电测试, 每次都从 image 开始回放 ops, 最后卸载
+pub struct PowerCutTest {
+    /// 回放前整个设备的内容, 必须已经格式化
+    pub image: Vec<u8>,
+    pub block_size: usize,
+    pub ops: Vec<CrashOp>,
+    /// Some(seed) 时模拟带易失写缓存的设备, 每个断电点用不同的种子, 见 FaultyDevice::enable_write_cache
+    pub write_cache: Option<u64>,
+}
+
+impl PowerCutTest {
+    pub fn new(image: Vec<u8>, block_size: usize, ops: Vec<CrashOp>) -> Self {
+        Self {
+            image,
+            block_size,
+            ops,
+            write_cache: None,
+        }
+    }
+    /// 先完整回放一遍得到写次数 n, 再依次在第 0..n 次写时断电
+    /// 全部通过时返回 n, 丢失的簇这类不破坏数据的问题不算失败
+    pub fn run(&self) -> Result<usize, PowerCutFailure> {
+        let (disk, device) = self.device(None);
+        self.replay(Arc::clone(&device))
+            .map_err(PowerCutFailure::Replay)?;
+        let writes = device.writes();
+        self.verify(disk, writes)?;
+        for cut_at in 0..writes {
+            let (disk, device) = self.device(Some(cut_at));
+            // 断电后读到的可能是旧数据, 操作出错是正常的
+            let _ = self.replay(device);
+            self.verify(disk, cut_at)?;
+        }
+        Ok(writes)
+    }
+    fn device(&self, cut_at: Option<usize>) -> (Arc<RamDisk>, Arc<FaultyDevice>) {
+        let disk = Arc::new(RamDisk::from_vec(self.image.clone(), self.block_size));
+        let device = Arc::new(FaultyDevice::new(disk.clone()));
+        if let Some(seed) = self.write_cache {
+            device.enable_write_cache(seed.wrapping_add(cut_at.unwrap_or_default() as u64));
+        }
+        if let Some(cut_at) = cut_at {
+            device.cut_power_after(cut_at);
+        }
+        (disk, device)
+    }
+    // 断电后程序也停下, 不再执行后面的操作
+    fn replay(&self, device: Arc<FaultyDevice>) -> Result<(), FSError> {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone())?));
+        {
+            let root_dir = runfs.read().root_vfile(&runfs);
+            for op in self.ops.iter() {
+                apply(&runfs, &root_dir, op)?;
+                if device.is_powered_off() {
+                    return Ok(());
+                }
+            }
+        }
+        match Arc::try_unwrap(runfs) {
+            Ok(runfs) => runfs.into_inner().unmount(),
+            Err(runfs) => runfs.read().sync(),
+        }
+    }
+    // 断电后直接挂载内存盘并检查
+    fn verify(&self, disk: Arc<RamDisk>, cut_at: usize) -> Result<(), PowerCutFailure> {
+        let runfs =
+            RunFileSystem::new(disk).map_err(|error| PowerCutFailure::Mount { cut_at, error })?;
+        let report = runfs
+            .check()
+            .map_err(|error| PowerCutFailure::Mount { cut_at, error })?;
+        if report.has_errors() {
+            log::error!("power cut at write {}: {:?}", cut_at, report.issues);
+            return Err(PowerCutFailure::Check { cut_at, report });
+        }
+        Ok(())
+    }
+}
+
+fn apply(
+    runfs: &Arc<RwLock<RunFileSystem>>,
+    root_dir: &VFile,
+    op: &CrashOp,
+) -> Result<(), FSError> {
+    match op {
+        CrashOp::CreateDir(path) => {
+            let (parent, name) = split_path(root_dir, path)?;
+            parent.create(name, FileAttributes::DIRECTORY)?;
+        }
+        CrashOp::CreateFile(path) => {
+            let (parent, name) = split_path(root_dir, path)?;
+            parent.create(name, FileAttributes::FILE)?;
+        }
+        CrashOp::Write { path, offset, data } => {
+            let file = root_dir.find_vfile_bypath(path)?;
+            if file.write_at(*offset, data)? != data.len() {
+                return Err(FSError::WriteZero);
+            }
+        }
+        CrashOp::Delete(path) => {
+            root_dir.find_vfile_bypath(path)?.delete()?;
+        }
+        CrashOp::Sync => runfs.read().sync()?,
+    }
+    Ok(())
+}
+
+// 拆成父目录和最后一级名字
+fn split_path<'a>(root_dir: &VFile, path: &'a str) -> Result<(Arc<VFile>, &'a str), FSError> {
+    let (parent, name) = path.rsplit_once('/').ok_or(FSError::InvalidInput)?;
+    if name.is_empty() {
+        return Err(FSError::InvalidInput);
+    }
+    let parent = root_dir.find_vfile_bypath(if parent.is_empty() { "/" } else { parent })?;
+    Ok((parent, name))
+}
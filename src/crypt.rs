@@ -0,0 +1,243 @@
+// 块级透明加密: AES-XTS, 逻辑扇区号作为 tweak, 文件系统看到的仍然是普通的 FAT 卷
+use crate::block_device::BlockDevice;
+use crate::error::{FSError, IOError};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256, Block};
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+// 头部: 魔数, 版本, 密钥字节数, 块大小, 校验密钥用的密文
+const HEADER_MAGIC: &[u8; 8] = b"RUNFSXTS";
+const HEADER_VERSION: u16 = 1;
+const VERIFIER_OFFSET: usize = 16;
+const VERIFIER: &[u8; 32] = b"runfs encrypted volume key check";
+// 校验密文用的 tweak, 数据扇区用不到
+const VERIFIER_TWEAK: u128 = u128::MAX;
+const AES_BLOCK_SZ: usize = 16;
+
+// 每个设备只有一份, 不必装箱
+#[allow(clippy::large_enum_variant)]
+enum Cipher {
+    Aes128(Aes128),
+    Aes256(Aes256),
+}
+
+impl Cipher {
+    fn new(key: &[u8]) -> Self {
+        match key.len() {
+            16 => Cipher::Aes128(Aes128::new_from_slice(key).unwrap()),
+            _ => Cipher::Aes256(Aes256::new_from_slice(key).unwrap()),
+        }
+    }
+    fn encrypt(&self, block: &mut Block) {
+        match self {
+            Cipher::Aes128(cipher) => cipher.encrypt_block(block),
+            Cipher::Aes256(cipher) => cipher.encrypt_block(block),
+        }
+    }
+    fn decrypt(&self, block: &mut Block) {
+        match self {
+            Cipher::Aes128(cipher) => cipher.decrypt_block(block),
+            Cipher::Aes256(cipher) => cipher.decrypt_block(block),
+        }
+    }
+}
+
+/// XTS 的两个密钥, 前一半加密数据, 后一半加密 tweak
+struct Xts {
+    data: Cipher,
+    tweak: Cipher,
+}
+
+impl Xts {
+    fn new(key: &[u8]) -> Self {
+        let (data, tweak) = key.split_at(key.len() / 2);
+        Self {
+            data: Cipher::new(data),
+            tweak: Cipher::new(tweak),
+        }
+    }
+    // 每 16 字节用一个 tweak, 之后在 GF(2^128) 中乘 x, 长度必须是 16 的整数倍
+    fn apply(&self, sector: u128, buf: &mut [u8], encrypt: bool) {
+        let mut tweak = Block::from(sector.to_le_bytes());
+        self.tweak.encrypt(&mut tweak);
+        for chunk in buf.chunks_exact_mut(AES_BLOCK_SZ) {
+            let block = Block::from_mut_slice(chunk);
+            xor(block, &tweak);
+            if encrypt {
+                self.data.encrypt(block);
+            } else {
+                self.data.decrypt(block);
+            }
+            xor(block, &tweak);
+            let carry = tweak[AES_BLOCK_SZ - 1] >> 7;
+            for i in (1..AES_BLOCK_SZ).rev() {
+                tweak[i] = (tweak[i] << 1) | (tweak[i - 1] >> 7);
+            }
+            tweak[0] = (tweak[0] << 1) ^ (carry * 0x87);
+        }
+    }
+}
+
+fn xor(block: &mut Block, tweak: &Block) {
+    block
+        .iter_mut()
+        .zip(tweak.iter())
+        .for_each(|(b, t)| *b ^= t);
+}
+
+/// 加密包装, 底层设备的第 0 块是头部, 文件系统的第 n 块存放在第 n + 1 块, 用 n 作 tweak
+/// 密钥是用户提供的 32 字节(AES-128-XTS) 或 64 字节(AES-256-XTS), 头部中只有校验密钥用的密文
+/// 不转发 discard, 避免从 TRIM 中看出哪些块没有使用
+pub struct EncryptedDevice {
+    inner: Arc<dyn BlockDevice>,
+    xts: Xts,
+    block_size: usize,
+}
+
+impl EncryptedDevice {
+    /// 在 inner 上写新的头部, 原有内容都不能再解密, 之后再用 RunFileSystem::format 格式化
+    /// 块大小从设备获取, 不知道时按 512 字节, 必须是 16 的整数倍
+    pub fn create(inner: Arc<dyn BlockDevice>, key: &[u8]) -> Result<Self, FSError> {
+        let device = Self::new(inner, key)?;
+        let mut header: Vec<u8> = vec![0; device.block_size];
+        header[..8].copy_from_slice(HEADER_MAGIC);
+        header[8..10].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+        header[10..12].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        header[12..16].copy_from_slice(&(device.block_size as u32).to_le_bytes());
+        header[VERIFIER_OFFSET..VERIFIER_OFFSET + VERIFIER.len()]
+            .copy_from_slice(&device.verifier());
+        device.inner.write_block(0, &header)?;
+        device.inner.flush()?;
+        Ok(device)
+    }
+    /// 打开 create 创建的加密设备, 不是加密设备时返回 CorruptedFileSystem, 密钥不对时返回 InvalidKey
+    pub fn open(inner: Arc<dyn BlockDevice>, key: &[u8]) -> Result<Self, FSError> {
+        let device = Self::new(inner, key)?;
+        let mut header: Vec<u8> = vec![0; device.block_size];
+        device.inner.read_block(0, &mut header)?;
+        if &header[..8] != HEADER_MAGIC
+            || u16::from_le_bytes([header[8], header[9]]) != HEADER_VERSION
+            || u32::from_le_bytes([header[12], header[13], header[14], header[15]])
+                != device.block_size as u32
+        {
+            log::error!("no encrypted volume header found");
+            return Err(FSError::CorruptedFileSystem);
+        }
+        if u16::from_le_bytes([header[10], header[11]]) as usize != key.len()
+            || header[VERIFIER_OFFSET..VERIFIER_OFFSET + VERIFIER.len()] != device.verifier()
+        {
+            return Err(FSError::InvalidKey);
+        }
+        Ok(device)
+    }
+    fn new(inner: Arc<dyn BlockDevice>, key: &[u8]) -> Result<Self, FSError> {
+        if key.len() != 32 && key.len() != 64 {
+            return Err(FSError::InvalidInput);
+        }
+        let block_size = inner.block_size().unwrap_or(512);
+        if !block_size.is_multiple_of(AES_BLOCK_SZ) || block_size < VERIFIER_OFFSET + VERIFIER.len()
+        {
+            return Err(FSError::InvalidInput);
+        }
+        Ok(Self {
+            inner,
+            xts: Xts::new(key),
+            block_size,
+        })
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    fn verifier(&self) -> [u8; 32] {
+        let mut verifier = *VERIFIER;
+        self.xts.apply(VERIFIER_TWEAK, &mut verifier, true);
+        verifier
+    }
+    // 逻辑块号换算成底层设备的块号, 跳过头部
+    fn translate(&self, blocks: Range<usize>) -> Result<usize, IOError> {
+        let end = blocks.end.checked_add(1).ok_or(IOError::OutOfRange)?;
+        if self
+            .inner
+            .num_blocks()
+            .is_some_and(|num_blocks| end > num_blocks)
+        {
+            return Err(IOError::OutOfRange);
+        }
+        Ok(blocks.start + 1)
+    }
+}
+
+impl BlockDevice for EncryptedDevice {
+    // 整块解密后复制, buf 可以比块短
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if buf.len() > self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        let mut block: Vec<u8> = vec![0; self.block_size];
+        self.inner
+            .read_block(self.translate(block_id..block_id + 1)?, &mut block)?;
+        self.xts.apply(block_id as u128, &mut block, false);
+        buf.copy_from_slice(&block[..buf.len()]);
+        Ok(())
+    }
+    // 只接受整块写入
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        if buf.len() != self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        let mut block = buf.to_vec();
+        self.xts.apply(block_id as u128, &mut block, true);
+        self.inner
+            .write_block(self.translate(block_id..block_id + 1)?, &block)
+    }
+    // 簇缓存一次读写整簇, 合并成一次底层请求再逐块加解密
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if count == 0 || buf.len() != count * self.block_size {
+            return crate::block_device::read_blocks_each(self, start_block, count, buf);
+        }
+        self.inner.read_blocks(
+            self.translate(start_block..start_block + count)?,
+            count,
+            buf,
+        )?;
+        for (i, block) in buf.chunks_exact_mut(self.block_size).enumerate() {
+            self.xts.apply((start_block + i) as u128, block, false);
+        }
+        Ok(())
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        if count == 0 || buf.len() != count * self.block_size {
+            return crate::block_device::write_blocks_each(self, start_block, count, buf);
+        }
+        let mut blocks = buf.to_vec();
+        for (i, block) in blocks.chunks_exact_mut(self.block_size).enumerate() {
+            self.xts.apply((start_block + i) as u128, block, true);
+        }
+        self.inner.write_blocks(
+            self.translate(start_block..start_block + count)?,
+            count,
+            &blocks,
+        )
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        self.inner.flush()
+    }
+    fn discard(&self, _blocks: Range<usize>) -> Result<(), IOError> {
+        Ok(())
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner
+            .num_blocks()
+            .map(|num_blocks| num_blocks.saturating_sub(1))
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
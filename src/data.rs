@@ -1,5 +1,6 @@
 use super::{
-    BiosParameterBlock, BlockDevice, ClusterCacheManager, LongDirectoryEntry, ShortDirectoryEntry,
+    BiosParameterBlock, BlockDevice, ClusterCacheManager, FSError, FatType, LongDirectoryEntry,
+    SectorCacheManager, ShortDirectoryEntry, VolumeLabelEntry,
 };
 #[cfg(not(feature = "std"))]
 use alloc::sync::Arc;
@@ -7,80 +8,196 @@ use spin::RwLock;
 #[cfg(feature = "std")]
 use std::sync::Arc;
 
+/// FAT12/16 固定根目录区在目录项位置中使用的簇号, 偏移量是相对根目录区起始的字节数
+pub(crate) const FIXED_ROOT_CLUSTER: usize = 0;
+
 pub struct DataManager {
+    bpb: Arc<BiosParameterBlock>,
     root_dirent: Arc<RwLock<ShortDirectoryEntry>>, // 根目录项
     cluster_cache: ClusterCacheManager,
+    root_cache: SectorCacheManager, // FAT12/16 固定根目录区按扇区缓存
 }
 
 impl DataManager {
     pub(crate) fn new(
         bpb: Arc<BiosParameterBlock>,
         root_dirent: Arc<RwLock<ShortDirectoryEntry>>,
+        start_sector: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> DataManager {
         Self {
+            bpb: Arc::clone(&bpb),
             root_dirent,
-            cluster_cache: ClusterCacheManager::new(bpb, block_device),
+            cluster_cache: ClusterCacheManager::new(
+                bpb.cache_geometry(),
+                start_sector,
+                Arc::clone(&block_device),
+            ),
+            root_cache: SectorCacheManager::new(bpb.cache_geometry(), start_sector, block_device),
         }
     }
+    // 簇号为 FIXED_ROOT_CLUSTER 时访问的是 FAT12/16 的固定根目录区
+    pub(crate) fn is_fixed_root(&self, cluster_id: usize) -> bool {
+        cluster_id == FIXED_ROOT_CLUSTER && self.bpb.fat_type() != FatType::Fat32
+    }
+    // 根目录区中的偏移换算成扇区号和扇区内偏移
+    pub(crate) fn root_position(&self, offset: usize) -> (usize, usize) {
+        let sector_size = self.bpb.bytes_per_sector() as usize;
+        assert!(
+            offset < self.bpb.root_dir_size(),
+            "offset out of fixed root directory"
+        );
+        (
+            self.bpb.first_root_dir_sector() as usize + offset / sector_size,
+            offset % sector_size,
+        )
+    }
     pub fn root_dirent(&self) -> Arc<RwLock<ShortDirectoryEntry>> {
         self.root_dirent.clone()
     }
+    pub(crate) fn cluster_cache(&mut self) -> &mut ClusterCacheManager {
+        &mut self.cluster_cache
+    }
+    pub(crate) fn root_cache(&mut self) -> &mut SectorCacheManager {
+        &mut self.root_cache
+    }
+    /// 缓存命中时读出 cluster_id 中 offset 处的 buf.len() 字节, 不能跨簇或固定根目录区的扇区
+    /// 没有命中时返回 false, 异步路径等待加载后再试
+    pub(crate) fn read_cached(&self, cluster_id: usize, offset: usize, buf: &mut [u8]) -> bool {
+        if self.is_fixed_root(cluster_id) {
+            let (sector_id, offset) = self.root_position(offset);
+            return self.root_cache.read_cached(sector_id, offset, buf);
+        }
+        self.cluster_cache.read_cached(cluster_id, offset, buf)
+    }
+    /// 缓存命中时把 buf 写到 cluster_id 中 offset 处, 范围和 read_cached 相同
+    pub(crate) fn write_cached(&self, cluster_id: usize, offset: usize, buf: &[u8]) -> bool {
+        if self.is_fixed_root(cluster_id) {
+            let (sector_id, offset) = self.root_position(offset);
+            return self.root_cache.write_cached(sector_id, offset, buf);
+        }
+        self.cluster_cache.write_cached(cluster_id, offset, buf)
+    }
+    // 同步加载 cluster_id 中 offset 所在的簇或固定根目录区扇区
+    fn load(&mut self, cluster_id: usize, offset: usize) -> Result<(), FSError> {
+        if self.is_fixed_root(cluster_id) {
+            let (sector_id, _) = self.root_position(offset);
+            self.root_cache.get_cache(sector_id)?;
+        } else {
+            self.cluster_cache.get_cache(cluster_id)?;
+        }
+        Ok(())
+    }
+    /// 和 read_cached 相同, 没有命中时同步加载
+    pub(crate) fn read_bytes(
+        &mut self,
+        cluster_id: usize,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<(), FSError> {
+        if !self.read_cached(cluster_id, offset, buf) {
+            self.load(cluster_id, offset)?;
+            self.read_cached(cluster_id, offset, buf);
+        }
+        Ok(())
+    }
+    /// 和 write_cached 相同, 没有命中时同步加载
+    pub(crate) fn write_bytes(
+        &mut self,
+        cluster_id: usize,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<(), FSError> {
+        if !self.write_cached(cluster_id, offset, buf) {
+            self.load(cluster_id, offset)?;
+            self.write_cached(cluster_id, offset, buf);
+        }
+        Ok(())
+    }
     /// buf 长度必须比簇 cache 大
-    pub fn read_cluster(&mut self, cluster_id: usize, buf: &mut [u8]) {
-        let cache = self.cluster_cache.get_cache(cluster_id);
+    pub fn read_cluster(&mut self, cluster_id: usize, buf: &mut [u8]) -> Result<(), FSError> {
+        let cache = self.cluster_cache.get_cache(cluster_id)?;
         let len = cache.read().len();
         for i in 0..len {
             cache.write().read(i, |d: &u8| buf[i] = *d);
         }
+        Ok(())
     }
     /// buf 长度必须比簇 cache 大
-    pub fn write_cluster(&mut self, cluster_id: usize, buf: &[u8]) {
-        let cache = self.cluster_cache.get_cache(cluster_id);
+    pub fn write_cluster(&mut self, cluster_id: usize, buf: &[u8]) -> Result<(), FSError> {
+        let cache = self.cluster_cache.get_cache(cluster_id)?;
         let len = cache.read().len();
         for i in 0..len {
             cache.write().modify(i, |d: &mut u8| *d = buf[i]);
         }
+        Ok(())
+    }
+    /// 读物理上连续的多个整簇, buf 长度是簇大小的整数倍
+    pub fn read_clusters(&mut self, first_cluster: usize, buf: &mut [u8]) -> Result<(), FSError> {
+        Ok(self.cluster_cache.read_clusters(first_cluster, buf)?)
+    }
+    /// 写物理上连续的多个整簇, buf 长度是簇大小的整数倍
+    pub fn write_clusters(&mut self, first_cluster: usize, buf: &[u8]) -> Result<(), FSError> {
+        Ok(self.cluster_cache.write_clusters(first_cluster, buf)?)
     }
-    pub fn clear_cluster(&mut self, cluster_id: usize) {
-        let cache = self.cluster_cache.get_cache(cluster_id);
+    /// 写回缓存中修改过的簇和固定根目录区扇区
+    pub fn sync(&mut self) -> Result<(), FSError> {
+        self.cluster_cache.data_cache_sync_all()?;
+        self.root_cache.info_cache_sync_all()?;
+        Ok(())
+    }
+    pub fn clear_cluster(&mut self, cluster_id: usize) -> Result<(), FSError> {
+        let cache = self.cluster_cache.get_cache(cluster_id)?;
         let u32_size = core::mem::size_of::<u32>();
         let u32_len = cache.read().len() / u32_size;
         for i in 0..u32_len {
             cache.write().modify(i * u32_size, |d: &mut u32| *d = 0);
         }
+        Ok(())
     }
     pub fn read_cluster_at<T, V>(
         &mut self,
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&T) -> V,
-    ) -> V
+    ) -> Result<V, FSError>
 // where
     //     T: ?Sized,
     {
-        let cache = self.cluster_cache.get_cache(cluster_id);
+        if self.is_fixed_root(cluster_id) {
+            let (sector_id, offset) = self.root_position(offset);
+            let cache = self.root_cache.get_cache(sector_id)?;
+            let cache_read = cache.read();
+            return Ok(f(cache_read.get_ref(offset)));
+        }
+        let cache = self.cluster_cache.get_cache(cluster_id)?;
         let cache_read = cache.read();
         let cache_ref = cache_read.get_ref(offset);
-        f(cache_ref)
+        Ok(f(cache_ref))
     }
     pub fn write_cluster_at<T, V>(
         &mut self,
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&mut T) -> V,
-    ) -> V {
-        let cache = self.cluster_cache.get_cache(cluster_id);
+    ) -> Result<V, FSError> {
+        if self.is_fixed_root(cluster_id) {
+            let (sector_id, offset) = self.root_position(offset);
+            let cache = self.root_cache.get_cache(sector_id)?;
+            let mut cache_write = cache.write();
+            return Ok(f(cache_write.get_mut(offset)));
+        }
+        let cache = self.cluster_cache.get_cache(cluster_id)?;
         let mut cache_write = cache.write();
         let cache_mut = cache_write.get_mut(offset);
-        f(cache_mut)
+        Ok(f(cache_mut))
     }
     pub fn read_short_dirent<V>(
         &mut self,
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&ShortDirectoryEntry) -> V,
-    ) -> V {
+    ) -> Result<V, FSError> {
         self.read_cluster_at(cluster_id, offset, f)
     }
     pub fn modify_short_dirent<V>(
@@ -88,7 +205,7 @@ impl DataManager {
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&mut ShortDirectoryEntry) -> V,
-    ) -> V {
+    ) -> Result<V, FSError> {
         self.write_cluster_at(cluster_id, offset, f)
     }
     pub fn read_long_dirent<V>(
@@ -96,7 +213,7 @@ impl DataManager {
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&LongDirectoryEntry) -> V,
-    ) -> V {
+    ) -> Result<V, FSError> {
         self.read_cluster_at(cluster_id, offset, f)
     }
     pub fn modify_long_dirent<V>(
@@ -104,7 +221,23 @@ impl DataManager {
         cluster_id: usize,
         offset: usize,
         f: impl FnOnce(&mut LongDirectoryEntry) -> V,
-    ) -> V {
+    ) -> Result<V, FSError> {
+        self.write_cluster_at(cluster_id, offset, f)
+    }
+    pub fn read_volume_dirent<V>(
+        &mut self,
+        cluster_id: usize,
+        offset: usize,
+        f: impl FnOnce(&VolumeLabelEntry) -> V,
+    ) -> Result<V, FSError> {
+        self.read_cluster_at(cluster_id, offset, f)
+    }
+    pub fn modify_volume_dirent<V>(
+        &mut self,
+        cluster_id: usize,
+        offset: usize,
+        f: impl FnOnce(&mut VolumeLabelEntry) -> V,
+    ) -> Result<V, FSError> {
         self.write_cluster_at(cluster_id, offset, f)
     }
 }
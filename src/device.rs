@@ -0,0 +1,1070 @@
+// 现成的块设备: 内存盘, 只读包装, 区域切片, 块大小转换, 故障注入, 写时复制覆盖层, 块校验和, 以及 std 下的镜像文件和裸设备
+use crate::block_device::{read_blocks_each, write_blocks_each, BlockDevice};
+use crate::crc32::crc32;
+use crate::error::IOError;
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+use spin::{Mutex, RwLock};
+#[cfg(feature = "std")]
+use std::{collections::BTreeMap, sync::Arc};
+
+// 默认的块大小
+const DEFAULT_BLOCK_SZ: usize = 512;
+
+/// 内存盘, 数据全部放在一块连续的内存里, 只需要 alloc
+pub struct RamDisk {
+    data: RwLock<Vec<u8>>,
+    block_size: usize,
+}
+
+impl RamDisk {
+    /// 全 0 的内存盘, 块大小为 512 字节
+    pub fn new(num_blocks: usize) -> Self {
+        Self::with_block_size(num_blocks, DEFAULT_BLOCK_SZ)
+    }
+    pub fn with_block_size(num_blocks: usize, block_size: usize) -> Self {
+        Self::from_vec(vec![0; num_blocks * block_size], block_size)
+    }
+    /// 用已有的镜像内容创建, 末尾不足一块的部分访问不到
+    pub fn from_vec(data: Vec<u8>, block_size: usize) -> Self {
+        assert!(block_size > 0);
+        Self {
+            data: RwLock::new(data),
+            block_size,
+        }
+    }
+    /// 复制出整个镜像
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.read().clone()
+    }
+    // 块号和长度对应的字节范围, 超出设备时返回 OutOfRange
+    fn range(&self, block_id: usize, len: usize) -> Result<Range<usize>, IOError> {
+        let start = block_id
+            .checked_mul(self.block_size)
+            .ok_or(IOError::OutOfRange)?;
+        let end = start.checked_add(len).ok_or(IOError::OutOfRange)?;
+        if end > self.num_blocks_inner() * self.block_size {
+            return Err(IOError::OutOfRange);
+        }
+        Ok(start..end)
+    }
+    fn num_blocks_inner(&self) -> usize {
+        self.data.read().len() / self.block_size
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        let range = self.range(block_id, buf.len())?;
+        buf.copy_from_slice(&self.data.read()[range]);
+        Ok(())
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        let range = self.range(block_id, buf.len())?;
+        self.data.write()[range].copy_from_slice(buf);
+        Ok(())
+    }
+    // 内存连续, 每份正好一块时一次复制完成
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if count > 0 && buf.len() == count * self.block_size {
+            return self.read_block(start_block, buf);
+        }
+        read_blocks_each(self, start_block, count, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        if count > 0 && buf.len() == count * self.block_size {
+            return self.write_block(start_block, buf);
+        }
+        write_blocks_each(self, start_block, count, buf)
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.num_blocks_inner())
+    }
+}
+
+/// 只读包装, 写入和 discard 都返回 WriteProtected
+pub struct ReadOnlyDevice {
+    inner: Arc<dyn BlockDevice>,
+}
+
+impl ReadOnlyDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        Self { inner }
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+}
+
+impl BlockDevice for ReadOnlyDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner.read_block(block_id, buf)
+    }
+    fn write_block(&self, _block_id: usize, _buf: &[u8]) -> Result<(), IOError> {
+        Err(IOError::WriteProtected)
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner.read_blocks(start_block, count, buf)
+    }
+    fn write_blocks(&self, _start_block: usize, _count: usize, _buf: &[u8]) -> Result<(), IOError> {
+        Err(IOError::WriteProtected)
+    }
+    fn discard(&self, _blocks: Range<usize>) -> Result<(), IOError> {
+        Err(IOError::WriteProtected)
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// 把底层设备的 [start, start + len) 块当成一个新设备, 块号从 0 开始, 越界访问返回 OutOfRange
+/// 用来从同时放着引导程序和配置区的 flash 中划出文件系统区域
+pub struct SliceDevice {
+    inner: Arc<dyn BlockDevice>,
+    start: usize,
+    len: usize,
+}
+
+impl SliceDevice {
+    /// 底层设备知道自己的块数时检查区域没有超出设备
+    pub fn new(inner: Arc<dyn BlockDevice>, start: usize, len: usize) -> Result<Self, IOError> {
+        let end = start.checked_add(len).ok_or(IOError::OutOfRange)?;
+        if inner
+            .num_blocks()
+            .is_some_and(|num_blocks| end > num_blocks)
+        {
+            return Err(IOError::OutOfRange);
+        }
+        Ok(Self { inner, start, len })
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    /// 区域在底层设备中的起始块
+    pub fn start(&self) -> usize {
+        self.start
+    }
+    // 区域内的 count 块换算成底层设备的块号
+    fn translate(&self, block_id: usize, count: usize) -> Result<usize, IOError> {
+        match block_id.checked_add(count) {
+            Some(end) if end <= self.len => Ok(self.start + block_id),
+            _ => Err(IOError::OutOfRange),
+        }
+    }
+}
+
+impl BlockDevice for SliceDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner.read_block(self.translate(block_id, 1)?, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.inner.write_block(self.translate(block_id, 1)?, buf)
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner
+            .read_blocks(self.translate(start_block, count)?, count, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.inner
+            .write_blocks(self.translate(start_block, count)?, count, buf)
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        self.inner.flush()
+    }
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let start = self.translate(blocks.start, blocks.len())?;
+        self.inner.discard(start..start + blocks.len())
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.len)
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 块大小转换, 把底层设备的块映射成 sector_size 字节的逻辑扇区
+/// 逻辑扇区小于设备块时(4K 页的 NAND/FTL 上用 512 字节扇区)写入要先读出整块再改写,
+/// 大于设备块时(512 字节块的 SD 卡上用 4K 扇区)一个扇区对应连续多块
+pub struct BlockSizeAdapter {
+    inner: Arc<dyn BlockDevice>,
+    sector_size: usize,
+    block_size: usize,
+    // 读-改-写期间不能有别的写入, 否则会被写回的旧数据覆盖
+    write_lock: Mutex<()>,
+}
+
+impl BlockSizeAdapter {
+    /// 设备块大小从设备获取, 设备不知道时按 512 字节
+    pub fn new(inner: Arc<dyn BlockDevice>, sector_size: usize) -> Self {
+        let block_size = inner.block_size().unwrap_or(DEFAULT_BLOCK_SZ);
+        Self::with_block_size(inner, sector_size, block_size)
+    }
+    pub fn with_block_size(
+        inner: Arc<dyn BlockDevice>,
+        sector_size: usize,
+        block_size: usize,
+    ) -> Self {
+        assert!(sector_size > 0 && block_size > 0);
+        Self {
+            inner,
+            sector_size,
+            block_size,
+            write_lock: Mutex::new(()),
+        }
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    // 从字节偏移 offset 开始读, 不对齐时读出覆盖的整块再复制
+    fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let first = offset / self.block_size;
+        let count = (offset + buf.len()).div_ceil(self.block_size) - first;
+        if offset.is_multiple_of(self.block_size) && buf.len() == count * self.block_size {
+            return self.inner.read_blocks(first, count, buf);
+        }
+        let mut blocks: Vec<u8> = vec![0; count * self.block_size];
+        self.inner.read_blocks(first, count, &mut blocks)?;
+        let start = offset - first * self.block_size;
+        buf.copy_from_slice(&blocks[start..start + buf.len()]);
+        Ok(())
+    }
+    // 从字节偏移 offset 开始写, 不对齐时读-改-写
+    fn write_bytes(&self, offset: usize, buf: &[u8]) -> Result<(), IOError> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let first = offset / self.block_size;
+        let count = (offset + buf.len()).div_ceil(self.block_size) - first;
+        let _guard = self.write_lock.lock();
+        if offset.is_multiple_of(self.block_size) && buf.len() == count * self.block_size {
+            return self.inner.write_blocks(first, count, buf);
+        }
+        let mut blocks: Vec<u8> = vec![0; count * self.block_size];
+        self.inner.read_blocks(first, count, &mut blocks)?;
+        let start = offset - first * self.block_size;
+        blocks[start..start + buf.len()].copy_from_slice(buf);
+        self.inner.write_blocks(first, count, &blocks)
+    }
+}
+
+impl BlockDevice for BlockSizeAdapter {
+    // buf 可以比扇区短, 只读扇区开头的部分
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if buf.len() > self.sector_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        self.read_bytes(block_id * self.sector_size, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        if buf.len() > self.sector_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        self.write_bytes(block_id * self.sector_size, buf)
+    }
+    // 连续的扇区换算成一段连续的字节, 一次读写
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if count > 0 && buf.len() == count * self.sector_size {
+            return self.read_bytes(start_block * self.sector_size, buf);
+        }
+        read_blocks_each(self, start_block, count, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        if count > 0 && buf.len() == count * self.sector_size {
+            return self.write_bytes(start_block * self.sector_size, buf);
+        }
+        write_blocks_each(self, start_block, count, buf)
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        self.inner.flush()
+    }
+    // 只 discard 完全落在范围内的设备块
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        let start = (blocks.start * self.sector_size).div_ceil(self.block_size);
+        let end = blocks.end * self.sector_size / self.block_size;
+        if start >= end {
+            return Ok(());
+        }
+        self.inner.discard(start..end)
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.sector_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner
+            .num_blocks()
+            .map(|num_blocks| num_blocks * self.block_size / self.sector_size)
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 故障注入包装, 用来做断电和出错测试
+/// 可以让第 n 次读写失败, 读某些块时返回损坏的数据, 在第 n 次写时断电,
+/// 还可以模拟带易失写缓存的设备: 没有 flush 的写在断电时随机丢失, 到达介质的顺序也是乱的
+/// 读写按块计数, 多块读写每块算一次, 次数都从 0 开始
+pub struct FaultyDevice {
+    inner: Arc<dyn BlockDevice>,
+    state: Mutex<FaultState>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    reads: usize,
+    writes: usize,
+    fail_read: Option<(usize, IOError)>,
+    fail_write: Option<(usize, IOError)>,
+    corrupt: Option<Range<usize>>,
+    power_cut_at: Option<usize>,
+    powered_off: bool,
+    // 打开写缓存时的随机种子和还没有到达介质的写
+    write_cache: Option<u64>,
+    pending: Vec<(usize, Vec<u8>)>,
+}
+
+impl FaultyDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(FaultState::default()),
+        }
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    /// 已经发生的读次数
+    pub fn reads(&self) -> usize {
+        self.state.lock().reads
+    }
+    /// 已经发生的写次数, 包括断电后丢掉的写
+    pub fn writes(&self) -> usize {
+        self.state.lock().writes
+    }
+    /// 第 n 次读返回 error, 只失败一次
+    pub fn fail_read(&self, n: usize, error: IOError) {
+        self.state.lock().fail_read = Some((n, error));
+    }
+    /// 第 n 次写返回 error 并且不写入, 只失败一次
+    pub fn fail_write(&self, n: usize, error: IOError) {
+        self.state.lock().fail_write = Some((n, error));
+    }
+    /// 读这些块时每个字节取反, 介质上的内容不变, None 表示恢复正常
+    pub fn corrupt_reads(&self, blocks: Option<Range<usize>>) {
+        self.state.lock().corrupt = blocks;
+    }
+    /// 从第 n 次写开始断电, 这次和之后的写都返回成功但不会写入, 之后的读返回 Timeout
+    pub fn cut_power_after(&self, n: usize) {
+        let mut state = self.state.lock();
+        state.power_cut_at = Some(n);
+        if state.writes >= n {
+            self.power_off(&mut state);
+        }
+    }
+    /// 立即断电
+    pub fn cut_power(&self) {
+        let mut state = self.state.lock();
+        self.power_off(&mut state);
+    }
+    pub fn is_powered_off(&self) -> bool {
+        self.state.lock().powered_off
+    }
+    /// 打开易失写缓存, 写入在 flush 时才到达介质, 断电时按 seed 随机保留一部分并打乱顺序
+    pub fn enable_write_cache(&self, seed: u64) {
+        self.state.lock().write_cache = Some(seed);
+    }
+    /// 关闭写缓存, 缓存中的写先按顺序写入
+    pub fn disable_write_cache(&self) -> Result<(), IOError> {
+        let mut state = self.state.lock();
+        self.write_back(&mut state)?;
+        state.write_cache = None;
+        Ok(())
+    }
+    fn write_back(&self, state: &mut FaultState) -> Result<(), IOError> {
+        for (block_id, data) in state.pending.drain(..) {
+            self.inner.write_block(block_id, &data)?;
+        }
+        Ok(())
+    }
+    // 断电时缓存中的写随机丢失, 剩下的以打乱的顺序写入, 同一块的多次写可能是旧的留下
+    fn power_off(&self, state: &mut FaultState) {
+        if state.powered_off {
+            return;
+        }
+        state.powered_off = true;
+        let mut pending = core::mem::take(&mut state.pending);
+        let mut seed = state.write_cache.unwrap_or_default() ^ 0x9E37_79B9_7F4A_7C15;
+        for i in (1..pending.len()).rev() {
+            pending.swap(i, (xorshift(&mut seed) % (i as u64 + 1)) as usize);
+        }
+        for (block_id, data) in pending {
+            if xorshift(&mut seed) & 1 == 1 {
+                let _ = self.inner.write_block(block_id, &data);
+            }
+        }
+    }
+}
+
+// 断电时打乱写缓存用的伪随机数
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+impl BlockDevice for FaultyDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        let mut state = self.state.lock();
+        let n = state.reads;
+        state.reads += 1;
+        if state.powered_off {
+            return Err(IOError::Timeout);
+        }
+        if let Some((_, error)) = state.fail_read.filter(|(at, _)| *at == n) {
+            return Err(error);
+        }
+        // 缓存中最新的写优先
+        match state.pending.iter().rev().find(|(id, _)| *id == block_id) {
+            Some((_, data)) if data.len() >= buf.len() => buf.copy_from_slice(&data[..buf.len()]),
+            _ => self.inner.read_block(block_id, buf)?,
+        }
+        if state
+            .corrupt
+            .as_ref()
+            .is_some_and(|blocks| blocks.contains(&block_id))
+        {
+            buf.iter_mut().for_each(|b| *b = !*b);
+        }
+        Ok(())
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        let mut state = self.state.lock();
+        let n = state.writes;
+        state.writes += 1;
+        if state.power_cut_at.is_some_and(|at| n >= at) {
+            self.power_off(&mut state);
+        }
+        if state.powered_off {
+            return Ok(());
+        }
+        if let Some((_, error)) = state.fail_write.filter(|(at, _)| *at == n) {
+            return Err(error);
+        }
+        if state.write_cache.is_some() {
+            state.pending.push((block_id, buf.to_vec()));
+            return Ok(());
+        }
+        self.inner.write_block(block_id, buf)
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        let mut state = self.state.lock();
+        if state.powered_off {
+            return Ok(());
+        }
+        self.write_back(&mut state)?;
+        self.inner.flush()
+    }
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        if self.state.lock().powered_off {
+            return Ok(());
+        }
+        self.inner.discard(blocks)
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 写时复制覆盖层, 基础镜像只读, 所有写入都记在稀疏的覆盖层中
+/// 覆盖层默认放在内存里, 也可以放在第二个块设备上, 块号到覆盖层位置的映射总是在内存中
+/// 基础镜像只有 commit 时才会被写, 通常用 ReadOnlyDevice 包装后传进来
+pub struct OverlayDevice {
+    base: Arc<dyn BlockDevice>,
+    store: Option<Arc<dyn BlockDevice>>, // None 时覆盖层在内存中
+    block_size: usize,
+    state: RwLock<OverlayState>,
+}
+
+#[derive(Default)]
+struct OverlayState {
+    slots: BTreeMap<usize, usize>, // 基础镜像的块号 -> 覆盖层中的位置, 位置按写入顺序分配
+    memory: Vec<u8>,               // 覆盖层在内存中时按位置存放
+}
+
+impl OverlayDevice {
+    /// 覆盖层在内存中, 块大小从基础镜像获取, 不知道时按 512 字节
+    pub fn new(base: Arc<dyn BlockDevice>) -> Self {
+        let block_size = base.block_size().unwrap_or(DEFAULT_BLOCK_SZ);
+        Self {
+            base,
+            store: None,
+            block_size,
+            state: RwLock::new(OverlayState::default()),
+        }
+    }
+    /// 覆盖层放在 store 上, 从第 0 块开始使用, 两个设备的块大小必须相同
+    /// store 写满后再写新的块返回 OutOfRange
+    pub fn with_store(base: Arc<dyn BlockDevice>, store: Arc<dyn BlockDevice>) -> Self {
+        let block_size = base
+            .block_size()
+            .or(store.block_size())
+            .unwrap_or(DEFAULT_BLOCK_SZ);
+        assert!(store.block_size().is_none_or(|size| size == block_size));
+        Self {
+            base,
+            store: Some(store),
+            block_size,
+            state: RwLock::new(OverlayState::default()),
+        }
+    }
+    pub fn base(&self) -> &Arc<dyn BlockDevice> {
+        &self.base
+    }
+    /// 被改写过的块号, 从小到大
+    pub fn modified_blocks(&self) -> Vec<usize> {
+        self.state.read().slots.keys().copied().collect()
+    }
+    /// 把覆盖层按块号顺序写进基础镜像并刷新, 成功后清空覆盖层, 失败时覆盖层保持不变可以重试
+    pub fn commit(&self) -> Result<(), IOError> {
+        let mut state = self.state.write();
+        let mut buf: Vec<u8> = vec![0; self.block_size];
+        for (block_id, slot) in state.slots.iter() {
+            self.load(&state, *slot, &mut buf)?;
+            self.base.write_block(*block_id, &buf)?;
+        }
+        self.base.flush()?;
+        *state = OverlayState::default();
+        Ok(())
+    }
+    /// 丢掉覆盖层中的所有写入, 回到基础镜像的内容
+    pub fn reset(&self) {
+        *self.state.write() = OverlayState::default();
+    }
+    /// 导出覆盖层, 每项是块号和整块内容, 按块号排序
+    pub fn export(&self) -> Result<Vec<(usize, Vec<u8>)>, IOError> {
+        let state = self.state.read();
+        let mut delta = Vec::with_capacity(state.slots.len());
+        for (block_id, slot) in state.slots.iter() {
+            let mut buf: Vec<u8> = vec![0; self.block_size];
+            self.load(&state, *slot, &mut buf)?;
+            delta.push((*block_id, buf));
+        }
+        Ok(delta)
+    }
+    /// 把 export 导出的内容写进覆盖层
+    pub fn import(&self, delta: &[(usize, Vec<u8>)]) -> Result<(), IOError> {
+        delta
+            .iter()
+            .try_for_each(|(block_id, data)| self.write_block(*block_id, data))
+    }
+    // 读覆盖层中第 slot 个位置, buf 可以比块短
+    fn load(&self, state: &OverlayState, slot: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        match &self.store {
+            Some(store) => store.read_block(slot, buf),
+            None => {
+                let start = slot * self.block_size;
+                buf.copy_from_slice(&state.memory[start..start + buf.len()]);
+                Ok(())
+            }
+        }
+    }
+    fn save(&self, state: &mut OverlayState, slot: usize, buf: &[u8]) -> Result<(), IOError> {
+        match &self.store {
+            Some(store) => store.write_block(slot, buf),
+            None => {
+                let start = slot * self.block_size;
+                if state.memory.len() < start + self.block_size {
+                    state.memory.resize(start + self.block_size, 0);
+                }
+                state.memory[start..start + buf.len()].copy_from_slice(buf);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl BlockDevice for OverlayDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if buf.len() > self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        let state = self.state.read();
+        match state.slots.get(&block_id) {
+            Some(slot) => self.load(&state, *slot, buf),
+            None => self.base.read_block(block_id, buf),
+        }
+    }
+    // 只接受整块写入, 覆盖层中没有部分块
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        if buf.len() != self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        if self
+            .base
+            .num_blocks()
+            .is_some_and(|num_blocks| block_id >= num_blocks)
+        {
+            return Err(IOError::OutOfRange);
+        }
+        let mut state = self.state.write();
+        let slot = match state.slots.get(&block_id) {
+            Some(slot) => *slot,
+            None => {
+                let slot = state.slots.len();
+                if let Some(store) = &self.store {
+                    if store
+                        .num_blocks()
+                        .is_some_and(|num_blocks| slot >= num_blocks)
+                    {
+                        return Err(IOError::OutOfRange);
+                    }
+                }
+                slot
+            }
+        };
+        self.save(&mut state, slot, buf)?;
+        state.slots.insert(block_id, slot);
+        Ok(())
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        match &self.store {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+    // 基础镜像不能改, 覆盖层中的块也不回收, 直接忽略
+    fn discard(&self, _blocks: Range<usize>) -> Result<(), IOError> {
+        Ok(())
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.base.num_blocks()
+    }
+}
+
+/// 每块一个 CRC32 校验和, 读时校验, 对不上返回 ChecksumMismatch, 用来发现介质上静默翻转的位
+/// 校验和放在设备末尾的保留区或单独的设备上, 一个校验和块记录 块大小 / 4 个块
+/// 先写数据再写校验和, 两次写之间断电时这个块会被当成损坏
+pub struct ChecksumDevice {
+    inner: Arc<dyn BlockDevice>,
+    store: Option<Arc<dyn BlockDevice>>, // None 时校验和在 inner 末尾
+    block_size: usize,
+    num_blocks: usize,
+    lock: RwLock<()>, // 写数据和改校验和一起完成, 读不会看到一半
+}
+
+impl ChecksumDevice {
+    /// 保留 inner 末尾的块放校验和, 剩下的块给上层用
+    /// inner 不知道块数或块大小小于 4 字节时返回 InvalidInput, 放不下数据块时返回 OutOfRange
+    /// 已有的内容要先 rebuild 一次
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Result<Self, IOError> {
+        let block_size = Self::sum_block_size(&inner)?;
+        let total = inner.num_blocks().ok_or(IOError::InvalidInput)?;
+        let sum_blocks = total.div_ceil(block_size / 4 + 1);
+        if sum_blocks >= total {
+            return Err(IOError::OutOfRange);
+        }
+        Ok(Self {
+            inner,
+            store: None,
+            block_size,
+            num_blocks: total - sum_blocks,
+            lock: RwLock::new(()),
+        })
+    }
+    /// 校验和放在 store 上, 从第 0 块开始使用, 两个设备的块大小不同时返回 InvalidInput
+    /// 块数受 store 的大小限制
+    pub fn with_store(
+        inner: Arc<dyn BlockDevice>,
+        store: Arc<dyn BlockDevice>,
+    ) -> Result<Self, IOError> {
+        let block_size = Self::sum_block_size(&inner)?;
+        if store.block_size().is_some_and(|size| size != block_size) {
+            return Err(IOError::InvalidInput);
+        }
+        let mut num_blocks = inner.num_blocks().ok_or(IOError::InvalidInput)?;
+        if let Some(sum_blocks) = store.num_blocks() {
+            num_blocks = num_blocks.min(sum_blocks * (block_size / 4));
+        }
+        Ok(Self {
+            inner,
+            store: Some(store),
+            block_size,
+            num_blocks,
+            lock: RwLock::new(()),
+        })
+    }
+    // 一个校验和块至少要放下一个 CRC32
+    fn sum_block_size(inner: &Arc<dyn BlockDevice>) -> Result<usize, IOError> {
+        let block_size = inner.block_size().unwrap_or(DEFAULT_BLOCK_SZ);
+        if block_size < 4 {
+            return Err(IOError::InvalidInput);
+        }
+        Ok(block_size)
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    /// 按现在的内容重新计算所有校验和, 第一次使用或者确认数据没问题后调用
+    pub fn rebuild(&self) -> Result<(), IOError> {
+        let _guard = self.lock.write();
+        let per_block = self.block_size / 4;
+        let mut data: Vec<u8> = vec![0; per_block * self.block_size];
+        let mut sums: Vec<u8> = vec![0; self.block_size];
+        for sum_block in 0..self.num_blocks.div_ceil(per_block) {
+            let start = sum_block * per_block;
+            let count = per_block.min(self.num_blocks - start);
+            let data = &mut data[..count * self.block_size];
+            self.inner.read_blocks(start, count, data)?;
+            sums.fill(0);
+            for (i, block) in data.chunks_exact(self.block_size).enumerate() {
+                sums[i * 4..i * 4 + 4].copy_from_slice(&crc32(block).to_le_bytes());
+            }
+            self.save_sums(sum_block, &sums)?;
+        }
+        self.flush()
+    }
+    /// 校验所有块, 返回校验和对不上的块号
+    pub fn verify(&self) -> Result<Vec<usize>, IOError> {
+        let _guard = self.lock.read();
+        let per_block = self.block_size / 4;
+        let mut data: Vec<u8> = vec![0; per_block * self.block_size];
+        let mut sums: Vec<u8> = vec![0; self.block_size];
+        let mut damaged = Vec::new();
+        for sum_block in 0..self.num_blocks.div_ceil(per_block) {
+            let start = sum_block * per_block;
+            let count = per_block.min(self.num_blocks - start);
+            let data = &mut data[..count * self.block_size];
+            self.inner.read_blocks(start, count, data)?;
+            self.load_sums(sum_block, &mut sums)?;
+            for (i, block) in data.chunks_exact(self.block_size).enumerate() {
+                if sums[i * 4..i * 4 + 4] != crc32(block).to_le_bytes() {
+                    damaged.push(start + i);
+                }
+            }
+        }
+        Ok(damaged)
+    }
+    // 第 sum_block 个校验和块所在的设备和块号
+    fn sum_location(&self, sum_block: usize) -> (&Arc<dyn BlockDevice>, usize) {
+        match &self.store {
+            Some(store) => (store, sum_block),
+            None => (&self.inner, self.num_blocks + sum_block),
+        }
+    }
+    fn load_sums(&self, sum_block: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        let (device, block_id) = self.sum_location(sum_block);
+        device.read_block(block_id, buf)
+    }
+    fn save_sums(&self, sum_block: usize, buf: &[u8]) -> Result<(), IOError> {
+        let (device, block_id) = self.sum_location(sum_block);
+        device.write_block(block_id, buf)
+    }
+    fn check_range(&self, start_block: usize, count: usize) -> Result<(), IOError> {
+        match start_block.checked_add(count) {
+            Some(end) if end <= self.num_blocks => Ok(()),
+            _ => Err(IOError::OutOfRange),
+        }
+    }
+    // data 是从 start_block 开始的整块, 和记录的校验和比较
+    fn verify_blocks(&self, start_block: usize, data: &[u8]) -> Result<(), IOError> {
+        let per_block = self.block_size / 4;
+        let mut sums: Vec<u8> = vec![0; self.block_size];
+        let mut loaded = None;
+        for (i, block) in data.chunks_exact(self.block_size).enumerate() {
+            let block_id = start_block + i;
+            if loaded != Some(block_id / per_block) {
+                loaded = Some(block_id / per_block);
+                self.load_sums(block_id / per_block, &mut sums)?;
+            }
+            let offset = block_id % per_block * 4;
+            if sums[offset..offset + 4] != crc32(block).to_le_bytes() {
+                log::error!("block {} checksum mismatch", block_id);
+                return Err(IOError::ChecksumMismatch);
+            }
+        }
+        Ok(())
+    }
+    // 更新从 start_block 开始的整块的校验和, 每个校验和块只读写一次
+    fn update_sums(&self, start_block: usize, data: &[u8]) -> Result<(), IOError> {
+        let per_block = self.block_size / 4;
+        let mut sums: Vec<u8> = vec![0; self.block_size];
+        let mut loaded = None;
+        for (i, block) in data.chunks_exact(self.block_size).enumerate() {
+            let block_id = start_block + i;
+            if loaded != Some(block_id / per_block) {
+                if let Some(sum_block) = loaded {
+                    self.save_sums(sum_block, &sums)?;
+                }
+                loaded = Some(block_id / per_block);
+                self.load_sums(block_id / per_block, &mut sums)?;
+            }
+            let offset = block_id % per_block * 4;
+            sums[offset..offset + 4].copy_from_slice(&crc32(block).to_le_bytes());
+        }
+        match loaded {
+            Some(sum_block) => self.save_sums(sum_block, &sums),
+            None => Ok(()),
+        }
+    }
+}
+
+impl BlockDevice for ChecksumDevice {
+    // 整块读出校验后复制, buf 可以比块短
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if buf.len() > self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        self.check_range(block_id, 1)?;
+        let _guard = self.lock.read();
+        let mut block: Vec<u8> = vec![0; self.block_size];
+        self.inner.read_block(block_id, &mut block)?;
+        self.verify_blocks(block_id, &block)?;
+        buf.copy_from_slice(&block[..buf.len()]);
+        Ok(())
+    }
+    // 只接受整块写入
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        if buf.len() != self.block_size {
+            return Err(IOError::NotEnoughBuffer);
+        }
+        self.check_range(block_id, 1)?;
+        let _guard = self.lock.write();
+        self.inner.write_block(block_id, buf)?;
+        self.update_sums(block_id, buf)
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        if count == 0 || buf.len() != count * self.block_size {
+            return read_blocks_each(self, start_block, count, buf);
+        }
+        self.check_range(start_block, count)?;
+        let _guard = self.lock.read();
+        self.inner.read_blocks(start_block, count, buf)?;
+        self.verify_blocks(start_block, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        if count == 0 || buf.len() != count * self.block_size {
+            return write_blocks_each(self, start_block, count, buf);
+        }
+        self.check_range(start_block, count)?;
+        let _guard = self.lock.write();
+        self.inner.write_blocks(start_block, count, buf)?;
+        self.update_sums(start_block, buf)
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        self.inner.flush()?;
+        match &self.store {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+    // discard 后的内容不确定, 校验和会对不上, 不转发
+    fn discard(&self, _blocks: Range<usize>) -> Result<(), IOError> {
+        Ok(())
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.num_blocks)
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+#[cfg(feature = "std")]
+pub use file::FileBlockDevice;
+
+#[cfg(feature = "std")]
+mod file {
+    use super::DEFAULT_BLOCK_SZ;
+    use crate::block_device::{read_blocks_each, write_blocks_each, BlockDevice};
+    use crate::error::IOError;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Seek, SeekFrom};
+    use std::path::Path;
+
+    /// 镜像文件或裸设备(/dev/sdX 等), 一直持有同一个文件句柄, 按位置读写不移动文件指针
+    pub struct FileBlockDevice {
+        file: File,
+        block_size: usize,
+        num_blocks: usize,
+        read_only: bool,
+    }
+
+    impl FileBlockDevice {
+        /// 以读写方式打开已有的镜像文件或设备, 块大小为 512 字节
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            Self::from_file(file, DEFAULT_BLOCK_SZ, false)
+        }
+        /// 以只读方式打开, 写入返回 WriteProtected
+        pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = File::open(path)?;
+            Self::from_file(file, DEFAULT_BLOCK_SZ, true)
+        }
+        /// 创建 num_blocks 块全 0 的镜像文件, 已有的文件会被截断
+        pub fn create<P: AsRef<Path>>(path: P, num_blocks: usize) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            file.set_len((num_blocks * DEFAULT_BLOCK_SZ) as u64)?;
+            Self::from_file(file, DEFAULT_BLOCK_SZ, false)
+        }
+        /// 用已经打开的文件创建, 设备大小用 seek 到末尾得到, 裸设备的 metadata 长度是 0
+        pub fn from_file(mut file: File, block_size: usize, read_only: bool) -> io::Result<Self> {
+            assert!(block_size > 0);
+            let len = file.seek(SeekFrom::End(0))? as usize;
+            Ok(Self {
+                file,
+                block_size,
+                num_blocks: len / block_size,
+                read_only,
+            })
+        }
+        // 块号和长度对应的文件偏移, 超出设备时返回 OutOfRange
+        fn offset(&self, block_id: usize, len: usize) -> Result<u64, IOError> {
+            let start = block_id
+                .checked_mul(self.block_size)
+                .ok_or(IOError::OutOfRange)?;
+            match start.checked_add(len) {
+                Some(end) if end <= self.num_blocks * self.block_size => Ok(start as u64),
+                _ => Err(IOError::OutOfRange),
+            }
+        }
+    }
+
+    impl BlockDevice for FileBlockDevice {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+            let offset = self.offset(block_id, buf.len())?;
+            read_exact_at(&self.file, buf, offset).map_err(io_error)
+        }
+        fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+            if self.read_only {
+                return Err(IOError::WriteProtected);
+            }
+            let offset = self.offset(block_id, buf.len())?;
+            write_all_at(&self.file, buf, offset).map_err(io_error)
+        }
+        // 文件中的块是连续的, 每份正好一块时一次读写完成
+        fn read_blocks(
+            &self,
+            start_block: usize,
+            count: usize,
+            buf: &mut [u8],
+        ) -> Result<(), IOError> {
+            if count > 0 && buf.len() == count * self.block_size {
+                return self.read_block(start_block, buf);
+            }
+            read_blocks_each(self, start_block, count, buf)
+        }
+        fn write_blocks(
+            &self,
+            start_block: usize,
+            count: usize,
+            buf: &[u8],
+        ) -> Result<(), IOError> {
+            if count > 0 && buf.len() == count * self.block_size {
+                return self.write_block(start_block, buf);
+            }
+            write_blocks_each(self, start_block, count, buf)
+        }
+        fn flush(&self) -> Result<(), IOError> {
+            if self.read_only {
+                return Ok(());
+            }
+            self.file.sync_data().map_err(io_error)
+        }
+        fn block_size(&self) -> Option<usize> {
+            Some(self.block_size)
+        }
+        fn num_blocks(&self) -> Option<usize> {
+            Some(self.num_blocks)
+        }
+        fn is_read_only(&self) -> bool {
+            self.read_only
+        }
+    }
+
+    fn io_error(e: io::Error) -> IOError {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => IOError::OutOfRange,
+            io::ErrorKind::PermissionDenied => IOError::WriteProtected,
+            io::ErrorKind::TimedOut => IOError::Timeout,
+            _ => IOError::MediaError,
+        }
+    }
+
+    #[cfg(unix)]
+    fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+
+    #[cfg(unix)]
+    fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+    }
+
+    // Windows 的 seek_read/seek_write 会移动文件指针, 但每次都指定了位置, 不影响结果
+    #[cfg(windows)]
+    fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            match file.seek_read(buf, offset)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        while !buf.is_empty() {
+            match file.seek_write(buf, offset)? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use super::RunFileSystem;
+use super::{FatType, RunFileSystem};
+use crate::cluster_walk::{root_segments, ClusterTransfer, Segment};
+use crate::data::FIXED_ROOT_CLUSTER;
+use crate::error::FSError;
 #[cfg(not(feature = "std"))]
 use alloc::{string::String, sync::Arc};
 use bitflags::bitflags;
@@ -142,6 +145,17 @@ impl ShortDirectoryEntry {
             + sec) as u64;
         (year, month, day, hour, min, sec, long_sec)
     }
+    /// 把创建, 修改和访问时间都设成同一时刻, 秒数只能精确到 2 秒
+    pub fn set_time(&mut self, year: u32, month: u32, day: u32, hour: u32, min: u32, sec: u32) {
+        let date = (((year - START_YEAR) << 9) | (month << 5) | day) as u16;
+        let time = ((hour << 11) | (min << 5) | (sec >> 1)) as u16;
+        self._creation_tenths = 0;
+        self.creation_time = time;
+        self.creation_date = date;
+        self.last_acc_date = date;
+        self.modification_time = time;
+        self.modification_date = date;
+    }
     // 获取文件起始簇号
     pub fn first_cluster(&self) -> u32 {
         ((self.cluster_high as u32) << 16) + (self.cluster_low as u32)
@@ -161,6 +175,12 @@ impl ShortDirectoryEntry {
     pub fn set_size(&mut self, size: u32) {
         self.size = size;
     }
+    // 写到 end 为止, 在文件中间写不会让文件变小
+    pub fn grow_size(&mut self, end: u32) {
+        if self.size < end {
+            self.size = end;
+        }
+    }
     // 获取短文件名,短文件名默认都是大写
     pub fn name(&self) -> String {
         let mut name: String = String::new();
@@ -223,16 +243,35 @@ impl ShortDirectoryEntry {
             )
         }
     }
-    /// 获取文件偏移量所在的簇和偏移
-    pub fn pos(&self, offset: usize, fs: &Arc<RwLock<RunFileSystem>>) -> (Option<usize>, usize) {
+    /// FAT12/16 的根目录(包括指向根目录的 ..)起始簇号为 0, 在固定区域中, 返回区域的字节数
+    pub(crate) fn fixed_root_size(&self, runfs: &RunFileSystem) -> Option<usize> {
+        let bpb = runfs.bpb();
+        if self.is_dir() && self.first_cluster() == 0 && bpb.fat_type() != FatType::Fat32 {
+            Some(bpb.root_dir_size())
+        } else {
+            None
+        }
+    }
+    /// 获取文件偏移量所在的簇和偏移, 固定根目录区的簇为 FIXED_ROOT_CLUSTER
+    pub fn pos(
+        &self,
+        offset: usize,
+        fs: &Arc<RwLock<RunFileSystem>>,
+    ) -> Result<(Option<usize>, usize), FSError> {
         let runfs = fs.read();
+        if let Some(root_size) = self.fixed_root_size(&runfs) {
+            if offset < root_size {
+                return Ok((Some(FIXED_ROOT_CLUSTER), offset));
+            }
+            return Ok((None, offset));
+        }
         let bytes_per_cluster = runfs.bpb().cluster_size() as usize;
         let cluster_index = offset / bytes_per_cluster;
         let current_cluster = runfs
             .fat_manager_modify()
-            .search_cluster(self.first_cluster() as usize, cluster_index);
+            .search_cluster(self.first_cluster() as usize, cluster_index)?;
         // println!("first_cluster: {}", self.first_cluster() as usize);
-        (current_cluster, offset % bytes_per_cluster)
+        Ok((current_cluster, offset % bytes_per_cluster))
     }
     /// 以偏移量读取文件, 返回实际读取的长度
     pub fn read_at(
@@ -240,137 +279,136 @@ impl ShortDirectoryEntry {
         offset: usize,
         buf: &mut [u8],
         runfs: &Arc<RwLock<RunFileSystem>>,
-    ) -> usize {
-        // println!("1-0-0-0");
+    ) -> Result<usize, FSError> {
+        let fixed_root_size = self.fixed_root_size(&runfs.read());
+        if let Some(root_size) = fixed_root_size {
+            let sector_size = runfs.read().bpb().bytes_per_sector() as usize;
+            let offset_end_pos = (offset + buf.len()).min(root_size);
+            for (root_offset, range) in root_segments(offset, offset_end_pos, sector_size) {
+                runfs.read().data_manager_modify().read_bytes(
+                    FIXED_ROOT_CLUSTER,
+                    root_offset,
+                    &mut buf[range],
+                )?;
+            }
+            return Ok(offset_end_pos.saturating_sub(offset));
+        }
         let cluster_size = runfs.read().bpb().cluster_size();
-        let mut current_offset = offset;
-        let mut size = self.size as usize;
+        let first_cluster = self.first_cluster() as usize;
         // 计算文件夹占用的空间
-        if self.is_dir() {
-            size = cluster_size
+        let size = if self.is_dir() {
+            cluster_size
                 * runfs
                     .read()
                     .fat_manager_modify()
-                    .count_clusters(self.first_cluster() as usize);
-        }
-        // println!("read_at size = {}", size);
-        // println!("1-0-0-1");
-        let offset_end_pos = (offset + buf.len()).min(size);
-        // println!(
-        //     "read_at current_offset = {}; offset_end_pos = {}",
-        //     current_offset, offset_end_pos
-        // );
-        // println!("1-0-0-2");
-        if current_offset >= offset_end_pos {
-            return 0;
-        }
-        let (cluster_id, _) = self.pos(offset, runfs);
-        let mut current_cluster = match cluster_id {
-            None => return 0,
-            Some(id) => id,
+                    .count_clusters(first_cluster)?
+        } else {
+            self.size as usize
         };
-        // println!("current_cluster: {}", current_cluster);
-        let mut read_size = 0usize;
-        loop {
-            // println!("1-0-0-3");
-            // 将偏移量向上对齐簇大小
-            let mut current_cluster_end_pos = (current_offset / cluster_size + 1) * cluster_size;
-            current_cluster_end_pos = current_cluster_end_pos.min(offset_end_pos);
-            // println!("current_cluster_end_pos = {}", current_cluster_end_pos);
-            // 开始读
-            let cluster_read_size = current_cluster_end_pos - current_offset;
-            let offset_in_cluster = current_offset % cluster_size;
-            let dst = &mut buf[read_size..read_size + cluster_read_size];
-            for i in 0..cluster_read_size {
-                runfs.read().data_manager_modify().read_cluster_at(
-                    current_cluster,
-                    offset_in_cluster + i,
-                    |data: &u8| {
-                        dst[i] = *data;
-                    },
-                );
-            }
-            // println!("1-0-0-4");
-            // 更新读取长度
-            read_size += cluster_read_size;
-            if current_cluster_end_pos == offset_end_pos {
-                break;
+        let offset_end_pos = (offset + buf.len()).min(size);
+        let mut transfer =
+            ClusterTransfer::new(first_cluster, offset, offset_end_pos, cluster_size);
+        while let Some(segment) = transfer.segment() {
+            match segment {
+                Segment::Next(cluster_id) => {
+                    let next = runfs.read().fat_manager_modify().next_cluster(cluster_id)?;
+                    transfer.set_next(next);
+                }
+                // 从簇边界开始的整簇一起读, 物理上连续的簇合并成一次块设备请求
+                Segment::Whole { cluster, max } => {
+                    let (count, next) = runfs
+                        .read()
+                        .fat_manager_modify()
+                        .contiguous_run(cluster, max)?;
+                    let range = transfer.buf_range(count * cluster_size);
+                    runfs
+                        .read()
+                        .data_manager_modify()
+                        .read_clusters(cluster, &mut buf[range])?;
+                    transfer.advance_run(count, next);
+                }
+                Segment::Partial {
+                    cluster,
+                    offset,
+                    len,
+                } => {
+                    let range = transfer.buf_range(len);
+                    runfs.read().data_manager_modify().read_bytes(
+                        cluster,
+                        offset,
+                        &mut buf[range],
+                    )?;
+                    transfer.advance(len);
+                }
             }
-            // 更新索引参数
-            current_offset = current_cluster_end_pos;
-            let next_cluster = runfs
-                .read()
-                .fat_manager_modify()
-                .next_cluster(current_cluster);
-            current_cluster = match next_cluster {
-                None => break, // 没有下一个簇
-                Some(id) => id,
-            };
         }
-        // println!("read_size: {}", read_size);
-        // println!("1-0-0-5");
-        read_size
+        Ok(transfer.done())
     }
 
     /// 以偏移量写文件
-    pub fn write_at(&self, offset: usize, buf: &[u8], runfs: &Arc<RwLock<RunFileSystem>>) -> usize {
-        let cluster_size = runfs.read().bpb().cluster_size() as usize;
-        let mut current_offset = offset;
+    pub fn write_at(
+        &self,
+        offset: usize,
+        buf: &[u8],
+        runfs: &Arc<RwLock<RunFileSystem>>,
+    ) -> Result<usize, FSError> {
+        let fixed_root_size = self.fixed_root_size(&runfs.read());
+        if let Some(root_size) = fixed_root_size {
+            let sector_size = runfs.read().bpb().bytes_per_sector() as usize;
+            let offset_end_pos = (offset + buf.len()).min(root_size);
+            for (root_offset, range) in root_segments(offset, offset_end_pos, sector_size) {
+                runfs.read().data_manager_modify().write_bytes(
+                    FIXED_ROOT_CLUSTER,
+                    root_offset,
+                    &buf[range],
+                )?;
+            }
+            return Ok(offset_end_pos.saturating_sub(offset));
+        }
+        let cluster_size = runfs.read().bpb().cluster_size();
+        let first_cluster = self.first_cluster() as usize;
         let capacity = cluster_size
             * runfs
                 .read()
                 .fat_manager_modify()
-                .count_clusters(self.first_cluster() as usize) as usize;
-        // println!("write_at size = {}", capacity);
+                .count_clusters(first_cluster)?;
         let offset_end_pos = (offset + buf.len()).min(capacity);
-        if current_offset >= offset_end_pos {
-            return 0;
-        }
-        // println!(
-        //     "write_at current_offset = {}; offset_end_pos = {}",
-        //     current_offset, offset_end_pos
-        // );
-        let (cluster_id, _) = self.pos(offset, runfs);
-        let mut current_cluster = match cluster_id {
-            None => return 0,
-            Some(id) => id,
-        };
-        // println!("current_cluster = {}", current_cluster);
-        let mut write_size = 0usize;
-        loop {
-            // 将偏移量向上对齐簇大小
-            let mut current_cluster_end_pos = (current_offset / cluster_size + 1) * cluster_size;
-            current_cluster_end_pos = current_cluster_end_pos.min(offset_end_pos);
-            // 开始写
-            let cluster_write_size = current_cluster_end_pos - current_offset;
-            let offset_in_cluster = current_offset % cluster_size;
-            let src = &buf[write_size..write_size + cluster_write_size];
-            for i in 0..cluster_write_size {
-                runfs.read().data_manager_modify().write_cluster_at(
-                    current_cluster,
-                    offset_in_cluster + i,
-                    |data: &mut u8| {
-                        *data = src[i];
-                    },
-                );
-            }
-            // 更新写入长度
-            write_size += cluster_write_size;
-            if current_cluster_end_pos == offset_end_pos {
-                break;
+        let mut transfer =
+            ClusterTransfer::new(first_cluster, offset, offset_end_pos, cluster_size);
+        while let Some(segment) = transfer.segment() {
+            match segment {
+                Segment::Next(cluster_id) => {
+                    let next = runfs.read().fat_manager_modify().next_cluster(cluster_id)?;
+                    transfer.set_next(next);
+                }
+                // 和 read_at 一样, 整簇写入时合并物理上连续的簇
+                Segment::Whole { cluster, max } => {
+                    let (count, next) = runfs
+                        .read()
+                        .fat_manager_modify()
+                        .contiguous_run(cluster, max)?;
+                    let range = transfer.buf_range(count * cluster_size);
+                    runfs
+                        .read()
+                        .data_manager_modify()
+                        .write_clusters(cluster, &buf[range])?;
+                    transfer.advance_run(count, next);
+                }
+                Segment::Partial {
+                    cluster,
+                    offset,
+                    len,
+                } => {
+                    let range = transfer.buf_range(len);
+                    runfs
+                        .read()
+                        .data_manager_modify()
+                        .write_bytes(cluster, offset, &buf[range])?;
+                    transfer.advance(len);
+                }
             }
-            // 更新索引参数
-            current_offset = current_cluster_end_pos;
-            let next_cluster = runfs
-                .read()
-                .fat_manager_modify()
-                .next_cluster(current_cluster);
-            current_cluster = match next_cluster {
-                None => break, // 没有下一个簇
-                Some(id) => id,
-            };
         }
-        write_size
+        Ok(transfer.done())
     }
 }
 
@@ -518,58 +556,103 @@ impl LongDirectoryEntry {
     }
 }
 
-// const VOLUME_NAME_LEN: usize = 11;
+pub(crate) const VOLUME_NAME_LEN: usize = 11;
+/// BPB 中没有卷名时填的值
+pub(crate) const NO_VOLUME_NAME: [u8; VOLUME_NAME_LEN] = *b"NO NAME    ";
+// 短文件名中同样不允许的字符
+const INVALID_VOLUME_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]|";
 
-// 卷标目录项
-// #[derive(Default)]
-// struct VolumeLabelEntry {
-//     name: [u8; VOLUME_NAME_LEN], // 删除时第0位为0xE5，未使用时为0x00. 有多余可以用0x20填充
-//     attribute: FileAttributes,   // 删除时为0xE5
-//     os_reserved: u8,
-//     entry_reserved_1: [u8; 9],
-//     modification_time: u16,
-//     modification_date: u16,
-//     entry_reserved_2: [u8; 6],
-// }
+/// 卷标目录项, 只在根目录中出现, 卷名和 BPB 中的卷名相同
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Default)]
+pub struct VolumeLabelEntry {
+    name: [u8; VOLUME_NAME_LEN], // 删除时第0位为0xE5，未使用时为0x00. 有多余可以用0x20填充
+    attribute: FileAttributes,   // 固定为 VOLUME_ID
+    os_reserved: u8,
+    entry_reserved_1: [u8; 9],
+    modification_time: u16,
+    modification_date: u16,
+    entry_reserved_2: [u8; 6],
+}
 
-// impl VolumeLabelEntry {
-//     pub fn new(name: [u8; VOLUME_NAME_LEN], attribute: FileAttributes) -> Self {
-//         Self {
-//             name,
-//             attribute,
-//             ..Self::default()
-//         }
-//     }
-//     // 获取卷名
-//     pub fn name(&self) -> String {
-//         let mut name: String = String::new();
-//         for i in 0..VOLUME_NAME_LEN {
-//             // 记录文件名
-//             if self.name[i] == 0x20 {
-//                 break;
-//             } else {
-//                 name.push(self.name[i] as char);
-//             }
-//         }
-//         name
-//     }
-//     pub fn attribute(&self) -> FileAttributes {
-//         self.attribute
-//     }
-//     pub fn modification_time(&self) -> (u32, u32, u32, u32, u32, u32, u64) {
-//         // year-month-day-Hour-min-sec
-//         let year: u32 = ((self.modification_date & 0xFE00) >> 9) as u32 + START_YEAR;
-//         let month: u32 = ((self.modification_date & 0x01E0) >> 5) as u32;
-//         let day: u32 = (self.modification_date & 0x001F) as u32;
-//         let hour: u32 = ((self.modification_time & 0xF800) >> 11) as u32;
-//         let min: u32 = ((self.modification_time & 0x07E0) >> 5) as u32;
-//         let sec: u32 = ((self.modification_time & 0x001F) << 1) as u32; // 秒数需要*2
-//         let long_sec: u64 = ((((year - START_YEAR) * 365 + month * 30 + day) * 24 + hour) * 3600
-//             + min * 60
-//             + sec) as u64;
-//         (year, month, day, hour, min, sec, long_sec)
-//     }
-// }
+impl VolumeLabelEntry {
+    pub fn new(name: [u8; VOLUME_NAME_LEN]) -> Self {
+        Self {
+            name,
+            attribute: FileAttributes::VOLUME_ID,
+            ..Self::default()
+        }
+    }
+    /// 获取卷名, 去掉末尾填充的空格
+    pub fn name(&self) -> String {
+        volume_label_string(&self.name)
+    }
+    pub fn set_name(&mut self, name: [u8; VOLUME_NAME_LEN]) {
+        self.name = name;
+    }
+    pub fn attribute(&self) -> FileAttributes {
+        self.attribute
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
+    }
+    /// 卷标目录项只有 VOLUME_ID 属性, 长文件名目录项也带这一位
+    pub fn is_volume_label(&self) -> bool {
+        self.name[0] != 0x00
+            && self.name[0] != DIR_ENTRY_DELETED_FLAG
+            && self.attribute & FileAttributes::LONG_NAME_MASK == FileAttributes::VOLUME_ID
+    }
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == 0x00
+    }
+    pub fn is_free(&self) -> bool {
+        self.name[0] == 0x00 || self.name[0] == DIR_ENTRY_DELETED_FLAG
+    }
+    pub fn set_deleted(&mut self) {
+        self.name[0] = DIR_ENTRY_DELETED_FLAG;
+    }
+    pub fn modification_time(&self) -> (u32, u32, u32, u32, u32, u32, u64) {
+        // year-month-day-Hour-min-sec
+        let year: u32 = ((self.modification_date & 0xFE00) >> 9) as u32 + START_YEAR;
+        let month: u32 = ((self.modification_date & 0x01E0) >> 5) as u32;
+        let day: u32 = (self.modification_date & 0x001F) as u32;
+        let hour: u32 = ((self.modification_time & 0xF800) >> 11) as u32;
+        let min: u32 = ((self.modification_time & 0x07E0) >> 5) as u32;
+        let sec: u32 = ((self.modification_time & 0x001F) << 1) as u32; // 秒数需要*2
+        let long_sec: u64 = ((((year - START_YEAR) * 365 + month * 30 + day) * 24 + hour) * 3600
+            + min * 60
+            + sec) as u64;
+        (year, month, day, hour, min, sec, long_sec)
+    }
+}
+
+/// 检查卷名并转换成存储格式: 最多 11 个 ASCII 字符, 小写字母转成大写, 不足用空格填充
+/// 不能以空格开头, 不能有控制字符和短文件名中不允许的字符
+pub(crate) fn volume_label_bytes(label: &str) -> Result<[u8; VOLUME_NAME_LEN], FSError> {
+    if label.len() > VOLUME_NAME_LEN {
+        return Err(FSError::InvalidFileNameLength);
+    }
+    if label.starts_with(' ') {
+        return Err(FSError::UnsupportedFileNameCharacter);
+    }
+    let mut name = [SHORT_FILE_NAME_PADDING; VOLUME_NAME_LEN];
+    for (i, c) in label.bytes().enumerate() {
+        if !c.is_ascii() || c < 0x20 || c == 0x7F || INVALID_VOLUME_CHARS.contains(&c) {
+            return Err(FSError::UnsupportedFileNameCharacter);
+        }
+        name[i] = c.to_ascii_uppercase();
+    }
+    Ok(name)
+}
+
+/// 存储格式的卷名转成字符串, 去掉末尾填充的空格
+pub(crate) fn volume_label_string(name: &[u8; VOLUME_NAME_LEN]) -> String {
+    let len = name
+        .iter()
+        .rposition(|c| *c != SHORT_FILE_NAME_PADDING)
+        .map_or(0, |i| i + 1);
+    name[..len].iter().map(|c| *c as char).collect()
+}
 
 // /// 目录项抽象
 // pub enum DirectoryEntry {
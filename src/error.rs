@@ -20,10 +20,32 @@ pub enum FSError {
     InvalidFileNameLength,
     /// The provided file name contains an invalid character.
     UnsupportedFileNameCharacter,
+    /// The block device failed to read or write.
+    Io(IOError),
+    /// The key does not match the one the encrypted volume was created with.
+    InvalidKey,
 }
 
-#[derive(Debug)]
+impl From<IOError> for FSError {
+    fn from(e: IOError) -> Self {
+        FSError::Io(e)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum IOError {
     /// buffer size is smaller than requested
-    NotEnoughBuffer
-}
\ No newline at end of file
+    NotEnoughBuffer,
+    /// The medium reported an unrecoverable read or write error (bad sector, CRC error, ...).
+    MediaError,
+    /// The device did not complete the request in time.
+    Timeout,
+    /// The requested block is beyond the end of the device.
+    OutOfRange,
+    /// The device is write-protected.
+    WriteProtected,
+    /// The data read does not match the checksum stored when it was written.
+    ChecksumMismatch,
+    /// The device cannot be used this way (unknown size, unsupported block size, ...).
+    InvalidInput,
+}
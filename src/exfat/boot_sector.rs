@@ -0,0 +1,267 @@
+// exFAT 的启动区域: 启动扇区, 8 个扩展启动扇区, OEM 参数, 保留扇区和校验和扇区, 主备各 12 个扇区
+use super::super::{BlockDevice, CacheGeometry, START_CLUS_ID};
+use crate::config::{MAX_CLUS_SZ, MAX_SEC_SZ};
+use crate::error::FSError;
+#[cfg(not(feature = "std"))]
+use alloc::{slice, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{slice, sync::Arc};
+
+pub(crate) const BOOT_REGION_SECTORS: usize = 12;
+pub(crate) const BACKUP_BOOT_REGION: usize = BOOT_REGION_SECTORS;
+const EXTENDED_BOOT_SECTORS: usize = 8;
+const CHECKSUM_SECTOR: usize = 11;
+const JUMP_BOOT: [u8; 3] = [0xEB, 0x76, 0x90];
+const FILE_SYSTEM_NAME: [u8; 8] = *b"EXFAT   ";
+const BOOT_SIGNATURE: u16 = 0xAA55;
+const EXTENDED_BOOT_SIGNATURE: u32 = 0xAA55_0000;
+const FILE_SYSTEM_REVISION: u16 = 0x0100;
+// 计算校验和时跳过 VolumeFlags 和 PercentInUse, 它们会在运行时改变
+const VOLUME_FLAGS_OFFSET: usize = 106;
+const PERCENT_IN_USE_OFFSET: usize = 112;
+const ACTIVE_FAT_FLAG: u16 = 0x0001;
+pub(crate) const MAX_CLUSTER_COUNT: u32 = 0xFFFF_FFF5;
+pub(crate) const FORMAT_SECTOR_SZ: usize = 512; // 格式化时固定使用的扇区大小
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ExFatBootSector {
+    jump_boot: [u8; 3],
+    file_system_name: [u8; 8],
+    must_be_zero: [u8; 53], // 对应 FAT 的 BPB, 防止被当成 FAT 挂载
+    partition_offset: u64,
+    volume_length: u64,
+    fat_offset: u32,
+    fat_length: u32,
+    cluster_heap_offset: u32,
+    cluster_count: u32,
+    first_cluster_of_root_directory: u32,
+    volume_serial_number: u32,
+    file_system_revision: u16,
+    volume_flags: u16,
+    bytes_per_sector_shift: u8,
+    sectors_per_cluster_shift: u8,
+    number_of_fats: u8,
+    drive_select: u8,
+    percent_in_use: u8,
+    reserved: [u8; 7],
+    boot_code: [u8; 390],
+    boot_signature: u16,
+}
+
+impl ExFatBootSector {
+    fn zeroed() -> Self {
+        Self {
+            jump_boot: [0; 3],
+            file_system_name: [0; 8],
+            must_be_zero: [0; 53],
+            partition_offset: 0,
+            volume_length: 0,
+            fat_offset: 0,
+            fat_length: 0,
+            cluster_heap_offset: 0,
+            cluster_count: 0,
+            first_cluster_of_root_directory: 0,
+            volume_serial_number: 0,
+            file_system_revision: 0,
+            volume_flags: 0,
+            bytes_per_sector_shift: 0,
+            sectors_per_cluster_shift: 0,
+            number_of_fats: 0,
+            drive_select: 0,
+            percent_in_use: 0,
+            reserved: [0; 7],
+            boot_code: [0; 390],
+            boot_signature: 0,
+        }
+    }
+    /// 格式化时使用的启动扇区, 只有一个 FAT
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn for_format(
+        partition_offset: u64,
+        volume_length: u64,
+        fat_offset: u32,
+        fat_length: u32,
+        cluster_heap_offset: u32,
+        cluster_count: u32,
+        root_cluster: u32,
+        volume_serial_number: u32,
+        sectors_per_cluster_shift: u8,
+    ) -> Self {
+        let mut boot_sector = Self::zeroed();
+        boot_sector.jump_boot = JUMP_BOOT;
+        boot_sector.file_system_name = FILE_SYSTEM_NAME;
+        boot_sector.partition_offset = partition_offset;
+        boot_sector.volume_length = volume_length;
+        boot_sector.fat_offset = fat_offset;
+        boot_sector.fat_length = fat_length;
+        boot_sector.cluster_heap_offset = cluster_heap_offset;
+        boot_sector.cluster_count = cluster_count;
+        boot_sector.first_cluster_of_root_directory = root_cluster;
+        boot_sector.volume_serial_number = volume_serial_number;
+        boot_sector.file_system_revision = FILE_SYSTEM_REVISION;
+        boot_sector.bytes_per_sector_shift = FORMAT_SECTOR_SZ.trailing_zeros() as u8;
+        boot_sector.sectors_per_cluster_shift = sectors_per_cluster_shift;
+        boot_sector.number_of_fats = 1;
+        boot_sector.drive_select = 0x80;
+        boot_sector.percent_in_use = 0xFF; // 不可用
+        boot_sector.boot_signature = BOOT_SIGNATURE;
+        boot_sector
+    }
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const ExFatBootSector) as *const u8,
+                core::mem::size_of::<ExFatBootSector>(),
+            )
+        }
+    }
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(
+                (self as *mut ExFatBootSector) as *mut u8,
+                core::mem::size_of::<ExFatBootSector>(),
+            )
+        }
+    }
+    /// 读取 region_start 处的启动区域(主启动区域为 0, 备份为 12), 校验启动扇区和校验和
+    pub(crate) fn read_region(
+        block_device: &Arc<dyn BlockDevice>,
+        start_sector: usize,
+        region_start: usize,
+    ) -> Result<Self, FSError> {
+        let mut boot_sector = Self::zeroed();
+        block_device.read_block(start_sector + region_start, boot_sector.as_bytes_mut())?;
+        boot_sector.validate()?;
+        let sector_size = boot_sector.bytes_per_sector();
+        let mut region: Vec<u8> = vec![0; sector_size * BOOT_REGION_SECTORS];
+        block_device.read_blocks(
+            start_sector + region_start,
+            BOOT_REGION_SECTORS,
+            &mut region,
+        )?;
+        let checksum = boot_checksum(&region[..sector_size * CHECKSUM_SECTOR]);
+        // 校验和扇区中重复存放同一个校验和
+        let all_match = region[sector_size * CHECKSUM_SECTOR..]
+            .chunks_exact(4)
+            .all(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) == checksum);
+        if !all_match {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(boot_sector)
+    }
+    /// 写入主备两份启动区域, 扩展启动扇区只有签名
+    pub(crate) fn write_regions(
+        &self,
+        block_device: &Arc<dyn BlockDevice>,
+        start_sector: usize,
+    ) -> Result<(), FSError> {
+        let sector_size = self.bytes_per_sector();
+        let mut region: Vec<u8> = vec![0; sector_size * BOOT_REGION_SECTORS];
+        region[..sector_size].copy_from_slice(self.as_bytes());
+        for i in 1..=EXTENDED_BOOT_SECTORS {
+            let end = (i + 1) * sector_size;
+            region[end - 4..end].copy_from_slice(&EXTENDED_BOOT_SIGNATURE.to_le_bytes());
+        }
+        let checksum = boot_checksum(&region[..sector_size * CHECKSUM_SECTOR]);
+        for c in region[sector_size * CHECKSUM_SECTOR..].chunks_exact_mut(4) {
+            c.copy_from_slice(&checksum.to_le_bytes());
+        }
+        for region_start in [0, BACKUP_BOOT_REGION] {
+            block_device.write_blocks(start_sector + region_start, BOOT_REGION_SECTORS, &region)?;
+        }
+        Ok(())
+    }
+    fn validate(&self) -> Result<(), FSError> {
+        if self.jump_boot != JUMP_BOOT
+            || self.file_system_name != FILE_SYSTEM_NAME
+            || self.must_be_zero.iter().any(|b| *b != 0)
+            || self.boot_signature != BOOT_SIGNATURE
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        // 只支持 1.x 版本
+        if self.file_system_revision >> 8 != 1 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        // 扇区 512-4096 Byte, 簇最大 32MB, 但还要受缓存大小限制
+        if !(9..=12).contains(&self.bytes_per_sector_shift)
+            || self.sectors_per_cluster_shift > 25 - self.bytes_per_sector_shift
+            || self.bytes_per_sector() > MAX_SEC_SZ
+            || self.cluster_size() > MAX_CLUS_SZ
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        if self.number_of_fats != 1 && self.number_of_fats != 2 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let fats_end = u64::from(self.fat_offset)
+            + u64::from(self.fat_length) * u64::from(self.number_of_fats);
+        let heap_end = u64::from(self.cluster_heap_offset)
+            + (u64::from(self.cluster_count) << self.sectors_per_cluster_shift);
+        let fat_bytes = (u64::from(self.cluster_count) + START_CLUS_ID as u64) * 4;
+        if (self.fat_offset as usize) < 2 * BOOT_REGION_SECTORS
+            || (u64::from(self.fat_length) << self.bytes_per_sector_shift) < fat_bytes
+            || u64::from(self.cluster_heap_offset) < fats_end
+            || self.cluster_count > MAX_CLUSTER_COUNT
+            || heap_end > self.volume_length
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let root_cluster = self.first_cluster_of_root_directory;
+        if root_cluster < START_CLUS_ID as u32 || root_cluster >= self.cluster_count + 2 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(())
+    }
+    pub(crate) fn bytes_per_sector(&self) -> usize {
+        1 << self.bytes_per_sector_shift
+    }
+    pub(crate) fn sectors_per_cluster(&self) -> usize {
+        1 << self.sectors_per_cluster_shift
+    }
+    pub(crate) fn cluster_size(&self) -> usize {
+        self.bytes_per_sector() << self.sectors_per_cluster_shift
+    }
+    pub(crate) fn volume_length(&self) -> u64 {
+        self.volume_length
+    }
+    pub(crate) fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+    pub(crate) fn root_cluster(&self) -> u32 {
+        self.first_cluster_of_root_directory
+    }
+    pub(crate) fn volume_serial_number(&self) -> u32 {
+        self.volume_serial_number
+    }
+    /// 当前使用的 FAT 表的起始扇区, 有两个 FAT 时由 VolumeFlags 的 ActiveFat 位决定
+    pub(crate) fn active_fat_sector(&self) -> usize {
+        let active = if self.number_of_fats == 2 && self.volume_flags & ACTIVE_FAT_FLAG != 0 {
+            1
+        } else {
+            0
+        };
+        self.fat_offset as usize + active * self.fat_length as usize
+    }
+    pub(crate) fn cache_geometry(&self) -> CacheGeometry {
+        CacheGeometry {
+            bytes_per_sector: self.bytes_per_sector(),
+            sectors_per_cluster: self.sectors_per_cluster(),
+            first_data_sector: self.cluster_heap_offset as usize,
+            total_clusters: self.cluster_count as usize,
+        }
+    }
+}
+
+/// 启动区域前 11 个扇区的校验和
+fn boot_checksum(sectors: &[u8]) -> u32 {
+    let mut checksum: u32 = 0;
+    for (i, byte) in sectors.iter().enumerate() {
+        if i == VOLUME_FLAGS_OFFSET || i == VOLUME_FLAGS_OFFSET + 1 || i == PERCENT_IN_USE_OFFSET {
+            continue;
+        }
+        checksum = checksum.rotate_right(1).wrapping_add(u32::from(*byte));
+    }
+    checksum
+}
@@ -0,0 +1,303 @@
+// exFAT 目录项, 文件由 File, Stream Extension 和若干 File Name 目录项组成的目录项集合描述
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::FSError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+pub(crate) const ENTRY_TYPE_END: u8 = 0x00;
+pub(crate) const ENTRY_TYPE_BITMAP: u8 = 0x81;
+pub(crate) const ENTRY_TYPE_UPCASE: u8 = 0x82;
+pub(crate) const ENTRY_TYPE_VOLUME_LABEL: u8 = 0x83;
+pub(crate) const ENTRY_TYPE_FILE: u8 = 0x85;
+pub(crate) const ENTRY_TYPE_STREAM: u8 = 0xC0;
+pub(crate) const ENTRY_TYPE_NAME: u8 = 0xC1;
+pub(crate) const ENTRY_IN_USE: u8 = 0x80; // 类型最高位清零表示已删除
+pub(crate) const NAME_CHARS_PER_ENTRY: usize = 15;
+pub(crate) const MAX_NAME_LEN: usize = 255;
+// Stream Extension 的 GeneralSecondaryFlags
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// 32 字节的原始目录项
+pub(crate) type RawEntry = [u8; DIRENT_SZ];
+
+/// 目录项都是 32 字节的 packed 结构, 可以直接和原始目录项互相转换
+pub(crate) trait ExFatEntry: Copy + Sized {
+    fn from_raw(raw: &RawEntry) -> Self {
+        assert_eq!(core::mem::size_of::<Self>(), DIRENT_SZ);
+        unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const Self) }
+    }
+    fn to_raw(&self) -> RawEntry {
+        assert_eq!(core::mem::size_of::<Self>(), DIRENT_SZ);
+        unsafe { core::ptr::read_unaligned((self as *const Self) as *const RawEntry) }
+    }
+}
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct FileEntry {
+    entry_type: u8,
+    secondary_count: u8,
+    set_checksum: u16,
+    file_attributes: u16,
+    reserved1: u16,
+    create_timestamp: u32,
+    last_modified_timestamp: u32,
+    last_accessed_timestamp: u32,
+    create_10ms_increment: u8,
+    last_modified_10ms_increment: u8,
+    create_utc_offset: u8,
+    last_modified_utc_offset: u8,
+    last_accessed_utc_offset: u8,
+    reserved2: [u8; 7],
+}
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct StreamEntry {
+    entry_type: u8,
+    flags: u8,
+    reserved1: u8,
+    name_length: u8,
+    name_hash: u16,
+    reserved2: u16,
+    valid_data_length: u64,
+    reserved3: u32,
+    first_cluster: u32,
+    data_length: u64,
+}
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct NameEntry {
+    entry_type: u8,
+    flags: u8,
+    file_name: [u16; NAME_CHARS_PER_ENTRY],
+}
+
+/// 分配位图和大写转换表目录项的布局相同, 只是中间字段含义不同
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct MetadataEntry {
+    entry_type: u8,
+    flags: u8, // 位图: BitmapFlags, 第 0 位表示对应第几个 FAT
+    reserved1: [u8; 2],
+    table_checksum: u32, // 大写转换表的校验和
+    reserved2: [u8; 12],
+    first_cluster: u32,
+    data_length: u64,
+}
+
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct VolumeLabelEntry {
+    entry_type: u8,
+    character_count: u8,
+    volume_label: [u16; 11],
+    reserved: [u8; 8],
+}
+
+impl ExFatEntry for FileEntry {}
+impl ExFatEntry for StreamEntry {}
+impl ExFatEntry for NameEntry {}
+impl ExFatEntry for MetadataEntry {}
+impl ExFatEntry for VolumeLabelEntry {}
+
+impl MetadataEntry {
+    pub(crate) fn new(entry_type: u8, first_cluster: u32, data_length: u64, checksum: u32) -> Self {
+        Self {
+            entry_type,
+            first_cluster,
+            data_length,
+            table_checksum: checksum,
+            ..Default::default()
+        }
+    }
+    pub(crate) fn is_first_bitmap(&self) -> bool {
+        self.flags & 0x01 == 0
+    }
+    pub(crate) fn first_cluster(&self) -> u32 {
+        self.first_cluster
+    }
+    pub(crate) fn data_length(&self) -> u64 {
+        self.data_length
+    }
+    pub(crate) fn table_checksum(&self) -> u32 {
+        self.table_checksum
+    }
+}
+
+impl VolumeLabelEntry {
+    /// 空卷名
+    pub(crate) fn empty() -> Self {
+        Self {
+            entry_type: ENTRY_TYPE_VOLUME_LABEL,
+            ..Default::default()
+        }
+    }
+    pub(crate) fn label(&self) -> Vec<u16> {
+        let label = self.volume_label;
+        let len = usize::from(self.character_count).min(label.len());
+        label[..len].to_vec()
+    }
+}
+
+/// 目录项集合, 不认识的次要目录项原样保留
+#[derive(Clone, Debug)]
+pub(crate) struct EntrySet {
+    file: FileEntry,
+    stream: StreamEntry,
+    names: Vec<NameEntry>,
+    others: Vec<RawEntry>,
+}
+
+impl EntrySet {
+    /// 新文件或目录的目录项集合, name_hash 由大写后的名字算出
+    pub(crate) fn new(name: &[u16], name_hash: u16, attributes: u16) -> Self {
+        let names: Vec<NameEntry> = name
+            .chunks(NAME_CHARS_PER_ENTRY)
+            .map(|chunk| {
+                let mut file_name = [0u16; NAME_CHARS_PER_ENTRY];
+                file_name[..chunk.len()].copy_from_slice(chunk);
+                NameEntry {
+                    entry_type: ENTRY_TYPE_NAME,
+                    flags: 0,
+                    file_name,
+                }
+            })
+            .collect();
+        let mut set = Self {
+            file: FileEntry {
+                entry_type: ENTRY_TYPE_FILE,
+                secondary_count: (names.len() + 1) as u8,
+                file_attributes: attributes,
+                ..Default::default()
+            },
+            stream: StreamEntry {
+                entry_type: ENTRY_TYPE_STREAM,
+                flags: ALLOCATION_POSSIBLE,
+                name_length: name.len() as u8,
+                name_hash,
+                ..Default::default()
+            },
+            names,
+            others: Vec::new(),
+        };
+        set.update_checksum();
+        set
+    }
+    /// 从连续的原始目录项解析, 检查类型, 数量和校验和
+    pub(crate) fn from_raw(entries: &[RawEntry]) -> Result<Self, FSError> {
+        if entries.len() < 3 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let file = FileEntry::from_raw(&entries[0]);
+        let secondary_count = usize::from(file.secondary_count);
+        if file.entry_type != ENTRY_TYPE_FILE
+            || secondary_count + 1 != entries.len()
+            || entries[1][0] != ENTRY_TYPE_STREAM
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        if set_checksum(entries) != file.set_checksum {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let stream = StreamEntry::from_raw(&entries[1]);
+        let name_entries = usize::from(stream.name_length).div_ceil(NAME_CHARS_PER_ENTRY);
+        if name_entries == 0 || name_entries + 2 > entries.len() {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let mut names: Vec<NameEntry> = Vec::new();
+        for raw in entries[2..2 + name_entries].iter() {
+            if raw[0] != ENTRY_TYPE_NAME {
+                return Err(FSError::CorruptedFileSystem);
+            }
+            names.push(NameEntry::from_raw(raw));
+        }
+        Ok(Self {
+            file,
+            stream,
+            names,
+            others: entries[2 + name_entries..].to_vec(),
+        })
+    }
+    pub(crate) fn to_raw(&self) -> Vec<RawEntry> {
+        let mut entries: Vec<RawEntry> = Vec::new();
+        entries.push(self.file.to_raw());
+        entries.push(self.stream.to_raw());
+        entries.extend(self.names.iter().map(|n| n.to_raw()));
+        entries.extend(self.others.iter().copied());
+        entries
+    }
+    /// 修改任何目录项后都要重新计算
+    pub(crate) fn update_checksum(&mut self) {
+        self.file.set_checksum = set_checksum(&self.to_raw());
+    }
+    pub(crate) fn entry_count(&self) -> usize {
+        usize::from(self.file.secondary_count) + 1
+    }
+    pub(crate) fn name(&self) -> Vec<u16> {
+        let len = usize::from(self.stream.name_length);
+        let mut name: Vec<u16> = Vec::new();
+        for entry in self.names.iter() {
+            let file_name = entry.file_name;
+            name.extend_from_slice(&file_name);
+        }
+        name.truncate(len);
+        name
+    }
+    pub(crate) fn name_hash(&self) -> u16 {
+        self.stream.name_hash
+    }
+    pub(crate) fn attributes(&self) -> u16 {
+        self.file.file_attributes
+    }
+    pub(crate) fn first_cluster(&self) -> u32 {
+        self.stream.first_cluster
+    }
+    pub(crate) fn no_fat_chain(&self) -> bool {
+        self.stream.flags & NO_FAT_CHAIN != 0
+    }
+    pub(crate) fn data_length(&self) -> u64 {
+        self.stream.data_length
+    }
+    pub(crate) fn valid_data_length(&self) -> u64 {
+        self.stream.valid_data_length
+    }
+    pub(crate) fn set_allocation(&mut self, first_cluster: u32, no_fat_chain: bool) {
+        self.stream.first_cluster = first_cluster;
+        if no_fat_chain {
+            self.stream.flags |= NO_FAT_CHAIN;
+        } else {
+            self.stream.flags &= !NO_FAT_CHAIN;
+        }
+    }
+    pub(crate) fn set_length(&mut self, data_length: u64, valid_data_length: u64) {
+        self.stream.data_length = data_length;
+        self.stream.valid_data_length = valid_data_length;
+    }
+}
+
+/// 目录项集合的校验和, 跳过 File 目录项中的 SetChecksum 字段
+pub(crate) fn set_checksum(entries: &[RawEntry]) -> u16 {
+    let mut checksum: u16 = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        for (j, byte) in entry.iter().enumerate() {
+            if i == 0 && (j == 2 || j == 3) {
+                continue;
+            }
+            checksum = checksum.rotate_right(1).wrapping_add(u16::from(*byte));
+        }
+    }
+    checksum
+}
+
+/// 名称哈希, 输入是经过大写转换表转换后的名字
+pub(crate) fn name_hash(upcased_name: &[u16]) -> u16 {
+    upcased_name
+        .iter()
+        .flat_map(|c| c.to_le_bytes())
+        .fold(0u16, |hash, byte| {
+            hash.rotate_right(1).wrapping_add(u16::from(byte))
+        })
+}
@@ -0,0 +1,353 @@
+// exFAT 的簇管理: 分配状态记录在分配位图中, FAT 表只记录不连续文件的簇链
+use super::super::{
+    BlockDevice, CacheGeometry, ClusterCacheManager, SectorCacheManager, START_CLUS_ID,
+};
+use crate::error::FSError;
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec::Vec};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+const FAT_ENTRY_SZ: usize = 4;
+pub(crate) const END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+const MEDIA_ENTRY: u32 = 0xFFFF_FFF8; // FAT 第 0 项
+
+/// 文件或目录数据所在的簇, NoFatChain 时簇是连续的, 不需要读 FAT 表
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct DataStream {
+    pub first_cluster: u32,
+    pub clusters: usize, // 已分配的簇数
+    pub no_fat_chain: bool,
+}
+
+pub(crate) struct ExFatManager {
+    geometry: CacheGeometry,
+    fat_sector: usize, // 活动 FAT 表的起始扇区
+    sector_cache: SectorCacheManager,
+    bitmap_cache: ClusterCacheManager,
+    bitmap_clusters: Vec<usize>,
+    free_clusters: u32,
+    next_free_cluster: u32,
+}
+
+impl ExFatManager {
+    pub(crate) fn new(
+        geometry: CacheGeometry,
+        fat_sector: usize,
+        start_sector: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
+        Self {
+            geometry,
+            fat_sector,
+            sector_cache: SectorCacheManager::new(geometry, start_sector, block_device.clone()),
+            bitmap_cache: ClusterCacheManager::new(geometry, start_sector, block_device),
+            bitmap_clusters: Vec::new(),
+            free_clusters: 0,
+            next_free_cluster: START_CLUS_ID as u32,
+        }
+    }
+    /// 挂载时根据位图目录项找到位图所在的簇并统计空闲簇
+    pub(crate) fn load_bitmap(&mut self, first_cluster: u32, length: u64) -> Result<(), FSError> {
+        let needed = self.geometry.total_clusters.div_ceil(8);
+        if (length as usize) < needed {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let clusters = needed.div_ceil(self.geometry.cluster_size());
+        let chain = self.chain(first_cluster, clusters)?;
+        self.bitmap_clusters = chain;
+        let mut allocated = 0;
+        for i in 0..self.geometry.total_clusters {
+            if self.is_allocated((i + START_CLUS_ID) as u32)? {
+                allocated += 1;
+            }
+        }
+        self.free_clusters = (self.geometry.total_clusters - allocated) as u32;
+        Ok(())
+    }
+    /// 写回缓存中修改过的 FAT 表扇区和分配位图
+    pub(crate) fn sync(&mut self) -> Result<(), FSError> {
+        self.sector_cache.info_cache_sync_all()?;
+        self.bitmap_cache.data_cache_sync_all()?;
+        Ok(())
+    }
+    pub(crate) fn free_clusters(&self) -> u32 {
+        self.free_clusters
+    }
+    fn is_valid_cluster(&self, cluster_id: u32) -> bool {
+        let cluster_id = cluster_id as usize;
+        (START_CLUS_ID..self.geometry.total_clusters + START_CLUS_ID).contains(&cluster_id)
+    }
+    fn entry_position(&self, cluster_id: u32) -> (usize, usize) {
+        let offset = cluster_id as usize * FAT_ENTRY_SZ;
+        let sector_size = self.geometry.bytes_per_sector;
+        (self.fat_sector + offset / sector_size, offset % sector_size)
+    }
+    pub(crate) fn entry(&mut self, cluster_id: u32) -> Result<u32, FSError> {
+        let (sector_id, offset) = self.entry_position(cluster_id);
+        Ok(self
+            .sector_cache
+            .get_cache(sector_id)?
+            .read()
+            .read(offset, |entry: &u32| *entry))
+    }
+    pub(crate) fn set_entry(&mut self, cluster_id: u32, value: u32) -> Result<(), FSError> {
+        let (sector_id, offset) = self.entry_position(cluster_id);
+        self.sector_cache
+            .get_cache(sector_id)?
+            .write()
+            .modify(offset, |entry: &mut u32| *entry = value);
+        Ok(())
+    }
+    // 位图中第 i 位对应簇 i + 2
+    fn bitmap_position(&self, cluster_id: u32) -> (usize, usize, u8) {
+        let index = cluster_id as usize - START_CLUS_ID;
+        let byte = index / 8;
+        let cluster_size = self.geometry.cluster_size();
+        (
+            self.bitmap_clusters[byte / cluster_size],
+            byte % cluster_size,
+            1 << (index % 8),
+        )
+    }
+    pub(crate) fn is_allocated(&mut self, cluster_id: u32) -> Result<bool, FSError> {
+        let (cluster, offset, mask) = self.bitmap_position(cluster_id);
+        Ok(self
+            .bitmap_cache
+            .get_cache(cluster)?
+            .read()
+            .read(offset, |byte: &u8| *byte & mask != 0))
+    }
+    fn set_allocated(&mut self, cluster_id: u32, allocated: bool) -> Result<(), FSError> {
+        let (cluster, offset, mask) = self.bitmap_position(cluster_id);
+        self.bitmap_cache
+            .get_cache(cluster)?
+            .write()
+            .modify(offset, |byte: &mut u8| {
+                if allocated {
+                    *byte |= mask
+                } else {
+                    *byte &= !mask
+                }
+            });
+        if allocated {
+            self.free_clusters -= 1;
+        } else {
+            self.free_clusters += 1;
+        }
+        Ok(())
+    }
+    /// 沿着 FAT 表读出 num 个簇, 簇链提前结束或者出现坏簇说明文件系统损坏
+    pub(crate) fn chain(&mut self, first_cluster: u32, num: usize) -> Result<Vec<usize>, FSError> {
+        let mut clusters: Vec<usize> = Vec::new();
+        let mut cluster_id = first_cluster;
+        while clusters.len() < num {
+            if !self.is_valid_cluster(cluster_id) {
+                return Err(FSError::CorruptedFileSystem);
+            }
+            clusters.push(cluster_id as usize);
+            cluster_id = self.entry(cluster_id)?;
+        }
+        Ok(clusters)
+    }
+    /// FAT 表中簇链的长度, 用于没有 Stream Extension 的根目录
+    pub(crate) fn count_chain(&mut self, first_cluster: u32) -> Result<usize, FSError> {
+        let mut count = 0;
+        let mut cluster_id = first_cluster;
+        while self.is_valid_cluster(cluster_id) && count < self.geometry.total_clusters {
+            count += 1;
+            cluster_id = self.entry(cluster_id)?;
+        }
+        Ok(count)
+    }
+    /// 数据流中第 index 个簇
+    pub(crate) fn cluster_at(
+        &mut self,
+        stream: &DataStream,
+        index: usize,
+    ) -> Result<Option<u32>, FSError> {
+        if index >= stream.clusters {
+            return Ok(None);
+        }
+        if stream.no_fat_chain {
+            return Ok(Some(stream.first_cluster + index as u32));
+        }
+        let mut cluster_id = stream.first_cluster;
+        for _ in 0..index {
+            cluster_id = self.entry(cluster_id)?;
+        }
+        Ok(self.is_valid_cluster(cluster_id).then_some(cluster_id))
+    }
+    /// 数据流中 cluster_id 的下一个簇
+    pub(crate) fn next_cluster(
+        &mut self,
+        stream: &DataStream,
+        cluster_id: u32,
+    ) -> Result<Option<u32>, FSError> {
+        let next = if stream.no_fat_chain {
+            let last = stream.first_cluster + stream.clusters as u32 - 1;
+            if cluster_id >= last {
+                return Ok(None);
+            }
+            cluster_id + 1
+        } else {
+            self.entry(cluster_id)?
+        };
+        Ok(self.is_valid_cluster(next).then_some(next))
+    }
+    // 从 start 开始找第一个空闲簇, 找到末尾后从头再找
+    fn search_free_cluster(&mut self, start: u32) -> Result<Option<u32>, FSError> {
+        let first = START_CLUS_ID as u32;
+        let end = (self.geometry.total_clusters + START_CLUS_ID) as u32;
+        let start = if self.is_valid_cluster(start) {
+            start
+        } else {
+            first
+        };
+        for cluster_id in (start..end).chain(first..start) {
+            if !self.is_allocated(cluster_id)? {
+                return Ok(Some(cluster_id));
+            }
+        }
+        Ok(None)
+    }
+    // 找 num 个连续的空闲簇
+    fn search_free_run(&mut self, num: usize) -> Result<Option<u32>, FSError> {
+        let end = (self.geometry.total_clusters + START_CLUS_ID) as u32;
+        let mut run_start = START_CLUS_ID as u32;
+        let mut run_len = 0;
+        for cluster_id in START_CLUS_ID as u32..end {
+            if self.is_allocated(cluster_id)? {
+                run_start = cluster_id + 1;
+                run_len = 0;
+            } else {
+                run_len += 1;
+                if run_len == num {
+                    return Ok(Some(run_start));
+                }
+            }
+        }
+        Ok(None)
+    }
+    // 连续的数据流改成用 FAT 表记录簇链
+    fn write_chain(&mut self, stream: &mut DataStream) -> Result<(), FSError> {
+        let first = stream.first_cluster;
+        let last = first + stream.clusters as u32 - 1;
+        for cluster_id in first..last {
+            self.set_entry(cluster_id, cluster_id + 1)?;
+        }
+        self.set_entry(last, END_OF_CHAIN)?;
+        stream.no_fat_chain = false;
+        Ok(())
+    }
+    /// 给数据流再分配 num 个簇, 尽量保持连续, 新分配的簇不清空
+    /// 原来连续的数据流后面被占用时, 先把已有的簇写进 FAT 表再按簇链分配
+    pub(crate) fn alloc_clusters(
+        &mut self,
+        stream: &mut DataStream,
+        num: usize,
+    ) -> Result<(), FSError> {
+        if num == 0 {
+            return Ok(());
+        }
+        if num > self.free_clusters as usize {
+            return Err(FSError::NotEnoughSpace);
+        }
+        if stream.clusters == 0 {
+            if let Some(first) = self.search_free_run(num)? {
+                for cluster_id in first..first + num as u32 {
+                    self.set_allocated(cluster_id, true)?;
+                }
+                *stream = DataStream {
+                    first_cluster: first,
+                    clusters: num,
+                    no_fat_chain: true,
+                };
+                return Ok(());
+            }
+            stream.no_fat_chain = false;
+        } else if stream.no_fat_chain {
+            let next = stream.first_cluster + stream.clusters as u32;
+            let mut contiguous = true;
+            for c in next..next + num as u32 {
+                if !self.is_valid_cluster(c) || self.is_allocated(c)? {
+                    contiguous = false;
+                    break;
+                }
+            }
+            if contiguous {
+                for cluster_id in next..next + num as u32 {
+                    self.set_allocated(cluster_id, true)?;
+                }
+                stream.clusters += num;
+                return Ok(());
+            }
+            self.write_chain(stream)?;
+        }
+        let mut prev = if stream.clusters == 0 {
+            None
+        } else {
+            self.cluster_at(stream, stream.clusters - 1)?
+        };
+        let mut hint = prev.map_or(self.next_free_cluster, |p| p + 1);
+        for _ in 0..num {
+            let cluster_id = self
+                .search_free_cluster(hint)?
+                .ok_or(FSError::NotEnoughSpace)?;
+            self.set_allocated(cluster_id, true)?;
+            self.set_entry(cluster_id, END_OF_CHAIN)?;
+            match prev {
+                Some(p) => self.set_entry(p, cluster_id)?,
+                None => stream.first_cluster = cluster_id,
+            }
+            stream.clusters += 1;
+            prev = Some(cluster_id);
+            hint = cluster_id + 1;
+        }
+        self.next_free_cluster = hint;
+        Ok(())
+    }
+    /// 回收数据流的全部簇, 返回回收的簇数
+    pub(crate) fn dealloc_clusters(&mut self, stream: &DataStream) -> Result<usize, FSError> {
+        let mut count = 0;
+        let mut cluster = self.cluster_at(stream, 0)?;
+        for _ in 0..stream.clusters {
+            let Some(cluster_id) = cluster else {
+                break;
+            };
+            cluster = self.next_cluster(stream, cluster_id)?;
+            if !stream.no_fat_chain {
+                self.set_entry(cluster_id, 0)?;
+            }
+            if self.is_allocated(cluster_id)? {
+                self.set_allocated(cluster_id, false)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    /// 格式化时使用: 写 FAT 表前两项和元数据(位图在最前面)的簇链, 再在位图中标记这些簇
+    pub(crate) fn format_metadata(
+        &mut self,
+        extents: &[(u32, usize)],
+        bitmap_length: u64,
+    ) -> Result<(), FSError> {
+        self.set_entry(0, MEDIA_ENTRY)?;
+        self.set_entry(1, END_OF_CHAIN)?;
+        for (first, clusters) in extents.iter() {
+            let mut stream = DataStream {
+                first_cluster: *first,
+                clusters: *clusters,
+                no_fat_chain: true,
+            };
+            self.write_chain(&mut stream)?;
+        }
+        self.load_bitmap(extents[0].0, bitmap_length)?;
+        for (first, clusters) in extents.iter() {
+            for cluster_id in *first..*first + *clusters as u32 {
+                self.set_allocated(cluster_id, true)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+// exFAT 文件系统的全局管理, 和 FAT 共用块设备和缓存层
+use super::boot_sector::{ExFatBootSector, BACKUP_BOOT_REGION};
+use super::dir_entry::{
+    ExFatEntry, MetadataEntry, RawEntry, VolumeLabelEntry, ENTRY_TYPE_BITMAP, ENTRY_TYPE_END,
+    ENTRY_TYPE_UPCASE, ENTRY_TYPE_VOLUME_LABEL,
+};
+use super::fat::{DataStream, ExFatManager};
+use super::mkfs::{self, ExFatFormatOptions};
+use super::upcase::{table_checksum, UpcaseTable};
+use super::vfile::ExFatVFile;
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::FSError;
+use crate::{BlockDevice, ClusterCacheManager, FileAttributes};
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use spin::{RwLock, RwLockWriteGuard};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+pub struct ExFatFileSystem {
+    boot_sector: ExFatBootSector,
+    start_sector: usize, // 文件系统在块设备上的起始扇区, 不分区时为 0
+    upcase: UpcaseTable,
+    volume_label: String,
+    fat_manager: Arc<RwLock<ExFatManager>>,
+    cluster_cache: Arc<RwLock<ClusterCacheManager>>,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl ExFatFileSystem {
+    pub fn new(block_device: Arc<dyn BlockDevice>) -> Result<Self, FSError> {
+        Self::new_at(block_device, 0)
+    }
+    /// 挂载从块设备 start_sector 扇区开始的 exFAT, 主启动区域损坏时使用备份启动区域
+    pub fn new_at(
+        block_device: Arc<dyn BlockDevice>,
+        start_sector: usize,
+    ) -> Result<Self, FSError> {
+        let boot_sector = match ExFatBootSector::read_region(&block_device, start_sector, 0) {
+            Ok(boot_sector) => boot_sector,
+            Err(e) => {
+                log::warn!("main boot region not valid: {:?}, trying backup", e);
+                ExFatBootSector::read_region(&block_device, start_sector, BACKUP_BOOT_REGION)?
+            }
+        };
+        let geometry = boot_sector.cache_geometry();
+        let fat_manager = ExFatManager::new(
+            geometry,
+            boot_sector.active_fat_sector(),
+            start_sector,
+            Arc::clone(&block_device),
+        );
+        let mut exfat = Self {
+            boot_sector,
+            start_sector,
+            upcase: UpcaseTable::from_raw(&[]),
+            volume_label: String::new(),
+            fat_manager: Arc::new(RwLock::new(fat_manager)),
+            cluster_cache: Arc::new(RwLock::new(ClusterCacheManager::new(
+                geometry,
+                start_sector,
+                Arc::clone(&block_device),
+            ))),
+            block_device,
+        };
+        exfat.load_metadata()?;
+        Ok(exfat)
+    }
+    // 从根目录中读取分配位图, 大写转换表和卷名目录项
+    fn load_metadata(&mut self) -> Result<(), FSError> {
+        let root = self.root_stream()?;
+        let mut raw: Vec<u8> = vec![0; root.clusters * self.cluster_size()];
+        self.read_stream(&root, 0, &mut raw)?;
+        let mut bitmap: Option<MetadataEntry> = None;
+        let mut upcase: Option<MetadataEntry> = None;
+        for chunk in raw.chunks_exact(DIRENT_SZ) {
+            let entry: &RawEntry = chunk.try_into().unwrap();
+            match entry[0] {
+                ENTRY_TYPE_END => break,
+                ENTRY_TYPE_BITMAP => {
+                    let bitmap_entry = MetadataEntry::from_raw(entry);
+                    // TexFAT 的第二个位图对应第二个 FAT, 这里只用第一个
+                    if bitmap.is_none() && bitmap_entry.is_first_bitmap() {
+                        bitmap = Some(bitmap_entry);
+                    }
+                }
+                ENTRY_TYPE_UPCASE => upcase = Some(MetadataEntry::from_raw(entry)),
+                ENTRY_TYPE_VOLUME_LABEL => {
+                    let label = VolumeLabelEntry::from_raw(entry).label();
+                    self.volume_label = String::from_utf16_lossy(&label);
+                }
+                _ => {}
+            }
+        }
+        let bitmap = bitmap.ok_or(FSError::CorruptedFileSystem)?;
+        self.fat_manager
+            .write()
+            .load_bitmap(bitmap.first_cluster(), bitmap.data_length())?;
+        let upcase = upcase.ok_or(FSError::CorruptedFileSystem)?;
+        let length = upcase.data_length() as usize;
+        let stream = DataStream {
+            first_cluster: upcase.first_cluster(),
+            clusters: length.div_ceil(self.cluster_size()),
+            no_fat_chain: false,
+        };
+        let mut table: Vec<u8> = vec![0; length];
+        if self.read_stream(&stream, 0, &mut table)? != length
+            || table_checksum(&table) != upcase.table_checksum()
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        self.upcase = UpcaseTable::from_raw(&table);
+        Ok(())
+    }
+    /// 在块设备上创建全新的 exFAT, 原有数据会被覆盖, 之后用 new 挂载
+    pub fn format(
+        block_device: Arc<dyn BlockDevice>,
+        options: ExFatFormatOptions,
+    ) -> Result<(), FSError> {
+        mkfs::format(block_device, 0, options)
+    }
+    /// 在块设备 start_sector 扇区开始的分区中创建 exFAT, 一般还要把 partition_offset 设成相同的值
+    pub fn format_at(
+        block_device: Arc<dyn BlockDevice>,
+        start_sector: usize,
+        options: ExFatFormatOptions,
+    ) -> Result<(), FSError> {
+        mkfs::format(block_device, start_sector, options)
+    }
+    pub fn volume_serial(&self) -> u32 {
+        self.boot_sector.volume_serial_number()
+    }
+    pub fn volume_label(&self) -> &str {
+        self.volume_label.as_str()
+    }
+    /// 文件系统在块设备上的起始扇区
+    pub fn start_sector(&self) -> usize {
+        self.start_sector
+    }
+    pub fn volume_length(&self) -> u64 {
+        self.boot_sector.volume_length()
+    }
+    pub fn cluster_size(&self) -> usize {
+        self.boot_sector.cluster_size()
+    }
+    pub fn total_clusters(&self) -> u32 {
+        self.boot_sector.cluster_count()
+    }
+    pub fn free_clusters(&self) -> u32 {
+        self.fat_manager.read().free_clusters()
+    }
+    /// 写回缓存中的修改并刷新块设备, 写回失败时返回错误
+    pub fn sync(&self) -> Result<(), FSError> {
+        self.fat_manager.write().sync()?;
+        self.cluster_cache.write().data_cache_sync_all()?;
+        Ok(self.block_device.flush()?)
+    }
+    /// 卸载文件系统, 和 RunFileSystem::unmount 一样先写回缓存再刷新块设备
+    pub fn unmount(self) -> Result<(), FSError> {
+        self.fat_manager.write().sync()?;
+        self.cluster_cache.write().data_cache_sync_all()?;
+        let block_device = Arc::clone(&self.block_device);
+        drop(self);
+        Ok(block_device.flush()?)
+    }
+    pub fn root_vfile(&self, exfat: &Arc<RwLock<Self>>) -> ExFatVFile {
+        ExFatVFile::new(
+            String::from("/"),
+            Vec::new(),
+            FileAttributes::DIRECTORY,
+            Arc::clone(exfat),
+        )
+    }
+    pub(crate) fn upcase(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+    pub(crate) fn fat_manager_modify(&self) -> RwLockWriteGuard<'_, ExFatManager> {
+        self.fat_manager.write()
+    }
+    /// 根目录没有 Stream Extension, 大小由 FAT 表中的簇链决定
+    pub(crate) fn root_stream(&self) -> Result<DataStream, FSError> {
+        let root_cluster = self.boot_sector.root_cluster();
+        Ok(DataStream {
+            first_cluster: root_cluster,
+            clusters: self.fat_manager.write().count_chain(root_cluster)?,
+            no_fat_chain: false,
+        })
+    }
+    pub(crate) fn read_entry(&self, cluster_id: usize, offset: usize) -> Result<RawEntry, FSError> {
+        Ok(self
+            .cluster_cache
+            .write()
+            .get_cache(cluster_id)?
+            .read()
+            .read(offset, |entry: &RawEntry| *entry))
+    }
+    pub(crate) fn write_entry(
+        &self,
+        cluster_id: usize,
+        offset: usize,
+        raw: &RawEntry,
+    ) -> Result<(), FSError> {
+        self.cluster_cache
+            .write()
+            .get_cache(cluster_id)?
+            .write()
+            .modify(offset, |entry: &mut RawEntry| *entry = *raw);
+        Ok(())
+    }
+    /// 读数据流中 offset 开始的数据, 最多读到已分配簇的末尾, 返回读取的字节数
+    pub(crate) fn read_stream(
+        &self,
+        stream: &DataStream,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, FSError> {
+        let cluster_size = self.cluster_size();
+        let end = (stream.clusters * cluster_size).min(offset + buf.len());
+        if offset >= end {
+            return Ok(0);
+        }
+        let mut cluster = self
+            .fat_manager
+            .write()
+            .cluster_at(stream, offset / cluster_size)?;
+        let mut pos = offset;
+        while let Some(cluster_id) = cluster {
+            let cluster_offset = pos % cluster_size;
+            let len = (cluster_size - cluster_offset).min(end - pos);
+            let cache = self.cluster_cache.write().get_cache(cluster_id as usize)?;
+            let cache = cache.read();
+            for (i, byte) in buf[pos - offset..pos - offset + len].iter_mut().enumerate() {
+                *byte = cache.read(cluster_offset + i, |b: &u8| *b);
+            }
+            pos += len;
+            if pos >= end {
+                break;
+            }
+            cluster = self.fat_manager.write().next_cluster(stream, cluster_id)?;
+        }
+        Ok(pos - offset)
+    }
+    /// 写数据流中 offset 开始的数据, 最多写到已分配簇的末尾, 返回写入的字节数
+    pub(crate) fn write_stream(
+        &self,
+        stream: &DataStream,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<usize, FSError> {
+        let cluster_size = self.cluster_size();
+        let end = (stream.clusters * cluster_size).min(offset + buf.len());
+        if offset >= end {
+            return Ok(0);
+        }
+        let mut cluster = self
+            .fat_manager
+            .write()
+            .cluster_at(stream, offset / cluster_size)?;
+        let mut pos = offset;
+        while let Some(cluster_id) = cluster {
+            let cluster_offset = pos % cluster_size;
+            let len = (cluster_size - cluster_offset).min(end - pos);
+            let cache = self.cluster_cache.write().get_cache(cluster_id as usize)?;
+            let mut cache = cache.write();
+            for (i, byte) in buf[pos - offset..pos - offset + len].iter().enumerate() {
+                cache.modify(cluster_offset + i, |b: &mut u8| *b = *byte);
+            }
+            pos += len;
+            if pos >= end {
+                break;
+            }
+            cluster = self.fat_manager.write().next_cluster(stream, cluster_id)?;
+        }
+        Ok(pos - offset)
+    }
+}
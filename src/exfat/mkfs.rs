@@ -0,0 +1,175 @@
+// 格式化, 在任意块设备上创建一个全新的 exFAT 文件系统
+use super::boot_sector::{
+    ExFatBootSector, BOOT_REGION_SECTORS, FORMAT_SECTOR_SZ, MAX_CLUSTER_COUNT,
+};
+use super::dir_entry::{
+    ExFatEntry, MetadataEntry, VolumeLabelEntry, ENTRY_TYPE_BITMAP, ENTRY_TYPE_UPCASE,
+};
+use super::fat::ExFatManager;
+use super::upcase::{table_checksum, UpcaseTable};
+use crate::config::MAX_CLUS_SZ;
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::FSError;
+use crate::mkfs::zero_sectors;
+use crate::{BlockDevice, START_CLUS_ID};
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+// 主备启动区域之后, 按 32 扇区对齐
+const FAT_OFFSET: u32 = 32;
+
+/// exFAT 格式化参数, 扇区大小固定为 512 Byte
+#[derive(Copy, Clone, Debug)]
+pub struct ExFatFormatOptions {
+    /// 文件系统占用的总扇区数
+    pub total_sectors: u64,
+    /// 每簇扇区数, 必须是二的整数次幂, None 则按照微软推荐的容量表自动选择
+    pub sectors_per_cluster: Option<u32>,
+    /// 分区在整个磁盘中的起始扇区, 格式化整盘时为 0
+    pub partition_offset: u64,
+    pub volume_serial: u32,
+}
+
+impl ExFatFormatOptions {
+    pub fn new(total_sectors: u64) -> Self {
+        Self {
+            total_sectors,
+            sectors_per_cluster: None,
+            partition_offset: 0,
+            volume_serial: 0,
+        }
+    }
+    // 微软推荐: <= 256MB 用 4KB, <= 32GB 用 32KB, 再大用 128KB, 但不能超过缓存能放下的簇
+    fn sectors_per_cluster(&self) -> u32 {
+        if let Some(n) = self.sectors_per_cluster {
+            return n;
+        }
+        let volume_size = self.total_sectors * FORMAT_SECTOR_SZ as u64;
+        let cluster_size = match volume_size {
+            0..=0x1000_0000 => 4096,
+            0x1000_0001..=0x8_0000_0000 => 32768,
+            _ => 131072,
+        };
+        (cluster_size.min(MAX_CLUS_SZ) / FORMAT_SECTOR_SZ) as u32
+    }
+    fn validate(&self) -> Result<(), FSError> {
+        let sectors_per_cluster = self.sectors_per_cluster();
+        if sectors_per_cluster.count_ones() != 1
+            || sectors_per_cluster as usize * FORMAT_SECTOR_SZ > MAX_CLUS_SZ
+        {
+            return Err(FSError::InvalidInput);
+        }
+        if self.total_sectors <= u64::from(FAT_OFFSET) {
+            return Err(FSError::NotEnoughSpace);
+        }
+        Ok(())
+    }
+    /// 计算 FAT 表扇区数和簇堆起始扇区, 簇堆按簇对齐, FAT 表越大簇越少, 迭代两轮就能收敛
+    fn layout(&self, sectors_per_cluster: u32) -> (u32, u32, u32) {
+        let sectors_per_cluster = u64::from(sectors_per_cluster);
+        let mut fat_length: u64 = 1;
+        loop {
+            let heap_offset =
+                (u64::from(FAT_OFFSET) + fat_length).next_multiple_of(sectors_per_cluster);
+            let clusters = self.total_sectors.saturating_sub(heap_offset) / sectors_per_cluster;
+            let clusters = clusters.min(u64::from(MAX_CLUSTER_COUNT));
+            let needed = ((clusters + START_CLUS_ID as u64) * 4).div_ceil(FORMAT_SECTOR_SZ as u64);
+            if needed <= fat_length {
+                return (fat_length as u32, heap_offset as u32, clusters as u32);
+            }
+            fat_length = needed;
+        }
+    }
+}
+
+/// 按格式化参数在块设备 start_sector 起始处写入启动区域, FAT 表, 分配位图, 大写转换表和空的根目录
+pub(crate) fn format(
+    block_device: Arc<dyn BlockDevice>,
+    start_sector: usize,
+    options: ExFatFormatOptions,
+) -> Result<(), FSError> {
+    options.validate()?;
+    let sectors_per_cluster = options.sectors_per_cluster();
+    let cluster_size = sectors_per_cluster as usize * FORMAT_SECTOR_SZ;
+    let (fat_length, heap_offset, cluster_count) = options.layout(sectors_per_cluster);
+    // 元数据依次放在簇堆开头: 分配位图, 大写转换表, 根目录
+    let bitmap_length = (cluster_count as usize).div_ceil(8);
+    let bitmap_clusters = bitmap_length.div_ceil(cluster_size);
+    let upcase_table = UpcaseTable::default_raw();
+    let upcase_clusters = upcase_table.len().div_ceil(cluster_size);
+    let meta_clusters = bitmap_clusters + upcase_clusters + 1;
+    if (cluster_count as usize) <= meta_clusters {
+        return Err(FSError::NotEnoughSpace);
+    }
+    let bitmap_cluster = START_CLUS_ID as u32;
+    let upcase_cluster = bitmap_cluster + bitmap_clusters as u32;
+    let root_cluster = upcase_cluster + upcase_clusters as u32;
+    let boot_sector = ExFatBootSector::for_format(
+        options.partition_offset,
+        options.total_sectors,
+        FAT_OFFSET,
+        fat_length,
+        heap_offset,
+        cluster_count,
+        root_cluster,
+        options.volume_serial,
+        sectors_per_cluster.trailing_zeros() as u8,
+    );
+
+    // 清空启动区域之后的保留扇区, FAT 表和元数据所在的簇
+    let clear_end = heap_offset as usize + meta_clusters * sectors_per_cluster as usize;
+    zero_sectors(
+        &block_device,
+        start_sector + 2 * BOOT_REGION_SECTORS,
+        clear_end - 2 * BOOT_REGION_SECTORS,
+        FORMAT_SECTOR_SZ,
+    )?;
+    let cluster_sector = |cluster_id: u32| {
+        start_sector
+            + heap_offset as usize
+            + (cluster_id as usize - START_CLUS_ID) * sectors_per_cluster as usize
+    };
+    // 大写转换表
+    let upcase_sectors = upcase_table.len().div_ceil(FORMAT_SECTOR_SZ);
+    let mut sectors: Vec<u8> = vec![0; upcase_sectors * FORMAT_SECTOR_SZ];
+    sectors[..upcase_table.len()].copy_from_slice(&upcase_table);
+    block_device.write_blocks(cluster_sector(upcase_cluster), upcase_sectors, &sectors)?;
+    // 根目录: 空卷名, 分配位图和大写转换表
+    let root_entries = [
+        VolumeLabelEntry::empty().to_raw(),
+        MetadataEntry::new(ENTRY_TYPE_BITMAP, bitmap_cluster, bitmap_length as u64, 0).to_raw(),
+        MetadataEntry::new(
+            ENTRY_TYPE_UPCASE,
+            upcase_cluster,
+            upcase_table.len() as u64,
+            table_checksum(&upcase_table),
+        )
+        .to_raw(),
+    ];
+    let mut sector: Vec<u8> = vec![0; FORMAT_SECTOR_SZ];
+    for (i, entry) in root_entries.iter().enumerate() {
+        sector[i * DIRENT_SZ..(i + 1) * DIRENT_SZ].copy_from_slice(entry);
+    }
+    block_device.write_block(cluster_sector(root_cluster), &sector)?;
+    // FAT 表和分配位图, 离开作用域时缓存写回
+    {
+        let mut fat_manager = ExFatManager::new(
+            boot_sector.cache_geometry(),
+            boot_sector.active_fat_sector(),
+            start_sector,
+            Arc::clone(&block_device),
+        );
+        fat_manager.format_metadata(
+            &[
+                (bitmap_cluster, bitmap_clusters),
+                (upcase_cluster, upcase_clusters),
+                (root_cluster, 1),
+            ],
+            bitmap_length as u64,
+        )?;
+    }
+    // 最后写启动区域, 中途失败的设备不会被当成 exFAT 挂载
+    boot_sector.write_regions(&block_device, start_sector)
+}
@@ -0,0 +1,12 @@
+// exFAT 文件系统, 和 FAT 共用块设备和缓存层, 文件接口和 VFile 一致
+mod boot_sector;
+mod dir_entry;
+mod fat;
+mod fs;
+mod mkfs;
+mod upcase;
+mod vfile;
+
+pub use fs::ExFatFileSystem;
+pub use mkfs::ExFatFormatOptions;
+pub use vfile::ExFatVFile;
@@ -0,0 +1,61 @@
+// exFAT 的大写转换表, 用于文件名不区分大小写的比较和名称哈希
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+// 压缩格式中 0xFFFF 之后的数表示一段恒等映射的长度
+const IDENTITY_RUN: u16 = 0xFFFF;
+
+/// 只保存不是恒等映射的字符, 完整的表展开后有 128KB, 单片机受不了
+pub(crate) struct UpcaseTable {
+    mappings: Vec<(u16, u16)>, // <字符, 大写>, 按字符排序
+}
+
+impl UpcaseTable {
+    /// 从磁盘上(可能压缩过)的表构造
+    pub(crate) fn from_raw(raw: &[u8]) -> Self {
+        let mut mappings: Vec<(u16, u16)> = Vec::new();
+        let mut chars = raw
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+        let mut index: u32 = 0;
+        while let Some(c) = chars.next() {
+            if index > u32::from(u16::MAX) {
+                break;
+            }
+            if c == IDENTITY_RUN {
+                if let Some(run) = chars.next() {
+                    index += u32::from(run);
+                    continue;
+                }
+            }
+            if u32::from(c) != index {
+                mappings.push((index as u16, c));
+            }
+            index += 1;
+        }
+        Self { mappings }
+    }
+    /// 格式化时写入的压缩表, 只转换 ASCII 小写字母, 其余都是恒等映射
+    pub(crate) fn default_raw() -> Vec<u8> {
+        let mut table: Vec<u16> = vec![IDENTITY_RUN, u16::from(b'a')];
+        table.extend(u16::from(b'A')..=u16::from(b'Z'));
+        table.extend([IDENTITY_RUN, (0x1_0000 - u32::from(b'z') - 1) as u16]);
+        table.iter().flat_map(|c| c.to_le_bytes()).collect()
+    }
+    pub(crate) fn to_upper(&self, c: u16) -> u16 {
+        match self.mappings.binary_search_by_key(&c, |m| m.0) {
+            Ok(i) => self.mappings[i].1,
+            Err(_) => c,
+        }
+    }
+    pub(crate) fn upcase(&self, name: &[u16]) -> Vec<u16> {
+        name.iter().map(|c| self.to_upper(*c)).collect()
+    }
+}
+
+/// 大写转换表的校验和, 存放在大写转换表目录项中
+pub(crate) fn table_checksum(raw: &[u8]) -> u32 {
+    raw.iter().fold(0u32, |checksum, byte| {
+        checksum.rotate_right(1).wrapping_add(u32::from(*byte))
+    })
+}
@@ -0,0 +1,374 @@
+// exFAT 的文件和目录, 接口和 FAT 的 VFile 保持一致
+use super::dir_entry::{
+    name_hash, EntrySet, RawEntry, ENTRY_IN_USE, ENTRY_TYPE_END, ENTRY_TYPE_FILE, MAX_NAME_LEN,
+};
+use super::fat::DataStream;
+use super::fs::ExFatFileSystem;
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::FSError;
+use crate::FileAttributes;
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use spin::RwLock;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+// 文件名中不能出现的字符, 另外还有 0x00-0x1F
+const INVALID_NAME_CHARS: [u16; 9] = [
+    b'"' as u16,
+    b'*' as u16,
+    b'/' as u16,
+    b':' as u16,
+    b'<' as u16,
+    b'>' as u16,
+    b'?' as u16,
+    b'\\' as u16,
+    b'|' as u16,
+];
+
+fn is_valid_name(name: &[u16]) -> bool {
+    (1..=MAX_NAME_LEN).contains(&name.len())
+        && name
+            .iter()
+            .all(|c| *c >= 0x20 && !INVALID_NAME_CHARS.contains(c))
+}
+
+/// 对目录项集合的抽象, 根目录没有目录项集合
+#[derive(Clone)]
+pub struct ExFatVFile {
+    name: String,
+    entry_pos: Vec<(usize, usize)>, // 目录项集合中每个目录项的位置<cluster, offset>
+    attribute: FileAttributes,
+    fs: Arc<RwLock<ExFatFileSystem>>,
+}
+
+impl ExFatVFile {
+    pub(crate) fn new(
+        name: String,
+        entry_pos: Vec<(usize, usize)>,
+        attribute: FileAttributes,
+        fs: Arc<RwLock<ExFatFileSystem>>,
+    ) -> Self {
+        Self {
+            name,
+            entry_pos,
+            attribute,
+            fs,
+        }
+    }
+    pub fn is_root(&self) -> bool {
+        self.entry_pos.is_empty()
+    }
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    pub fn attribute(&self) -> FileAttributes {
+        self.attribute
+    }
+    pub fn fs(&self) -> Arc<RwLock<ExFatFileSystem>> {
+        self.fs.clone()
+    }
+    pub fn is_dir(&self) -> bool {
+        self.attribute().contains(FileAttributes::DIRECTORY)
+    }
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+    // 根目录没有目录项集合, 调用前要先排除; 校验和不对时返回 CorruptedFileSystem
+    fn entry_set(&self) -> Result<EntrySet, FSError> {
+        if self.is_root() {
+            return Err(FSError::InvalidInput);
+        }
+        let fs = self.fs.read();
+        let mut raw: Vec<RawEntry> = Vec::new();
+        for (cluster, offset) in self.entry_pos.iter() {
+            raw.push(fs.read_entry(*cluster, *offset)?);
+        }
+        EntrySet::from_raw(&raw)
+    }
+    // 重新计算校验和后写回
+    fn write_entry_set(&self, set: &mut EntrySet) -> Result<(), FSError> {
+        set.update_checksum();
+        let fs = self.fs.read();
+        for (raw, (cluster, offset)) in set.to_raw().iter().zip(self.entry_pos.iter()) {
+            fs.write_entry(*cluster, *offset, raw)?;
+        }
+        Ok(())
+    }
+    fn stream_of(&self, set: &EntrySet) -> DataStream {
+        let cluster_size = self.fs.read().cluster_size();
+        if set.first_cluster() == 0 {
+            return DataStream::default();
+        }
+        DataStream {
+            first_cluster: set.first_cluster(),
+            clusters: (set.data_length() as usize).div_ceil(cluster_size),
+            no_fat_chain: set.no_fat_chain(),
+        }
+    }
+    fn stream(&self) -> Result<DataStream, FSError> {
+        if self.is_root() {
+            return self.fs.read().root_stream();
+        }
+        Ok(self.stream_of(&self.entry_set()?))
+    }
+    /// 文件大小是内容的字节数, 目录大小是已分配的字节数
+    pub fn size(&self) -> Result<usize, FSError> {
+        if self.is_root() {
+            return self.capacity();
+        }
+        Ok(self.entry_set()?.data_length() as usize)
+    }
+    /// 容量就是全部簇的总字节数
+    pub fn capacity(&self) -> Result<usize, FSError> {
+        Ok(self.stream()?.clusters * self.fs.read().cluster_size())
+    }
+    /// 读文件, ValidDataLength 之后的内容按 0 处理; 读目录得到原始目录项
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FSError> {
+        if self.is_dir() {
+            let stream = self.stream()?;
+            return self.fs.read().read_stream(&stream, offset, buf);
+        }
+        let set = self.entry_set()?;
+        let size = set.data_length() as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let end = size.min(offset + buf.len());
+        let valid_end = end.min(set.valid_data_length() as usize).max(offset);
+        let stream = self.stream_of(&set);
+        let read_size =
+            self.fs
+                .read()
+                .read_stream(&stream, offset, &mut buf[..valid_end - offset])?;
+        buf[read_size..end - offset].fill(0);
+        Ok(end - offset)
+    }
+    /// 写文件, 需要时分配新簇, 目录只能通过 create 和 delete 修改
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, FSError> {
+        if self.is_dir() {
+            return Ok(0);
+        }
+        let mut set = self.entry_set()?;
+        let fs = self.fs.read();
+        let cluster_size = fs.cluster_size();
+        let end = offset + buf.len();
+        let mut stream = self.stream_of(&set);
+        let needed = end.div_ceil(cluster_size);
+        if needed > stream.clusters {
+            let num = needed - stream.clusters;
+            fs.fat_manager_modify().alloc_clusters(&mut stream, num)?;
+            set.set_allocation(stream.first_cluster, stream.no_fat_chain);
+        }
+        // 新分配的簇没有清空, 写入位置之前的空洞要补 0
+        let valid = set.valid_data_length() as usize;
+        if offset > valid {
+            let zero: Vec<u8> = vec![0; cluster_size];
+            let mut pos = valid;
+            while pos < offset {
+                let len = cluster_size.min(offset - pos);
+                pos += fs.write_stream(&stream, pos, &zero[..len])?;
+            }
+        }
+        let write_size = fs.write_stream(&stream, offset, buf)?;
+        let size = (set.data_length() as usize).max(offset + write_size);
+        let valid = valid.max(offset + write_size);
+        set.set_length(size as u64, valid as u64);
+        drop(fs);
+        self.write_entry_set(&mut set)?;
+        Ok(write_size)
+    }
+    // 读出整个目录的原始目录项
+    fn read_dir(&self) -> Result<Vec<RawEntry>, FSError> {
+        let mut raw: Vec<u8> = vec![0; self.capacity()?];
+        let stream = self.stream()?;
+        self.fs.read().read_stream(&stream, 0, &mut raw)?;
+        Ok(raw
+            .chunks_exact(DIRENT_SZ)
+            .map(|c| c.try_into().unwrap())
+            .collect())
+    }
+    /// 目录中全部有效的目录项集合, 返回<偏移, 目录项集合>, 校验和错误的集合被跳过
+    fn entry_sets(&self) -> Result<Vec<(usize, EntrySet)>, FSError> {
+        let entries = self.read_dir()?;
+        let mut sets: Vec<(usize, EntrySet)> = Vec::new();
+        let mut i = 0;
+        while i < entries.len() {
+            match entries[i][0] {
+                ENTRY_TYPE_END => break,
+                ENTRY_TYPE_FILE => {
+                    let count = usize::from(entries[i][1]) + 1;
+                    if i + count <= entries.len() {
+                        match EntrySet::from_raw(&entries[i..i + count]) {
+                            Ok(set) => {
+                                sets.push((i * DIRENT_SZ, set));
+                                i += count;
+                                continue;
+                            }
+                            Err(e) => log::warn!("entry set at {} not valid: {:?}", i, e),
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(sets)
+    }
+    // 目录中 offset 开始的 num 个目录项的位置
+    fn entry_positions(&self, offset: usize, num: usize) -> Result<Vec<(usize, usize)>, FSError> {
+        let stream = self.stream()?;
+        let fs = self.fs.read();
+        let cluster_size = fs.cluster_size();
+        let mut positions: Vec<(usize, usize)> = Vec::new();
+        for i in 0..num {
+            let pos = offset + i * DIRENT_SZ;
+            let cluster = fs
+                .fat_manager_modify()
+                .cluster_at(&stream, pos / cluster_size)?
+                .ok_or(FSError::CorruptedFileSystem)?;
+            positions.push((cluster as usize, pos % cluster_size));
+        }
+        Ok(positions)
+    }
+    fn vfile_of(&self, offset: usize, set: &EntrySet) -> Result<ExFatVFile, FSError> {
+        Ok(ExFatVFile::new(
+            String::from_utf16_lossy(&set.name()),
+            self.entry_positions(offset, set.entry_count())?,
+            FileAttributes::from_bits_truncate(set.attributes() as u8),
+            self.fs.clone(),
+        ))
+    }
+    /// 根据名称搜索, 先比较名称哈希, 再用大写转换表做不区分大小写的比较, 搜不到返回 NotFound
+    pub fn find_vfile_byname(&self, name: &str) -> Result<ExFatVFile, FSError> {
+        assert!(self.is_dir());
+        let name_u16: Vec<u16> = name.encode_utf16().collect();
+        let upcased = self.fs.read().upcase().upcase(&name_u16);
+        let hash = name_hash(&upcased);
+        let sets = self.entry_sets()?;
+        let (offset, set) = sets
+            .iter()
+            .find(|(_, set)| {
+                set.name_hash() == hash && self.fs.read().upcase().upcase(&set.name()) == upcased
+            })
+            .ok_or(FSError::NotFound)?;
+        self.vfile_of(*offset, set)
+    }
+    /// 根据路径递归搜索, 以 / 开头是绝对路径, 否则是相对路径
+    pub fn find_vfile_bypath(&self, path: &str) -> Result<Arc<ExFatVFile>, FSError> {
+        let mut current_vfile = if path.starts_with('/') {
+            self.fs.read().root_vfile(&self.fs)
+        } else {
+            self.clone()
+        };
+        for name in path.split('/') {
+            if name.is_empty() || name == "." {
+                continue;
+            }
+            current_vfile = current_vfile.find_vfile_byname(name)?;
+        }
+        Ok(Arc::new(current_vfile))
+    }
+    // 找连续 num 个未使用的目录项, 不够时给目录再分配一个簇
+    fn find_free_entries(&self, num: usize) -> Result<usize, FSError> {
+        loop {
+            let entries = self.read_dir()?;
+            let mut run = 0;
+            for (i, entry) in entries.iter().enumerate() {
+                if entry[0] & ENTRY_IN_USE != 0 {
+                    run = 0;
+                    continue;
+                }
+                run += 1;
+                if run == num {
+                    return Ok((i + 1 - num) * DIRENT_SZ);
+                }
+            }
+            self.grow_dir()?;
+        }
+    }
+    // 目录增加一个清空的簇, 子目录还要修改自己的目录项集合
+    fn grow_dir(&self) -> Result<(), FSError> {
+        let mut stream = self.stream()?;
+        let fs = self.fs.read();
+        let cluster_size = fs.cluster_size();
+        let capacity = stream.clusters * cluster_size;
+        fs.fat_manager_modify().alloc_clusters(&mut stream, 1)?;
+        fs.write_stream(&stream, capacity, &vec![0; cluster_size])?;
+        drop(fs);
+        if !self.is_root() {
+            let mut set = self.entry_set()?;
+            let size = (capacity + cluster_size) as u64;
+            set.set_allocation(stream.first_cluster, stream.no_fat_chain);
+            set.set_length(size, size);
+            self.write_entry_set(&mut set)?;
+        }
+        Ok(())
+    }
+    /// 在当前目录下创建文件或目录, 名字不区分大小写
+    /// 名字不合法时返回 InvalidInput, 已经存在时返回 AlreadyExists
+    pub fn create(
+        &self,
+        filename: &str,
+        attribute: FileAttributes,
+    ) -> Result<Arc<ExFatVFile>, FSError> {
+        assert!(self.is_dir());
+        let name_u16: Vec<u16> = filename.encode_utf16().collect();
+        if !is_valid_name(&name_u16) {
+            return Err(FSError::InvalidInput);
+        }
+        match self.find_vfile_byname(filename) {
+            Ok(_) => return Err(FSError::AlreadyExists),
+            Err(FSError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let upcased = self.fs.read().upcase().upcase(&name_u16);
+        let mut set = EntrySet::new(&name_u16, name_hash(&upcased), u16::from(attribute.bits()));
+        let offset = self.find_free_entries(set.entry_count())?;
+        // 文件一开始不分配簇, 目录分配一个清空的簇
+        if attribute.contains(FileAttributes::DIRECTORY) {
+            let fs = self.fs.read();
+            let cluster_size = fs.cluster_size();
+            let mut stream = DataStream::default();
+            fs.fat_manager_modify().alloc_clusters(&mut stream, 1)?;
+            fs.write_stream(&stream, 0, &vec![0; cluster_size])?;
+            set.set_allocation(stream.first_cluster, stream.no_fat_chain);
+            set.set_length(cluster_size as u64, cluster_size as u64);
+        }
+        let vfile = self.vfile_of(offset, &set)?;
+        vfile.write_entry_set(&mut set)?;
+        Ok(Arc::new(vfile))
+    }
+    /// 删除文件或目录自己, 和 FAT 一样不递归删除目录中的内容, 返回回收的簇数
+    pub fn delete(&self) -> Result<usize, FSError> {
+        if self.is_root() {
+            return Ok(0);
+        }
+        let stream = self.stream_of(&self.entry_set()?);
+        let fs = self.fs.read();
+        for (cluster, offset) in self.entry_pos.iter() {
+            let mut raw = fs.read_entry(*cluster, *offset)?;
+            raw[0] &= !ENTRY_IN_USE;
+            fs.write_entry(*cluster, *offset, &raw)?;
+        }
+        let count = fs.fat_manager_modify().dealloc_clusters(&stream)?;
+        Ok(count)
+    }
+    /// 列出目录内容, 对文件调用返回 InvalidInput
+    pub fn ls(&self) -> Result<Vec<(String, FileAttributes)>, FSError> {
+        if self.is_file() {
+            return Err(FSError::InvalidInput);
+        }
+        let list = self
+            .entry_sets()?
+            .iter()
+            .map(|(_, set)| {
+                (
+                    String::from_utf16_lossy(&set.name()),
+                    FileAttributes::from_bits_truncate(set.attributes() as u8),
+                )
+            })
+            .collect();
+        Ok(list)
+    }
+}
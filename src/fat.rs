@@ -1,15 +1,61 @@
 // FAT 表结构体
 use super::{
-    BiosParameterBlock, BlockDevice, FSInfo, FSInfoSector, SectorCacheManager, START_CLUS_ID,
+    BiosParameterBlock, BlockDevice, FSError, FSInfo, FSInfoSector, SectorCacheManager,
+    START_CLUS_ID,
 };
+use crate::cluster_walk::{ChainWalk, ContiguousRun, CountClusters, SearchCluster};
 #[cfg(not(feature = "std"))]
 use alloc::{sync::Arc, vec::Vec};
 #[cfg(feature = "std")]
 use std::sync::Arc;
 
-const BYTES_PER_ENTRY: usize = 4;
-const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
-const FINAL_CLUSTER: u32 = 0x0FFF_FFFF;
+/// FAT16 最少需要 4085 个簇, 更少的是 FAT12
+pub(crate) const FAT16_MIN_CLUSTERS: u32 = 4085;
+/// FAT32 最少需要 65525 个簇, 更少的是 FAT16
+pub(crate) const FAT32_MIN_CLUSTERS: u32 = 65525;
+pub(crate) const FAT32_MAX_CLUSTERS: u32 = 0x0FFF_FFF4;
+
+/// FAT 类型, 按照微软规范只由数据区的簇数决定, 与 BPB 中的类型名无关
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    pub fn from_clusters(total_clusters: u32) -> Self {
+        if total_clusters < FAT16_MIN_CLUSTERS {
+            FatType::Fat12
+        } else if total_clusters < FAT32_MIN_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+    /// FAT 表项的位数
+    pub fn bits_per_entry(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+    // FAT32 表项高 4 位保留, 实际只有 28 位
+    fn entry_mask(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0FFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+    fn bad_cluster(&self) -> u32 {
+        self.entry_mask() - 8
+    }
+    pub(crate) fn final_cluster(&self) -> u32 {
+        self.entry_mask()
+    }
+}
 
 /// The high 4 bits of a FAT32 FAT entry are reserved.
 /// No FAT32 volume should ever be configured containing cluster numbers available for
@@ -23,25 +69,42 @@ pub enum FATEntry {
     Next(u32),
 }
 
-/// 管理 FAT 和 FSINFO
+/// 管理 FAT 和 FSINFO, FAT12/16 没有 FSINFO, 只在内存中维护
 pub struct FATManager {
     fsinfo: FSInfo,
+    fat_type: FatType,
     bpb: Arc<BiosParameterBlock>,
     sector_cache: SectorCacheManager,
+    start_sector: usize,
+    block_device: Arc<dyn BlockDevice>,
+    discard: bool, // 回收簇时通知块设备
 }
 
 impl FATManager {
     pub fn new(
         fsinfo: FSInfo,
         bpb: Arc<BiosParameterBlock>,
+        start_sector: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
         Self {
+            fat_type: bpb.fat_type(),
             bpb: Arc::clone(&bpb),
             fsinfo,
-            sector_cache: SectorCacheManager::new(bpb, block_device),
+            sector_cache: SectorCacheManager::new(
+                bpb.cache_geometry(),
+                start_sector,
+                Arc::clone(&block_device),
+            ),
+            start_sector,
+            block_device,
+            discard: false,
         }
     }
+    /// 打开后 dealloc_clusters 回收的簇会通过 BlockDevice::discard 通知块设备
+    pub fn set_discard(&mut self, discard: bool) {
+        self.discard = discard;
+    }
     /// 返回 None 只是代表不确定而已
     pub fn next_free_cluster(&self) -> Option<u32> {
         self.fsinfo.next_free_cluster()
@@ -53,59 +116,169 @@ impl FATManager {
     pub fn fsinfo(&self) -> FSInfo {
         self.fsinfo
     }
-    fn entrys_per_sector(&self) -> usize {
-        self.bpb.bytes_per_sector() as usize / BYTES_PER_ENTRY
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+    // 表项在 FAT 表中的字节偏移, FAT12 每两个表项占 3 Byte
+    fn entry_offset(&self, cluster_id: usize) -> usize {
+        match self.fat_type {
+            FatType::Fat12 => cluster_id + cluster_id / 2,
+            FatType::Fat16 => cluster_id * 2,
+            FatType::Fat32 => cluster_id * 4,
+        }
+    }
+    // 表项在第 fat 个 FAT 表中的扇区和扇区内偏移
+    fn position(&self, fat: u8, cluster_id: usize) -> (usize, usize) {
+        let sector_size = self.bpb.bytes_per_sector() as usize;
+        let entry_offset = self.entry_offset(cluster_id);
+        let fat_sector = self.bpb.fat_start_sector(fat) as usize + entry_offset / sector_size;
+        (fat_sector, entry_offset % sector_size)
+    }
+    // FAT12 表项可能跨扇区, 第二个字节在下一个扇区开头
+    fn next_byte_pos(&self, sector_id: usize, offset: usize) -> (usize, usize) {
+        if offset + 1 == self.bpb.bytes_per_sector() as usize {
+            (sector_id + 1, 0)
+        } else {
+            (sector_id, offset + 1)
+        }
+    }
+    /// 读 cluster_id 表项要用到的活动 FAT 表扇区, FAT12 表项跨扇区时有两个
+    pub(crate) fn entry_sectors(&self, cluster_id: usize) -> (usize, Option<usize>) {
+        let (sector_id, offset) = self.position(self.bpb.active_fat(), cluster_id);
+        let (next_sector, _) = self.next_byte_pos(sector_id, offset);
+        if self.fat_type == FatType::Fat12 && next_sector != sector_id {
+            (sector_id, Some(next_sector))
+        } else {
+            (sector_id, None)
+        }
     }
-    fn position(&self, cluster_id: usize) -> (usize, usize, usize) {
-        let fat_sector: usize =
-            self.bpb.first_fats_sector() as usize + (cluster_id / self.entrys_per_sector());
-        let backup_fat_sector: usize =
-            self.bpb.first_backup_fats_sector() as usize + (cluster_id / self.entrys_per_sector());
-        let offset = BYTES_PER_ENTRY * (cluster_id % self.entrys_per_sector());
-        (fat_sector, backup_fat_sector, offset)
+    pub(crate) fn sector_cache(&mut self) -> &mut SectorCacheManager {
+        &mut self.sector_cache
     }
-    fn entry_raw(&mut self, cluster_id: usize) -> u32 {
-        let (sector_id, _, offset) = self.position(cluster_id);
-        let sector = self.sector_cache.get_cache(sector_id);
-        let entry_raw = sector.read().read(offset, |e: &u32| *e);
-        entry_raw
+    fn read_u8(&mut self, sector_id: usize, offset: usize) -> Result<u8, FSError> {
+        let sector = self.sector_cache.get_cache(sector_id)?;
+        let byte = sector.read().read(offset, |e: &u8| *e);
+        Ok(byte)
     }
-    pub fn entry(&mut self, cluster_id: usize) -> FATEntry {
+    fn write_u8(&mut self, sector_id: usize, offset: usize, value: u8) -> Result<(), FSError> {
+        let sector = self.sector_cache.get_cache(sector_id)?;
+        sector.write().modify(offset, |e: &mut u8| *e = value);
+        Ok(())
+    }
+    fn read_entry_in(
+        &mut self,
+        cluster_id: usize,
+        sector_id: usize,
+        offset: usize,
+    ) -> Result<u32, FSError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let (next_sector, next_offset) = self.next_byte_pos(sector_id, offset);
+                let low = self.read_u8(sector_id, offset)?;
+                let high = self.read_u8(next_sector, next_offset)?;
+                let value = u16::from_le_bytes([low, high]);
+                // 奇数簇在高 12 位, 偶数簇在低 12 位
+                if cluster_id & 1 == 1 {
+                    Ok(u32::from(value >> 4))
+                } else {
+                    Ok(u32::from(value & 0x0FFF))
+                }
+            }
+            FatType::Fat16 => {
+                let sector = self.sector_cache.get_cache(sector_id)?;
+                let entry_raw = sector.read().read(offset, |e: &u16| *e);
+                Ok(u32::from(entry_raw))
+            }
+            FatType::Fat32 => {
+                let sector = self.sector_cache.get_cache(sector_id)?;
+                let entry_raw = sector.read().read(offset, |e: &u32| *e);
+                Ok(entry_raw)
+            }
+        }
+    }
+    fn write_entry_in(
+        &mut self,
+        cluster_id: usize,
+        sector_id: usize,
+        offset: usize,
+        value: u32,
+    ) -> Result<(), FSError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let (next_sector, next_offset) = self.next_byte_pos(sector_id, offset);
+                let low = self.read_u8(sector_id, offset)?;
+                let high = self.read_u8(next_sector, next_offset)?;
+                let old = u16::from_le_bytes([low, high]);
+                // 只改动属于自己的 12 位, 另外 4 位属于相邻表项
+                let value = (value & 0x0FFF) as u16;
+                let new = if cluster_id & 1 == 1 {
+                    (old & 0x000F) | (value << 4)
+                } else {
+                    (old & 0xF000) | value
+                };
+                let [low, high] = new.to_le_bytes();
+                self.write_u8(sector_id, offset, low)?;
+                self.write_u8(next_sector, next_offset, high)?;
+            }
+            FatType::Fat16 => {
+                let sector = self.sector_cache.get_cache(sector_id)?;
+                sector
+                    .write()
+                    .modify(offset, |e: &mut u16| *e = value as u16);
+            }
+            FatType::Fat32 => {
+                let sector = self.sector_cache.get_cache(sector_id)?;
+                sector.write().modify(offset, |e: &mut u32| *e = value);
+            }
+        }
+        Ok(())
+    }
+    // 只读活动 FAT 表, 镜像时就是第一个 FAT 表
+    fn entry_raw(&mut self, cluster_id: usize) -> Result<u32, FSError> {
+        let (sector_id, offset) = self.position(self.bpb.active_fat(), cluster_id);
+        self.read_entry_in(cluster_id, sector_id, offset)
+    }
+    pub fn entry(&mut self, cluster_id: usize) -> Result<FATEntry, FSError> {
         assert!(
             cluster_id <= self.bpb.total_clusters() as usize + START_CLUS_ID,
             "Invalid Cluster ID in FAT {}",
             cluster_id
         );
-        let entry_raw = self.entry_raw(cluster_id) & 0x0FFF_FFFF;
-        match entry_raw {
-            0 if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster_id) => FATEntry::Bad, // avoid accidental use or allocation into a FAT chain
+        let bad_cluster = self.fat_type.bad_cluster();
+        let final_cluster = self.fat_type.final_cluster();
+        let special_ids = bad_cluster as usize..=final_cluster as usize;
+        let entry_raw = self.entry_raw(cluster_id)? & self.fat_type.entry_mask();
+        Ok(match entry_raw {
+            0 if special_ids.contains(&cluster_id) => FATEntry::Bad, // avoid accidental use or allocation into a FAT chain
             0 => FATEntry::Free,
-            0x0FFF_FFF7 => FATEntry::Bad,
-            0x0FFF_FFF8..=0x0FFF_FFFF => FATEntry::End,
-            _n if (0x0FFF_FFF7..=0x0FFF_FFFF).contains(&cluster_id) => FATEntry::Bad, // avoid accidental use or allocation into a FAT chain
+            n if n == bad_cluster => FATEntry::Bad,
+            n if n > bad_cluster => FATEntry::End,
+            _n if special_ids.contains(&cluster_id) => FATEntry::Bad, // avoid accidental use or allocation into a FAT chain
             n => FATEntry::Next(n),
-        }
+        })
     }
-    fn set_entry_raw(&mut self, cluster_id: usize, value: u32) {
-        let (sector_id, backup_sector_id, offset) = self.position(cluster_id);
-        // FAT1
-        let sector = self.sector_cache.get_cache(sector_id);
-        sector.write().modify(offset, |e: &mut u32| *e = value);
-        // FAT2
-        let backup_sector = self.sector_cache.get_cache(backup_sector_id);
-        backup_sector
-            .write()
-            .modify(offset, |e: &mut u32| *e = value);
+    // 镜像时写所有 FAT 表, 否则只写活动 FAT 表
+    fn set_entry_raw(&mut self, cluster_id: usize, value: u32) -> Result<(), FSError> {
+        for fat in self.bpb.written_fats() {
+            let (sector_id, offset) = self.position(fat, cluster_id);
+            self.write_entry_in(cluster_id, sector_id, offset, value)?;
+        }
+        Ok(())
     }
-    pub fn set_entry(&mut self, cluster_id: usize, entry: FATEntry) {
+    pub fn set_entry(&mut self, cluster_id: usize, entry: FATEntry) -> Result<(), FSError> {
         assert!(
             ((cluster_id <= self.bpb.total_clusters() as usize + START_CLUS_ID)
                 && (cluster_id >= START_CLUS_ID)),
             "Invalid Cluster ID in FAT"
         );
-        let old_reserved_bits = self.entry_raw(cluster_id) & 0xF000_0000;
-        if entry == FATEntry::Free && cluster_id >= 0x0FFF_FFF7 && cluster_id <= 0x0FFF_FFFF {
-            let tmp = if cluster_id == 0x0FFF_FFF7 {
+        let bad_cluster = self.fat_type.bad_cluster();
+        let final_cluster = self.fat_type.final_cluster();
+        let old_reserved_bits = self.entry_raw(cluster_id)? & !self.fat_type.entry_mask();
+        if entry == FATEntry::Free
+            && cluster_id >= bad_cluster as usize
+            && cluster_id <= final_cluster as usize
+        {
+            let tmp = if cluster_id == bad_cluster as usize {
                 "BAD_CLUSTER"
             } else {
                 "End-of-Chain"
@@ -117,80 +290,91 @@ impl FATManager {
         };
         let value = match entry {
             FATEntry::Free => 0,
-            FATEntry::Bad => BAD_CLUSTER,
-            FATEntry::End => FINAL_CLUSTER,
+            FATEntry::Bad => bad_cluster,
+            FATEntry::End => final_cluster,
             FATEntry::Next(n) => n,
         };
         let value = value | old_reserved_bits; // must preserve original reserved values
-        self.set_entry_raw(cluster_id, value);
+        self.set_entry_raw(cluster_id, value)
     }
-    pub fn next_cluster(&mut self, cluster_id: usize) -> Option<usize> {
-        let val = self.entry(cluster_id);
+    pub fn next_cluster(&mut self, cluster_id: usize) -> Result<Option<usize>, FSError> {
+        let val = self.entry(cluster_id)?;
         match val {
-            FATEntry::Next(n) => Some(n as usize),
-            _ => None,
+            FATEntry::Next(n) => Ok(Some(n as usize)),
+            _ => Ok(None),
         }
     }
-    pub fn set_next_cluster(&mut self, cluster_id: usize, next_cluster: u32) {
-        self.set_entry(cluster_id, FATEntry::Next(next_cluster));
+    pub fn set_next_cluster(
+        &mut self,
+        cluster_id: usize,
+        next_cluster: u32,
+    ) -> Result<(), FSError> {
+        self.set_entry(cluster_id, FATEntry::Next(next_cluster))
     }
-    pub fn set_end(&mut self, cluster_id: usize) {
-        self.set_entry(cluster_id, FATEntry::End);
+    pub fn set_end(&mut self, cluster_id: usize) -> Result<(), FSError> {
+        self.set_entry(cluster_id, FATEntry::End)
     }
-    pub fn set_free(&mut self, cluster_id: usize) {
-        self.set_entry(cluster_id, FATEntry::Free);
+    pub fn set_free(&mut self, cluster_id: usize) -> Result<(), FSError> {
+        self.set_entry(cluster_id, FATEntry::Free)
     }
-    pub fn set_bad(&mut self, cluster_id: usize) {
-        self.set_entry(cluster_id, FATEntry::Bad);
+    pub fn set_bad(&mut self, cluster_id: usize) -> Result<(), FSError> {
+        self.set_entry(cluster_id, FATEntry::Bad)
     }
-    pub fn final_cluster(&mut self, start_cluster: usize) -> usize {
+    pub fn final_cluster(&mut self, start_cluster: usize) -> Result<usize, FSError> {
         let mut curr_cluster = start_cluster;
         // assert_ne!(start_cluster, 0);
         loop {
-            if let Some(next_cluster) = self.next_cluster(curr_cluster) {
+            if let Some(next_cluster) = self.next_cluster(curr_cluster)? {
                 curr_cluster = next_cluster;
             } else {
-                return curr_cluster & 0x0FFFFFFF;
+                return Ok(curr_cluster & 0x0FFFFFFF);
             }
         }
     }
-    pub fn last_cluster(&mut self, start_cluster: usize) -> usize {
+    pub fn last_cluster(&mut self, start_cluster: usize) -> Result<usize, FSError> {
         let mut curr_cluster = start_cluster;
         loop {
-            if let Some(next_cluster) = self.next_cluster(curr_cluster) {
+            if let Some(next_cluster) = self.next_cluster(curr_cluster)? {
                 curr_cluster = next_cluster;
             } else {
-                return curr_cluster;
+                return Ok(curr_cluster);
             }
         }
     }
-    pub fn all_clusters(&mut self, start_cluster: usize) -> Vec<usize> {
+    pub fn all_clusters(&mut self, start_cluster: usize) -> Result<Vec<usize>, FSError> {
         let mut curr_cluster = start_cluster;
         let mut clusters: Vec<usize> = Vec::new();
         loop {
             clusters.push(curr_cluster & 0x0FFFFFFF);
-            if let Some(next_cluster) = self.next_cluster(curr_cluster) {
+            if let Some(next_cluster) = self.next_cluster(curr_cluster)? {
                 curr_cluster = next_cluster;
             } else {
-                return clusters;
+                return Ok(clusters);
             }
         }
     }
-    pub fn count_clusters(&mut self, start_cluster: usize) -> usize {
-        let mut curr_cluster = start_cluster;
-        let mut num = 0;
-        loop {
-            num += 1;
-            if let Some(next_cluster) = self.next_cluster(curr_cluster) {
-                curr_cluster = next_cluster;
-            } else {
-                return num;
-            }
+    /// 从 cluster_id 开始沿簇链数出最多 max 个物理上连续的簇, 返回个数和这一段之后的下一个簇
+    pub fn contiguous_run(
+        &mut self,
+        cluster_id: usize,
+        max: usize,
+    ) -> Result<(usize, Option<usize>), FSError> {
+        self.walk(ContiguousRun::new(cluster_id, max))
+    }
+    pub fn count_clusters(&mut self, start_cluster: usize) -> Result<usize, FSError> {
+        self.walk(CountClusters::new(start_cluster))
+    }
+    /// 同步查表走完簇链, 异步路径在 async_fs 中等待 FAT 扇区加载后走同样的步骤
+    pub(crate) fn walk<W: ChainWalk>(&mut self, mut walk: W) -> Result<W::Output, FSError> {
+        while let Some(cluster_id) = walk.pending() {
+            let next = self.next_cluster(cluster_id)?;
+            walk.advance(next);
         }
+        Ok(walk.finish())
     }
     /// 在 FSINFO 没有提供的情况下使用, 返回 None 代表没有空闲簇了
     /// 如果 FSINFO 中有空簇且 id > start_cluster 就返回空簇, 否则没有就从起始簇开始线性搜索
-    pub fn search_free_cluster(&mut self, start_cluster: usize) -> Option<u32> {
+    pub fn search_free_cluster(&mut self, start_cluster: usize) -> Result<Option<u32>, FSError> {
         let end_cluster = self.bpb.total_clusters() as usize + START_CLUS_ID;
         assert!(
             start_cluster < end_cluster,
@@ -199,13 +383,13 @@ impl FATManager {
         let next = self.fsinfo.next_free_cluster();
         if next.is_some() && next.unwrap() > (start_cluster as u32) {
             // println!("free cluster {:?}, from fsinfo", next);
-            return next;
+            Ok(next)
         } else {
             // 从当前搜到末尾
             let mut cluster_id = start_cluster;
             while cluster_id < end_cluster {
-                if self.entry(cluster_id) == FATEntry::Free {
-                    return Some(cluster_id as u32);
+                if self.entry(cluster_id)? == FATEntry::Free {
+                    return Ok(Some(cluster_id as u32));
                 }
                 cluster_id += 1;
             }
@@ -213,34 +397,39 @@ impl FATManager {
             let end_cluster = start_cluster;
             cluster_id = START_CLUS_ID;
             while cluster_id < end_cluster {
-                if self.entry(cluster_id) == FATEntry::Free {
-                    return Some(cluster_id as u32);
+                if self.entry(cluster_id)? == FATEntry::Free {
+                    return Ok(Some(cluster_id as u32));
                 }
                 cluster_id += 1;
             }
-            return None;
+            Ok(None)
         }
     }
     /// 在 FSINFO 没有提供的情况下使用, 返回 0 代表没有空闲簇了
-    pub fn count_free_clusters(&mut self) -> u32 {
+    pub fn count_free_clusters(&mut self) -> Result<u32, FSError> {
         if let Some(num) = self.fsinfo.free_clusters() {
-            return num;
+            Ok(num)
         } else {
             // 从开始搜到末尾
             let mut cluster_id = START_CLUS_ID;
             let mut num = 0;
             let end_cluster = self.bpb.total_clusters() as usize + START_CLUS_ID;
             while cluster_id < end_cluster {
-                if self.entry(cluster_id) == FATEntry::Free {
+                if self.entry(cluster_id)? == FATEntry::Free {
                     num += 1;
                 }
                 cluster_id += 1;
             }
-            return num;
+            Ok(num)
         }
     }
     /// 创建文件系统时调用, 很多阴逼文件系统关闭时不回写, 泪目
-    pub fn recalculate_fsinfo(&mut self) {
+    /// 空闲簇数未知(FAT12/16 没有 FSINFO)时扫描整个 FAT 表
+    pub fn recalculate_fsinfo(&mut self) -> Result<(), FSError> {
+        if self.fsinfo.free_clusters().is_none() {
+            let num = self.count_free_clusters()?;
+            self.fsinfo.set_free_cluster_count(Some(num));
+        }
         let mut cluster_id = START_CLUS_ID;
         // let mut num = 0;
         let end_cluster = self.bpb.total_clusters() as usize + START_CLUS_ID;
@@ -254,16 +443,16 @@ impl FATManager {
         // self.fsinfo.set_free_cluster_count(Some(num));
         // next
         if let Some(next) = self.fsinfo.next_free_cluster() {
-            if self.entry(next as usize) != FATEntry::Free {
+            if self.entry(next as usize)? != FATEntry::Free {
                 // 从当前搜到末位
-                let new_next = self.search_free_cluster(next as usize);
+                let new_next = self.search_free_cluster(next as usize)?;
                 self.fsinfo.set_next_free_cluster(new_next);
             }
         } else {
             // 从开始搜到末位
             cluster_id = START_CLUS_ID;
             while cluster_id < end_cluster {
-                if self.entry(cluster_id) == FATEntry::Free {
+                if self.entry(cluster_id)? == FATEntry::Free {
                     break;
                 }
                 cluster_id += 1;
@@ -272,70 +461,134 @@ impl FATManager {
                 self.fsinfo.set_next_free_cluster(Some(cluster_id as u32));
             }
         }
+        Ok(())
     }
-    /// 只是返回可以使用的簇 ID, 不对簇清零或者提供别的功能
-    pub fn alloc_cluster(&mut self, prev: Option<u32>) -> Option<u32> {
-        if let Some(free_id) = self.search_free_cluster(START_CLUS_ID) {
-            self.set_entry(free_id as usize, FATEntry::End);
+    /// 只是返回可以使用的簇 ID, 不对簇清零或者提供别的功能, 没有空闲簇时返回 NotEnoughSpace
+    pub fn alloc_cluster(&mut self, prev: Option<u32>) -> Result<u32, FSError> {
+        if let Some(free_id) = self.search_free_cluster(START_CLUS_ID)? {
+            self.set_entry(free_id as usize, FATEntry::End)?;
             if let Some(prev) = prev {
-                self.set_entry(prev as usize, FATEntry::Next(free_id));
+                self.set_entry(prev as usize, FATEntry::Next(free_id))?;
             }
-            let next_free = self.search_free_cluster(free_id as usize);
+            let next_free = self.search_free_cluster(free_id as usize)?;
             self.fsinfo.set_next_free_cluster(next_free);
             self.fsinfo.map_free_clusters(|n| n - 1);
-            return Some(free_id);
+            Ok(free_id)
         } else {
-            return None;
+            Err(FSError::NotEnoughSpace)
         }
     }
-    /// 只是返回可以使用的第一个簇 ID, 如果需要的簇不够, 就直接返回 None
-    pub fn alloc_clusters(&mut self, num: usize, prev: Option<u32>) -> Option<u32> {
+    /// 只是返回可以使用的第一个簇 ID, 如果需要的簇不够, 就直接返回 NotEnoughSpace
+    pub fn alloc_clusters(&mut self, num: usize, prev: Option<u32>) -> Result<u32, FSError> {
+        if num == 0 {
+            return Err(FSError::InvalidInput);
+        }
         let free_clusters = self.fsinfo.free_clusters();
         if free_clusters.is_some() && num <= free_clusters.unwrap() as usize {
             let mut prev_id = prev;
             let mut first: Option<u32> = None;
             for i in 0..num {
-                prev_id = self.alloc_cluster(prev_id);
+                prev_id = Some(self.alloc_cluster(prev_id)?);
                 if i == 0 {
                     first = prev_id;
                 }
             }
-            return first;
+            first.ok_or(FSError::InvalidInput)
         } else {
-            return None;
+            Err(FSError::NotEnoughSpace)
         }
     }
+    /// 分配 num 个连续的簇并连成簇链, 找不到足够长的连续空闲簇时返回 NotEnoughSpace
+    pub fn alloc_contiguous_clusters(&mut self, num: usize) -> Result<u32, FSError> {
+        let end_cluster = self.bpb.total_clusters() as usize + START_CLUS_ID;
+        let mut run_start = START_CLUS_ID;
+        let mut run_len = 0;
+        let mut cluster_id = START_CLUS_ID;
+        while cluster_id < end_cluster && run_len < num {
+            if self.entry(cluster_id)? == FATEntry::Free {
+                if run_len == 0 {
+                    run_start = cluster_id;
+                }
+                run_len += 1;
+            } else {
+                run_len = 0;
+            }
+            cluster_id += 1;
+        }
+        if num == 0 {
+            return Err(FSError::InvalidInput);
+        }
+        if run_len < num {
+            return Err(FSError::NotEnoughSpace);
+        }
+        let run_end = run_start + num;
+        for id in run_start..run_end - 1 {
+            self.set_entry(id, FATEntry::Next(id as u32 + 1))?;
+        }
+        self.set_entry(run_end - 1, FATEntry::End)?;
+        // FSInfo 记录的下一个空闲簇可能已经被分配了
+        self.fsinfo.set_next_free_cluster(None);
+        let next_free = self.search_free_cluster(if run_end < end_cluster {
+            run_end
+        } else {
+            START_CLUS_ID
+        })?;
+        self.fsinfo.set_next_free_cluster(next_free);
+        self.fsinfo.map_free_clusters(|n| n - num as u32);
+        Ok(run_start as u32)
+    }
     /// 如果这个簇不是簇链中最后一个簇也会删除, 悬空后自己负责, 成功返回下一个要删除的 id
     /// 如果要删除的簇本身就是空的或者坏的或者最后一个,则返回None
-    pub fn dealloc_cluster(&mut self, cluster_id: usize, prev: Option<u32>) -> Option<u32> {
-        let entry = self.entry(cluster_id);
+    pub fn dealloc_cluster(
+        &mut self,
+        cluster_id: usize,
+        prev: Option<u32>,
+    ) -> Result<Option<u32>, FSError> {
+        let entry = self.entry(cluster_id)?;
         match entry {
-            FATEntry::Free => None,
-            FATEntry::Bad => None,
+            FATEntry::Free => Ok(None),
+            FATEntry::Bad => Ok(None),
             FATEntry::End => {
-                self.set_entry(cluster_id, FATEntry::Free);
+                self.set_entry(cluster_id, FATEntry::Free)?;
                 if let Some(prev) = prev {
-                    self.set_entry(prev as usize, FATEntry::End);
+                    self.set_entry(prev as usize, FATEntry::End)?;
                 }
                 self.fsinfo.map_free_clusters(|n| n + 1);
-                None
+                Ok(None)
             }
             FATEntry::Next(next_cluster) => {
-                self.set_entry(cluster_id as usize, FATEntry::Free);
+                self.set_entry(cluster_id, FATEntry::Free)?;
                 if let Some(prev) = prev {
-                    self.set_entry(prev as usize, FATEntry::End);
+                    self.set_entry(prev as usize, FATEntry::End)?;
                 }
                 self.fsinfo.map_free_clusters(|n| n + 1);
-                Some(next_cluster)
+                Ok(Some(next_cluster))
             }
         }
     }
     /// 返回真正回收的簇数量
-    pub fn dealloc_clusters(&mut self, first_cluster: usize, mut prev: Option<u32>) -> usize {
+    pub fn dealloc_clusters(
+        &mut self,
+        first_cluster: usize,
+        mut prev: Option<u32>,
+    ) -> Result<usize, FSError> {
         let mut num = 0;
         let mut recycle_cluster = first_cluster;
+        // 打开 discard 时把回收的簇按物理上连续的段记下来, (起始簇, 簇数)
+        let mut runs: Vec<(usize, usize)> = Vec::new();
         loop {
-            let next_cluster = self.dealloc_cluster(recycle_cluster, prev);
+            if self.discard
+                && matches!(
+                    self.entry(recycle_cluster)?,
+                    FATEntry::End | FATEntry::Next(_)
+                )
+            {
+                match runs.last_mut() {
+                    Some((start, len)) if *start + *len == recycle_cluster => *len += 1,
+                    _ => runs.push((recycle_cluster, 1)),
+                }
+            }
+            let next_cluster = self.dealloc_cluster(recycle_cluster, prev)?;
             if next_cluster.is_none() {
                 break;
             } else {
@@ -344,19 +597,27 @@ impl FATManager {
                 num += 1;
             }
         }
-        num
+        self.discard_clusters(&runs);
+        Ok(num)
     }
-    /// 返回簇链中第 n 个元素的 id
-    pub fn search_cluster(&mut self, chain_start_cluster: usize, index: usize) -> Option<usize> {
-        let mut curr_cluster = chain_start_cluster;
-        for _ in 0..index {
-            if let Some(next_cluster) = self.next_cluster(curr_cluster) {
-                curr_cluster = next_cluster;
-            } else {
-                return None;
+    // discard 只是提示, 失败不影响回收
+    fn discard_clusters(&self, runs: &[(usize, usize)]) {
+        let geometry = self.bpb.cache_geometry();
+        for (first_cluster, count) in runs {
+            let start = self.start_sector + geometry.cluster_sector(*first_cluster);
+            let end = start + count * geometry.sectors_per_cluster;
+            if let Err(e) = self.block_device.discard(start..end) {
+                log::warn!("discard sectors {}..{} failed: {:?}", start, end, e);
             }
         }
-        return Some(curr_cluster);
+    }
+    /// 返回簇链中第 n 个元素的 id
+    pub fn search_cluster(
+        &mut self,
+        chain_start_cluster: usize,
+        index: usize,
+    ) -> Result<Option<usize>, FSError> {
+        self.walk(SearchCluster::new(chain_start_cluster, index))
     }
     // /// 从提供的 cluster_id 开始截断分配的簇链, 并把后面的簇都归还
     // pub fn truncate_cluster_chain(&mut self, cluster_id: u32) {
@@ -366,20 +627,53 @@ impl FATManager {
     // pub fn free_cluster_chain(&mut self, cluster_id: u32) {
     //     self.fsinfo.map_free_clusters(|n| n + num_free);
     // }
-    /// 同步 FSINFO 回外存
-    pub fn sync_fsinfo(&mut self) {
+    /// 把活动 FAT 表整个复制到其余 FAT 表, 用于修复后重新同步
+    pub fn mirror_fats(&mut self) -> Result<(), FSError> {
+        let active_fat = self.bpb.active_fat();
+        let sector_size = self.bpb.bytes_per_sector() as usize;
+        let word_size = core::mem::size_of::<u32>();
+        for fat in (0..self.bpb.fats_number()).filter(|fat| *fat != active_fat) {
+            for i in 0..self.bpb.fats_sectors() {
+                let src = self
+                    .sector_cache
+                    .get_cache((self.bpb.fat_start_sector(active_fat) + i) as usize)?;
+                let dst = self
+                    .sector_cache
+                    .get_cache((self.bpb.fat_start_sector(fat) + i) as usize)?;
+                for offset in (0..sector_size).step_by(word_size) {
+                    let word = src.read().read(offset, |w: &u32| *w);
+                    dst.write().modify(offset, |w: &mut u32| *w = word);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 写回 FSINFO 和缓存中修改过的 FAT 表扇区
+    pub fn sync(&mut self) -> Result<(), FSError> {
+        self.sync_fsinfo()?;
+        self.sector_cache.info_cache_sync_all()?;
+        Ok(())
+    }
+    /// 同步 FSINFO 回外存, FAT12/16 没有 FSINFO 扇区
+    pub fn sync_fsinfo(&mut self) -> Result<(), FSError> {
+        if self.fat_type != FatType::Fat32 {
+            return Ok(());
+        }
         let fsinfo_sector = FSInfoSector::from_fsinfo(self.fsinfo);
         let cache = self
             .sector_cache
-            .get_cache(self.bpb.fsinfo_sector() as usize);
+            .get_cache(self.bpb.fsinfo_sector() as usize)?;
         cache
             .write()
             .modify(0, |s: &mut FSInfoSector| *s = fsinfo_sector);
+        Ok(())
     }
 }
 
 impl Drop for FATManager {
     fn drop(&mut self) {
-        self.sync_fsinfo()
+        if let Err(e) = self.sync_fsinfo() {
+            log::error!("FSINFO lost on unmount: {:?}", e);
+        }
     }
 }
@@ -0,0 +1,248 @@
+// 一致性检查: 从根目录遍历目录树, 检查簇链, 交叉链接, 文件大小和丢失的簇, 只读不改
+// scrub 时再把目录和文件的每个簇读一遍, 找出读不出来的簇
+use super::{FATEntry, FatType, RunFileSystem, START_CLUS_ID};
+use crate::data::FIXED_ROOT_CLUSTER;
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::{FSError, IOError};
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec, vec::Vec};
+
+/// 一致性检查发现的问题, path 是用短文件名拼出的绝对路径
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckIssue {
+    /// 簇链中出现空闲簇, 坏簇或超出范围的簇号, cluster 是出问题的簇
+    InvalidChain { path: String, cluster: u32 },
+    /// 簇已经属于别的簇链, 簇链成环也算
+    CrossLinked { path: String, cluster: u32 },
+    /// 文件大小超过簇链的容量
+    SizeMismatch {
+        path: String,
+        size: u32,
+        capacity: u64,
+    },
+    /// 已分配但不属于任何文件的簇, 断电后常见, 只浪费空间
+    LostClusters { count: u32 },
+    /// 记录的空闲簇数和 FAT 表不一致, 只影响空闲空间统计
+    FreeCountMismatch { recorded: u32, actual: u32 },
+    /// scrub 时簇读不出来, 校验和不对时 error 是 ChecksumMismatch, 固定根目录区的簇号是 0
+    Unreadable {
+        path: String,
+        cluster: u32,
+        error: IOError,
+    },
+}
+
+impl CheckIssue {
+    /// 丢失的簇和空闲簇数不一致不会破坏已有数据, 不算错误
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            CheckIssue::LostClusters { .. } | CheckIssue::FreeCountMismatch { .. }
+        )
+    }
+}
+
+/// 一致性检查的结果
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    pub issues: Vec<CheckIssue>,
+    /// 遍历到的文件数和目录数, 不包括根目录
+    pub files: usize,
+    pub directories: usize,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(CheckIssue::is_error)
+    }
+}
+
+pub(crate) fn check(runfs: &RunFileSystem, scrub: bool) -> Result<CheckReport, FSError> {
+    let bpb = runfs.bpb();
+    let end_cluster = bpb.total_clusters() as usize + START_CLUS_ID;
+    let mut checker = Checker {
+        runfs,
+        end_cluster,
+        used: vec![false; end_cluster],
+        scrub,
+        report: CheckReport::default(),
+    };
+    if bpb.fat_type() == FatType::Fat32 {
+        let clusters = checker.walk_chain("/", bpb.root_dir_cluster() as usize)?;
+        checker.check_dir("/", &clusters, bpb.cluster_size(), false)?;
+    } else {
+        checker.check_dir("/", &[FIXED_ROOT_CLUSTER], bpb.root_dir_size(), false)?;
+    }
+    // 剩下的已分配簇都是丢失的
+    let mut lost = 0;
+    let mut free = 0;
+    let mut fat_manager = runfs.fat_manager_modify();
+    for cluster_id in START_CLUS_ID..end_cluster {
+        match fat_manager.entry(cluster_id)? {
+            FATEntry::Free => free += 1,
+            FATEntry::Bad => {}
+            _ if !checker.used[cluster_id] => lost += 1,
+            _ => {}
+        }
+    }
+    let mut report = checker.report;
+    if lost > 0 {
+        report.issues.push(CheckIssue::LostClusters { count: lost });
+    }
+    if let Some(recorded) = fat_manager.free_clusters() {
+        if recorded != free {
+            report.issues.push(CheckIssue::FreeCountMismatch {
+                recorded,
+                actual: free,
+            });
+        }
+    }
+    Ok(report)
+}
+
+struct Checker<'a> {
+    runfs: &'a RunFileSystem,
+    end_cluster: usize,
+    used: Vec<bool>, // 已经出现在某条簇链中的簇
+    scrub: bool,     // 读不出来的簇记为 Unreadable, 不返回错误
+    report: CheckReport,
+}
+
+impl Checker<'_> {
+    /// 沿簇链走到结尾或第一个出问题的簇, 返回之前有效的簇
+    fn walk_chain(&mut self, path: &str, first_cluster: usize) -> Result<Vec<usize>, FSError> {
+        let mut clusters = Vec::new();
+        let mut fat_manager = self.runfs.fat_manager_modify();
+        let mut cluster_id = first_cluster;
+        loop {
+            if cluster_id < START_CLUS_ID || cluster_id >= self.end_cluster {
+                self.report.issues.push(CheckIssue::InvalidChain {
+                    path: String::from(path),
+                    cluster: cluster_id as u32,
+                });
+                return Ok(clusters);
+            }
+            if self.used[cluster_id] {
+                self.report.issues.push(CheckIssue::CrossLinked {
+                    path: String::from(path),
+                    cluster: cluster_id as u32,
+                });
+                return Ok(clusters);
+            }
+            match fat_manager.entry(cluster_id)? {
+                FATEntry::Next(next) => {
+                    self.used[cluster_id] = true;
+                    clusters.push(cluster_id);
+                    cluster_id = next as usize;
+                }
+                FATEntry::End => {
+                    self.used[cluster_id] = true;
+                    clusters.push(cluster_id);
+                    return Ok(clusters);
+                }
+                FATEntry::Free | FATEntry::Bad => {
+                    self.report.issues.push(CheckIssue::InvalidChain {
+                        path: String::from(path),
+                        cluster: cluster_id as u32,
+                    });
+                    return Ok(clusters);
+                }
+            }
+        }
+    }
+    /// 检查目录中的每一项, 子目录递归处理, 子目录开头的 . 和 .. 按位置跳过
+    fn check_dir(
+        &mut self,
+        path: &str,
+        clusters: &[usize],
+        cluster_size: usize,
+        has_dots: bool,
+    ) -> Result<(), FSError> {
+        let data_cluster_size = self.runfs.bpb().cluster_size();
+        for (index, cluster_id) in clusters.iter().enumerate() {
+            for offset in (0..cluster_size).step_by(DIRENT_SZ) {
+                let dirent = match self.runfs.data_manager_modify().read_short_dirent(
+                    *cluster_id,
+                    offset,
+                    |e| *e,
+                ) {
+                    Ok(dirent) => dirent,
+                    Err(FSError::Io(error)) if self.scrub => {
+                        // 目录自己的路径不带结尾的 /
+                        let dir_path = match path.len() {
+                            1 => path,
+                            len => &path[..len - 1],
+                        };
+                        self.report.issues.push(CheckIssue::Unreadable {
+                            path: String::from(dir_path),
+                            cluster: *cluster_id as u32,
+                            error,
+                        });
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                if dirent.is_empty() {
+                    return Ok(());
+                }
+                if dirent.is_deleted() || !dirent.is_short() || dirent.is_volume() {
+                    continue;
+                }
+                if has_dots && index == 0 && offset < 2 * DIRENT_SZ {
+                    continue;
+                }
+                let child_path = format!("{}{}", path, dirent.name());
+                let first_cluster = dirent.first_cluster() as usize;
+                if dirent.is_dir() {
+                    self.report.directories += 1;
+                    let child_clusters = self.walk_chain(&child_path, first_cluster)?;
+                    let dir_path = format!("{}/", child_path);
+                    self.check_dir(&dir_path, &child_clusters, data_cluster_size, true)?;
+                    continue;
+                }
+                self.report.files += 1;
+                let size = dirent.size().unwrap_or(0);
+                // 空文件没有簇, 簇链本身有问题时不再比较大小
+                let issues = self.report.issues.len();
+                let clusters = if first_cluster == 0 {
+                    Vec::new()
+                } else {
+                    self.walk_chain(&child_path, first_cluster)?
+                };
+                let capacity = clusters.len() as u64 * data_cluster_size as u64;
+                if self.report.issues.len() == issues && u64::from(size) > capacity {
+                    self.report.issues.push(CheckIssue::SizeMismatch {
+                        path: child_path.clone(),
+                        size,
+                        capacity,
+                    });
+                }
+                if self.scrub {
+                    self.read_clusters(&child_path, &clusters);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// 逐簇从设备读文件的数据, 不经过簇缓存, 缓存中的簇在 scrub 开始时已经写回
+    fn read_clusters(&mut self, path: &str, clusters: &[usize]) {
+        let mut buf: Vec<u8> = vec![0; self.runfs.bpb().cluster_size()];
+        for cluster_id in clusters {
+            if let Err(error) = self
+                .runfs
+                .data_manager_modify()
+                .cluster_cache()
+                .read_clusters_uncached(*cluster_id, &mut buf)
+            {
+                self.report.issues.push(CheckIssue::Unreadable {
+                    path: String::from(path),
+                    cluster: *cluster_id as u32,
+                    error,
+                });
+            }
+        }
+    }
+}
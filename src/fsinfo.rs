@@ -1,7 +1,7 @@
 // 对FSInfo的抽象
 
 use super::{BlockDevice, START_CLUS_ID};
-use crate::error::FSError;
+use crate::error::{FSError, IOError};
 #[cfg(not(feature = "std"))]
 use alloc::{slice, sync::Arc};
 #[cfg(feature = "std")]
@@ -98,7 +98,10 @@ impl FSInfoSector {
     const TRAIL_SIGNATURE: u32 = 0xAA55_0000;
 
     // 直接通过块设备读取获得启动扇区, 只用于 RunFileSystem 创建
-    pub(crate) fn directly_new(fsinfo_block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+    pub(crate) fn directly_new(
+        fsinfo_block_id: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Result<Self, IOError> {
         // println!("size of BootSector: {}", core::mem::size_of::<BootSector>());
         let fsinfo_sector = FSInfoSector::default();
         // 调试没问题,能够获取 512 Byte 准确数据
@@ -108,10 +111,8 @@ impl FSInfoSector {
                 core::mem::size_of::<FSInfoSector>(),
             )
         };
-        block_device
-            .read_block(fsinfo_block_id, sector_slice)
-            .unwrap();
-        fsinfo_sector
+        block_device.read_block(fsinfo_block_id, sector_slice)?;
+        Ok(fsinfo_sector)
     }
     pub(crate) fn from_fsinfo(fsinfo: FSInfo) -> Self {
         Self {
@@ -131,6 +132,14 @@ impl FSInfoSector {
             ..Self::default()
         }
     }
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const FSInfoSector) as *const u8,
+                core::mem::size_of::<FSInfoSector>(),
+            )
+        }
+    }
     #[must_use]
     pub(crate) fn validate(&self) -> Result<(), FSError> {
         if self.lead_signature != Self::LEAD_SIGNATURE
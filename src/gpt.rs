@@ -0,0 +1,251 @@
+// 对 GUID 分区表 GPT 的抽象, 校验保护性 MBR, 主备 GPT 头和分区表项的 CRC32
+use super::BlockDevice;
+use crate::crc32::crc32;
+use crate::error::{FSError, IOError};
+use crate::mbr::MasterBootRecord;
+#[cfg(not(feature = "std"))]
+use alloc::{slice, string::String, sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{slice, sync::Arc};
+
+const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+const GPT_HEADER_SZ: usize = 92;
+const GPT_BLOCK_SZ: usize = 512;
+const GPT_ENTRY_MIN_SZ: usize = 128;
+/// 分区表项最多读取的数量, 防止损坏的头导致读取整个磁盘
+const GPT_MAX_ENTRIES: u32 = 1024;
+const GPT_NAME_LEN: usize = 36;
+
+/// GUID, 按磁盘上的字节序保存(前三段小端)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+    /// EFI System Partition: C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+    pub const EFI_SYSTEM: Guid = Guid::from_fields(
+        0xC12A_7328,
+        0xF81F,
+        0x11D2,
+        [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+    );
+    /// Microsoft Basic Data: EBD0A0A2-B9E5-4433-87C0-68B6B72699C7
+    pub const BASIC_DATA: Guid = Guid::from_fields(
+        0xEBD0_A0A2,
+        0xB9E5,
+        0x4433,
+        [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+    );
+
+    /// 按字符串书写顺序的各段构造 GUID
+    pub const fn from_fields(d1: u32, d2: u16, d3: u16, d4: [u8; 8]) -> Self {
+        let a = d1.to_le_bytes();
+        let b = d2.to_le_bytes();
+        let c = d3.to_le_bytes();
+        Guid([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d4[0], d4[1], d4[2], d4[3], d4[4],
+            d4[5], d4[6], d4[7],
+        ])
+    }
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+}
+
+// GPT 头只有前 92 Byte 有意义, 剩下的补齐一个扇区
+#[repr(C, packed(1))]
+#[derive(Copy, Clone)]
+struct GptHeader {
+    signature: [u8; 8],
+    revision: u32,
+    header_size: u32,
+    header_crc32: u32,
+    reserved: u32,
+    my_lba: u64,
+    alternate_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    disk_guid: [u8; 16],
+    partition_entry_lba: u64,
+    num_partition_entries: u32,
+    size_of_partition_entry: u32,
+    partition_entry_array_crc32: u32,
+    padding: [u8; GPT_BLOCK_SZ - GPT_HEADER_SZ],
+}
+
+impl Default for GptHeader {
+    fn default() -> Self {
+        Self {
+            signature: [0; 8],
+            revision: 0,
+            header_size: 0,
+            header_crc32: 0,
+            reserved: 0,
+            my_lba: 0,
+            alternate_lba: 0,
+            first_usable_lba: 0,
+            last_usable_lba: 0,
+            disk_guid: [0; 16],
+            partition_entry_lba: 0,
+            num_partition_entries: 0,
+            size_of_partition_entry: 0,
+            partition_entry_array_crc32: 0,
+            padding: [0; GPT_BLOCK_SZ - GPT_HEADER_SZ],
+        }
+    }
+}
+
+impl GptHeader {
+    fn directly_new(block_id: usize, block_device: &Arc<dyn BlockDevice>) -> Result<Self, IOError> {
+        let header = GptHeader::default();
+        let sector_slice = unsafe {
+            slice::from_raw_parts_mut(
+                (&header as *const GptHeader) as *mut u8,
+                core::mem::size_of::<GptHeader>(),
+            )
+        };
+        block_device.read_block(block_id, sector_slice)?;
+        Ok(header)
+    }
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const GptHeader) as *const u8,
+                core::mem::size_of::<GptHeader>(),
+            )
+        }
+    }
+    // 计算 CRC 时 header_crc32 字段按 0 处理
+    fn validate(&self, my_lba: u64) -> Result<(), FSError> {
+        let header_size = self.header_size as usize;
+        if self.signature != GPT_SIGNATURE
+            || !(GPT_HEADER_SZ..=GPT_BLOCK_SZ).contains(&header_size)
+            || self.my_lba != my_lba
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let mut header = *self;
+        header.header_crc32 = 0;
+        if crc32(&header.as_bytes()[..header_size]) != self.header_crc32 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        let entry_size = self.size_of_partition_entry as usize;
+        if entry_size < GPT_ENTRY_MIN_SZ
+            || !entry_size.is_multiple_of(GPT_ENTRY_MIN_SZ)
+            || self.num_partition_entries > GPT_MAX_ENTRIES
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(())
+    }
+    /// 读出全部分区表项的原始字节并校验 CRC32
+    fn read_entries(&self, block_device: &Arc<dyn BlockDevice>) -> Result<Vec<u8>, FSError> {
+        let array_size =
+            self.num_partition_entries as usize * self.size_of_partition_entry as usize;
+        let blocks = array_size.div_ceil(GPT_BLOCK_SZ);
+        let mut entries: Vec<u8> = vec![0; blocks * GPT_BLOCK_SZ];
+        let first_block = self.partition_entry_lba as usize;
+        for (i, chunk) in entries.chunks_mut(GPT_BLOCK_SZ).enumerate() {
+            block_device.read_block(first_block + i, chunk)?;
+        }
+        entries.truncate(array_size);
+        if crc32(&entries) != self.partition_entry_array_crc32 {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(entries)
+    }
+}
+
+/// GPT 中的一个分区
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GptPartition {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// 分区最后一个扇区(包含)
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    fn from_raw(raw: &[u8]) -> Self {
+        let u64_at = |pos: usize| u64::from_le_bytes(raw[pos..pos + 8].try_into().unwrap());
+        let name_u16: Vec<u16> = raw[56..56 + GPT_NAME_LEN * 2]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        Self {
+            type_guid: Guid(raw[0..16].try_into().unwrap()),
+            unique_guid: Guid(raw[16..32].try_into().unwrap()),
+            first_lba: u64_at(32),
+            last_lba: u64_at(40),
+            attributes: u64_at(48),
+            name: String::from_utf16_lossy(&name_u16),
+        }
+    }
+    pub fn sectors(&self) -> u64 {
+        self.last_lba + 1 - self.first_lba
+    }
+    pub fn is_efi_system(&self) -> bool {
+        self.type_guid == Guid::EFI_SYSTEM
+    }
+}
+
+// 优先使用主 GPT 头, 主头或其分区表损坏时使用备份 GPT 头
+fn read_valid_table(
+    mbr: &MasterBootRecord,
+    block_device: &Arc<dyn BlockDevice>,
+) -> Result<(GptHeader, Vec<u8>), FSError> {
+    let primary = GptHeader::directly_new(1, block_device)?;
+    let primary_res = primary
+        .validate(1)
+        .and_then(|_| primary.read_entries(block_device));
+    if let Ok(entries) = primary_res {
+        // 主头完好时也检查备份头, 备份损坏只是警告
+        let backup_lba = primary.alternate_lba;
+        let backup = GptHeader::directly_new(backup_lba as usize, block_device);
+        if !backup.is_ok_and(|backup| backup.validate(backup_lba).is_ok()) {
+            log::warn!("backup GPT header at LBA {} corrupted", backup_lba);
+        }
+        return Ok((primary, entries));
+    }
+    log::warn!("primary GPT header corrupted, trying backup header");
+    // 主头本身完好时用它记录的 alternate_lba, 否则用保护性 MBR 记录的磁盘末尾
+    let backup_lba = if primary.validate(1).is_ok() {
+        primary.alternate_lba
+    } else {
+        mbr.protective_last_lba()
+            .ok_or(FSError::CorruptedFileSystem)?
+    };
+    let backup = GptHeader::directly_new(backup_lba as usize, block_device)?;
+    backup.validate(backup_lba)?;
+    let entries = backup.read_entries(block_device)?;
+    Ok((backup, entries))
+}
+
+/// 读取 GPT 分区表, 按表中顺序返回非空分区
+pub fn read_gpt_partitions(
+    block_device: &Arc<dyn BlockDevice>,
+) -> Result<Vec<GptPartition>, FSError> {
+    let mbr = MasterBootRecord::directly_new(0, block_device)?;
+    mbr.validate()?;
+    if !mbr.is_protective() {
+        return Err(FSError::NotFound);
+    }
+    let (header, entries) = read_valid_table(&mbr, block_device)?;
+    let partitions: Vec<GptPartition> = entries
+        .chunks_exact(header.size_of_partition_entry as usize)
+        .map(GptPartition::from_raw)
+        .filter(|p| !p.type_guid.is_zero())
+        .collect();
+    // 结束扇区在起始扇区之前或者扇区数超出 u64 的表项是损坏的, sectors() 要求范围有效
+    if partitions
+        .iter()
+        .any(|p| p.last_lba < p.first_lba || p.last_lba == u64::MAX)
+    {
+        return Err(FSError::CorruptedFileSystem);
+    }
+    Ok(partitions)
+}
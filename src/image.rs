@@ -0,0 +1,174 @@
+// 从主机目录生成可复现的镜像: 目录项按名称排序, 时间和卷序列号固定, 短文件名按固定规则生成
+use super::{
+    BlockDevice, FSError, FileAttributes, FormatOptions, RunFileSystem, ShortDirectoryEntry, VFile,
+    DIRENT_SZ, SHORT_FILE_NAME_LEN, SHORT_FILE_NAME_PADDING, SHORT_NAME_LEN,
+};
+use crate::vfs::generate_short_name;
+use spin::RwLock;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+// 每次从主机文件读入的字节数
+const COPY_CHUNK_SZ: usize = 64 * 1024;
+// 数字尾巴 ~N 的上限, 和 Windows 一样
+const MAX_NUMERIC_TAIL: u32 = 999_999;
+
+/// 目录项时间: 年, 月, 日, 时, 分, 秒, 年份在 1980-2107 之间, 秒数只能精确到 2 秒
+pub type Timestamp = (u32, u32, u32, u32, u32, u32);
+
+pub(crate) const DEFAULT_TIMESTAMP: Timestamp = (1980, 1, 1, 0, 0, 0);
+
+/// 生成镜像的参数, 相同的参数和输入目录总是生成逐字节相同的镜像
+#[derive(Copy, Clone, Debug)]
+pub struct ImageOptions {
+    /// 格式化参数, 卷序列号取 format.volume_id, 默认为 0
+    pub format: FormatOptions,
+    /// 所有目录项的创建, 修改和访问时间
+    pub timestamp: Timestamp,
+}
+
+impl ImageOptions {
+    /// 时间默认为 1980-01-01 00:00:00
+    pub fn new(format: FormatOptions) -> Self {
+        Self {
+            format,
+            timestamp: DEFAULT_TIMESTAMP,
+        }
+    }
+}
+
+pub(crate) fn validate_timestamp(timestamp: Timestamp) -> Result<(), FSError> {
+    let (year, month, day, hour, min, sec) = timestamp;
+    if !(1980..=2107).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || min > 59
+        || sec > 59
+    {
+        return Err(FSError::InvalidInput);
+    }
+    Ok(())
+}
+
+/// 格式化块设备并把主机目录 source 中的内容按名称顺序写进根目录
+/// 只会写文件系统用到的区域, 需要整个设备逐字节一致时设备要先清零
+pub fn build_image(
+    block_device: Arc<dyn BlockDevice>,
+    source: &Path,
+    options: &ImageOptions,
+) -> Result<(), FSError> {
+    validate_timestamp(options.timestamp)?;
+    RunFileSystem::format(Arc::clone(&block_device), options.format)?;
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(block_device)?));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    copy_dir(&root_dir, source, options)
+}
+
+// 把主机目录中的内容写进 dir, 子目录递归处理
+fn copy_dir(dir: &VFile, source: &Path, options: &ImageOptions) -> Result<(), FSError> {
+    let mut entries: Vec<(String, fs::Metadata)> = Vec::new();
+    for entry in fs::read_dir(source).map_err(host_error)? {
+        let entry = entry.map_err(host_error)?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| FSError::UnsupportedFileNameCharacter)?;
+        // 跟随符号链接
+        let metadata = fs::metadata(entry.path()).map_err(host_error)?;
+        entries.push((name, metadata));
+    }
+    // 按 UTF-8 字节序排序, 和主机文件系统返回的顺序无关
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut short_names: Vec<[u8; SHORT_NAME_LEN]> = Vec::new();
+    for (name, metadata) in entries {
+        let attribute = if metadata.is_dir() {
+            FileAttributes::DIRECTORY
+        } else if metadata.is_file() {
+            FileAttributes::FILE
+        } else {
+            log::warn!("skipping {:?}: not a file or directory", source.join(&name));
+            continue;
+        };
+        let short_name = unique_short_name(&name, &short_names)?;
+        short_names.push(short_name);
+        let vfile = dir.create_with_short_name(&name, short_name, attribute)?;
+        set_time(&vfile, options.timestamp)?;
+        let path = source.join(&name);
+        if metadata.is_dir() {
+            copy_dir(&vfile, &path, options)?;
+        } else {
+            copy_file(&vfile, &path)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_file(vfile: &VFile, path: &Path) -> Result<(), FSError> {
+    let mut file = fs::File::open(path).map_err(host_error)?;
+    let mut buf: Vec<u8> = vec![0; COPY_CHUNK_SZ];
+    let mut offset = 0;
+    loop {
+        let len = file.read(&mut buf).map_err(host_error)?;
+        if len == 0 {
+            return Ok(());
+        }
+        if vfile.write_at(offset, &buf[..len])? != len {
+            return Err(FSError::NotEnoughSpace);
+        }
+        offset += len;
+    }
+}
+
+/// 设置文件自己的短目录项的时间, 目录还包括 . 和 ..
+pub(crate) fn set_time(vfile: &VFile, timestamp: Timestamp) -> Result<(), FSError> {
+    let (year, month, day, hour, min, sec) = timestamp;
+    let set = |entry: &mut ShortDirectoryEntry| entry.set_time(year, month, day, hour, min, sec);
+    let fs = vfile.fs();
+    let runfs = fs.read();
+    let mut data_manager = runfs.data_manager_modify();
+    let (cluster_id, offset) = vfile.short_pos();
+    data_manager.modify_short_dirent(cluster_id, offset, set)?;
+    if vfile.is_dir() {
+        let first_cluster =
+            data_manager.read_short_dirent(cluster_id, offset, |e| e.first_cluster())?;
+        data_manager.modify_short_dirent(first_cluster as usize, 0, set)?;
+        data_manager.modify_short_dirent(first_cluster as usize, DIRENT_SZ, set)?;
+    }
+    Ok(())
+}
+
+/// 目录中不重复的短文件名, 先用 generate_short_name 的结果, 重复时按目录项顺序加上 ~1, ~2 ... 的数字尾巴
+fn unique_short_name(
+    name: &str,
+    used: &[[u8; SHORT_NAME_LEN]],
+) -> Result<[u8; SHORT_NAME_LEN], FSError> {
+    let short_name = generate_short_name(name);
+    if !used.contains(&short_name) {
+        return Ok(short_name);
+    }
+    let base_len = short_name[..SHORT_FILE_NAME_LEN]
+        .iter()
+        .position(|b| *b == SHORT_FILE_NAME_PADDING)
+        .unwrap_or(SHORT_FILE_NAME_LEN);
+    for n in 1..=MAX_NUMERIC_TAIL {
+        let tail = format!("~{}", n);
+        let keep = base_len.min(SHORT_FILE_NAME_LEN - tail.len());
+        let mut candidate = short_name;
+        candidate[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        candidate[keep + tail.len()..SHORT_FILE_NAME_LEN].fill(SHORT_FILE_NAME_PADDING);
+        if !used.contains(&candidate) {
+            return Ok(candidate);
+        }
+    }
+    Err(FSError::AlreadyExists)
+}
+
+pub(crate) fn host_error(e: io::Error) -> FSError {
+    match e.kind() {
+        io::ErrorKind::NotFound => FSError::NotFound,
+        _ => FSError::InvalidInput,
+    }
+}
@@ -4,19 +4,38 @@
 extern crate alloc;
 
 
+mod async_device;
+mod async_fs;
 mod block_device;
 mod boot_sector;
 mod cluster_cache;
+mod cluster_walk;
 mod config;
+mod crash;
+mod crc32;
+#[cfg(feature = "aes")]
+mod crypt;
 mod data;
+mod device;
 mod dir_entry;
 mod error;
+mod exfat;
 mod fat;
+mod fsck;
 mod fsinfo;
+mod gpt;
+#[cfg(feature = "std")]
+mod image;
+#[cfg(feature = "std")]
+mod manifest;
+mod mbr;
+mod mkfs;
+mod resize;
 mod runfs;
 #[cfg(not(feature = "std"))]
 mod sbi;
 mod sector_cache;
+mod trace;
 mod vfs;
 
 #[macro_use]
@@ -26,19 +45,42 @@ mod console;
 use cluster_cache::ClusterCacheManager;
 use data::DataManager;
 use dir_entry::{
-    LongDirectoryEntry, ShortDirectoryEntry, DIRENT_SZ, LAST_LONG_ENTRY, LONG_NAME_LEN,
-    SHORT_FILE_EXT_LEN, SHORT_FILE_NAME_LEN, SHORT_FILE_NAME_PADDING, SHORT_NAME_LEN,
+    LongDirectoryEntry, ShortDirectoryEntry, VolumeLabelEntry, DIRENT_SZ, LAST_LONG_ENTRY,
+    LONG_NAME_LEN, SHORT_FILE_EXT_LEN, SHORT_FILE_NAME_LEN, SHORT_FILE_NAME_PADDING,
+    SHORT_NAME_LEN,
 };
 use fat::FATManager;
 use fsinfo::{FSInfo, FSInfoSector};
-use sector_cache::SectorCacheManager;
+use sector_cache::{
+    AsyncLoad, CacheGeometry, CacheLoad, SectorCacheManager, WriteBackPin, WriteBackState,
+};
 
+pub use async_device::{block_on, AsyncAdapter, AsyncBlockDevice, BlockFuture, BlockingAdapter};
 pub use block_device::BlockDevice;
-pub use boot_sector::{BiosParameterBlock, BootSector};
+pub use boot_sector::{BiosParameterBlock, BootSector, SectorCopy};
+pub use crash::{CrashOp, PowerCutFailure, PowerCutTest};
+#[cfg(feature = "aes")]
+pub use crypt::EncryptedDevice;
+#[cfg(feature = "std")]
+pub use device::FileBlockDevice;
+pub use device::{
+    BlockSizeAdapter, ChecksumDevice, FaultyDevice, OverlayDevice, RamDisk, ReadOnlyDevice,
+    SliceDevice,
+};
 pub use dir_entry::FileAttributes;
 pub use error::{FSError, IOError};
-pub use fat::FATEntry;
-pub use runfs::RunFileSystem;
+pub use exfat::{ExFatFileSystem, ExFatFormatOptions, ExFatVFile};
+pub use fat::{FATEntry, FatType};
+pub use fsck::{CheckIssue, CheckReport};
+pub use gpt::{read_gpt_partitions, GptPartition, Guid};
+#[cfg(feature = "std")]
+pub use image::{build_image, ImageOptions, Timestamp};
+#[cfg(feature = "std")]
+pub use manifest::{Manifest, ManifestEntry, ManifestKind, ManifestSource};
+pub use mbr::{read_mbr_partitions, MbrPartition};
+pub use mkfs::FormatOptions;
+pub use runfs::{MountOptions, RunFileSystem};
+pub use trace::{Trace, TraceDevice, TraceEvent, TraceMode, TraceReplayer};
 pub use vfs::{long_name_split, VFile};
 
 const START_CLUS_ID: usize = 2;
@@ -0,0 +1,284 @@
+// 镜像清单: 用简单的文本格式描述卷中的目录和文件, 解析后写进刚格式化的文件系统
+//
+// 每行一项, # 开头的行和空行忽略, 字段之间用空白分隔, 含空白的字段用双引号括起来:
+//
+//     dir  /boot  attr=SYSTEM
+//     file /boot/kernel.bin  src=target/kernel.bin  attr=SYSTEM,READ_ONLY  contiguous
+//     file /etc/hostname  text="rcore\n"  time=2022-06-10T12:34:56
+//     file /boot/magic.bin  hex=7f454c46
+//
+// 路径必须是绝对路径, 没有声明的父目录会自动创建; 文件内容来自 src(相对清单所在目录的主机路径),
+// text(支持 \\ \" \n \r \t \0 转义) 或 hex 之一, 都没有时为空文件;
+// attr 可以是 HIDDEN, SYSTEM, READ_ONLY, ARCHIVE 的组合; time 默认为 1980-01-01T00:00:00;
+// contiguous 要求文件占用连续的簇, 做不到时报错
+use super::{FSError, FileAttributes, RunFileSystem, VFile};
+use crate::image::{host_error, set_time, validate_timestamp, Timestamp, DEFAULT_TIMESTAMP};
+use spin::RwLock;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 文件内容的来源
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestSource {
+    /// 主机文件, 相对路径按清单所在目录解析
+    Host(PathBuf),
+    /// 清单中直接给出的内容
+    Inline(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestKind {
+    Dir,
+    File(ManifestSource),
+}
+
+/// 清单中的一项
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// 卷中的绝对路径, 以 / 分隔
+    pub path: String,
+    pub kind: ManifestKind,
+    /// 只会包含 HIDDEN, SYSTEM, READ_ONLY 和 ARCHIVE
+    pub attributes: FileAttributes,
+    pub timestamp: Timestamp,
+    /// 文件必须占用连续的簇
+    pub contiguous: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// 解析清单文本, 出错的行号写在日志里
+    pub fn parse(text: &str) -> Result<Self, FSError> {
+        let mut entries: Vec<ManifestEntry> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_line(line).map_err(|e| {
+                log::error!("manifest line {}: {:?}", index + 1, e);
+                e
+            })?;
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+    /// 读取并解析主机上的清单文件, 之后 apply 时 base_dir 一般就是它所在的目录
+    pub fn from_file(path: &Path) -> Result<Self, FSError> {
+        let text = fs::read_to_string(path).map_err(host_error)?;
+        Self::parse(&text)
+    }
+    /// 按清单顺序创建目录和文件, 已经存在的项报 AlreadyExists
+    pub fn apply(
+        &self,
+        runfs: &Arc<RwLock<RunFileSystem>>,
+        base_dir: &Path,
+    ) -> Result<(), FSError> {
+        let root_dir = runfs.read().root_vfile(runfs);
+        for entry in self.entries.iter() {
+            apply_entry(&root_dir, entry, base_dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Result<ManifestEntry, FSError> {
+    let mut fields = split_fields(line)?.into_iter();
+    let kind = fields.next().ok_or(FSError::InvalidInput)?;
+    let path = fields.next().ok_or(FSError::InvalidInput)?;
+    if !path.starts_with('/') || path.split('/').all(|c| c.is_empty()) {
+        return Err(FSError::InvalidInput);
+    }
+    let mut source: Option<ManifestSource> = None;
+    let mut attributes = FileAttributes::empty();
+    let mut timestamp = DEFAULT_TIMESTAMP;
+    let mut contiguous = false;
+    for field in fields {
+        let (key, value) = match field.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (field.as_str(), None),
+        };
+        match (key, value) {
+            ("contiguous", None) => contiguous = true,
+            ("attr", Some(value)) => attributes = parse_attributes(value)?,
+            ("time", Some(value)) => timestamp = parse_timestamp(value)?,
+            ("src" | "text" | "hex", Some(_)) if source.is_some() => {
+                return Err(FSError::InvalidInput)
+            }
+            ("src", Some(value)) => source = Some(ManifestSource::Host(PathBuf::from(value))),
+            ("text", Some(value)) => {
+                source = Some(ManifestSource::Inline(value.as_bytes().to_vec()))
+            }
+            ("hex", Some(value)) => source = Some(ManifestSource::Inline(parse_hex(value)?)),
+            _ => return Err(FSError::InvalidInput),
+        }
+    }
+    let kind = match kind.as_str() {
+        "dir" if source.is_none() && !contiguous => ManifestKind::Dir,
+        "file" => ManifestKind::File(source.unwrap_or(ManifestSource::Inline(Vec::new()))),
+        _ => return Err(FSError::InvalidInput),
+    };
+    Ok(ManifestEntry {
+        path,
+        kind,
+        attributes,
+        timestamp,
+        contiguous,
+    })
+}
+
+// 按空白切分字段, 双引号中的空白不切分, 引号本身去掉并处理转义
+fn split_fields(line: &str) -> Result<Vec<String>, FSError> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_field = false;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_field = true;
+            }
+            '\\' if in_quotes => {
+                let escaped = match chars.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    _ => return Err(FSError::InvalidInput),
+                };
+                field.push(escaped);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_field {
+                    fields.push(core::mem::take(&mut field));
+                    in_field = false;
+                }
+            }
+            c => {
+                field.push(c);
+                in_field = true;
+            }
+        }
+    }
+    if in_quotes {
+        return Err(FSError::InvalidInput);
+    }
+    if in_field {
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+fn parse_attributes(value: &str) -> Result<FileAttributes, FSError> {
+    let mut attributes = FileAttributes::empty();
+    for name in value.split(',') {
+        attributes |= match name {
+            "HIDDEN" => FileAttributes::HIDDEN,
+            "SYSTEM" => FileAttributes::SYSTEM,
+            "READ_ONLY" => FileAttributes::READ_ONLY,
+            "ARCHIVE" => FileAttributes::ARCHIVE,
+            _ => return Err(FSError::InvalidInput),
+        };
+    }
+    Ok(attributes)
+}
+
+// YYYY-MM-DDTHH:MM:SS
+fn parse_timestamp(value: &str) -> Result<Timestamp, FSError> {
+    let (date, time) = value.split_once('T').ok_or(FSError::InvalidInput)?;
+    let number = |s: &str| s.parse::<u32>().map_err(|_| FSError::InvalidInput);
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || time.len() != 3 {
+        return Err(FSError::InvalidInput);
+    }
+    let timestamp = (
+        number(date[0])?,
+        number(date[1])?,
+        number(date[2])?,
+        number(time[0])?,
+        number(time[1])?,
+        number(time[2])?,
+    );
+    validate_timestamp(timestamp)?;
+    Ok(timestamp)
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, FSError> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return Err(FSError::InvalidInput);
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| FSError::InvalidInput))
+        .collect()
+}
+
+fn apply_entry(root_dir: &VFile, entry: &ManifestEntry, base_dir: &Path) -> Result<(), FSError> {
+    let components: Vec<&str> = entry.path.split('/').filter(|c| !c.is_empty()).collect();
+    let (name, parents) = components.split_last().ok_or(FSError::InvalidInput)?;
+    // 逐级找到父目录, 没有就创建
+    let mut dir = Arc::new(root_dir.clone());
+    for component in parents {
+        dir = match dir.find_vfile_byname(component) {
+            Ok(vfile) if vfile.is_dir() => Arc::new(vfile),
+            Ok(_) => return Err(FSError::AlreadyExists),
+            Err(FSError::NotFound) => {
+                let vfile = dir.create(component, FileAttributes::DIRECTORY)?;
+                set_time(&vfile, DEFAULT_TIMESTAMP)?;
+                vfile
+            }
+            Err(e) => return Err(e),
+        };
+    }
+    match dir.find_vfile_byname(name) {
+        Ok(_) => return Err(FSError::AlreadyExists),
+        Err(FSError::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+    let data = match &entry.kind {
+        ManifestKind::Dir => None,
+        ManifestKind::File(ManifestSource::Inline(data)) => Some(data.clone()),
+        ManifestKind::File(ManifestSource::Host(path)) => {
+            Some(fs::read(base_dir.join(path)).map_err(host_error)?)
+        }
+    };
+    let attribute = if data.is_some() {
+        entry.attributes
+    } else {
+        entry.attributes | FileAttributes::DIRECTORY
+    };
+    let vfile = dir.create(name, attribute)?;
+    if let Some(data) = data {
+        if entry.contiguous {
+            make_contiguous(&vfile, data.len())?;
+        }
+        if vfile.write_at(0, &data)? != data.len() {
+            return Err(FSError::NotEnoughSpace);
+        }
+    }
+    set_time(&vfile, entry.timestamp)?;
+    Ok(())
+}
+
+// 把刚创建的文件换成一段足够放下 size 字节的连续簇
+fn make_contiguous(vfile: &VFile, size: usize) -> Result<(), FSError> {
+    let fs = vfile.fs();
+    let cluster_size = fs.read().bpb().cluster_size();
+    let clusters = size.div_ceil(cluster_size).max(1);
+    let old_first = vfile.first_data_cluster()?;
+    // 先分配再改目录项, 最后释放创建时分配的簇, 空间不够时文件仍然完好
+    let first_cluster = fs.write().alloc_contiguous_clusters(clusters)?;
+    vfile.set_first_cluster(first_cluster)?;
+    fs.write().dealloc_clusters(old_first as usize, None)?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+// 对主引导记录 MBR 和扩展分区链的抽象, 用于在整盘镜像中找到 FAT32 分区
+use super::BlockDevice;
+use crate::error::{FSError, IOError};
+#[cfg(not(feature = "std"))]
+use alloc::{slice, sync::Arc, vec::Vec};
+#[cfg(feature = "std")]
+use std::{slice, sync::Arc};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const PRIMARY_PARTITIONS: usize = 4;
+/// 扩展分区链最长跟踪的逻辑分区数, 防止损坏的链表成环
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+// 分区类型
+const PARTITION_TYPE_EMPTY: u8 = 0x00;
+const PARTITION_TYPE_EXTENDED_CHS: u8 = 0x05;
+const PARTITION_TYPE_FAT32_CHS: u8 = 0x0B;
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0C;
+const PARTITION_TYPE_EXTENDED_LBA: u8 = 0x0F;
+const PARTITION_TYPE_EXTENDED_LINUX: u8 = 0x85;
+const PARTITION_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+// 分区表项 16 Byte
+#[repr(C, packed(1))]
+#[derive(Copy, Clone, Debug, Default)]
+struct PartitionEntry {
+    boot_indicator: u8, // 0x80 为活动分区
+    start_chs: [u8; 3], // LBA 时代不关心 CHS
+    partition_type: u8,
+    end_chs: [u8; 3],
+    start_lba: u32, // 主分区为相对磁盘起始, 逻辑分区为相对所在 EBR
+    sectors: u32,
+}
+
+impl PartitionEntry {
+    fn is_empty(&self) -> bool {
+        self.partition_type == PARTITION_TYPE_EMPTY || self.sectors == 0
+    }
+    fn is_extended(&self) -> bool {
+        matches!(
+            self.partition_type,
+            PARTITION_TYPE_EXTENDED_CHS
+                | PARTITION_TYPE_EXTENDED_LBA
+                | PARTITION_TYPE_EXTENDED_LINUX
+        )
+    }
+}
+
+// MBR 和 EBR 的结构相同, 512 Byte
+#[repr(C, packed(1))]
+#[derive(Copy, Clone)]
+pub(crate) struct MasterBootRecord {
+    boot_code: [u8; 446],
+    entries: [PartitionEntry; PRIMARY_PARTITIONS],
+    signature: [u8; 2],
+}
+
+impl Default for MasterBootRecord {
+    fn default() -> Self {
+        Self {
+            boot_code: [0; 446],
+            entries: [PartitionEntry::default(); PRIMARY_PARTITIONS],
+            signature: [0; 2],
+        }
+    }
+}
+
+impl MasterBootRecord {
+    // 直接通过块设备读取, 块大小必须是 512 Byte
+    pub(crate) fn directly_new(
+        block_id: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Result<Self, IOError> {
+        let mbr = MasterBootRecord::default();
+        let sector_slice = unsafe {
+            slice::from_raw_parts_mut(
+                (&mbr as *const MasterBootRecord) as *mut u8,
+                core::mem::size_of::<MasterBootRecord>(),
+            )
+        };
+        block_device.read_block(block_id, sector_slice)?;
+        Ok(mbr)
+    }
+    pub(crate) fn validate(&self) -> Result<(), FSError> {
+        if self.signature != MBR_SIGNATURE {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        // 活动标志只能是 0x00 或 0x80
+        if self
+            .entries
+            .iter()
+            .any(|e| e.boot_indicator != 0x00 && e.boot_indicator != 0x80)
+        {
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(())
+    }
+    /// 是否是 GPT 的保护性 MBR
+    pub(crate) fn is_protective(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.partition_type == PARTITION_TYPE_GPT_PROTECTIVE)
+    }
+    /// 保护性分区覆盖到的最后一个扇区, 也就是备份 GPT 头所在位置, 磁盘过大时记录的是 0xFFFFFFFF
+    pub(crate) fn protective_last_lba(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|e| e.partition_type == PARTITION_TYPE_GPT_PROTECTIVE)
+            .filter(|e| e.sectors != u32::MAX)
+            .and_then(|e| (u64::from(e.start_lba) + u64::from(e.sectors)).checked_sub(1))
+    }
+}
+
+/// MBR 中的一个分区, start_lba 已经换算成相对磁盘起始的扇区号
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MbrPartition {
+    pub bootable: bool,
+    pub partition_type: u8,
+    pub start_lba: u32,
+    pub sectors: u32,
+    /// 是否是扩展分区中的逻辑分区
+    pub logical: bool,
+}
+
+impl MbrPartition {
+    // 起始扇区超出 u32 说明分区表损坏
+    fn from_entry(entry: &PartitionEntry, base_lba: u32, logical: bool) -> Result<Self, FSError> {
+        Ok(Self {
+            bootable: entry.boot_indicator == 0x80,
+            partition_type: entry.partition_type,
+            start_lba: base_lba
+                .checked_add(entry.start_lba)
+                .ok_or(FSError::CorruptedFileSystem)?,
+            sectors: entry.sectors,
+            logical,
+        })
+    }
+    /// 分区类型是否标记为 FAT32
+    pub fn is_fat32(&self) -> bool {
+        matches!(
+            self.partition_type,
+            PARTITION_TYPE_FAT32_CHS | PARTITION_TYPE_FAT32_LBA
+        )
+    }
+}
+
+/// 读取 MBR 分区表, 按顺序返回非空的主分区和扩展分区链中的逻辑分区, 扩展分区本身不返回
+pub fn read_mbr_partitions(
+    block_device: &Arc<dyn BlockDevice>,
+) -> Result<Vec<MbrPartition>, FSError> {
+    let mbr = MasterBootRecord::directly_new(0, block_device)?;
+    mbr.validate()?;
+    let mut partitions: Vec<MbrPartition> = Vec::new();
+    let mut extended_start: Option<u32> = None;
+    for entry in mbr.entries.iter() {
+        if entry.is_empty() {
+            continue;
+        }
+        if entry.is_extended() {
+            // 一个 MBR 只允许一个扩展分区
+            if extended_start.is_some() {
+                return Err(FSError::CorruptedFileSystem);
+            }
+            extended_start = Some(entry.start_lba);
+        } else {
+            partitions.push(MbrPartition::from_entry(entry, 0, false)?);
+        }
+    }
+    // 扩展分区链: 每个 EBR 第一项是逻辑分区(相对该 EBR), 第二项指向下一个 EBR(相对扩展分区起始)
+    if let Some(extended_start) = extended_start {
+        let mut ebr_lba = extended_start;
+        for _ in 0..MAX_LOGICAL_PARTITIONS {
+            let ebr = MasterBootRecord::directly_new(ebr_lba as usize, block_device)?;
+            ebr.validate()?;
+            let logical = &ebr.entries[0];
+            if !logical.is_empty() {
+                partitions.push(MbrPartition::from_entry(logical, ebr_lba, true)?);
+            }
+            let next = &ebr.entries[1];
+            if next.is_empty() || !next.is_extended() {
+                break;
+            }
+            ebr_lba = extended_start
+                .checked_add(next.start_lba)
+                .ok_or(FSError::CorruptedFileSystem)?;
+        }
+    }
+    Ok(partitions)
+}
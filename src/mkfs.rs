@@ -0,0 +1,282 @@
+// 格式化, 在任意块设备上创建一个全新的 FAT12/16/32 文件系统
+use super::{BiosParameterBlock, BlockDevice, BootSector, FSInfo, FSInfoSector, START_CLUS_ID};
+use crate::config::{BATCH_SECTORS, MAX_CLUS_SZ, MAX_SEC_SZ};
+use crate::dir_entry::{VolumeLabelEntry, DIRENT_SZ, NO_VOLUME_NAME};
+use crate::error::{FSError, IOError};
+use crate::fat::{FatType, FAT16_MIN_CLUSTERS, FAT32_MAX_CLUSTERS, FAT32_MIN_CLUSTERS};
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+const DEFAULT_RESERVED_SECTORS: u16 = 32;
+const DEFAULT_RESERVED_SECTORS_16: u16 = 1; // FAT12/16 只需要放启动扇区
+const DEFAULT_ROOT_ENTRIES: u16 = 512;
+const DEFAULT_FATS_NUMBER: u8 = 2;
+const DEFAULT_MEDIA: u8 = 0xF8; // 固定磁盘
+pub(crate) const FSINFO_SECTOR: u16 = 1;
+pub(crate) const BACKUP_BOOT_SECTOR: u16 = 6;
+
+/// 格式化参数, 除了总扇区数都有默认值
+#[derive(Copy, Clone, Debug)]
+pub struct FormatOptions {
+    /// 文件系统占用的总扇区数
+    pub total_sectors: u32,
+    /// 簇数必须落在该类型的范围内, 否则挂载时会被识别成别的类型
+    pub fat_type: FatType,
+    /// 扇区字节数, 必须是 512-MAX_SEC_SZ(默认 4096) 中二的整指数倍, 需要和块设备的块大小一致
+    pub bytes_per_sector: u16,
+    /// 每簇扇区数, None 则按照微软推荐的容量表自动选择
+    pub sectors_per_cluster: Option<u8>,
+    pub reserved_sectors: u16,
+    /// FAT 表数, 1 或 2
+    pub fats_number: u8,
+    /// 存储介质类型
+    pub media: u8,
+    /// 分区在整个磁盘中的起始扇区, 格式化整盘时为 0
+    pub hidden_sectors: u32,
+    pub volume_id: u32,
+    /// 卷名, 不足 11 字节用空格填充, 不是 NO NAME 时还会在根目录中创建卷标目录项
+    pub volume_label: [u8; 11],
+    /// FAT12/16 固定根目录区的目录项数, 要占满整数个扇区, FAT32 忽略
+    pub root_entries: u16,
+}
+
+impl FormatOptions {
+    /// FAT32 的默认参数
+    pub fn new(total_sectors: u32) -> Self {
+        Self::with_fat_type(total_sectors, FatType::Fat32)
+    }
+    /// 指定 FAT 类型的默认参数, FAT12/16 只保留 1 个扇区
+    pub fn with_fat_type(total_sectors: u32, fat_type: FatType) -> Self {
+        Self {
+            total_sectors,
+            fat_type,
+            bytes_per_sector: 512,
+            sectors_per_cluster: None,
+            reserved_sectors: if fat_type == FatType::Fat32 {
+                DEFAULT_RESERVED_SECTORS
+            } else {
+                DEFAULT_RESERVED_SECTORS_16
+            },
+            fats_number: DEFAULT_FATS_NUMBER,
+            media: DEFAULT_MEDIA,
+            hidden_sectors: 0,
+            volume_id: 0,
+            volume_label: *b"NO NAME    ",
+            root_entries: DEFAULT_ROOT_ENTRIES,
+        }
+    }
+    // 微软 FAT32 规范中按卷大小推荐的簇大小
+    fn default_cluster_size(&self) -> usize {
+        let volume_size = self.total_sectors as u64 * u64::from(self.bytes_per_sector);
+        match volume_size {
+            0..=0x1040_0000 => 512,                 // <= 260MB
+            0x1040_0001..=0x2_0000_0000 => 4096,    // <= 8GB
+            0x2_0000_0001..=0x4_0000_0000 => 8192,  // <= 16GB
+            0x4_0000_0001..=0x8_0000_0000 => 16384, // <= 32GB
+            _ => 32768,
+        }
+    }
+    fn sectors_per_cluster(&self) -> u8 {
+        if let Some(n) = self.sectors_per_cluster {
+            return n;
+        }
+        if self.fat_type != FatType::Fat32 {
+            return self.smallest_sectors_per_cluster();
+        }
+        let cluster_size = self
+            .default_cluster_size()
+            .max(usize::from(self.bytes_per_sector));
+        (cluster_size / usize::from(self.bytes_per_sector)) as u8
+    }
+    // FAT12/16 选择簇数不超过该类型上限的最小簇
+    fn smallest_sectors_per_cluster(&self) -> u8 {
+        let max_clusters = match self.fat_type {
+            FatType::Fat12 => FAT16_MIN_CLUSTERS - 1,
+            _ => FAT32_MIN_CLUSTERS - 1,
+        };
+        let mut sectors_per_cluster: u8 = 1;
+        while sectors_per_cluster < 128
+            && usize::from(sectors_per_cluster) * usize::from(self.bytes_per_sector) < MAX_CLUS_SZ
+            && self.total_clusters(sectors_per_cluster) > max_clusters
+        {
+            sectors_per_cluster *= 2;
+        }
+        sectors_per_cluster
+    }
+    fn root_dir_sectors(&self) -> u32 {
+        if self.fat_type == FatType::Fat32 {
+            return 0;
+        }
+        (u32::from(self.root_entries) * DIRENT_SZ as u32).div_ceil(u32::from(self.bytes_per_sector))
+    }
+    fn total_clusters(&self, sectors_per_cluster: u8) -> u32 {
+        let meta_sectors = u32::from(self.reserved_sectors)
+            + self.root_dir_sectors()
+            + u32::from(self.fats_number) * self.fats_sectors(sectors_per_cluster);
+        self.total_sectors.saturating_sub(meta_sectors) / u32::from(sectors_per_cluster)
+    }
+    fn validate(&self) -> Result<(), FSError> {
+        if self.bytes_per_sector.count_ones() != 1
+            || self.bytes_per_sector < 512
+            || usize::from(self.bytes_per_sector) > MAX_SEC_SZ
+        {
+            return Err(FSError::InvalidInput);
+        }
+        let sectors_per_cluster = self.sectors_per_cluster();
+        if sectors_per_cluster.count_ones() != 1 || sectors_per_cluster > 128 {
+            return Err(FSError::InvalidInput);
+        }
+        if usize::from(sectors_per_cluster) * usize::from(self.bytes_per_sector) > MAX_CLUS_SZ {
+            return Err(FSError::InvalidInput);
+        }
+        match self.fat_type {
+            // 需要能放下启动扇区, FSInfo 和它们的备份
+            FatType::Fat32 => {
+                if self.reserved_sectors <= BACKUP_BOOT_SECTOR + FSINFO_SECTOR {
+                    return Err(FSError::InvalidInput);
+                }
+            }
+            FatType::Fat12 | FatType::Fat16 => {
+                let root_dir_bytes = usize::from(self.root_entries) * DIRENT_SZ;
+                if self.reserved_sectors == 0
+                    || self.root_entries == 0
+                    || !root_dir_bytes.is_multiple_of(usize::from(self.bytes_per_sector))
+                {
+                    return Err(FSError::InvalidInput);
+                }
+            }
+        }
+        if self.fats_number == 0 || self.fats_number > 2 {
+            return Err(FSError::InvalidInput);
+        }
+        Ok(())
+    }
+    /// 计算每个 FAT 表的扇区数, 保证 FAT 表能装下全部簇和前两个保留项, 结果会略微偏大
+    /// FAT 表越大簇越少, 从 1 开始迭代两轮就能收敛
+    pub(crate) fn fats_sectors(&self, sectors_per_cluster: u8) -> u32 {
+        let meta_sectors = u32::from(self.reserved_sectors) + self.root_dir_sectors();
+        let bits_per_entry = u64::from(self.fat_type.bits_per_entry());
+        let mut fats_sectors: u32 = 1;
+        loop {
+            let data_sectors = self
+                .total_sectors
+                .saturating_sub(meta_sectors + u32::from(self.fats_number) * fats_sectors);
+            let clusters = u64::from(data_sectors / u32::from(sectors_per_cluster));
+            let fat_bytes = ((clusters + START_CLUS_ID as u64) * bits_per_entry).div_ceil(8);
+            let needed = fat_bytes.div_ceil(u64::from(self.bytes_per_sector)) as u32;
+            if needed <= fats_sectors {
+                return fats_sectors;
+            }
+            fats_sectors = needed;
+        }
+    }
+}
+
+/// 按格式化参数在块设备 start_sector 起始处写入启动扇区, FSInfo(仅 FAT32), FAT 表和空的根目录
+pub(crate) fn format(
+    block_device: Arc<dyn BlockDevice>,
+    start_sector: usize,
+    options: FormatOptions,
+) -> Result<(), FSError> {
+    options.validate()?;
+    let sectors_per_cluster = options.sectors_per_cluster();
+    if options.total_sectors <= u32::from(options.reserved_sectors) {
+        return Err(FSError::NotEnoughSpace);
+    }
+    let fats_sectors = options.fats_sectors(sectors_per_cluster);
+    let bpb = BiosParameterBlock::for_format(&options, sectors_per_cluster, fats_sectors);
+    let meta_sectors = bpb.first_data_sector();
+    if options.total_sectors <= meta_sectors {
+        return Err(FSError::NotEnoughSpace);
+    }
+    // 簇数决定 FAT 类型, 必须与要求的类型一致
+    let total_clusters = bpb.total_clusters();
+    let (min_clusters, max_clusters) = match options.fat_type {
+        FatType::Fat12 => (1, FAT16_MIN_CLUSTERS - 1),
+        FatType::Fat16 => (FAT16_MIN_CLUSTERS, FAT32_MIN_CLUSTERS - 1),
+        FatType::Fat32 => (FAT32_MIN_CLUSTERS, FAT32_MAX_CLUSTERS),
+    };
+    if total_clusters < min_clusters {
+        return Err(FSError::NotEnoughSpace);
+    }
+    if total_clusters > max_clusters {
+        return Err(FSError::InvalidInput);
+    }
+    let boot_sector = BootSector::for_format(bpb);
+    boot_sector.validate()?;
+
+    let sector_size = usize::from(options.bytes_per_sector);
+    // 清空保留区, FAT 表区和根目录, FAT32 根目录是紧跟其后的第一个数据簇
+    let mut clear_end = bpb.first_data_sector() as usize;
+    if options.fat_type == FatType::Fat32 {
+        clear_end += usize::from(sectors_per_cluster);
+    }
+    zero_sectors(&block_device, start_sector, clear_end, sector_size)?;
+    // 有卷名时根目录第一项是卷标目录项, 和 BPB 中的卷名一致
+    let mut sector: Vec<u8> = vec![0; sector_size];
+    if options.volume_label != NO_VOLUME_NAME {
+        let volume_entry = VolumeLabelEntry::new(options.volume_label);
+        let root_sector = match options.fat_type {
+            FatType::Fat32 => bpb.first_data_sector(),
+            FatType::Fat12 | FatType::Fat16 => bpb.first_root_dir_sector(),
+        };
+        sector[..DIRENT_SZ].copy_from_slice(volume_entry.as_bytes());
+        block_device.write_block(start_sector + root_sector as usize, &sector)?;
+        sector.fill(0);
+    }
+    // 启动扇区
+    sector[..core::mem::size_of::<BootSector>()].copy_from_slice(boot_sector.as_bytes());
+    block_device.write_block(start_sector, &sector)?;
+    if options.fat_type == FatType::Fat32 {
+        // 备份启动扇区
+        block_device.write_block(start_sector + bpb.backup_boot_sector() as usize, &sector)?;
+        // FSInfo 和备份 FSInfo, 根目录已经占了一个簇
+        let fsinfo = FSInfo::new(total_clusters - 1, START_CLUS_ID as u32 + 1);
+        let fsinfo_sector = FSInfoSector::from_fsinfo(fsinfo);
+        sector.fill(0);
+        sector[..core::mem::size_of::<FSInfoSector>()].copy_from_slice(fsinfo_sector.as_bytes());
+        block_device.write_block(start_sector + bpb.fsinfo_sector() as usize, &sector)?;
+        block_device.write_block(
+            start_sector + (bpb.backup_boot_sector() + bpb.fsinfo_sector()) as usize,
+            &sector,
+        )?;
+    }
+    // FAT 表前两项保留, 第 0 项低字节为介质类型, FAT32 第 2 项是根目录簇链的结尾
+    sector.fill(0);
+    let media = options.media;
+    match options.fat_type {
+        FatType::Fat12 => sector[..3].copy_from_slice(&[media, 0xFF, 0xFF]),
+        FatType::Fat16 => sector[..4].copy_from_slice(&[media, 0xFF, 0xFF, 0xFF]),
+        FatType::Fat32 => {
+            let end = options.fat_type.final_cluster();
+            let reserved_entries = [0x0FFF_FF00 | u32::from(media), end, end];
+            for (i, entry) in reserved_entries.iter().enumerate() {
+                sector[i * 4..(i + 1) * 4].copy_from_slice(&entry.to_le_bytes());
+            }
+        }
+    }
+    for fat in 0..u32::from(options.fats_number) {
+        let fat_sector = bpb.first_fats_sector() + fat * fats_sectors;
+        block_device.write_block(start_sector + fat_sector as usize, &sector)?;
+    }
+    Ok(())
+}
+
+/// 从 start 开始清零 count 个扇区, 每次最多写 BATCH_SECTORS 个
+pub(crate) fn zero_sectors(
+    block_device: &Arc<dyn BlockDevice>,
+    start: usize,
+    count: usize,
+    sector_size: usize,
+) -> Result<(), IOError> {
+    let zero: Vec<u8> = vec![0; sector_size * BATCH_SECTORS.min(count)];
+    let mut done = 0;
+    while done < count {
+        let n = BATCH_SECTORS.min(count - done);
+        block_device.write_blocks(start + done, n, &zero[..n * sector_size])?;
+        done += n;
+    }
+    Ok(())
+}
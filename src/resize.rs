@@ -0,0 +1,291 @@
+// 调整 FAT32 卷的大小, 簇号保持不变, FAT 表大小改变时整体搬移数据区
+use super::{
+    BiosParameterBlock, BlockDevice, FATEntry, FSInfo, FatType, FormatOptions, RunFileSystem,
+    START_CLUS_ID,
+};
+use crate::config::BATCH_SECTORS;
+use crate::dir_entry::DIRENT_SZ;
+use crate::error::FSError;
+use crate::mkfs::zero_sectors;
+#[cfg(not(feature = "std"))]
+use alloc::{sync::Arc, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+pub(crate) fn resize(runfs: &mut RunFileSystem, total_sectors: u32) -> Result<(), FSError> {
+    let bpb = *runfs.bpb();
+    if bpb.fat_type() != FatType::Fat32 {
+        return Err(FSError::InvalidInput);
+    }
+    if total_sectors == bpb.total_sectors() {
+        return Ok(());
+    }
+    // 放不下新卷的块设备, 改完就挂载不上了
+    if let Some(num_blocks) = runfs.block_device().num_blocks() {
+        if runfs.start_sector() as u64 + u64::from(total_sectors) > num_blocks as u64 {
+            return Err(FSError::InvalidInput);
+        }
+    }
+    // 扩容时 FAT 表只增不减, 缩小时顺便收回多余的 FAT 表扇区
+    let needed = fats_sectors_for(&bpb, total_sectors);
+    let fats_sectors = if total_sectors > bpb.total_sectors() {
+        needed.max(bpb.fats_sectors())
+    } else {
+        needed.min(bpb.fats_sectors())
+    };
+    let new_bpb = bpb.resized(total_sectors, fats_sectors, bpb.root_dir_cluster());
+    // 簇数太少会被识别成 FAT16
+    if total_sectors <= new_bpb.first_data_sector() || new_bpb.fat_type() != FatType::Fat32 {
+        return Err(FSError::InvalidInput);
+    }
+    new_bpb.validate().map_err(|_| FSError::InvalidInput)?;
+    let total_clusters = new_bpb.total_clusters();
+    let mut root_dir_cluster = bpb.root_dir_cluster();
+    if total_clusters < bpb.total_clusters() {
+        let free = runfs.fat_manager_modify().count_free_clusters()?;
+        if bpb.total_clusters() - free > total_clusters {
+            return Err(FSError::NotEnoughSpace);
+        }
+        root_dir_cluster = relocate_clusters(runfs, total_clusters as usize + START_CLUS_ID)?;
+    }
+    let highest = highest_allocated_cluster(runfs)?;
+    if highest >= total_clusters as usize + START_CLUS_ID {
+        // 目录树中找不到的簇链没法搬
+        log::error!("cluster {} is allocated but not reachable", highest);
+        return Err(FSError::CorruptedFileSystem);
+    }
+    let new_bpb = bpb.resized(total_sectors, fats_sectors, root_dir_cluster);
+    let block_device = runfs.block_device();
+    let start_sector = runfs.start_sector();
+    if fats_sectors != bpb.fats_sectors() {
+        // 先写回缓存, 之后直接读写块设备
+        let fsinfo = runfs.fsinfo();
+        runfs.reload(bpb, fsinfo)?;
+        let sectors = (highest + 1 - START_CLUS_ID) as u32 * u32::from(bpb.sectors_per_cluster());
+        // 数据区下移时会盖住旧的 FAT 表(不镜像时活动的可能是后面的表), 要先把 FAT 表搬到新位置
+        // 数据区上移时新的 FAT 表会盖住旧数据区的开头, 要先搬数据区
+        let shrink = new_bpb.first_data_sector() < bpb.first_data_sector();
+        if shrink {
+            rewrite_fats(&block_device, start_sector, &bpb, &new_bpb)?;
+        }
+        move_sectors(
+            &block_device,
+            start_sector,
+            &bpb,
+            bpb.first_data_sector(),
+            new_bpb.first_data_sector(),
+            sectors,
+        )?;
+        if !shrink {
+            rewrite_fats(&block_device, start_sector, &bpb, &new_bpb)?;
+        }
+    }
+    runfs.write_bpb(new_bpb)?;
+    // 空闲簇数和下一个空闲簇都重新扫描 FAT 表得到
+    runfs.reload(new_bpb, FSInfo::default())?;
+    runfs.fat_manager_modify().sync_fsinfo()
+}
+
+// 新卷大小需要的 FAT 表扇区数, 和格式化时的算法一致
+fn fats_sectors_for(bpb: &BiosParameterBlock, total_sectors: u32) -> u32 {
+    let mut options = FormatOptions::new(total_sectors);
+    options.bytes_per_sector = bpb.bytes_per_sector();
+    options.reserved_sectors = bpb.reserved_sectors() as u16;
+    options.fats_number = bpb.fats_number();
+    options.fats_sectors(bpb.sectors_per_cluster())
+}
+
+// 最大的已分配簇号, 坏簇不算
+fn highest_allocated_cluster(runfs: &RunFileSystem) -> Result<usize, FSError> {
+    let end_cluster = runfs.bpb().total_clusters() as usize + START_CLUS_ID;
+    let mut fat_manager = runfs.fat_manager_modify();
+    for cluster_id in (START_CLUS_ID..end_cluster).rev() {
+        if !matches!(
+            fat_manager.entry(cluster_id)?,
+            FATEntry::Free | FATEntry::Bad
+        ) {
+            return Ok(cluster_id);
+        }
+    }
+    Ok(START_CLUS_ID)
+}
+
+// 把 sectors 个扇区从 from 搬到 to, 区域重叠时从远离移动方向的一端开始复制
+fn move_sectors(
+    block_device: &Arc<dyn BlockDevice>,
+    start_sector: usize,
+    bpb: &BiosParameterBlock,
+    from: u32,
+    to: u32,
+    sectors: u32,
+) -> Result<(), FSError> {
+    let sector_size = usize::from(bpb.bytes_per_sector());
+    let mut buf: Vec<u8> = vec![0; sector_size * BATCH_SECTORS.min(sectors as usize)];
+    // 每段先整段读出再写入, 段内重叠不影响结果
+    let mut copy = |(i, n): (u32, usize)| {
+        let buf = &mut buf[..n * sector_size];
+        block_device.read_blocks(start_sector + (from + i) as usize, n, buf)?;
+        block_device.write_blocks(start_sector + (to + i) as usize, n, buf)?;
+        Ok(())
+    };
+    let mut runs = (0..sectors)
+        .step_by(BATCH_SECTORS)
+        .map(|i| (i, BATCH_SECTORS.min((sectors - i) as usize)));
+    if to > from {
+        runs.rev().try_for_each(&mut copy)
+    } else {
+        runs.try_for_each(&mut copy)
+    }
+}
+
+// 以活动 FAT 表为准在新位置重写所有 FAT 表, 新增的扇区清零
+// 先把活动 FAT 表搬到新位置, 再复制给其余 FAT 表, 避免覆盖还没搬走的表项
+fn rewrite_fats(
+    block_device: &Arc<dyn BlockDevice>,
+    start_sector: usize,
+    old_bpb: &BiosParameterBlock,
+    new_bpb: &BiosParameterBlock,
+) -> Result<(), FSError> {
+    let active_fat = old_bpb.active_fat();
+    let fats_sectors = new_bpb.fats_sectors();
+    let kept = old_bpb.fats_sectors().min(fats_sectors);
+    let active_sector = new_bpb.fat_start_sector(active_fat);
+    move_sectors(
+        block_device,
+        start_sector,
+        new_bpb,
+        old_bpb.fat_start_sector(active_fat),
+        active_sector,
+        kept,
+    )?;
+    for fat in (0..new_bpb.fats_number()).filter(|fat| *fat != active_fat) {
+        move_sectors(
+            block_device,
+            start_sector,
+            new_bpb,
+            active_sector,
+            new_bpb.fat_start_sector(fat),
+            kept,
+        )?;
+    }
+    for fat in 0..new_bpb.fats_number() {
+        zero_sectors(
+            block_device,
+            start_sector + (new_bpb.fat_start_sector(fat) + kept) as usize,
+            (fats_sectors - kept) as usize,
+            usize::from(new_bpb.bytes_per_sector()),
+        )?;
+    }
+    Ok(())
+}
+
+// 把簇号不小于 limit 的簇搬到前面的空闲簇, 从根目录开始遍历整个目录树, 返回根目录新的起始簇
+fn relocate_clusters(runfs: &RunFileSystem, limit: usize) -> Result<u32, FSError> {
+    let mut relocator = Relocator {
+        runfs,
+        limit,
+        next_free: START_CLUS_ID,
+        buf: vec![0; runfs.bpb().cluster_size()],
+    };
+    let root_dir_cluster = relocator.relocate_chain(runfs.bpb().root_dir_cluster() as usize)?;
+    relocator.relocate_dir(root_dir_cluster, None)?;
+    Ok(root_dir_cluster as u32)
+}
+
+struct Relocator<'a> {
+    runfs: &'a RunFileSystem,
+    limit: usize,     // 搬移后所有簇号都要小于它
+    next_free: usize, // 下一次查找空闲簇的起点, 前面的都已经用掉了
+    buf: Vec<u8>,
+}
+
+impl Relocator<'_> {
+    fn free_cluster(&mut self) -> Result<usize, FSError> {
+        let mut fat_manager = self.runfs.fat_manager_modify();
+        while self.next_free < self.limit {
+            let cluster_id = self.next_free;
+            self.next_free += 1;
+            if fat_manager.entry(cluster_id)? == FATEntry::Free {
+                return Ok(cluster_id);
+            }
+        }
+        Err(FSError::NotEnoughSpace)
+    }
+    /// 把簇链中超出范围的簇逐个搬走, 返回簇链新的起始簇
+    fn relocate_chain(&mut self, first_cluster: usize) -> Result<usize, FSError> {
+        let mut first = first_cluster;
+        let mut prev: Option<usize> = None;
+        let mut cluster = Some(first_cluster);
+        while let Some(mut cluster_id) = cluster {
+            cluster = self.runfs.fat_manager_modify().next_cluster(cluster_id)?;
+            if cluster_id >= self.limit {
+                let target = self.free_cluster()?;
+                let mut data_manager = self.runfs.data_manager_modify();
+                data_manager.read_cluster(cluster_id, &mut self.buf)?;
+                data_manager.write_cluster(target, &self.buf)?;
+                let mut fat_manager = self.runfs.fat_manager_modify();
+                let entry = fat_manager.entry(cluster_id)?;
+                fat_manager.set_entry(target, entry)?;
+                match prev {
+                    Some(prev) => fat_manager.set_next_cluster(prev, target as u32)?,
+                    None => first = target,
+                }
+                fat_manager.set_free(cluster_id)?;
+                cluster_id = target;
+            }
+            prev = Some(cluster_id);
+        }
+        Ok(first)
+    }
+    /// 搬移目录中每一项的簇链并修正起始簇, 子目录递归处理
+    /// parent 是写进 .. 的父目录簇号(父目录是根目录时为 0), 根目录没有 . 和 ..
+    fn relocate_dir(&mut self, dir_cluster: usize, parent: Option<usize>) -> Result<(), FSError> {
+        let clusters = self.runfs.fat_manager_modify().all_clusters(dir_cluster)?;
+        let children_parent = if parent.is_some() { dir_cluster } else { 0 };
+        for (index, cluster_id) in clusters.into_iter().enumerate() {
+            for offset in (0..self.buf.len()).step_by(DIRENT_SZ) {
+                let dirent = self.runfs.data_manager_modify().read_short_dirent(
+                    cluster_id,
+                    offset,
+                    |e| *e,
+                )?;
+                if dirent.is_empty() {
+                    return Ok(());
+                }
+                if dirent.is_deleted() || !dirent.is_short() || dirent.is_volume() {
+                    continue;
+                }
+                // 子目录开头的两项是 . 和 .., 按位置识别
+                if let (Some(parent), 0) = (parent, index) {
+                    if offset < 2 * DIRENT_SZ {
+                        let target = if offset == 0 { dir_cluster } else { parent };
+                        if dirent.first_cluster() as usize != target {
+                            self.runfs.data_manager_modify().modify_short_dirent(
+                                cluster_id,
+                                offset,
+                                |e| e.set_first_cluster(target as u32),
+                            )?;
+                        }
+                        continue;
+                    }
+                }
+                let first_cluster = dirent.first_cluster() as usize;
+                if first_cluster < START_CLUS_ID {
+                    continue;
+                }
+                let new_first = self.relocate_chain(first_cluster)?;
+                if new_first != first_cluster {
+                    self.runfs.data_manager_modify().modify_short_dirent(
+                        cluster_id,
+                        offset,
+                        |e| e.set_first_cluster(new_first as u32),
+                    )?;
+                }
+                if dirent.is_dir() {
+                    self.relocate_dir(new_first, Some(children_parent))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,42 +1,162 @@
 //对文件系统的全局管理.
 use super::{
-    BiosParameterBlock, BlockDevice, BootSector, DataManager, FATManager, FSInfo, FSInfoSector,
-    FileAttributes, ShortDirectoryEntry, VFile,
+    fsck, gpt, mbr, mkfs, resize, AsyncAdapter, AsyncBlockDevice, BiosParameterBlock, BlockDevice,
+    BlockingAdapter, BootSector, CheckReport, DataManager, FATManager, FSError, FSInfo,
+    FSInfoSector, FatType, FileAttributes, FormatOptions, SectorCopy, ShortDirectoryEntry, VFile,
+    VolumeLabelEntry, DIRENT_SZ, START_CLUS_ID,
 };
+use crate::data::FIXED_ROOT_CLUSTER;
+use crate::dir_entry::{volume_label_bytes, volume_label_string, NO_VOLUME_NAME};
 #[cfg(not(feature = "std"))]
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 #[cfg(feature = "std")]
 use std::sync::Arc;
 
+/// 挂载参数, 默认全部关闭
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MountOptions {
+    /// 回收簇时用 BlockDevice::discard 通知块设备(TRIM)
+    pub discard: bool,
+}
+
 /// 包括 BPB 和 FSInfo 的信息
 pub struct RunFileSystem {
     bpb: Arc<BiosParameterBlock>,
+    start_sector: usize, // 文件系统在块设备上的起始扇区, 不分区时为 0
+    block_device: Arc<dyn BlockDevice>,
+    boot_sector_copy: SectorCopy,
+    fsinfo_copy: Option<SectorCopy>, // FAT12/16 或两份都损坏时为 None
+    options: MountOptions,
     fat_manager: Arc<RwLock<FATManager>>,
     data_manager: Arc<RwLock<DataManager>>,
+    async_device: Option<Arc<dyn AsyncBlockDevice>>, // 用 from_async 挂载时的异步块设备
 }
 
 impl RunFileSystem {
-    pub fn new(block_device: Arc<dyn BlockDevice>) -> Self {
-        let boot_sector = BootSector::directly_new(Arc::clone(&block_device));
-        let res = boot_sector.validate();
-        match res {
-            Ok(v) => v,
-            Err(e) => log::error!("Bios Parameter Block not valid: {:?}", e),
-        }
+    pub fn new(block_device: Arc<dyn BlockDevice>) -> Result<Self, FSError> {
+        Self::new_at(block_device, 0)
+    }
+    /// 挂载从块设备 start_sector 扇区开始的文件系统, 之后所有扇区号都会加上这个偏移
+    /// 主启动扇区或主 FSInfo 损坏时使用 FAT32 的备份, 启动扇区都不可用时返回错误
+    pub fn new_at(
+        block_device: Arc<dyn BlockDevice>,
+        start_sector: usize,
+    ) -> Result<Self, FSError> {
+        Self::mount(block_device, start_sector, MountOptions::default())
+    }
+    /// 和 new_at 相同, 但可以指定挂载参数
+    /// 块设备报告了块大小或总块数时, 扇区大小不一致或文件系统超出设备都会返回错误
+    pub fn mount(
+        block_device: Arc<dyn BlockDevice>,
+        start_sector: usize,
+        options: MountOptions,
+    ) -> Result<Self, FSError> {
+        let (boot_sector, boot_sector_copy) = BootSector::read_valid(&block_device, start_sector)
+            .map_err(|e| {
+            log::error!("Bios Parameter Block not valid: {:?}", e);
+            e
+        })?;
         let bpb = Arc::new(boot_sector.bpb);
-        let fsinfo_block_id: usize = bpb.fsinfo_sector().try_into().unwrap();
-        let fsinfo_sector = FSInfoSector::directly_new(fsinfo_block_id, Arc::clone(&block_device));
-        let res = fsinfo_sector.validate();
-        match res {
-            Ok(v) => v,
-            Err(e) => log::error!("FSInfo Block not valid: {:?}", e),
-        }
-        let mut fsinfo = FSInfo::new(
-            fsinfo_sector.free_clusters_raw(),
-            fsinfo_sector.next_free_cluster_raw(),
-        );
-        fsinfo.validate_and_fix(bpb.total_clusters());
+        Self::check_device(&block_device, start_sector, &bpb)?;
+        // FAT12/16 没有 FSInfo, 空闲簇信息在 recalculate_fsinfo 中扫描 FAT 表得到
+        let mut fsinfo = FSInfo::default();
+        let mut fsinfo_copy = None;
+        if bpb.fat_type() == FatType::Fat32 {
+            let copies = [
+                (SectorCopy::Primary, bpb.fsinfo_sector()),
+                (
+                    SectorCopy::Backup,
+                    bpb.backup_boot_sector() + bpb.fsinfo_sector(),
+                ),
+            ];
+            let mut found = None;
+            for (copy, sector_id) in copies {
+                let fsinfo_sector = FSInfoSector::directly_new(
+                    start_sector + sector_id as usize,
+                    Arc::clone(&block_device),
+                )?;
+                if fsinfo_sector.validate().is_ok() {
+                    found = Some((fsinfo_sector, copy));
+                    break;
+                }
+            }
+            match found {
+                Some((fsinfo_sector, copy)) => {
+                    fsinfo = FSInfo::new(
+                        fsinfo_sector.free_clusters_raw(),
+                        fsinfo_sector.next_free_cluster_raw(),
+                    );
+                    fsinfo.validate_and_fix(bpb.total_clusters());
+                    fsinfo_copy = Some(copy);
+                }
+                None => log::error!("FSInfo Block not valid, recalculating"),
+            }
+        }
+        let (fat_manager, data_manager) =
+            Self::managers(&bpb, fsinfo, start_sector, &block_device, options);
+        fat_manager.write().recalculate_fsinfo()?;
+        Ok(Self {
+            bpb,
+            start_sector,
+            block_device,
+            boot_sector_copy,
+            fsinfo_copy,
+            options,
+            fat_manager,
+            data_manager,
+            async_device: None,
+        })
+    }
+    /// 在异步块设备上挂载, VFile 的 read_at_async/write_at_async 直接等待这个设备
+    /// 挂载本身和其他同步接口经过 BlockingAdapter, 会忙等块设备完成
+    pub fn from_async(
+        device: Arc<dyn AsyncBlockDevice>,
+        start_sector: usize,
+        options: MountOptions,
+    ) -> Result<Self, FSError> {
+        let blocking = Arc::new(BlockingAdapter::new(Arc::clone(&device)));
+        let mut runfs = Self::mount(blocking, start_sector, options)?;
+        runfs.async_device = Some(device);
+        Ok(runfs)
+    }
+    // 块设备知道自己的几何参数时检查文件系统能不能放下
+    fn check_device(
+        block_device: &Arc<dyn BlockDevice>,
+        start_sector: usize,
+        bpb: &BiosParameterBlock,
+    ) -> Result<(), FSError> {
+        if let Some(block_size) = block_device.block_size() {
+            if block_size != bpb.bytes_per_sector() as usize {
+                log::error!(
+                    "block size {} does not match sector size {}",
+                    block_size,
+                    bpb.bytes_per_sector()
+                );
+                return Err(FSError::InvalidInput);
+            }
+        }
+        if let Some(num_blocks) = block_device.num_blocks() {
+            if start_sector as u64 + u64::from(bpb.total_sectors()) > num_blocks as u64 {
+                log::error!(
+                    "file system ({} sectors at {}) is larger than the device ({} blocks)",
+                    bpb.total_sectors(),
+                    start_sector,
+                    num_blocks
+                );
+                return Err(FSError::CorruptedFileSystem);
+            }
+        }
+        Ok(())
+    }
+    // 按 BPB 创建 FAT 表和数据区的管理器
+    fn managers(
+        bpb: &Arc<BiosParameterBlock>,
+        fsinfo: FSInfo,
+        start_sector: usize,
+        block_device: &Arc<dyn BlockDevice>,
+        options: MountOptions,
+    ) -> (Arc<RwLock<FATManager>>, Arc<RwLock<DataManager>>) {
         let root_dirent = ShortDirectoryEntry::new(
             [0x2F, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20], // 根目录文件名为 /
             [0x20, 0x20, 0x20],
@@ -46,26 +166,393 @@ impl RunFileSystem {
         let fat_manager = Arc::new(RwLock::new(FATManager::new(
             fsinfo,
             bpb.clone(),
-            Arc::clone(&block_device),
+            start_sector,
+            Arc::clone(block_device),
         )));
-        fat_manager.write().recalculate_fsinfo();
-        Self {
-            bpb: bpb.clone(),
-            fat_manager,
-            data_manager: Arc::new(RwLock::new(DataManager::new(
-                bpb.clone(),
-                Arc::new(RwLock::new(root_dirent)),
-                Arc::clone(&block_device),
-            ))),
+        fat_manager.write().set_discard(options.discard);
+        let data_manager = Arc::new(RwLock::new(DataManager::new(
+            bpb.clone(),
+            Arc::new(RwLock::new(root_dirent)),
+            start_sector,
+            Arc::clone(block_device),
+        )));
+        (fat_manager, data_manager)
+    }
+    /// 换上新的 BPB 重建管理器, 先写回旧管理器的缓存, 之后可以直接读写块设备
+    /// 写回失败时返回错误, 不换管理器
+    pub(crate) fn reload(
+        &mut self,
+        bpb: BiosParameterBlock,
+        fsinfo: FSInfo,
+    ) -> Result<(), FSError> {
+        self.fat_manager.write().sync()?;
+        self.data_manager.write().sync()?;
+        self.bpb = Arc::new(bpb);
+        let (fat_manager, data_manager) = Self::managers(
+            &self.bpb,
+            fsinfo,
+            self.start_sector,
+            &self.block_device,
+            self.options,
+        );
+        self.fat_manager = fat_manager;
+        self.data_manager = data_manager;
+        self.fat_manager.write().recalculate_fsinfo()
+    }
+    /// 挂载整盘镜像 MBR 分区表中的第 index 个分区(从 0 开始, 主分区在前, 逻辑分区在后)
+    pub fn open_mbr_partition(
+        block_device: Arc<dyn BlockDevice>,
+        index: usize,
+    ) -> Result<Self, FSError> {
+        let partitions = mbr::read_mbr_partitions(&block_device)?;
+        let partition = partitions.get(index).ok_or(FSError::NotFound)?;
+        Self::open_partition(
+            block_device,
+            u64::from(partition.start_lba),
+            u64::from(partition.sectors),
+        )
+    }
+    /// 挂载整盘镜像 GPT 中的第 index 个非空分区(从 0 开始)
+    pub fn open_gpt_partition(
+        block_device: Arc<dyn BlockDevice>,
+        index: usize,
+    ) -> Result<Self, FSError> {
+        let partitions = gpt::read_gpt_partitions(&block_device)?;
+        let partition = partitions.get(index).ok_or(FSError::NotFound)?;
+        Self::open_partition(block_device, partition.first_lba, partition.sectors())
+    }
+    /// 挂载整盘镜像 GPT 中的第一个 EFI 系统分区
+    pub fn open_efi_system_partition(block_device: Arc<dyn BlockDevice>) -> Result<Self, FSError> {
+        let partitions = gpt::read_gpt_partitions(&block_device)?;
+        let partition = partitions
+            .iter()
+            .find(|p| p.is_efi_system())
+            .ok_or(FSError::NotFound)?;
+        Self::open_partition(block_device, partition.first_lba, partition.sectors())
+    }
+    // 按分区起始扇区挂载, 并检查 BPB 与分区表是否一致
+    fn open_partition(
+        block_device: Arc<dyn BlockDevice>,
+        start_lba: u64,
+        sectors: u64,
+    ) -> Result<Self, FSError> {
+        let start_sector: usize = start_lba.try_into().map_err(|_| FSError::InvalidInput)?;
+        let runfs = Self::new_at(block_device, start_sector)?;
+        let bpb = runfs.bpb();
+        if u64::from(bpb.hidden_sectors()) != start_lba {
+            log::warn!(
+                "hidden_sectors {} in BPB does not match partition start {}",
+                bpb.hidden_sectors(),
+                start_lba
+            );
         }
+        if u64::from(bpb.total_sectors()) > sectors {
+            log::error!(
+                "file system ({} sectors) is larger than its partition ({} sectors)",
+                bpb.total_sectors(),
+                sectors
+            );
+            return Err(FSError::CorruptedFileSystem);
+        }
+        Ok(runfs)
+    }
+    /// 在块设备上创建全新的 FAT 文件系统, 类型由 options.fat_type 指定, 原有数据会被覆盖, 之后用 new 挂载
+    pub fn format(
+        block_device: Arc<dyn BlockDevice>,
+        options: FormatOptions,
+    ) -> Result<(), FSError> {
+        mkfs::format(block_device, 0, options)
+    }
+    /// 在块设备 start_sector 扇区开始的分区中创建文件系统, 一般还要把 hidden_sectors 设成相同的值
+    pub fn format_at(
+        block_device: Arc<dyn BlockDevice>,
+        start_sector: usize,
+        options: FormatOptions,
+    ) -> Result<(), FSError> {
+        mkfs::format(block_device, start_sector, options)
+    }
+    /// 把 FAT32 卷调整为 total_sectors 个扇区, 块设备上必须已经有这么多扇区
+    /// FAT 表大小改变时整体搬移数据区, 缩小时先把超出范围的簇搬到前面, 放不下返回 NotEnoughSpace
+    /// 缩小后之前打开的 VFile 记录的目录项位置可能失效, 需要重新查找
+    pub fn resize(&mut self, total_sectors: u32) -> Result<(), FSError> {
+        resize::resize(self, total_sectors)
+    }
+    /// 一致性检查, 遍历整个目录树和 FAT 表, 只读不改
+    /// 返回发现的问题, 读块设备失败时返回错误
+    pub fn check(&self) -> Result<CheckReport, FSError> {
+        fsck::check(self, false)
+    }
+    /// 先写回缓存, 再做一致性检查并读一遍所有目录和文件的簇, 文件的簇直接从块设备读
+    /// 读不出来的簇记为 Unreadable, 配合 ChecksumDevice 可以找出哪个文件的哪个簇坏了
+    /// FAT 表读不出来时仍然返回错误
+    pub fn scrub(&self) -> Result<CheckReport, FSError> {
+        self.sync()?;
+        fsck::check(self, true)
     }
     /// Returns a volume identifier read from BPB in the Boot Sector.
     pub fn volume_id(&self) -> u32 {
         self.bpb.volumn_id()
     }
+    /// 卷名, 优先使用根目录中的卷标目录项, 其次是 BPB 中的卷名, 都没有时为空字符串
+    pub fn volume_label(&self) -> Result<String, FSError> {
+        if let Some((cluster_id, offset)) = self.find_volume_dirent()? {
+            return self.data_manager_modify().read_volume_dirent(
+                cluster_id,
+                offset,
+                |entry: &VolumeLabelEntry| entry.name(),
+            );
+        }
+        let volume_label = self.bpb.volume_label();
+        if volume_label == NO_VOLUME_NAME {
+            Ok(String::new())
+        } else {
+            Ok(volume_label_string(&volume_label))
+        }
+    }
+    /// 修改卷名, 同时写启动扇区(FAT32 还有备份启动扇区)和根目录中的卷标目录项
+    /// 小写字母会转成大写, 空字符串表示删除卷名
+    pub fn set_volume_label(&mut self, label: &str) -> Result<(), FSError> {
+        let name = volume_label_bytes(label)?;
+        let position = self.find_volume_dirent()?;
+        if label.is_empty() {
+            if let Some((cluster_id, offset)) = position {
+                self.data_manager_modify().modify_volume_dirent(
+                    cluster_id,
+                    offset,
+                    |entry: &mut VolumeLabelEntry| entry.set_deleted(),
+                )?;
+            }
+        } else {
+            let (cluster_id, offset) = match position {
+                Some(position) => position,
+                None => self.free_root_dirent()?,
+            };
+            self.data_manager_modify().modify_volume_dirent(
+                cluster_id,
+                offset,
+                |entry: &mut VolumeLabelEntry| *entry = VolumeLabelEntry::new(name),
+            )?;
+        }
+        let mut bpb = *self.bpb;
+        bpb.set_volume_label(if label.is_empty() {
+            NO_VOLUME_NAME
+        } else {
+            name
+        });
+        self.write_bpb(bpb)?;
+        self.bpb = Arc::new(bpb);
+        Ok(())
+    }
+    /// 把活动 FAT 表复制到其余 FAT 表, 用于修复后重新同步
+    pub fn mirror_fats(&mut self) -> Result<(), FSError> {
+        self.fat_manager.write().mirror_fats()
+    }
+    /// 切换活动 FAT 表, 只用于 FAT32
+    /// Some(n) 关闭镜像, 之后只读写第 n 个 FAT 表; None 先把当前活动 FAT 表复制到其余 FAT 表再打开镜像
+    pub fn set_active_fat(&mut self, active_fat: Option<u8>) -> Result<(), FSError> {
+        if self.bpb.fat_type() != FatType::Fat32 {
+            return Err(FSError::InvalidInput);
+        }
+        match active_fat {
+            Some(n) if n >= self.bpb.fats_number() => return Err(FSError::InvalidInput),
+            Some(_) => {}
+            None => self.mirror_fats()?,
+        }
+        let mut bpb = *self.bpb;
+        bpb.set_active_fat(active_fat);
+        self.write_bpb(bpb)?;
+        // FAT 管理器按新的 BPB 选择读写的 FAT 表
+        let fsinfo = self.fsinfo();
+        self.reload(bpb, fsinfo)
+    }
+    /// 挂载时使用的启动扇区副本
+    pub fn boot_sector_copy(&self) -> SectorCopy {
+        self.boot_sector_copy
+    }
+    /// 挂载时使用的 FSInfo 副本, FAT12/16 或两份都损坏时为 None
+    pub fn fsinfo_copy(&self) -> Option<SectorCopy> {
+        self.fsinfo_copy
+    }
+    /// 用挂载时通过校验的备份重写损坏的主启动扇区, 主 FSInfo 损坏时按当前的空闲簇信息重写
+    /// FAT12/16 没有备份, 什么也不做
+    pub fn repair_boot_sector(&mut self) -> Result<(), FSError> {
+        if self.bpb.fat_type() != FatType::Fat32 {
+            return Ok(());
+        }
+        if self.boot_sector_copy == SectorCopy::Backup {
+            // 整个扇区照搬, 包括引导代码
+            let mut sector: Vec<u8> = vec![0; usize::from(self.bpb.bytes_per_sector())];
+            self.block_device.read_block(
+                self.start_sector + self.bpb.backup_boot_sector() as usize,
+                &mut sector,
+            )?;
+            self.block_device.write_block(self.start_sector, &sector)?;
+            self.boot_sector_copy = SectorCopy::Primary;
+            log::info!("boot sector repaired from backup");
+        }
+        if self.fsinfo_copy != Some(SectorCopy::Primary) {
+            self.fat_manager_modify().sync_fsinfo()?;
+            self.fsinfo_copy = Some(SectorCopy::Primary);
+        }
+        Ok(())
+    }
+    /// 安装引导代码和跳转指令, 只支持 FAT32, BPB 保持不变, 主备启动扇区一起写
+    /// boot_file 是根目录中要链式加载的文件的短文件名, 它的起始簇号记录在引导代码区的最后 4 Byte,
+    /// 这时引导代码最长 BOOT_FILE_CLUSTER_OFFSET Byte
+    pub fn install_boot_code(
+        &mut self,
+        jump: [u8; 3],
+        code: &[u8],
+        boot_file: Option<&str>,
+    ) -> Result<(), FSError> {
+        if self.bpb.fat_type() != FatType::Fat32 {
+            return Err(FSError::InvalidInput);
+        }
+        if boot_file.is_some() && code.len() > BootSector::BOOT_FILE_CLUSTER_OFFSET {
+            return Err(FSError::InvalidInput);
+        }
+        let mut boot_sector = self.read_boot_sector()?;
+        boot_sector.set_bootjump(jump)?;
+        boot_sector.set_boot_code(code)?;
+        if let Some(name) = boot_file {
+            let cluster = self.find_root_file(name)?;
+            boot_sector.set_boot_file_cluster(cluster);
+        }
+        boot_sector.write_back(&self.block_device, self.start_sector)
+    }
+    // 按短文件名在根目录中查找文件, 返回起始簇号, 空文件没有簇不能链式加载
+    fn find_root_file(&self, name: &str) -> Result<u32, FSError> {
+        let positions = self.root_dirent_positions()?;
+        let mut data_manager = self.data_manager_modify();
+        for (cluster_id, offset) in positions {
+            let entry = data_manager.read_short_dirent(cluster_id, offset, |e| *e)?;
+            if entry.is_empty() {
+                break;
+            }
+            if entry.is_deleted() || !entry.is_short() || entry.is_volume() {
+                continue;
+            }
+            if entry.name().eq_ignore_ascii_case(name) {
+                if !entry.is_file() || (entry.first_cluster() as usize) < START_CLUS_ID {
+                    return Err(FSError::InvalidInput);
+                }
+                return Ok(entry.first_cluster());
+            }
+        }
+        Err(FSError::NotFound)
+    }
+    // 挂载时通过校验的那份启动扇区
+    fn read_boot_sector(&self) -> Result<BootSector, FSError> {
+        let sector_id = match self.boot_sector_copy {
+            SectorCopy::Primary => self.start_sector,
+            SectorCopy::Backup => self.start_sector + self.bpb.backup_boot_sector() as usize,
+        };
+        BootSector::directly_new_at(self.block_device(), sector_id)
+    }
+    /// 把 BPB 写回启动扇区, FAT32 同时写备份启动扇区
+    pub(crate) fn write_bpb(&self, bpb: BiosParameterBlock) -> Result<(), FSError> {
+        let mut boot_sector = self.read_boot_sector()?;
+        boot_sector.bpb = bpb;
+        boot_sector.write_back(&self.block_device, self.start_sector)
+    }
+    // 根目录中所有目录项的位置, FAT12/16 固定根目录区的簇号为 FIXED_ROOT_CLUSTER
+    fn root_dirent_positions(&self) -> Result<Vec<(usize, usize)>, FSError> {
+        let (clusters, size) = if self.bpb.fat_type() == FatType::Fat32 {
+            let root_dir_cluster = self.bpb.root_dir_cluster() as usize;
+            (
+                self.fat_manager_modify().all_clusters(root_dir_cluster)?,
+                self.bpb.cluster_size(),
+            )
+        } else {
+            (vec![FIXED_ROOT_CLUSTER], self.bpb.root_dir_size())
+        };
+        Ok(clusters
+            .into_iter()
+            .flat_map(|cluster_id| {
+                (0..size)
+                    .step_by(DIRENT_SZ)
+                    .map(move |offset| (cluster_id, offset))
+            })
+            .collect())
+    }
+    // 根目录中卷标目录项的位置
+    fn find_volume_dirent(&self) -> Result<Option<(usize, usize)>, FSError> {
+        let positions = self.root_dirent_positions()?;
+        let mut data_manager = self.data_manager_modify();
+        for (cluster_id, offset) in positions {
+            let (is_empty, is_volume_label) = data_manager.read_volume_dirent(
+                cluster_id,
+                offset,
+                |entry: &VolumeLabelEntry| (entry.is_empty(), entry.is_volume_label()),
+            )?;
+            if is_empty {
+                break;
+            }
+            if is_volume_label {
+                return Ok(Some((cluster_id, offset)));
+            }
+        }
+        Ok(None)
+    }
+    // 根目录中第一个空闲目录项, FAT32 根目录满了再分配一个簇, FAT12/16 根目录区大小固定
+    fn free_root_dirent(&mut self) -> Result<(usize, usize), FSError> {
+        let positions = self.root_dirent_positions()?;
+        for (cluster_id, offset) in positions.iter() {
+            let is_free = self.data_manager_modify().read_volume_dirent(
+                *cluster_id,
+                *offset,
+                |entry: &VolumeLabelEntry| entry.is_free(),
+            )?;
+            if is_free {
+                return Ok((*cluster_id, *offset));
+            }
+        }
+        if self.bpb.fat_type() != FatType::Fat32 {
+            return Err(FSError::NotEnoughSpace);
+        }
+        let last_cluster = positions.last().map(|(cluster_id, _)| *cluster_id as u32);
+        let cluster_id = self.alloc_cluster(last_cluster)?;
+        Ok((cluster_id as usize, 0))
+    }
+    /// 写回缓存中所有修改过的 FAT 表, FSInfo 和数据, 再刷新块设备的写缓存
+    pub fn sync(&self) -> Result<(), FSError> {
+        self.fat_manager.write().sync()?;
+        self.data_manager.write().sync()?;
+        Ok(self.block_device.flush()?)
+    }
+    /// 卸载文件系统, 先写回缓存再刷新块设备, 写回失败时返回错误
+    /// 用 Arc<RwLock<RunFileSystem>> 挂载时, 要先丢弃所有 VFile 才能取出 RunFileSystem
+    pub fn unmount(self) -> Result<(), FSError> {
+        self.fat_manager.write().sync()?;
+        self.data_manager.write().sync()?;
+        let block_device = Arc::clone(&self.block_device);
+        drop(self);
+        Ok(block_device.flush()?)
+    }
+    pub fn mount_options(&self) -> MountOptions {
+        self.options
+    }
+    /// 块设备是否只读
+    pub fn is_read_only(&self) -> bool {
+        self.block_device.is_read_only()
+    }
     pub fn bpb(&self) -> Arc<BiosParameterBlock> {
         self.bpb.clone()
     }
+    /// 文件系统在块设备上的起始扇区
+    pub fn start_sector(&self) -> usize {
+        self.start_sector
+    }
+    pub(crate) fn block_device(&self) -> Arc<dyn BlockDevice> {
+        Arc::clone(&self.block_device)
+    }
+    /// 异步接口使用的块设备, 不是用 from_async 挂载时包装同步块设备
+    pub fn async_device(&self) -> Arc<dyn AsyncBlockDevice> {
+        match &self.async_device {
+            Some(device) => Arc::clone(device),
+            None => Arc::new(AsyncAdapter::new(Arc::clone(&self.block_device))),
+        }
+    }
     pub fn fat_manager_read(&self) -> RwLockReadGuard<FATManager> {
         self.fat_manager.read()
     }
@@ -89,37 +576,51 @@ impl RunFileSystem {
     pub fn fsinfo(&self) -> FSInfo {
         self.fat_manager_read().fsinfo()
     }
-    /// 在 FAT 表中分配项并清空对应簇中的数据, 成功返回 id, 失败返回 None
-    pub fn alloc_cluster(&mut self, prev: Option<u32>) -> Option<u32> {
-        if let Some(cluster_id) = self.fat_manager.write().alloc_cluster(prev) {
-            self.data_manager.write().clear_cluster(cluster_id as usize);
-            return Some(cluster_id);
-        }
-        return None;
+    /// 在 FAT 表中分配项并清空对应簇中的数据, 成功返回 id, 没有空闲簇时返回 NotEnoughSpace
+    pub fn alloc_cluster(&mut self, prev: Option<u32>) -> Result<u32, FSError> {
+        let cluster_id = self.fat_manager.write().alloc_cluster(prev)?;
+        self.data_manager
+            .write()
+            .clear_cluster(cluster_id as usize)?;
+        Ok(cluster_id)
     }
-    /// 在 FAT 表中分配多个项并清空对应簇中的数据, 成功返回分配的第一个 id, 失败返回 None
-    pub fn alloc_clusters(&mut self, num: usize, prev: Option<u32>) -> Option<u32> {
+    /// 在 FAT 表中分配多个项并清空对应簇中的数据, 成功返回分配的第一个 id
+    /// num 为 0 时返回 InvalidInput, 空闲簇不够时返回 NotEnoughSpace
+    pub fn alloc_clusters(&mut self, num: usize, prev: Option<u32>) -> Result<u32, FSError> {
         if num == 0 {
-            return None;
+            return Err(FSError::InvalidInput);
         }
         let mut fat_manager = self.fat_manager.write();
-        if let Some(first_cluster) = fat_manager.alloc_clusters(num, prev) {
-            let id_vec = fat_manager.all_clusters(first_cluster as usize);
-            for id in id_vec {
-                self.data_manager.write().clear_cluster(id);
-            }
-            return Some(first_cluster);
-        } else {
-            return None;
+        let first_cluster = fat_manager.alloc_clusters(num, prev)?;
+        let id_vec = fat_manager.all_clusters(first_cluster as usize)?;
+        for id in id_vec {
+            self.data_manager.write().clear_cluster(id)?;
+        }
+        Ok(first_cluster)
+    }
+    /// 分配 num 个连续的簇并清空数据, 成功返回第一个簇的 id, 没有足够长的连续空闲簇时返回 NotEnoughSpace
+    pub fn alloc_contiguous_clusters(&mut self, num: usize) -> Result<u32, FSError> {
+        let first_cluster = self.fat_manager.write().alloc_contiguous_clusters(num)?;
+        for id in first_cluster as usize..first_cluster as usize + num {
+            self.data_manager.write().clear_cluster(id)?;
         }
+        Ok(first_cluster)
     }
     /// 如果这个簇不是簇链中最后一个簇也会删除, 悬空后自己负责, 成功返回下一个要删除的 id
     /// 如果要删除的簇本身就是空的或者坏的或者最后一个,则返回None
-    pub fn dealloc_cluster(&mut self, cluster_id: usize, prev: Option<u32>) -> Option<u32> {
+    pub fn dealloc_cluster(
+        &mut self,
+        cluster_id: usize,
+        prev: Option<u32>,
+    ) -> Result<Option<u32>, FSError> {
         self.fat_manager.write().dealloc_cluster(cluster_id, prev)
     }
     /// 返回真正回收的簇数量
-    pub fn dealloc_clusters(&mut self, first_cluster: usize, prev: Option<u32>) -> usize {
+    pub fn dealloc_clusters(
+        &mut self,
+        first_cluster: usize,
+        prev: Option<u32>,
+    ) -> Result<usize, FSError> {
         self.fat_manager
             .write()
             .dealloc_clusters(first_cluster, prev)
@@ -1,46 +1,82 @@
-/// 块缓存层，用于 FAT32 的保留扇区和 FAT 表区
-use super::{BiosParameterBlock, BlockDevice};
-use crate::config::{INFOSEC_CACHE_SZ, MAX_SEC_SZ};
+/// 块缓存层，用于保留扇区, FAT 表区和 FAT12/16 的固定根目录区
+use super::{BlockDevice, IOError, START_CLUS_ID};
+use crate::config::INFOSEC_CACHE_SZ;
 #[cfg(not(feature = "std"))]
 use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::any::Any;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::RwLock;
 #[cfg(feature = "std")]
 use std::{collections::VecDeque, sync::Arc};
-use spin::RwLock;
+
+/// 缓存层需要的卷几何参数, 由 FAT 的 BPB 或 exFAT 的启动扇区得到, 扇区号都相对文件系统起始
+#[derive(Copy, Clone, Debug)]
+pub struct CacheGeometry {
+    pub bytes_per_sector: usize,
+    pub sectors_per_cluster: usize,
+    pub first_data_sector: usize, // 数据区(exFAT 的簇堆)起始扇区, 之前的扇区才能用扇区缓存
+    pub total_clusters: usize,
+}
+
+impl CacheGeometry {
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+    /// 簇的第一个扇区, 相对文件系统起始
+    pub fn cluster_sector(&self, cluster_id: usize) -> usize {
+        (cluster_id - START_CLUS_ID) * self.sectors_per_cluster + self.first_data_sector
+    }
+}
 
 // 在本系统设计中, BlockCache 块缓存被认为是硬件存储的最小分配单元,逻辑上来说不是文件系统读取的最小单位.
 pub struct BlockCache {
     cache: Vec<u8>,
     sector_id: usize,
+    start_sector: usize, // 文件系统所在分区的起始扇区
     modified: bool,
-    bpb: Arc<BiosParameterBlock>,
+    write_back: Arc<WriteBackState>,
+    geometry: CacheGeometry,
     block_dev: Arc<dyn BlockDevice>, // Arc + dyn 实现 BlockDevice Trait 的动态分发
 }
 
 impl BlockCache {
+    /// sector_id 是相对文件系统起始扇区的编号, 读不出扇区时返回块设备的错误
     pub fn new(
         sector_id: usize,
+        start_sector: usize,
         block_dev: Arc<dyn BlockDevice>,
-        bpb: Arc<BiosParameterBlock>,
-    ) -> Self {
-        let data_start_sector: usize = bpb.first_data_sector().try_into().unwrap();
-        let sector_size: usize = bpb.bytes_per_sector().try_into().unwrap();
+        geometry: CacheGeometry,
+    ) -> Result<Self, IOError> {
+        let data_start_sector: usize = geometry.first_data_sector;
+        let sector_size: usize = geometry.bytes_per_sector;
         assert!(sector_id < data_start_sector, "sector id not in info range");
-        let mut cache: Vec<u8> = vec![0; MAX_SEC_SZ];
-        block_dev.read_block(sector_id, &mut cache).unwrap();
-        // 先占后缩,适配尽可能宽的簇大小范围,同时避免空间不够用
-        cache.resize_with(sector_size, Default::default);
-        cache.shrink_to(sector_size);
-        // println!("cache.len: {}", cache.len());
-        // println!("cache.capacity: {}", cache.capacity());
-        assert!(
-            cache.len() == sector_size,
-            "sector cache len cannot be shrink to proper size"
-        );
+        // 缓存按卷的实际扇区大小分配
+        let mut cache: Vec<u8> = vec![0; sector_size];
+        block_dev.read_block(start_sector + sector_id, &mut cache)?;
+        Ok(Self::from_data(
+            sector_id,
+            start_sector,
+            block_dev,
+            geometry,
+            cache,
+        ))
+    }
+    /// 用已经读到的扇区内容创建, 异步加载时使用
+    pub fn from_data(
+        sector_id: usize,
+        start_sector: usize,
+        block_dev: Arc<dyn BlockDevice>,
+        geometry: CacheGeometry,
+        cache: Vec<u8>,
+    ) -> Self {
+        assert_eq!(cache.len(), geometry.bytes_per_sector);
         Self {
             cache,
             sector_id,
+            start_sector,
             modified: false,
-            bpb,
+            write_back: Arc::new(WriteBackState::default()),
+            geometry,
             block_dev,
         }
     }
@@ -55,7 +91,7 @@ impl BlockCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        let block_size: usize = self.bpb.bytes_per_sector().try_into().unwrap();
+        let block_size: usize = self.geometry.bytes_per_sector;
         assert!(offset + type_size <= block_size);
         unsafe {
             &*((&self.cache[offset..offset + type_size]).as_ptr() as *const _ as usize as *const T)
@@ -67,7 +103,7 @@ impl BlockCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        let block_size: usize = self.bpb.bytes_per_sector().try_into().unwrap();
+        let block_size: usize = self.geometry.bytes_per_sector;
         assert!(offset + type_size <= block_size);
         self.set_modify();
         unsafe {
@@ -84,70 +120,257 @@ impl BlockCache {
     fn set_modify(&mut self) {
         self.modified = true
     }
-    pub fn sync(&mut self) {
+    // 异步写回的旧快照落盘之后才能再写, 写回失败时恢复修改标记
+    fn wait_write_back(&mut self) {
+        if self.write_back.wait() {
+            self.modified = true;
+        }
+    }
+    /// 写回失败时仍然保留修改标记, 之后可以再试
+    pub fn sync(&mut self) -> Result<(), IOError> {
+        self.wait_write_back();
         if self.modified {
-            self.modified = false;
             self.block_dev
-                .write_block(self.sector_id, self.cache.as_ref())
-                .unwrap();
+                .write_block(self.start_sector + self.sector_id, self.cache.as_ref())?;
+            self.modified = false;
         }
+        Ok(())
     }
 }
 
 impl Drop for BlockCache {
     fn drop(&mut self) {
-        self.sync()
+        // 丢弃时没法返回错误, 需要知道结果的要先调用 sync
+        if let Err(e) = self.sync() {
+            log::error!("sector {} lost on write back: {:?}", self.sector_id, e);
+        }
     }
 }
 
 // 在本系统设计中, 文件系统最小读取单位 SectorCache 等于硬件最小的分配单元.
 pub type SectorCache = BlockCache;
 
+const WRITE_BACK_IDLE: u8 = 0;
+const WRITE_BACK_IN_FLIGHT: u8 = 1;
+const WRITE_BACK_FAILED: u8 = 2;
+
+/// 缓存的异步写回状态, 由缓存和 WriteBackPin 共用, 等待时不需要拿缓存的锁
+#[derive(Default)]
+pub(crate) struct WriteBackState(AtomicU8);
+
+impl WriteBackState {
+    /// 忙等正在进行的异步写回结束, 上一次写回失败时返回 true
+    /// 只有持有缓存写锁的一方会离开 IN_FLIGHT 以外的状态, 读完再写不会和 WriteBackPin 冲突
+    pub(crate) fn wait(&self) -> bool {
+        loop {
+            match self.0.load(Ordering::Acquire) {
+                WRITE_BACK_IN_FLIGHT => core::hint::spin_loop(),
+                WRITE_BACK_IDLE => return false,
+                _ => {
+                    self.0.store(WRITE_BACK_IDLE, Ordering::Release);
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// 异步写回期间持有, 被写回的缓存不会被换出, 同一缓存的 sync 会等到写回结束
+/// 没有调用 finish 就丢弃时(比如 future 被取消)按写回失败处理
+pub(crate) struct WriteBackPin {
+    _cache: Arc<dyn Any + Send + Sync>,
+    state: Arc<WriteBackState>,
+}
+
+impl WriteBackPin {
+    pub(crate) fn new(cache: Arc<dyn Any + Send + Sync>, state: &Arc<WriteBackState>) -> Self {
+        state.0.store(WRITE_BACK_IN_FLIGHT, Ordering::Release);
+        Self {
+            _cache: cache,
+            state: Arc::clone(state),
+        }
+    }
+    /// 写回结束, 失败时缓存下次写回或 sync 时重新写
+    pub(crate) fn finish(self, ok: bool) {
+        let state = if ok {
+            WRITE_BACK_IDLE
+        } else {
+            WRITE_BACK_FAILED
+        };
+        self.state.0.store(state, Ordering::Release);
+    }
+}
+
+impl Drop for WriteBackPin {
+    fn drop(&mut self) {
+        let _ = self.state.0.compare_exchange(
+            WRITE_BACK_IN_FLIGHT,
+            WRITE_BACK_FAILED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+}
+
+/// 异步加载缓存时的下一步, 由 prepare_load 在锁内给出, I/O 在锁外完成
+pub(crate) enum CacheLoad {
+    /// 已经在缓存中
+    Cached,
+    /// 先写回要换出的缓存(已经清掉修改标记), 写完后用结果调用 pin.finish, 再调用 prepare_load
+    WriteBack {
+        block_id: usize,
+        count: usize,
+        data: Vec<u8>,
+        pin: WriteBackPin,
+    },
+    /// 从 block_id 开始读 count 块, 共 len 字节, 读到的内容交给 finish_load
+    Read {
+        block_id: usize,
+        count: usize,
+        len: usize,
+    },
+}
+
+/// 扇区缓存和簇缓存的异步加载, 调用方在两步之间释放锁去等待块设备
+pub(crate) trait AsyncLoad {
+    fn prepare_load(&mut self, id: usize) -> CacheLoad;
+    /// 等待期间别人已经加载过时丢掉 data, 队列满时和 get_cache 一样同步换出
+    fn finish_load(&mut self, id: usize, data: Vec<u8>) -> Result<(), IOError>;
+}
+
 pub struct SectorCacheManager {
-    bpb: Arc<BiosParameterBlock>,
+    geometry: CacheGeometry,
+    start_sector: usize,
     queue: VecDeque<(usize, Arc<RwLock<SectorCache>>)>,
     block_device: Arc<dyn BlockDevice>,
 }
 
 impl SectorCacheManager {
-    pub fn new(bpb: Arc<BiosParameterBlock>, block_device: Arc<dyn BlockDevice>) -> Self {
+    pub fn new(
+        geometry: CacheGeometry,
+        start_sector: usize,
+        block_device: Arc<dyn BlockDevice>,
+    ) -> Self {
         Self {
-            bpb,
+            geometry,
+            start_sector,
             block_device,
             queue: VecDeque::new(),
         }
     }
-    pub fn get_cache(&mut self, sector_id: usize) -> Arc<RwLock<SectorCache>> {
+    pub fn get_cache(&mut self, sector_id: usize) -> Result<Arc<RwLock<SectorCache>>, IOError> {
         if let Some(pair) = self.queue.iter().find(|pair| pair.0 == sector_id) {
-            Arc::clone(&pair.1)
+            Ok(Arc::clone(&pair.1))
         } else {
-            // substitute
-            if self.queue.len() == INFOSEC_CACHE_SZ {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of SectorCache!");
-                }
-            }
+            self.evict()?;
             // load sector into mem and push back
             let sector_cache = Arc::new(RwLock::new(BlockCache::new(
                 sector_id,
+                self.start_sector,
                 Arc::clone(&self.block_device),
-                Arc::clone(&self.bpb),
-            )));
+                self.geometry,
+            )?));
             self.queue.push_back((sector_id, Arc::clone(&sector_cache)));
-            sector_cache
+            Ok(sector_cache)
         }
     }
-    // pub fn info_cache_sync_all(&mut self) {
-    //     for (_, cache) in self.queue.iter() {
-    //         cache.write().sync();
-    //     }
-    // }
+    // 队列满时换出最早的没有被使用的缓存
+    fn evict(&mut self) -> Result<(), IOError> {
+        if self.queue.len() == INFOSEC_CACHE_SZ {
+            // from front to tail
+            if let Some((idx, _)) = self
+                .queue
+                .iter()
+                .enumerate()
+                .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+            {
+                // 先写回, 失败时缓存留在队列中, 修改不会丢
+                self.queue[idx].1.write().sync()?;
+                self.queue.drain(idx..=idx);
+            } else {
+                panic!("Run out of SectorCache!");
+            }
+        }
+        Ok(())
+    }
+    /// 扇区在缓存中时从 offset 处读满 buf, 不在时返回 false
+    pub(crate) fn read_cached(&self, sector_id: usize, offset: usize, buf: &mut [u8]) -> bool {
+        self.queue
+            .iter()
+            .find(|pair| pair.0 == sector_id)
+            .is_some_and(|pair| {
+                buf.copy_from_slice(&pair.1.read().cache[offset..offset + buf.len()]);
+                true
+            })
+    }
+    /// 扇区在缓存中时把 buf 写到 offset 处并标记修改, 不在时返回 false
+    pub(crate) fn write_cached(&self, sector_id: usize, offset: usize, buf: &[u8]) -> bool {
+        self.queue
+            .iter()
+            .find(|pair| pair.0 == sector_id)
+            .is_some_and(|pair| {
+                let mut cache = pair.1.write();
+                cache.cache[offset..offset + buf.len()].copy_from_slice(buf);
+                cache.set_modify();
+                true
+            })
+    }
+    /// 写回所有修改过的扇区, 缓存仍然保留
+    pub fn info_cache_sync_all(&mut self) -> Result<(), IOError> {
+        for (_, cache) in self.queue.iter() {
+            cache.write().sync()?;
+        }
+        Ok(())
+    }
+}
+
+impl AsyncLoad for SectorCacheManager {
+    fn prepare_load(&mut self, sector_id: usize) -> CacheLoad {
+        if self.queue.iter().any(|pair| pair.0 == sector_id) {
+            return CacheLoad::Cached;
+        }
+        if self.queue.len() == INFOSEC_CACHE_SZ {
+            if let Some(idx) = self
+                .queue
+                .iter()
+                .position(|pair| Arc::strong_count(&pair.1) == 1)
+            {
+                let mut cache = self.queue[idx].1.write();
+                cache.wait_write_back();
+                if cache.modified {
+                    cache.modified = false;
+                    let pin = Arc::clone(&self.queue[idx].1) as Arc<dyn Any + Send + Sync>;
+                    return CacheLoad::WriteBack {
+                        block_id: cache.start_sector + cache.sector_id,
+                        count: 1,
+                        data: cache.cache.clone(),
+                        pin: WriteBackPin::new(pin, &cache.write_back),
+                    };
+                }
+                drop(cache);
+                self.queue.remove(idx);
+            }
+        }
+        CacheLoad::Read {
+            block_id: self.start_sector + sector_id,
+            count: 1,
+            len: self.geometry.bytes_per_sector,
+        }
+    }
+    fn finish_load(&mut self, sector_id: usize, data: Vec<u8>) -> Result<(), IOError> {
+        if self.queue.iter().any(|pair| pair.0 == sector_id) {
+            return Ok(());
+        }
+        self.evict()?;
+        let sector_cache = BlockCache::from_data(
+            sector_id,
+            self.start_sector,
+            Arc::clone(&self.block_device),
+            self.geometry,
+            data,
+        );
+        self.queue
+            .push_back((sector_id, Arc::new(RwLock::new(sector_cache))));
+        Ok(())
+    }
 }
@@ -0,0 +1,414 @@
+// 块设备访问记录和回放: 现场设备上记下每次读写, 回来后在镜像上一步步重放, 找出是哪一步把卷写坏的
+use crate::block_device::BlockDevice;
+use crate::crc32::crc32;
+use crate::error::{FSError, IOError};
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+use spin::Mutex;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+// 文件头: 魔数, 版本, 记录方式, 块大小
+const TRACE_MAGIC: &[u8; 8] = b"RUNFSTRC";
+const TRACE_VERSION: u16 = 1;
+const HEADER_SZ: usize = 15;
+// 每条记录开头的操作码
+const OP_READ: u8 = 0;
+const OP_WRITE: u8 = 1;
+const OP_FLUSH: u8 = 2;
+const OP_DISCARD: u8 = 3;
+const OP_MARK: u8 = 4;
+
+/// 每次读写记下多少内容
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceMode {
+    /// 只有块号和长度, 只能看访问模式, 不能回放写
+    Metadata,
+    /// 另外记下读写数据的 CRC32, 可以对比现场和镜像上的内容, 不能回放写
+    Checksum,
+    /// 写记下完整数据, 读只记 CRC32, 可以回放
+    Full,
+}
+
+impl TraceMode {
+    fn from_u8(mode: u8) -> Option<Self> {
+        match mode {
+            0 => Some(TraceMode::Metadata),
+            1 => Some(TraceMode::Checksum),
+            2 => Some(TraceMode::Full),
+            _ => None,
+        }
+    }
+}
+
+/// trace 中的一条记录, block_id 和 count 是 read_blocks/write_blocks 的参数, 单块读写的 count 是 1
+/// len 是缓冲区的字节数, 短读时比块小
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    Read {
+        block_id: usize,
+        count: usize,
+        len: usize,
+        checksum: Option<u32>,
+    },
+    /// Full 方式下 data 是写入的内容, checksum 从 data 算出
+    Write {
+        block_id: usize,
+        count: usize,
+        len: usize,
+        checksum: Option<u32>,
+        data: Option<Vec<u8>>,
+    },
+    Flush,
+    Discard(Range<usize>),
+    /// TraceDevice::mark 插入的标记, 用来把记录和上层的操作对应起来
+    Mark(String),
+}
+
+/// 解析后的 trace
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub mode: TraceMode,
+    pub block_size: usize,
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// 解析 TraceDevice 记下的内容, 末尾不完整的记录(记录时掉电)丢掉, 格式不对返回 InvalidInput
+    pub fn parse(data: &[u8]) -> Result<Self, FSError> {
+        if data.len() < HEADER_SZ
+            || &data[..8] != TRACE_MAGIC
+            || u16::from_le_bytes([data[8], data[9]]) != TRACE_VERSION
+        {
+            return Err(FSError::InvalidInput);
+        }
+        let mode = TraceMode::from_u8(data[10]).ok_or(FSError::InvalidInput)?;
+        let block_size = u32::from_le_bytes([data[11], data[12], data[13], data[14]]) as usize;
+        let mut reader = Reader {
+            data,
+            pos: HEADER_SZ,
+        };
+        let mut events = Vec::new();
+        while reader.pos < data.len() {
+            match reader.event(mode)? {
+                Some(event) => events.push(event),
+                None => {
+                    log::warn!("trace truncated after {} events", events.len());
+                    break;
+                }
+            }
+        }
+        Ok(Self {
+            mode,
+            block_size,
+            events,
+        })
+    }
+    /// 所有标记的位置和名字, 位置是标记在 events 中的下标
+    pub fn marks(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.events
+            .iter()
+            .enumerate()
+            .filter_map(|(step, event)| match event {
+                TraceEvent::Mark(label) => Some((step, label.as_str())),
+                _ => None,
+            })
+    }
+    /// 第一个名字是 label 的标记的位置
+    pub fn find_mark(&self, label: &str) -> Option<usize> {
+        self.marks()
+            .find(|(_, name)| *name == label)
+            .map(|(step, _)| step)
+    }
+}
+
+// 按顺序解析记录, 数据不够时返回 None
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+    fn varint(&mut self) -> Option<usize> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(value).ok();
+            }
+        }
+        None
+    }
+    fn checksum(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn event(&mut self, mode: TraceMode) -> Result<Option<TraceEvent>, FSError> {
+        let op = self.data[self.pos];
+        self.pos += 1;
+        let event = match op {
+            OP_READ => self.read(mode),
+            OP_WRITE => self.write(mode),
+            OP_FLUSH => Some(TraceEvent::Flush),
+            OP_DISCARD => self.discard(),
+            OP_MARK => self.mark(),
+            _ => return Err(FSError::InvalidInput),
+        };
+        Ok(event)
+    }
+    fn discard(&mut self) -> Option<TraceEvent> {
+        let (start, end) = (self.varint()?, self.varint()?);
+        Some(TraceEvent::Discard(start..end))
+    }
+    fn mark(&mut self) -> Option<TraceEvent> {
+        let len = self.varint()?;
+        let label = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        Some(TraceEvent::Mark(label))
+    }
+    fn read(&mut self, mode: TraceMode) -> Option<TraceEvent> {
+        let (block_id, count, len) = (self.varint()?, self.varint()?, self.varint()?);
+        let checksum = match mode {
+            TraceMode::Metadata => None,
+            _ => Some(self.checksum()?),
+        };
+        Some(TraceEvent::Read {
+            block_id,
+            count,
+            len,
+            checksum,
+        })
+    }
+    fn write(&mut self, mode: TraceMode) -> Option<TraceEvent> {
+        let (block_id, count, len) = (self.varint()?, self.varint()?, self.varint()?);
+        let (checksum, data) = match mode {
+            TraceMode::Metadata => (None, None),
+            TraceMode::Checksum => (Some(self.checksum()?), None),
+            TraceMode::Full => {
+                let data = self.bytes(len)?.to_vec();
+                (Some(crc32(&data)), Some(data))
+            }
+        };
+        Some(TraceEvent::Write {
+            block_id,
+            count,
+            len,
+            checksum,
+            data,
+        })
+    }
+}
+
+fn push_varint(buf: &mut Vec<u8>, value: usize) {
+    let mut value = value as u64;
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// 记录包装, 成功的读写, flush 和 discard 都按顺序记下来, 失败的请求不记
+/// 记录先放在内存里, 用 take_trace 取出后保存到别处
+pub struct TraceDevice {
+    inner: Arc<dyn BlockDevice>,
+    mode: TraceMode,
+    trace: Mutex<Vec<u8>>, // 请求和记录在同一把锁下完成, 记录的顺序就是请求完成的顺序
+}
+
+impl TraceDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>, mode: TraceMode) -> Self {
+        let block_size = inner.block_size().unwrap_or(512) as u32;
+        let mut trace = Vec::with_capacity(HEADER_SZ);
+        trace.extend_from_slice(TRACE_MAGIC);
+        trace.extend_from_slice(&TRACE_VERSION.to_le_bytes());
+        trace.push(mode as u8);
+        trace.extend_from_slice(&block_size.to_le_bytes());
+        Self {
+            inner,
+            mode,
+            trace: Mutex::new(trace),
+        }
+    }
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.inner
+    }
+    pub fn mode(&self) -> TraceMode {
+        self.mode
+    }
+    /// 插入一个标记, 比如在每个文件系统操作之前记下操作的名字
+    pub fn mark(&self, label: &str) {
+        let mut trace = self.trace.lock();
+        trace.push(OP_MARK);
+        push_varint(&mut trace, label.len());
+        trace.extend_from_slice(label.as_bytes());
+    }
+    /// 取出到现在为止记下的内容并清空, 多次取出的内容按顺序拼起来就是完整的 trace
+    pub fn take_trace(&self) -> Vec<u8> {
+        core::mem::take(&mut *self.trace.lock())
+    }
+    fn record(trace: &mut Vec<u8>, op: u8, block_id: usize, count: usize, len: usize) {
+        trace.push(op);
+        push_varint(trace, block_id);
+        push_varint(trace, count);
+        push_varint(trace, len);
+    }
+    fn traced_read(
+        &self,
+        block_id: usize,
+        count: usize,
+        buf: &mut [u8],
+        f: impl FnOnce(&mut [u8]) -> Result<(), IOError>,
+    ) -> Result<(), IOError> {
+        let mut trace = self.trace.lock();
+        f(buf)?;
+        Self::record(&mut trace, OP_READ, block_id, count, buf.len());
+        if self.mode != TraceMode::Metadata {
+            trace.extend_from_slice(&crc32(buf).to_le_bytes());
+        }
+        Ok(())
+    }
+    fn traced_write(
+        &self,
+        block_id: usize,
+        count: usize,
+        buf: &[u8],
+        f: impl FnOnce(&[u8]) -> Result<(), IOError>,
+    ) -> Result<(), IOError> {
+        let mut trace = self.trace.lock();
+        f(buf)?;
+        Self::record(&mut trace, OP_WRITE, block_id, count, buf.len());
+        match self.mode {
+            TraceMode::Metadata => {}
+            TraceMode::Checksum => trace.extend_from_slice(&crc32(buf).to_le_bytes()),
+            TraceMode::Full => trace.extend_from_slice(buf),
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for TraceDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.traced_read(block_id, 1, buf, |buf| self.inner.read_block(block_id, buf))
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.traced_write(block_id, 1, buf, |buf| {
+            self.inner.write_block(block_id, buf)
+        })
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.traced_read(start_block, count, buf, |buf| {
+            self.inner.read_blocks(start_block, count, buf)
+        })
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.traced_write(start_block, count, buf, |buf| {
+            self.inner.write_blocks(start_block, count, buf)
+        })
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        let mut trace = self.trace.lock();
+        self.inner.flush()?;
+        trace.push(OP_FLUSH);
+        Ok(())
+    }
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        let mut trace = self.trace.lock();
+        self.inner.discard(blocks.clone())?;
+        trace.push(OP_DISCARD);
+        push_varint(&mut trace, blocks.start);
+        push_varint(&mut trace, blocks.end);
+        Ok(())
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+}
+
+/// 把 trace 一步一步应用到 image 上, image 应该是开始记录时设备内容的副本
+/// 写必须有数据(Full 方式), 有 CRC32 的读会和 image 上的内容比较, 不一致返回 ChecksumMismatch
+/// 出错时停在出错的那一步
+pub struct TraceReplayer<'a> {
+    trace: &'a Trace,
+    image: Arc<dyn BlockDevice>,
+    position: usize,
+}
+
+impl<'a> TraceReplayer<'a> {
+    pub fn new(trace: &'a Trace, image: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            trace,
+            image,
+            position: 0,
+        }
+    }
+    /// 下一步要执行的记录的下标
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    pub fn is_finished(&self) -> bool {
+        self.position == self.trace.events.len()
+    }
+    /// 执行一步, 已经到结尾时返回 false
+    pub fn step(&mut self) -> Result<bool, FSError> {
+        let Some(event) = self.trace.events.get(self.position) else {
+            return Ok(false);
+        };
+        match event {
+            TraceEvent::Read {
+                block_id,
+                count,
+                len,
+                checksum,
+            } => {
+                let mut buf: Vec<u8> = vec![0; *len];
+                self.image.read_blocks(*block_id, *count, &mut buf)?;
+                if checksum.is_some_and(|checksum| checksum != crc32(&buf)) {
+                    log::error!("step {}: block {} differs", self.position, block_id);
+                    return Err(FSError::Io(IOError::ChecksumMismatch));
+                }
+            }
+            TraceEvent::Write {
+                block_id,
+                count,
+                data,
+                ..
+            } => {
+                let data = data.as_ref().ok_or(FSError::InvalidInput)?;
+                self.image.write_blocks(*block_id, *count, data)?;
+            }
+            TraceEvent::Flush => self.image.flush()?,
+            TraceEvent::Discard(blocks) => self.image.discard(blocks.clone())?,
+            TraceEvent::Mark(_) => {}
+        }
+        self.position += 1;
+        Ok(true)
+    }
+    /// 执行到第 step 条记录之前停下, step 往回退或超出记录数时返回 InvalidInput
+    pub fn run_to(&mut self, step: usize) -> Result<(), FSError> {
+        if step < self.position || step > self.trace.events.len() {
+            return Err(FSError::InvalidInput);
+        }
+        while self.position < step {
+            self.step()?;
+        }
+        Ok(())
+    }
+    /// 执行完所有记录
+    pub fn run(&mut self) -> Result<(), FSError> {
+        self.run_to(self.trace.events.len())
+    }
+}
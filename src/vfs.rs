@@ -1,8 +1,8 @@
 /// 虚拟文件系统, 将实际文件系统抽象成满足文件,文件夹创建读写删除功能的抽象文件系统
 use super::{
-    FSError, FileAttributes, LongDirectoryEntry, RunFileSystem, ShortDirectoryEntry, DIRENT_SZ,
-    LAST_LONG_ENTRY, LONG_NAME_LEN, SHORT_FILE_EXT_LEN, SHORT_FILE_NAME_LEN,
-    SHORT_FILE_NAME_PADDING, SHORT_NAME_LEN,
+    async_fs, FSError, FatType, FileAttributes, LongDirectoryEntry, RunFileSystem,
+    ShortDirectoryEntry, DIRENT_SZ, LAST_LONG_ENTRY, LONG_NAME_LEN, SHORT_FILE_EXT_LEN,
+    SHORT_FILE_NAME_LEN, SHORT_FILE_NAME_PADDING, SHORT_NAME_LEN,
 };
 #[cfg(not(feature = "std"))]
 use crate::println;
@@ -140,9 +140,9 @@ impl VFile {
         // let fat = self.fs.read();
         // fat.cache_write_back();
     }
-    pub fn first_data_cluster(&self) -> u32 {
+    pub fn first_data_cluster(&self) -> Result<u32, FSError> {
         if self.is_root() {
-            self.fs.read().bpb().root_dir_cluster()
+            Ok(self.fs.read().bpb().root_dir_cluster())
         } else {
             self.fs.read().data_manager_modify().read_short_dirent(
                 self.short_cluster,
@@ -151,14 +151,14 @@ impl VFile {
             )
         }
     }
-    pub fn last_data_cluster(&self) -> u32 {
+    pub fn last_data_cluster(&self) -> Result<u32, FSError> {
         self.fs.read().data_manager_modify().read_short_dirent(
             self.short_cluster,
             self.short_offset,
             |short_entry: &ShortDirectoryEntry| short_entry.first_cluster(),
         )
     }
-    pub fn set_first_cluster(&self, clu: u32) {
+    pub fn set_first_cluster(&self, clu: u32) -> Result<(), FSError> {
         self.fs.read().data_manager_modify().modify_short_dirent(
             self.short_cluster,
             self.short_offset,
@@ -167,43 +167,53 @@ impl VFile {
             },
         )
     }
-    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let mut entry = ShortDirectoryEntry::default();
+    // 复制文件自己的短目录项, 根目录没有目录项, 用内存中构造的
+    pub(crate) fn short_dirent(&self) -> Result<ShortDirectoryEntry, FSError> {
         if self.is_root() {
-            entry = self.fs.read().root_dirent();
-        } else {
-            self.fs.read().data_manager_modify().read_short_dirent(
-                self.short_cluster,
-                self.short_offset,
-                |short_entry: &ShortDirectoryEntry| entry = *short_entry,
-            );
+            return Ok(self.fs.read().root_dirent());
         }
+        self.fs.read().data_manager_modify().read_short_dirent(
+            self.short_cluster,
+            self.short_offset,
+            |short_entry: &ShortDirectoryEntry| *short_entry,
+        )
+    }
+    /// 读不出块设备时返回 FSError::Io
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FSError> {
+        let entry = self.short_dirent()?;
         entry.read_at(offset, buf, &self.fs)
     }
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        self.adjust_capacity(offset + buf.len()).unwrap();
-        let mut entry = ShortDirectoryEntry::default();
-        if self.is_root() {
-            entry = self.fs.read().root_dirent();
-        } else {
-            self.fs.read().data_manager_modify().read_short_dirent(
-                self.short_cluster,
-                self.short_offset,
-                |short_entry: &ShortDirectoryEntry| entry = *short_entry,
-            );
-        }
-        let size = entry.write_at(offset, buf, &self.fs);
+    /// 需要时先扩容, 簇不够时返回 NotEnoughSpace
+    /// 文件大小只在写过末尾时增长, 不会被截断到写入的长度
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, FSError> {
+        self.adjust_capacity(offset + buf.len())?;
+        let entry = self.short_dirent()?;
+        let size = entry.write_at(offset, buf, &self.fs)?;
         if self.is_file() {
             self.fs.read().data_manager_modify().modify_short_dirent(
                 self.short_cluster,
                 self.short_offset,
-                |short_entry: &mut ShortDirectoryEntry| short_entry.set_size(size as u32),
-            );
+                |short_entry: &mut ShortDirectoryEntry| {
+                    short_entry.grow_size((offset + size) as u32)
+                },
+            )?;
         }
-        size
+        Ok(size)
+    }
+    /// read_at 的异步版本, 等待 RunFileSystem::async_device 完成块设备请求
+    pub async fn read_at_async(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FSError> {
+        async_fs::read_at(self, offset, buf).await
+    }
+    /// write_at 的异步版本, 需要分配簇时仍然同步等待块设备
+    pub async fn write_at_async(&self, offset: usize, buf: &[u8]) -> Result<usize, FSError> {
+        async_fs::write_at(self, offset, buf).await
     }
     /// 长文件名方式搜索, 只支持本级搜索, 不支持递归搜索
-    fn find_long_name(&self, name: &str, dir_entry: &ShortDirectoryEntry) -> Option<VFile> {
+    fn find_long_name(
+        &self,
+        name: &str,
+        dir_entry: &ShortDirectoryEntry,
+    ) -> Result<Option<VFile>, FSError> {
         // 名字已经做了逆序处理
         // println!("name: {:#?}", name);
         let name_vec: Vec<[u16; LONG_NAME_LEN]> = long_name_split(name).into_iter().rev().collect();
@@ -216,11 +226,11 @@ impl VFile {
         loop {
             long_pos_vec.clear();
             // 读取 offset 处的目录项
-            let mut read_sz = dir_entry.read_at(dir_offset, long_entry.as_bytes_mut(), &self.fs);
+            let mut read_sz = dir_entry.read_at(dir_offset, long_entry.as_bytes_mut(), &self.fs)?;
             // println!("here0");
             // 以下成立说明读到头了
             if read_sz != DIRENT_SZ {
-                return None;
+                return Ok(None);
             }
             // println!("here1");
             if long_entry.is_free() {
@@ -244,9 +254,9 @@ impl VFile {
                         dir_offset + i * DIRENT_SZ,
                         long_entry.as_bytes_mut(),
                         &self.fs,
-                    );
+                    )?;
                     if read_sz != DIRENT_SZ {
-                        return None;
+                        return Ok(None);
                     }
                     if long_entry.name_to_array() != name_vec[i] || !long_entry.is_long() {
                         is_match = false;
@@ -257,30 +267,32 @@ impl VFile {
                     // 如果成功，读短目录项，进行校验
                     let mut short_entry = ShortDirectoryEntry::default();
                     let short_offset = dir_offset + entry_num * DIRENT_SZ;
-                    read_sz = dir_entry.read_at(short_offset, short_entry.as_bytes_mut(), &self.fs);
+                    read_sz =
+                        dir_entry.read_at(short_offset, short_entry.as_bytes_mut(), &self.fs)?;
                     if read_sz != DIRENT_SZ || short_entry.is_free() {
-                        return None;
+                        return Ok(None);
                     }
                     let short_checksum = short_entry.checksum();
                     // println!("short_checksum: {:#X?}", short_checksum);
                     if long_checksum == short_checksum {
-                        let (short_cluster, short_offset) = dir_entry.pos(short_offset, &self.fs);
+                        let (short_cluster, short_offset) =
+                            dir_entry.pos(short_offset, &self.fs)?;
                         for i in 0..raw_order as usize {
                             // 存入长名目录项位置了，第一个在栈顶
                             let (long_cluster, dir_offset) =
-                                dir_entry.pos(dir_offset + i, &self.fs);
+                                dir_entry.pos(dir_offset + i, &self.fs)?;
                             long_pos_vec.push((long_cluster.unwrap(), dir_offset));
                         }
-                        return Some(VFile::new(
+                        return Ok(Some(VFile::new(
                             String::from(name),
                             short_cluster.unwrap(),
                             short_offset,
                             long_pos_vec,
                             short_entry.attribute(),
                             self.fs.clone(),
-                        ));
+                        )));
                     } else {
-                        return None; // QUES
+                        return Ok(None); // QUES
                     }
                 } else {
                     dir_offset += DIRENT_SZ;
@@ -292,27 +304,35 @@ impl VFile {
         }
     }
     /// 短文件名搜索, 只支持本级搜索, 不支持递归搜索
-    fn find_short_name(&self, name: &str, dirent: &ShortDirectoryEntry) -> Option<VFile> {
+    fn find_short_name(
+        &self,
+        name: &str,
+        dirent: &ShortDirectoryEntry,
+    ) -> Result<Option<VFile>, FSError> {
         let mut short_entry = ShortDirectoryEntry::default();
         let mut offset = 0;
         let mut read_size;
         loop {
-            read_size = dirent.read_at(offset, short_entry.as_bytes_mut(), &self.fs);
+            read_size = dirent.read_at(offset, short_entry.as_bytes_mut(), &self.fs)?;
             if read_size != DIRENT_SZ || short_entry.is_free() {
-                return None;
+                return Ok(None);
             } else {
-                if (!short_entry.is_free()) && short_entry.is_short() && name == short_entry.name()
+                // 根目录中的卷标目录项不是文件
+                if (!short_entry.is_free())
+                    && short_entry.is_short()
+                    && !short_entry.is_volume()
+                    && name == short_entry.name()
                 {
-                    let (short_cluster, short_offset) = dirent.pos(offset, &self.fs);
+                    let (short_cluster, short_offset) = dirent.pos(offset, &self.fs)?;
                     let long_pos_vec: Vec<(usize, usize)> = Vec::new();
-                    return Some(VFile::new(
+                    return Ok(Some(VFile::new(
                         String::from(name),
                         short_cluster.unwrap_or(0),
                         short_offset,
                         long_pos_vec,
                         short_entry.attribute(),
                         self.fs.clone(),
-                    ));
+                    )));
                 } else {
                     offset += DIRENT_SZ;
                     continue;
@@ -320,35 +340,25 @@ impl VFile {
             }
         }
     }
-    /// 根据名称搜索, 默认用长文件名搜索, 搜不到再用短文件名搜
-    pub fn find_vfile_byname(&self, name: &str) -> Option<VFile> {
+    /// 根据名称搜索, 默认用长文件名搜索, 搜不到再用短文件名搜, 都搜不到返回 NotFound
+    pub fn find_vfile_byname(&self, name: &str) -> Result<VFile, FSError> {
         assert!(self.is_dir());
         // 复制文件夹自己的短文件名目录项
-        let mut short_entry = ShortDirectoryEntry::default();
-        if self.is_root() {
-            short_entry = self.fs.write().root_dirent();
-        } else {
-            self.fs.read().data_manager_modify().read_short_dirent(
-                self.short_cluster,
-                self.short_offset,
-                |entry: &ShortDirectoryEntry| short_entry = *entry,
-            );
-        }
+        let short_entry = self.short_dirent()?;
         // 长文件名搜索
-        let res = self.find_long_name(name, &short_entry);
-        if res.is_some() {
-            return res;
-        } else {
-            // 如果是 .. 则只能用短文件名搜索
-            return self.find_short_name(name, &short_entry);
+        if let Some(vfile) = self.find_long_name(name, &short_entry)? {
+            return Ok(vfile);
         }
+        // 如果是 .. 则只能用短文件名搜索
+        self.find_short_name(name, &short_entry)?
+            .ok_or(FSError::NotFound)
     }
     /// 根据路径递归搜索, 需要区分是绝对路径还是相对路径
-    pub fn find_vfile_bypath(&self, path: &str) -> Option<Arc<VFile>> {
+    pub fn find_vfile_bypath(&self, path: &str) -> Result<Arc<VFile>, FSError> {
         let pathv: Vec<&str> = path.split('/').collect();
         let len = pathv.len();
         if pathv.len() == 0 {
-            return None;
+            return Err(FSError::NotFound);
         }
         // 如果第一个串为空, 说明是绝对路径
         if pathv[0] == "" {
@@ -359,14 +369,10 @@ impl VFile {
                 if pathv[i] == "" || pathv[i] == "." {
                     continue;
                 }
-                if let Some(vfile) = current_vfile.find_vfile_byname(pathv[i]) {
-                    current_vfile = vfile;
-                } else {
-                    return None;
-                }
+                current_vfile = current_vfile.find_vfile_byname(pathv[i])?;
             }
             // println!("here100");
-            Some(Arc::new(current_vfile))
+            Ok(Arc::new(current_vfile))
         }
         // 如果第一个串不为空, 说明是相对路径
         else {
@@ -375,25 +381,21 @@ impl VFile {
                 if pathv[i] == "" || pathv[i] == "." {
                     continue;
                 }
-                if let Some(vfile) = current_vfile.find_vfile_byname(pathv[i]) {
-                    current_vfile = vfile;
-                } else {
-                    return None;
-                }
+                current_vfile = current_vfile.find_vfile_byname(pathv[i])?;
             }
-            Some(Arc::new(current_vfile))
+            Ok(Arc::new(current_vfile))
         }
     }
     /// 计算文件或文件夹大小, 如果是文件夹, 大小就是全部目录项的总字节数, 如果是文件, 大小就是内容的字节数
-    pub fn size(&self) -> usize {
+    pub fn size(&self) -> Result<usize, FSError> {
         if self.is_dir() {
             let mut offset = 0;
             let mut size = 0;
             let mut tmp_dirent = ShortDirectoryEntry::default();
             loop {
-                let read_size = self.read_at(offset, tmp_dirent.as_bytes_mut());
+                let read_size = self.read_at(offset, tmp_dirent.as_bytes_mut())?;
                 if read_size == 0 {
-                    return size;
+                    return Ok(size);
                 }
                 if !tmp_dirent.is_free() {
                     size += DIRENT_SZ;
@@ -401,61 +403,86 @@ impl VFile {
                 offset += DIRENT_SZ;
             }
         } else {
-            self.fs.read().data_manager_modify().read_short_dirent(
+            let size = self.fs.read().data_manager_modify().read_short_dirent(
                 self.short_cluster,
                 self.short_offset,
                 |short_entry: &ShortDirectoryEntry| short_entry.size().unwrap(),
-            ) as usize
+            )?;
+            Ok(size as usize)
+        }
+    }
+    /// FAT12/16 的根目录(包括指向根目录的 ..)在固定区域中, 返回区域的字节数
+    fn fixed_root_size(&self) -> Result<Option<usize>, FSError> {
+        let bpb = self.fs.read().bpb();
+        if self.is_dir() && bpb.fat_type() != FatType::Fat32 && self.first_data_cluster()? == 0 {
+            Ok(Some(bpb.root_dir_size()))
+        } else {
+            Ok(None)
         }
     }
     /// 计算文件或文件夹容量, 容量就是全部簇的总字节数
-    pub fn capacity(&self) -> usize {
+    pub fn capacity(&self) -> Result<usize, FSError> {
+        if let Some(root_size) = self.fixed_root_size()? {
+            return Ok(root_size);
+        }
         let cluster_size = self.fs.read().bpb().cluster_size();
-        cluster_size
-            * self
-                .fs
-                .read()
-                .fat_manager_modify()
-                .count_clusters(self.first_data_cluster() as usize)
+        let first_cluster = self.first_data_cluster()? as usize;
+        let clusters = self
+            .fs
+            .read()
+            .fat_manager_modify()
+            .count_clusters(first_cluster)?;
+        Ok(cluster_size * clusters)
     }
     /// 改变文件或文件夹的容量, 成功返回 Ok, 失败返回 GG
     /// new_capacity 不一定要是 cluster_size 的整数倍, 函数会帮忙向上取整
+    /// FAT12/16 的固定根目录区不能扩容, 超出时返回 NotEnoughSpace
     // TODO: 减少容量
     pub fn adjust_capacity(&self, new_capacity: usize) -> Result<(), FSError> {
+        if let Some(root_size) = self.fixed_root_size()? {
+            if new_capacity > root_size {
+                return Err(FSError::NotEnoughSpace);
+            }
+            return Ok(());
+        }
         let cluster_size = self.fs.read().bpb().cluster_size();
-        let current_capacity = self.capacity();
+        let current_capacity = self.capacity()?;
         // println!("current_capacity: {}", current_capacity);
-        let num = (new_capacity + cluster_size - current_capacity) / cluster_size;
+        // 容量够用时不再多分配簇
+        if new_capacity <= current_capacity {
+            return Ok(());
+        }
+        let num = (new_capacity - current_capacity).div_ceil(cluster_size);
         // println!("num: {}", num);
+        let first_cluster = self.first_data_cluster()? as usize;
         let current_last_cluster = self
             .fs
             .read()
             .fat_manager_modify()
-            .last_cluster(self.first_data_cluster() as usize);
+            .last_cluster(first_cluster)?;
         self.fs
             .write()
-            .alloc_clusters(num, Some(current_last_cluster as u32));
+            .alloc_clusters(num, Some(current_last_cluster as u32))?;
         Ok(())
     }
-    /// 查找可用目录项，返回 offset，簇不够会增加
-    pub fn find_free_dirents(&self, num: usize) -> Option<usize> {
+    /// 查找可用目录项，返回 offset，簇不够会增加, 无法扩容时返回 NotEnoughSpace
+    pub fn find_free_dirents(&self, num: usize) -> Result<usize, FSError> {
         // println!("0-0-1-0");
         if self.is_file() || num == 0 {
             // println!("0-0-1-1");
-            return None;
+            return Err(FSError::InvalidInput);
         }
         let mut offset = 0;
         loop {
             // println!("0-0-1-2");
             let mut tmp_dirent = ShortDirectoryEntry::default();
-            let mut read_size = self.read_at(offset, tmp_dirent.as_bytes_mut());
+            let mut read_size = self.read_at(offset, tmp_dirent.as_bytes_mut())?;
             // println!("0-0-1-3");
             // 扩容
             if read_size == 0 {
-                let current_capacity = self.capacity();
+                let current_capacity = self.capacity()?;
                 // println!("new_capacity: {}", current_capacity + num * DIRENT_SZ);
-                self.adjust_capacity(current_capacity + num * DIRENT_SZ)
-                    .unwrap();
+                self.adjust_capacity(current_capacity + num * DIRENT_SZ)?;
             }
             // 找到第一个空簇
             if tmp_dirent.is_free() {
@@ -463,14 +490,13 @@ impl VFile {
                 let mut available = 1;
                 while available < num {
                     offset += DIRENT_SZ;
-                    read_size = self.read_at(offset, tmp_dirent.as_bytes_mut());
-                    let current_capacity = self.capacity();
+                    read_size = self.read_at(offset, tmp_dirent.as_bytes_mut())?;
+                    let current_capacity = self.capacity()?;
                     // 不够了,扩容后再读
                     if read_size == 0 && offset >= current_capacity {
                         // println!("new_capacity: {}", current_capacity + num * DIRENT_SZ);
-                        self.adjust_capacity(current_capacity + num * DIRENT_SZ)
-                            .unwrap();
-                        self.read_at(offset, tmp_dirent.as_bytes_mut());
+                        self.adjust_capacity(current_capacity + num * DIRENT_SZ)?;
+                        self.read_at(offset, tmp_dirent.as_bytes_mut())?;
                     }
                     if tmp_dirent.is_free() {
                         available += 1;
@@ -482,27 +508,40 @@ impl VFile {
                 if available == num {
                     // println!("0-0-1-4");
                     // println!("offset: {:#?} {:#X?}", offset, offset);
-                    return Some(first_offset);
+                    return Ok(first_offset);
                 }
             }
             // println!("0-0-1-5");
             offset += DIRENT_SZ;
         }
     }
-    pub fn is_already_exist(&self, filename: &str, attribute: FileAttributes) -> bool {
-        if let Some(vfile) = self.find_vfile_byname(filename) {
-            return (vfile.is_dir() && attribute.contains(FileAttributes::DIRECTORY))
-                || (!vfile.is_dir() && !attribute.contains(FileAttributes::DIRECTORY));
+    pub fn is_already_exist(
+        &self,
+        filename: &str,
+        attribute: FileAttributes,
+    ) -> Result<bool, FSError> {
+        match self.find_vfile_byname(filename) {
+            Ok(vfile) => Ok(vfile.is_dir() == attribute.contains(FileAttributes::DIRECTORY)),
+            Err(FSError::NotFound) => Ok(false),
+            Err(e) => Err(e),
         }
-        false
     }
-    /// 在当前目录下创建文件或目录
-    pub fn create(&self, filename: &str, attribute: FileAttributes) -> Option<Arc<VFile>> {
+    /// 在当前目录下创建文件或目录, 已经存在时返回 AlreadyExists
+    pub fn create(&self, filename: &str, attribute: FileAttributes) -> Result<Arc<VFile>, FSError> {
+        self.create_with_short_name(filename, generate_short_name(filename), attribute)
+    }
+    /// 用指定的短文件名创建文件或目录, 调用者保证短文件名在目录中不重复
+    pub(crate) fn create_with_short_name(
+        &self,
+        filename: &str,
+        short_name: [u8; SHORT_NAME_LEN],
+        attribute: FileAttributes,
+    ) -> Result<Arc<VFile>, FSError> {
         // 判断是否是文件夹
         assert!(self.is_dir());
-        /* 如果已经存在了就返回 None */
-        if self.is_already_exist(filename, attribute) {
-            return None;
+        /* 如果已经存在了就返回 AlreadyExists */
+        if self.is_already_exist(filename, attribute)? {
+            return Err(FSError::AlreadyExists);
         }
         /* 不存在就创建文件 */
         // 长文件名拆分
@@ -510,21 +549,15 @@ impl VFile {
         let long_entry_num = long_name_vec.len();
         // println!("need_dirent: {}", long_entry_num + 1);
         // 搜索能够在文件夹里创建足够目录项的空处
-        let mut dirent_offset: usize;
-        if let Some(offset) = self.find_free_dirents(long_entry_num + 1) {
-            dirent_offset = offset;
-        } else {
-            return None;
-        }
+        let mut dirent_offset = self.find_free_dirents(long_entry_num + 1)?;
         // println!("dirent_offset: {}", dirent_offset);
-        // 生成短文件名及对应目录项
-        let short_name: [u8; SHORT_NAME_LEN] = generate_short_name(filename);
+        // 短文件名对应的目录项
         let mut name = [0u8; SHORT_FILE_NAME_LEN];
         name.copy_from_slice(&short_name[0..SHORT_FILE_NAME_LEN]);
         let mut ext = [0u8; SHORT_FILE_EXT_LEN];
         ext.copy_from_slice(&short_name[SHORT_FILE_NAME_LEN..SHORT_NAME_LEN]);
         // 给文件或文件夹分配空间
-        let first_data_cluster = self.fs.write().alloc_cluster(None).unwrap();
+        let first_data_cluster = self.fs.write().alloc_cluster(None)?;
         // println!("first_data_cluster: {}", first_data_cluster);
         let short_entry = ShortDirectoryEntry::new(name, ext, attribute, first_data_cluster);
         let checksum = short_entry.checksum();
@@ -537,7 +570,7 @@ impl VFile {
             }
             let long_entry = LongDirectoryEntry::new(long_name_vec.pop().unwrap(), order, checksum);
             assert_eq!(
-                self.write_at(dirent_offset, long_entry.as_bytes()),
+                self.write_at(dirent_offset, long_entry.as_bytes())?,
                 DIRENT_SZ
             );
             dirent_offset += DIRENT_SZ;
@@ -545,11 +578,11 @@ impl VFile {
         }
         // 写短目录项
         assert_eq!(
-            self.write_at(dirent_offset, short_entry.as_bytes()),
+            self.write_at(dirent_offset, short_entry.as_bytes())?,
             DIRENT_SZ
         );
         // 检查文件是否创建成功
-        let vfile = self.find_vfile_byname(filename).unwrap();
+        let vfile = self.find_vfile_byname(filename)?;
         // 如果是目录类型，需要创建 .和 ..(根目录不需要, 但显然不会去创建根目录)
         if attribute.contains(FileAttributes::DIRECTORY) {
             let dot: [u8; SHORT_NAME_LEN] = generate_short_name(".");
@@ -568,15 +601,14 @@ impl VFile {
                     name,
                     ext,
                     FileAttributes::DIRECTORY,
-                    self.first_data_cluster() as u32,
+                    self.first_data_cluster()?,
                 );
             }
-            vfile.write_at(0, self_dir.as_bytes());
-            vfile.write_at(DIRENT_SZ, parent_dir.as_bytes());
+            vfile.write_at(0, self_dir.as_bytes())?;
+            vfile.write_at(DIRENT_SZ, parent_dir.as_bytes())?;
         }
-        return Some(Arc::new(vfile));
+        Ok(Arc::new(vfile))
     }
-    // 清空文件
     // pub fn clear(&self) {
     //     // 难点:长名目录项也要修改
     //     let first_cluster: u32 = self.first_cluster();
@@ -604,12 +636,12 @@ impl VFile {
     // }
 
     /// 目前只支持删除文件自己, 不能递归删除, 也无法清空文件夹, 如果文件夹里有东西, 那就等着悬空吧
-    pub fn delete(&self) -> usize {
+    pub fn delete(&self) -> Result<usize, FSError> {
         // println!(
         //     "entry cluster_id: {}, offset: {}",
         //     self.short_cluster, self.short_offset
         // );
-        let first_cluster: u32 = self.first_data_cluster();
+        let first_cluster: u32 = self.first_data_cluster()?;
         // println!("file first_cluster: {}", first_cluster);
         for (cluster, offset) in self.long_pos_vec.iter() {
             // println!("cluster_id: {}, offset: {}", *cluster, *offset);
@@ -619,7 +651,7 @@ impl VFile {
                 |long_entry: &mut LongDirectoryEntry| {
                     long_entry.set_deleted();
                 },
-            );
+            )?;
         }
         self.fs.read().data_manager_modify().modify_short_dirent(
             self.short_cluster,
@@ -627,53 +659,56 @@ impl VFile {
             |short_entry: &mut ShortDirectoryEntry| {
                 short_entry.set_deleted();
             },
-        );
+        )?;
         self.fs
             .write()
             .dealloc_clusters(first_cluster as usize, None)
     }
     /// 获取目录中 offset 处目录项的信息
     /// 如果 offset 处内容为空, 则返回空, 成功返回<name, offset, first_cluster, attributes>
-    pub fn dirent_info(&self, mut offset: usize) -> Option<(String, usize, u32, FileAttributes)> {
+    pub fn dirent_info(
+        &self,
+        mut offset: usize,
+    ) -> Result<Option<(String, usize, u32, FileAttributes)>, FSError> {
         if !self.is_dir() {
-            return None;
+            return Ok(None);
         }
         let mut long_entry = LongDirectoryEntry::default();
         let mut name = String::new();
         // 读第一个长文件名目录项
-        let mut read_size = self.read_at(offset, long_entry.as_bytes_mut());
+        let mut read_size = self.read_at(offset, long_entry.as_bytes_mut())?;
         if read_size != DIRENT_SZ
             || !long_entry.is_long()
             || long_entry.is_free()
             || !long_entry.is_last()
         {
-            return None;
+            return Ok(None);
         }
         // 确认了第一长文件名目录项后读剩余的文件名
         let raw_order = long_entry.raw_order();
         name.insert_str(0, long_entry.name_format().as_str());
         for _ in 1..raw_order {
             offset += DIRENT_SZ;
-            read_size = self.read_at(offset, long_entry.as_bytes_mut());
+            read_size = self.read_at(offset, long_entry.as_bytes_mut())?;
             if read_size != DIRENT_SZ || !long_entry.is_long() || long_entry.is_free() {
-                return None;
+                return Ok(None);
             }
             name.insert_str(0, long_entry.name_format().as_str());
         }
         // 读短文件名目录项
         let mut short_entry = ShortDirectoryEntry::default();
         offset += DIRENT_SZ;
-        read_size = self.read_at(offset, short_entry.as_bytes_mut());
+        read_size = self.read_at(offset, short_entry.as_bytes_mut())?;
         if read_size != DIRENT_SZ || !short_entry.is_short() || short_entry.is_free() {
-            return None;
+            return Ok(None);
         }
         let attribute = short_entry.attribute();
         let first_cluster = short_entry.first_cluster();
-        return Some((name, offset, first_cluster, attribute));
+        Ok(Some((name, offset, first_cluster, attribute)))
     }
     /// 获取目录中offset处目录项的信息 TODO:之后考虑和stat复用
     /// 返回<size, atime, mtime, ctime>
-    pub fn stat(&self) -> (i64, i64, i64, i64, u64) {
+    pub fn stat(&self) -> Result<(i64, i64, i64, i64, u64), FSError> {
         let mut stat = self.fs.read().data_manager_modify().read_short_dirent(
             self.short_cluster,
             self.short_offset,
@@ -690,19 +725,20 @@ impl VFile {
                     first_clu as u64,
                 )
             },
-        );
-        stat.0 = self.size() as i64;
-        stat
+        )?;
+        stat.0 = self.size()? as i64;
+        Ok(stat)
     }
-    pub fn ls(&self) -> Option<Vec<(String, FileAttributes)>> {
+    /// 列出目录内容, 对文件调用返回 InvalidInput
+    pub fn ls(&self) -> Result<Vec<(String, FileAttributes)>, FSError> {
         if self.is_file() {
-            return None;
+            return Err(FSError::InvalidInput);
         }
         let mut list: Vec<(String, FileAttributes)> = Vec::new();
-        let capacity = self.capacity();
+        let capacity = self.capacity()?;
         let mut offset = 0;
         while offset < capacity {
-            let item = self.dirent_info(offset);
+            let item = self.dirent_info(offset)?;
             match item {
                 None => offset += DIRENT_SZ,
                 Some(s) => {
@@ -711,6 +747,6 @@ impl VFile {
                 }
             }
         }
-        return Some(list);
+        Ok(list)
     }
 }
@@ -0,0 +1,374 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use runfs::{
+    block_on, AsyncBlockDevice, BlockDevice, BlockFuture, FatType, FileAttributes, IOError,
+    MountOptions, RamDisk, RunFileSystem,
+};
+use spin::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod common;
+use common::formatted_disk;
+
+// 第一次 poll 时返回 Pending, 模拟等待中断的驱动
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+struct SlowDisk {
+    disk: Arc<RamDisk>,
+    requests: AtomicUsize,
+}
+
+impl SlowDisk {
+    fn new(disk: Arc<RamDisk>) -> Self {
+        Self {
+            disk,
+            requests: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl AsyncBlockDevice for SlowDisk {
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            YieldOnce(false).await;
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.disk.read_block(block_id, buf)
+        })
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            YieldOnce(false).await;
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.disk.write_block(block_id, buf)
+        })
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.disk.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.disk.num_blocks()
+    }
+}
+
+// 打开 armed 后第一个写请求停在 Pending, 直到 released, fail 时这个请求失败
+// 其他请求照常完成, 包括同一块的
+struct GatedDisk {
+    disk: Arc<RamDisk>,
+    armed: AtomicBool,
+    released: AtomicBool,
+    fail: AtomicBool,
+    held: AtomicUsize,
+}
+
+impl GatedDisk {
+    fn new(disk: Arc<RamDisk>) -> Self {
+        Self {
+            disk,
+            armed: AtomicBool::new(false),
+            released: AtomicBool::new(false),
+            fail: AtomicBool::new(false),
+            held: AtomicUsize::new(usize::MAX),
+        }
+    }
+}
+
+struct Gate<'a> {
+    device: &'a GatedDisk,
+    block_id: usize,
+    gated: Option<bool>, // 第一次 poll 时决定是不是被拦下的请求
+}
+
+impl Future for Gate<'_> {
+    type Output = Result<(), IOError>;
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let device = self.device;
+        if self.gated.is_none() {
+            let gated = device.armed.swap(false, Ordering::SeqCst);
+            if gated {
+                device.held.store(self.block_id, Ordering::SeqCst);
+            }
+            self.gated = Some(gated);
+        }
+        if self.gated == Some(false) {
+            return Poll::Ready(Ok(()));
+        }
+        if !device.released.load(Ordering::SeqCst) {
+            return Poll::Pending;
+        }
+        device.held.store(usize::MAX, Ordering::SeqCst);
+        if device.fail.load(Ordering::SeqCst) {
+            Poll::Ready(Err(IOError::MediaError))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+impl AsyncBlockDevice for GatedDisk {
+    fn read_block<'a>(&'a self, block_id: usize, buf: &'a mut [u8]) -> BlockFuture<'a> {
+        Box::pin(async move { self.disk.read_block(block_id, buf) })
+    }
+    fn write_block<'a>(&'a self, block_id: usize, buf: &'a [u8]) -> BlockFuture<'a> {
+        Box::pin(async move {
+            Gate {
+                device: self,
+                block_id,
+                gated: None,
+            }
+            .await?;
+            self.disk.write_block(block_id, buf)
+        })
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.disk.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.disk.num_blocks()
+    }
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+#[test]
+fn test_async_read_write() {
+    let disk = formatted_disk(36 * 1024 * 2, FatType::Fat32);
+    let device = Arc::new(SlowDisk::new(disk.clone()));
+    let runfs = RunFileSystem::from_async(device.clone(), 0, MountOptions::default()).unwrap();
+    let runfs = Arc::new(RwLock::new(runfs));
+    let cluster_size = runfs.read().bpb().cluster_size();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let dir = root_dir.create("dir", FileAttributes::DIRECTORY).unwrap();
+    let file = dir.create("a.bin", FileAttributes::FILE).unwrap();
+    // 不对齐的开头和结尾, 中间是整簇
+    let data = pattern(cluster_size * 5 + 300, 0x5A);
+    let requests = device.requests.load(Ordering::Relaxed);
+    assert_eq!(
+        block_on(file.write_at_async(100, &data)).unwrap(),
+        data.len()
+    );
+    assert!(device.requests.load(Ordering::Relaxed) > requests);
+    assert_eq!(file.size().unwrap(), 100 + data.len());
+    // 每个簇写一小段, 缓存放不下时要先写回换出的簇
+    for k in 0..5 {
+        let patch = pattern(64, k as u8);
+        let offset = k * cluster_size + cluster_size / 2;
+        block_on(file.write_at_async(offset, &patch)).unwrap();
+    }
+    let mut expected = vec![0u8; 100];
+    expected.extend_from_slice(&data);
+    for k in 0..5 {
+        let offset = k * cluster_size + cluster_size / 2;
+        expected[offset..offset + 64].copy_from_slice(&pattern(64, k as u8));
+    }
+    let mut buf = vec![0u8; expected.len() + 50];
+    assert_eq!(
+        block_on(file.read_at_async(0, &mut buf)).unwrap(),
+        expected.len()
+    );
+    assert!(buf[..expected.len()] == expected);
+    // 和同步路径读到的一样
+    let mut sync_buf = vec![0u8; expected.len()];
+    file.read_at(0, &mut sync_buf).unwrap();
+    assert!(sync_buf == expected);
+    let mut part = vec![0u8; cluster_size];
+    assert_eq!(
+        block_on(file.read_at_async(cluster_size * 2 + 7, &mut part)).unwrap(),
+        cluster_size
+    );
+    assert!(part[..] == expected[cluster_size * 2 + 7..cluster_size * 3 + 7]);
+    let mut dir_buf = vec![0u8; cluster_size];
+    let mut sync_dir_buf = vec![0u8; cluster_size];
+    block_on(dir.read_at_async(0, &mut dir_buf)).unwrap();
+    dir.read_at(0, &mut sync_dir_buf).unwrap();
+    assert!(dir_buf == sync_dir_buf);
+    drop((root_dir, dir, file));
+    Arc::try_unwrap(runfs)
+        .ok()
+        .unwrap()
+        .into_inner()
+        .unmount()
+        .unwrap();
+    // 直接在同步设备上重新挂载
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk).unwrap()));
+    assert!(runfs.read().check().unwrap().is_clean());
+    let file = runfs
+        .read()
+        .root_vfile(&runfs)
+        .find_vfile_byname("dir")
+        .unwrap()
+        .find_vfile_byname("a.bin")
+        .unwrap();
+    let mut buf = vec![0u8; expected.len()];
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf == expected);
+}
+
+#[test]
+fn test_async_fixed_root() {
+    let disk = formatted_disk(8 * 1024 * 2, FatType::Fat16);
+    let device = Arc::new(SlowDisk::new(disk.clone()));
+    let runfs = RunFileSystem::from_async(device, 0, MountOptions::default()).unwrap();
+    let runfs = Arc::new(RwLock::new(runfs));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let names = ["a.txt", "b.txt", "c.txt"];
+    for name in names {
+        let file = root_dir.create(name, FileAttributes::FILE).unwrap();
+        block_on(file.write_at_async(0, name.as_bytes())).unwrap();
+    }
+    // 根目录在固定区域中, 跨扇区读
+    let root_size = root_dir.capacity().unwrap();
+    let mut buf = vec![0u8; 1024];
+    let mut sync_buf = vec![0u8; 1024];
+    assert_eq!(
+        block_on(root_dir.read_at_async(root_size - 1000, &mut buf)).unwrap(),
+        1000
+    );
+    root_dir.read_at(root_size - 1000, &mut sync_buf).unwrap();
+    assert!(buf == sync_buf);
+    block_on(root_dir.read_at_async(0, &mut buf)).unwrap();
+    root_dir.read_at(0, &mut sync_buf).unwrap();
+    assert!(buf == sync_buf);
+    // 目录项在根目录区的文件大小也更新了
+    for name in names {
+        let file = root_dir.find_vfile_byname(name).unwrap();
+        assert_eq!(file.size().unwrap(), name.len());
+        let mut content = [0u8; 16];
+        let len = block_on(file.read_at_async(0, &mut content)).unwrap();
+        assert_eq!(&content[..len], name.as_bytes());
+    }
+    runfs.read().sync().unwrap();
+    assert!(runfs.read().check().unwrap().is_clean());
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn test_async_adapter() {
+    // 同步设备上挂载时包装成异步设备
+    let disk = formatted_disk(8 * 1024 * 2, FatType::Fat16);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let dir = root_dir.create("d", FileAttributes::DIRECTORY).unwrap();
+    let file = dir.create("f.bin", FileAttributes::FILE).unwrap();
+    let data = pattern(10000, 0x11);
+    let write = file.write_at_async(0, &data);
+    assert_send(&write);
+    block_on(write).unwrap();
+    let mut buf = vec![0u8; data.len()];
+    let read = file.read_at_async(0, &mut buf);
+    assert_send(&read);
+    assert_eq!(block_on(read).unwrap(), data.len());
+    assert!(buf == data);
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf == data);
+}
+
+#[test]
+fn test_async_write_back_pinned() {
+    let disk = formatted_disk(36 * 1024 * 2, FatType::Fat32);
+    let device = Arc::new(GatedDisk::new(disk.clone()));
+    let runfs = RunFileSystem::from_async(device.clone(), 0, MountOptions::default()).unwrap();
+    let runfs = Arc::new(RwLock::new(runfs));
+    let cluster_size = runfs.read().bpb().cluster_size();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("pin.bin", FileAttributes::FILE).unwrap();
+    let data = pattern(cluster_size * 2 + 100, 0x33);
+    file.write_at(0, &data).unwrap();
+    runfs.read().sync().unwrap();
+    // 最后一个簇和根目录簇都在缓存中并且修改过
+    file.write_at(data.len(), b"tail!").unwrap();
+    // 异步读第二个簇, 先换出最后一个簇, 写回停在等待中
+    device.armed.store(true, Ordering::SeqCst);
+    let mut buf = [0u8; 16];
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut read = Box::pin(file.read_at_async(cluster_size + 3, &mut buf));
+    while device.held.load(Ordering::SeqCst) == usize::MAX {
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+    }
+    // 同步路径要换出缓存, 不能换出正在写回的簇
+    let mut first = [0u8; 16];
+    file.read_at(5, &mut first).unwrap();
+    assert!(first[..] == data[5..21]);
+    // 写回失败后修改标记恢复, 之后还能写回
+    device.fail.store(true, Ordering::SeqCst);
+    device.released.store(true, Ordering::SeqCst);
+    assert!(matches!(
+        block_on(read),
+        Err(runfs::FSError::Io(IOError::MediaError))
+    ));
+    device.fail.store(false, Ordering::SeqCst);
+    drop((root_dir, file));
+    runfs.read().sync().unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk).unwrap()));
+    assert!(runfs.read().check().unwrap().is_clean());
+    let file = runfs
+        .read()
+        .root_vfile(&runfs)
+        .find_vfile_byname("pin.bin")
+        .unwrap();
+    assert_eq!(file.size().unwrap(), data.len() + 5);
+    let mut tail = [0u8; 5];
+    file.read_at(data.len(), &mut tail).unwrap();
+    assert_eq!(&tail, b"tail!");
+}
+
+#[test]
+fn test_async_write_back_not_stale() {
+    let disk = formatted_disk(36 * 1024 * 2, FatType::Fat32);
+    let device = Arc::new(GatedDisk::new(disk.clone()));
+    let runfs = RunFileSystem::from_async(device.clone(), 0, MountOptions::default()).unwrap();
+    let runfs = Arc::new(RwLock::new(runfs));
+    let cluster_size = runfs.read().bpb().cluster_size();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("stale.bin", FileAttributes::FILE).unwrap();
+    let data = pattern(cluster_size * 2 + 100, 0x44);
+    file.write_at(0, &data).unwrap();
+    runfs.read().sync().unwrap();
+    file.write_at(data.len(), b"older").unwrap();
+    // 最后一个簇换出时的写回停在等待中
+    device.armed.store(true, Ordering::SeqCst);
+    let mut buf = [0u8; 16];
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut read = Box::pin(file.read_at_async(cluster_size + 3, &mut buf));
+    while device.held.load(Ordering::SeqCst) == usize::MAX {
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+    }
+    // 写回期间改了这个簇再 sync, sync 要等旧快照落盘后再写
+    file.write_at(data.len(), b"newer").unwrap();
+    let sync = {
+        let runfs = Arc::clone(&runfs);
+        std::thread::spawn(move || runfs.read().sync())
+    };
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    device.released.store(true, Ordering::SeqCst);
+    block_on(read).unwrap();
+    sync.join().unwrap().unwrap();
+    drop((root_dir, file));
+    drop(runfs);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk).unwrap()));
+    let file = runfs
+        .read()
+        .root_vfile(&runfs)
+        .find_vfile_byname("stale.bin")
+        .unwrap();
+    let mut tail = [0u8; 5];
+    file.read_at(data.len(), &mut tail).unwrap();
+    assert_eq!(&tail, b"newer");
+}
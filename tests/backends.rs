@@ -0,0 +1,126 @@
+use runfs::{
+    BlockDevice, FSError, FileAttributes, FileBlockDevice, FormatOptions, IOError, RamDisk,
+    ReadOnlyDevice, RunFileSystem,
+};
+use spin::RwLock;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+mod common;
+use common::{read_file, write_file};
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+// 测试用的镜像文件, 用完删除
+struct TempImage(PathBuf);
+
+impl TempImage {
+    fn new(tag: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("runfs-backend-{}-{}.img", std::process::id(), tag));
+        let _ = fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl Drop for TempImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_ram_disk() {
+    let device = RamDisk::new(8);
+    assert_eq!(device.block_size(), Some(512));
+    assert_eq!(device.num_blocks(), Some(8));
+    device.write_block(7, &[0xAB; 512]).unwrap();
+    let mut buf = [0u8; 512];
+    device.read_block(7, &mut buf).unwrap();
+    assert_eq!(buf, [0xAB; 512]);
+    assert_eq!(device.read_block(8, &mut buf), Err(IOError::OutOfRange));
+    assert_eq!(device.write_block(8, &buf), Err(IOError::OutOfRange));
+    // 多块读写和逐块读写结果一致
+    let data: Vec<u8> = (0..4 * 512).map(|i| (i % 251) as u8).collect();
+    device.write_blocks(2, 4, &data).unwrap();
+    let mut read_buf = vec![0u8; 4 * 512];
+    device.read_blocks(2, 4, &mut read_buf).unwrap();
+    assert_eq!(read_buf, data);
+    assert_eq!(&device.to_vec()[2 * 512..6 * 512], &data[..]);
+    let copy = RamDisk::from_vec(device.to_vec(), 1024);
+    assert_eq!(copy.num_blocks(), Some(4));
+}
+
+#[test]
+fn test_file_block_device() {
+    let image = TempImage::new("rw");
+    let device = FileBlockDevice::create(&image.0, TOTAL_SECTORS as usize).unwrap();
+    assert_eq!(device.num_blocks(), Some(TOTAL_SECTORS as usize));
+    assert!(!device.is_read_only());
+    let device: Arc<dyn BlockDevice> = Arc::new(device);
+    RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    let data: Vec<u8> = (0..10000).map(|i| (i % 253) as u8).collect();
+    write_file(device.clone(), "data.bin", &data);
+    device.flush().unwrap();
+    drop(device);
+    // 重新打开后内容还在
+    let device = Arc::new(FileBlockDevice::open(&image.0).unwrap());
+    assert_eq!(read_file(device.clone(), "data.bin").unwrap(), data);
+    let mut buf = [0u8; 512];
+    assert_eq!(
+        device.read_block(TOTAL_SECTORS as usize, &mut buf),
+        Err(IOError::OutOfRange)
+    );
+    assert_eq!(
+        device.write_block(TOTAL_SECTORS as usize, &buf),
+        Err(IOError::OutOfRange)
+    );
+    assert_eq!(
+        fs::metadata(&image.0).unwrap().len(),
+        TOTAL_SECTORS as u64 * 512
+    );
+}
+
+#[test]
+fn test_file_block_device_read_only() {
+    let image = TempImage::new("ro");
+    {
+        let device: Arc<dyn BlockDevice> =
+            Arc::new(FileBlockDevice::create(&image.0, TOTAL_SECTORS as usize).unwrap());
+        RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+        write_file(device, "a.txt", b"read only");
+    }
+    let device = Arc::new(FileBlockDevice::open_read_only(&image.0).unwrap());
+    assert!(device.is_read_only());
+    assert_eq!(
+        device.write_block(0, &[0u8; 512]),
+        Err(IOError::WriteProtected)
+    );
+    assert_eq!(read_file(device, "a.txt").unwrap(), b"read only");
+}
+
+#[test]
+fn test_read_only_device() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(disk.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    write_file(disk.clone(), "a.txt", b"wrapped");
+    let before = disk.num_blocks();
+    let device = Arc::new(ReadOnlyDevice::new(disk));
+    assert!(device.is_read_only());
+    assert_eq!(device.num_blocks(), before);
+    assert_eq!(
+        device.write_block(0, &[0u8; 512]),
+        Err(IOError::WriteProtected)
+    );
+    assert_eq!(device.discard(0..1), Err(IOError::WriteProtected));
+    assert_eq!(read_file(device.clone(), "a.txt").unwrap(), b"wrapped");
+    // 挂载和读都可以, 修改在写回时报错
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    root_dir.create("b.txt", FileAttributes::FILE).unwrap();
+    assert!(matches!(
+        runfs.read().sync(),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+}
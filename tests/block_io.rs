@@ -0,0 +1,141 @@
+use runfs::{BlockDevice, FatType, FileAttributes, FormatOptions, IOError, RamDisk, RunFileSystem};
+use spin::{Mutex, RwLock};
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+
+// 覆盖多块读写并记录每次请求的块数
+struct CountingBlockDevice {
+    inner: RamDisk,
+    reads: Mutex<Vec<usize>>,
+    writes: Mutex<Vec<usize>>,
+}
+
+impl CountingBlockDevice {
+    fn new(blocks: usize) -> Self {
+        Self {
+            inner: RamDisk::new(blocks),
+            reads: Mutex::new(Vec::new()),
+            writes: Mutex::new(Vec::new()),
+        }
+    }
+    fn reset(&self) {
+        self.reads.lock().clear();
+        self.writes.lock().clear();
+    }
+}
+
+impl BlockDevice for CountingBlockDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.reads.lock().push(1);
+        self.inner.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.writes.lock().push(1);
+        self.inner.write_block(block_id, buf)
+    }
+    fn read_blocks(&self, start_block: usize, count: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.reads.lock().push(count);
+        self.inner.read_blocks(start_block, count, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.writes.lock().push(count);
+        self.inner.write_blocks(start_block, count, buf)
+    }
+}
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+fn format(device: Arc<CountingBlockDevice>) {
+    let mut options = FormatOptions::new(TOTAL_SECTORS);
+    // 8 扇区一簇时簇数只够 FAT16
+    options.fat_type = FatType::Fat16;
+    options.sectors_per_cluster = Some(8);
+    RunFileSystem::format(device, options).unwrap();
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn test_default_multi_block() {
+    let device = RamDisk::new(16);
+    let data = pattern(4 * BLOCK_SZ);
+    device.write_blocks(3, 4, &data).unwrap();
+    let mut buf = vec![0u8; 4 * BLOCK_SZ];
+    device.read_blocks(3, 4, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    let mut block = vec![0u8; BLOCK_SZ];
+    device.read_block(5, &mut block).unwrap();
+    assert_eq!(block, data[2 * BLOCK_SZ..3 * BLOCK_SZ]);
+    // buf 不能平均分成 count 块
+    assert!(matches!(
+        device.read_blocks(0, 3, &mut buf),
+        Err(IOError::NotEnoughBuffer)
+    ));
+    assert!(device.write_blocks(0, 0, &[]).is_ok());
+}
+
+#[test]
+fn test_large_file_io_uses_multi_block() {
+    let device = Arc::new(CountingBlockDevice::new(TOTAL_SECTORS as usize));
+    format(device.clone());
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let sectors_per_cluster = runfs.read().bpb().sectors_per_cluster() as usize;
+    let cluster_size = runfs.read().bpb().cluster_size();
+    let data = pattern(cluster_size * 16);
+    let file = root_dir.create("big.bin", FileAttributes::FILE).unwrap();
+
+    device.reset();
+    assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+    // 新卷上文件的簇是连续的, 除了还在缓存中的簇, 其余的一次写下去
+    let largest = *device.writes.lock().iter().max().unwrap();
+    assert!(largest >= 12 * sectors_per_cluster);
+
+    device.reset();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert_eq!(buf, data);
+    let reads = device.reads.lock().clone();
+    assert!(*reads.iter().max().unwrap() >= 12 * sectors_per_cluster);
+    // 除了 FAT 表扇区之外没有按扇区的零散读
+    assert!(reads.iter().filter(|n| **n == 1).count() < 4);
+}
+
+#[test]
+fn test_multi_block_io_sees_cached_clusters() {
+    let device = Arc::new(CountingBlockDevice::new(TOTAL_SECTORS as usize));
+    format(device.clone());
+    let cluster_size;
+    let data = {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        cluster_size = runfs.read().bpb().cluster_size();
+        let mut data = pattern(cluster_size * 4);
+        let file = root_dir.create("mixed.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        // 改第二个簇中的几个字节, 这个簇留在缓存中还没写回
+        file.write_at(cluster_size + 10, b"cached").unwrap();
+        data[cluster_size + 10..cluster_size + 16].copy_from_slice(b"cached");
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+        assert!(buf == data);
+        // 整簇写入也要更新缓存中的簇
+        let second = vec![0xA5u8; cluster_size];
+        assert_eq!(file.write_at(cluster_size, &second).unwrap(), cluster_size);
+        data[cluster_size..cluster_size * 2].copy_from_slice(&second);
+        let mut byte = [0u8; 1];
+        file.read_at(cluster_size + 10, &mut byte).unwrap();
+        assert_eq!(byte[0], 0xA5);
+        data
+    };
+    // 卸载后缓存写回, 重新挂载读到的内容一致
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname("mixed.bin").unwrap();
+    let mut buf = vec![0u8; file.size().unwrap()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), cluster_size * 4);
+    assert!(buf == data);
+}
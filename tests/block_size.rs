@@ -0,0 +1,125 @@
+use runfs::{
+    BlockDevice, BlockSizeAdapter, FSError, FatType, FileAttributes, FormatOptions, IOError,
+    RamDisk, RunFileSystem,
+};
+use spin::{Mutex, RwLock};
+use std::sync::Arc;
+
+const VOLUME_SZ: usize = 64 * 1024 * 1024;
+
+// 记录每次写入的字节数
+struct RecordingBlockDevice {
+    inner: RamDisk,
+    writes: Mutex<Vec<usize>>,
+}
+
+impl BlockDevice for RecordingBlockDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.writes.lock().push(buf.len());
+        self.inner.write_block(block_id, buf)
+    }
+    fn write_blocks(&self, start_block: usize, count: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.writes.lock().push(buf.len());
+        self.inner.write_blocks(start_block, count, buf)
+    }
+    fn block_size(&self) -> Option<usize> {
+        self.inner.block_size()
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        self.inner.num_blocks()
+    }
+}
+
+fn round_trip(device: Arc<dyn BlockDevice>, sector_size: usize, fat_type: FatType) {
+    let mut options = FormatOptions::with_fat_type((VOLUME_SZ / sector_size) as u32, fat_type);
+    options.bytes_per_sector = sector_size as u16;
+    RunFileSystem::format(device.clone(), options).unwrap();
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 241) as u8).collect();
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let dir = root_dir.create("dir", FileAttributes::DIRECTORY).unwrap();
+        let file = dir.create("data.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        // 不对齐的改写
+        assert_eq!(file.write_at(777, b"patched").unwrap(), 7);
+    }
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_bypath("dir/data.bin").unwrap();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert_eq!(&buf[777..784], b"patched");
+    assert_eq!(&buf[..777], &data[..777]);
+    assert_eq!(&buf[784..], &data[784..]);
+}
+
+#[test]
+fn test_small_sectors_on_large_blocks() {
+    // 4K 页的 NAND 上用 512 字节扇区
+    let nand = Arc::new(RecordingBlockDevice {
+        inner: RamDisk::with_block_size(VOLUME_SZ / 4096, 4096),
+        writes: Mutex::new(Vec::new()),
+    });
+    let device = Arc::new(BlockSizeAdapter::new(nand.clone(), 512));
+    assert_eq!(device.block_size(), Some(512));
+    assert_eq!(device.num_blocks(), Some(VOLUME_SZ / 512));
+    // 块大小不一致时不能直接挂载
+    let mut options = FormatOptions::new((VOLUME_SZ / 512) as u32);
+    options.bytes_per_sector = 512;
+    RunFileSystem::format(device.clone(), options).unwrap();
+    assert!(matches!(
+        RunFileSystem::new(nand.clone()),
+        Err(FSError::InvalidInput)
+    ));
+    round_trip(device, 512, FatType::Fat32);
+    // 底层设备只收到整页的写入
+    let writes = nand.writes.lock();
+    assert!(!writes.is_empty());
+    assert!(writes.iter().all(|len| len % 4096 == 0));
+}
+
+#[test]
+// small-cache 只能挂载 512 字节扇区
+#[cfg(not(feature = "small-cache"))]
+fn test_large_sectors_on_small_blocks() {
+    // 512 字节块的 SD 卡上用 4K 扇区
+    let sd = Arc::new(RamDisk::new(VOLUME_SZ / 512));
+    let device = Arc::new(BlockSizeAdapter::new(sd.clone(), 4096));
+    assert_eq!(device.num_blocks(), Some(VOLUME_SZ / 4096));
+    // 4K 扇区时簇数只够 FAT16
+    round_trip(device, 4096, FatType::Fat16);
+    // 启动扇区在设备的第 0 块, 签名在 4K 扇区的第 510 字节处
+    let image = sd.to_vec();
+    assert_eq!(&image[510..512], &[0x55, 0xAA]);
+    assert_eq!(u16::from_le_bytes([image[11], image[12]]), 4096);
+}
+
+#[test]
+fn test_partial_block_access() {
+    let disk = Arc::new(RamDisk::with_block_size(4, 2048));
+    let device = BlockSizeAdapter::new(disk.clone(), 512);
+    assert_eq!(device.num_blocks(), Some(16));
+    device.write_block(5, &[0x11; 512]).unwrap();
+    // 同一设备块中的其它扇区不变
+    let image = disk.to_vec();
+    assert!(image[..2560].iter().all(|b| *b == 0));
+    assert!(image[2560..3072].iter().all(|b| *b == 0x11));
+    assert!(image[3072..].iter().all(|b| *b == 0));
+    // 跨设备块的多扇区写入
+    let data: Vec<u8> = (0..3 * 512).map(|i| (i % 199) as u8).collect();
+    device.write_blocks(3, 3, &data).unwrap();
+    assert_eq!(&disk.to_vec()[1536..3072], &data[..]);
+    let mut buf = vec![0u8; 3 * 512];
+    device.read_blocks(3, 3, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    // 比扇区短的 buf 只读开头
+    let mut head = [0u8; 16];
+    device.read_block(3, &mut head).unwrap();
+    assert_eq!(&head, &data[..16]);
+    let mut too_long = [0u8; 1024];
+    assert!(device.read_block(0, &mut too_long).is_err());
+}
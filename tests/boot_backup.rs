@@ -0,0 +1,134 @@
+use runfs::{
+    BlockDevice, FatType, FileAttributes, FormatOptions, RunFileSystem, SectorCopy, VFile,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+mod common;
+use common::{formatted, read_sector};
+
+const BLOCK_SZ: usize = 512;
+
+const FAT32_SECTORS: u32 = 64 * 1024 * 2;
+const FAT16_SECTORS: u32 = 16 * 1024 * 2;
+const BACKUP_BOOT_SECTOR: usize = 6;
+const FSINFO_SECTOR: usize = 1;
+
+fn zero_sector(device: &Arc<dyn BlockDevice>, sector_id: usize) {
+    device.write_block(sector_id, &[0u8; BLOCK_SZ]).unwrap();
+}
+
+fn create_file(device: &Arc<dyn BlockDevice>, name: &str, len: usize) {
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create(name, FileAttributes::FILE).unwrap();
+    let buf: Vec<u8> = (0..len).map(|i| (i % 253) as u8).collect();
+    assert_eq!(file.write_at(0, &buf).unwrap(), len);
+}
+
+fn check_file(root_dir: &VFile, name: &str, len: usize) {
+    let file = root_dir.find_vfile_byname(name).unwrap();
+    let mut buf = vec![0u8; len];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), len);
+    assert!(buf.iter().enumerate().all(|(i, b)| *b == (i % 253) as u8));
+}
+
+#[test]
+fn test_primary_used_when_valid() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert_eq!(runfs.boot_sector_copy(), SectorCopy::Primary);
+    assert_eq!(runfs.fsinfo_copy(), Some(SectorCopy::Primary));
+}
+
+#[test]
+fn test_mount_from_backup_and_repair() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    create_file(&device, "backup.txt", 3000);
+    let backup = read_sector(&device, BACKUP_BOOT_SECTOR);
+    zero_sector(&device, 0);
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        assert_eq!(runfs.read().boot_sector_copy(), SectorCopy::Backup);
+        let root_dir = runfs.read().root_vfile(&runfs);
+        check_file(&root_dir, "backup.txt", 3000);
+        runfs.write().repair_boot_sector().unwrap();
+        assert_eq!(runfs.read().boot_sector_copy(), SectorCopy::Primary);
+    }
+    assert_eq!(read_sector(&device, 0), backup);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert_eq!(runfs.read().boot_sector_copy(), SectorCopy::Primary);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_file(&root_dir, "backup.txt", 3000);
+}
+
+#[test]
+fn test_mount_from_backup_bad_total_sectors() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    create_file(&device, "sectors.txt", 3000);
+    // 总扇区数比数据区的起始扇区还小, 算簇数之前就要发现
+    let mut primary = read_sector(&device, 0);
+    primary[0x20..0x24].copy_from_slice(&10u32.to_le_bytes());
+    device.write_block(0, &primary).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert_eq!(runfs.read().boot_sector_copy(), SectorCopy::Backup);
+    assert_eq!(runfs.read().bpb().total_sectors(), FAT32_SECTORS);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_file(&root_dir, "sectors.txt", 3000);
+}
+
+#[test]
+fn test_fsinfo_fallback_and_repair() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let free_clusters = RunFileSystem::new(device.clone()).unwrap().free_clusters();
+    zero_sector(&device, FSINFO_SECTOR);
+    {
+        let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+        assert_eq!(runfs.boot_sector_copy(), SectorCopy::Primary);
+        assert_eq!(runfs.fsinfo_copy(), Some(SectorCopy::Backup));
+        assert_eq!(runfs.free_clusters(), free_clusters);
+        runfs.repair_boot_sector().unwrap();
+    }
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert_eq!(runfs.fsinfo_copy(), Some(SectorCopy::Primary));
+    assert_eq!(runfs.free_clusters(), free_clusters);
+}
+
+#[test]
+fn test_both_fsinfo_corrupted() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    create_file(&device, "a.txt", 5000);
+    let free_clusters = RunFileSystem::new(device.clone()).unwrap().free_clusters();
+    zero_sector(&device, FSINFO_SECTOR);
+    zero_sector(&device, BACKUP_BOOT_SECTOR + FSINFO_SECTOR);
+    {
+        let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+        assert_eq!(runfs.fsinfo_copy(), None);
+        // 空闲簇数重新扫描 FAT 表得到
+        assert_eq!(runfs.free_clusters(), free_clusters);
+        runfs.repair_boot_sector().unwrap();
+    }
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert_eq!(runfs.fsinfo_copy(), Some(SectorCopy::Primary));
+}
+
+#[test]
+fn test_both_boot_sectors_corrupted() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    zero_sector(&device, 0);
+    zero_sector(&device, BACKUP_BOOT_SECTOR);
+    assert!(RunFileSystem::new(device).is_err());
+}
+
+#[test]
+fn test_fat16_has_no_backup() {
+    let device = formatted(
+        FAT16_SECTORS,
+        FormatOptions::with_fat_type(FAT16_SECTORS, FatType::Fat16),
+    );
+    let runfs = RunFileSystem::new(device.clone()).unwrap();
+    assert_eq!(runfs.fsinfo_copy(), None);
+    drop(runfs);
+    zero_sector(&device, 0);
+    assert!(RunFileSystem::new(device).is_err());
+}
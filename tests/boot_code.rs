@@ -0,0 +1,120 @@
+use runfs::{BootSector, FSError, FatType, FileAttributes, FormatOptions, RunFileSystem};
+use spin::RwLock;
+use std::sync::Arc;
+
+mod common;
+use common::{formatted, read_sector};
+
+const BLOCK_SZ: usize = 512;
+
+const FAT32_SECTORS: u32 = 64 * 1024 * 2;
+const FAT16_SECTORS: u32 = 16 * 1024 * 2;
+const BACKUP_BOOT_SECTOR: usize = 6;
+const BOOT_CODE_START: usize = 90;
+const JUMP: [u8; 3] = [0xEB, 0x58, 0x90];
+
+fn boot_code() -> Vec<u8> {
+    (0..BootSector::BOOT_CODE_LEN)
+        .map(|i| (i % 200) as u8 + 1)
+        .collect()
+}
+
+#[test]
+fn test_install_boot_code() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let before = read_sector(&device, 0);
+    let code = boot_code();
+    {
+        let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+        runfs.install_boot_code(JUMP, &code, None).unwrap();
+    }
+    for sector_id in [0, BACKUP_BOOT_SECTOR] {
+        let sector = read_sector(&device, sector_id);
+        assert_eq!(&sector[..3], &JUMP);
+        // OEM 名称和 BPB 不变
+        assert_eq!(&sector[3..BOOT_CODE_START], &before[3..BOOT_CODE_START]);
+        assert_eq!(&sector[BOOT_CODE_START..510], &code[..]);
+        assert_eq!(&sector[510..], &[0x55, 0xAA]);
+    }
+    let boot_sector = BootSector::directly_new(device.clone()).unwrap();
+    assert_eq!(boot_sector.bootjump(), JUMP);
+    assert_eq!(boot_sector.boot_code(), &code[..]);
+    assert!(RunFileSystem::new(device).is_ok());
+}
+
+#[test]
+fn test_chain_load_boot_file() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let loader: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    let first_cluster = {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let file = root_dir.create("LOADER.BIN", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &loader).unwrap(), loader.len());
+        file.first_data_cluster().unwrap()
+    };
+    let code = &boot_code()[..BootSector::BOOT_FILE_CLUSTER_OFFSET];
+    {
+        let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+        runfs
+            .install_boot_code(JUMP, code, Some("loader.bin"))
+            .unwrap();
+    }
+    let boot_sector = BootSector::directly_new(device.clone()).unwrap();
+    assert_eq!(boot_sector.boot_file_cluster(), first_cluster);
+    assert_eq!(
+        &boot_sector.boot_code()[..BootSector::BOOT_FILE_CLUSTER_OFFSET],
+        code
+    );
+    // 引导程序按 BPB 找到簇号对应的扇区
+    let runfs = RunFileSystem::new(device.clone()).unwrap();
+    let bpb = runfs.bpb();
+    let sector_id = bpb.first_data_sector() as usize
+        + (first_cluster as usize - 2) * bpb.sectors_per_cluster() as usize;
+    assert_eq!(read_sector(&device, sector_id), &loader[..BLOCK_SZ]);
+}
+
+#[test]
+fn test_install_boot_code_errors() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    let code = boot_code();
+    // 跳进 BPB
+    assert!(matches!(
+        runfs.install_boot_code([0xEB, 0x3C, 0x90], &code, None),
+        Err(FSError::InvalidInput)
+    ));
+    assert!(matches!(
+        runfs.install_boot_code([0x90, 0x90, 0x90], &code, None),
+        Err(FSError::InvalidInput)
+    ));
+    let mut long_code = code.clone();
+    long_code.push(0);
+    assert!(matches!(
+        runfs.install_boot_code(JUMP, &long_code, None),
+        Err(FSError::InvalidInput)
+    ));
+    // 最后 4 Byte 要留给簇号
+    assert!(matches!(
+        runfs.install_boot_code(JUMP, &code, Some("LOADER.BIN")),
+        Err(FSError::InvalidInput)
+    ));
+    assert!(matches!(
+        runfs.install_boot_code(JUMP, &[], Some("LOADER.BIN")),
+        Err(FSError::NotFound)
+    ));
+    drop(runfs);
+    // 启动扇区没有被改动
+    let boot_sector = BootSector::directly_new(device).unwrap();
+    assert!(boot_sector.boot_code().iter().all(|b| *b == 0));
+
+    let device = formatted(
+        FAT16_SECTORS,
+        FormatOptions::with_fat_type(FAT16_SECTORS, FatType::Fat16),
+    );
+    let mut runfs = RunFileSystem::new(device).unwrap();
+    assert!(matches!(
+        runfs.install_boot_code(JUMP, &code, None),
+        Err(FSError::InvalidInput)
+    ));
+}
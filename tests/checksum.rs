@@ -0,0 +1,213 @@
+use runfs::{
+    BlockDevice, CheckIssue, ChecksumDevice, FSError, FatType, FileAttributes, FormatOptions,
+    IOError, RamDisk, RunFileSystem,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const TOTAL_SECTORS: u32 = 36 * 1024 * 2;
+
+// 把 disk 上第 block_id 块的一个字节取反, 模拟介质上翻转的位
+fn flip(disk: &RamDisk, block_id: usize) {
+    let mut buf = [0u8; 512];
+    disk.read_block(block_id, &mut buf).unwrap();
+    buf[100] ^= 0x10;
+    disk.write_block(block_id, &buf).unwrap();
+}
+
+// 内容全部是 byte 的第一个块
+fn find_block(disk: &RamDisk, byte: u8) -> usize {
+    disk.to_vec()
+        .chunks_exact(512)
+        .position(|block| block.iter().all(|b| *b == byte))
+        .unwrap()
+}
+
+#[test]
+fn test_checksum_device() {
+    let disk = Arc::new(RamDisk::new(1024));
+    let device = ChecksumDevice::new(disk.clone()).unwrap();
+    // 每个校验和块记录 128 个块
+    assert_eq!(device.num_blocks(), Some(1016));
+    // 没有 rebuild 的内容校验不过
+    let mut buf = [0u8; 512];
+    assert_eq!(
+        device.read_block(0, &mut buf),
+        Err(IOError::ChecksumMismatch)
+    );
+    device.rebuild().unwrap();
+    assert!(device.verify().unwrap().is_empty());
+    device.read_block(0, &mut buf).unwrap();
+    let data: Vec<u8> = (0..512 * 4).map(|i| (i % 251) as u8).collect();
+    device.write_blocks(126, 4, &data).unwrap();
+    device.write_block(1015, &[0x77; 512]).unwrap();
+    let mut read = vec![0u8; 512 * 4];
+    device.read_blocks(126, 4, &mut read).unwrap();
+    assert!(read == data);
+    flip(&disk, 128);
+    flip(&disk, 1015);
+    assert_eq!(device.verify().unwrap(), vec![128, 1015]);
+    assert_eq!(
+        device.read_blocks(126, 4, &mut read),
+        Err(IOError::ChecksumMismatch)
+    );
+    assert_eq!(
+        device.read_block(1015, &mut buf[..16]),
+        Err(IOError::ChecksumMismatch)
+    );
+    device.read_block(127, &mut buf[..16]).unwrap();
+    assert_eq!(buf[..16], data[512..512 + 16]);
+    // 重写后恢复
+    device.write_block(128, &[0x28; 512]).unwrap();
+    assert_eq!(device.verify().unwrap(), vec![1015]);
+    assert_eq!(
+        device.write_block(0, &[0; 100]),
+        Err(IOError::NotEnoughBuffer)
+    );
+    assert_eq!(
+        device.write_block(1016, &[0; 512]),
+        Err(IOError::OutOfRange)
+    );
+}
+
+#[test]
+fn test_checksum_store() {
+    let disk = Arc::new(RamDisk::new(1024));
+    let store = Arc::new(RamDisk::new(4));
+    let device = ChecksumDevice::with_store(disk.clone(), store.clone()).unwrap();
+    // 4 个校验和块只够 512 个块
+    assert_eq!(device.num_blocks(), Some(512));
+    device.rebuild().unwrap();
+    device.write_block(300, &[0x30; 512]).unwrap();
+    assert!(store.to_vec().iter().any(|b| *b != 0));
+    flip(&disk, 300);
+    assert_eq!(device.verify().unwrap(), vec![300]);
+    let device = ChecksumDevice::with_store(disk, Arc::new(RamDisk::new(16))).unwrap();
+    assert_eq!(device.num_blocks(), Some(1024));
+}
+
+#[test]
+fn test_checksum_invalid_device() {
+    // 放不下一个 CRC32 的块
+    let tiny = Arc::new(RamDisk::with_block_size(64, 2));
+    assert!(matches!(
+        ChecksumDevice::new(tiny),
+        Err(IOError::InvalidInput)
+    ));
+    // 只够放校验和, 没有数据块
+    assert!(matches!(
+        ChecksumDevice::new(Arc::new(RamDisk::new(1))),
+        Err(IOError::OutOfRange)
+    ));
+    let store = Arc::new(RamDisk::with_block_size(4, 4096));
+    assert!(matches!(
+        ChecksumDevice::with_store(Arc::new(RamDisk::new(64)), store),
+        Err(IOError::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_scrub() {
+    let disk = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let device = Arc::new(ChecksumDevice::new(disk.clone()).unwrap());
+    let sectors = device.num_blocks().unwrap() as u32;
+    device.rebuild().unwrap();
+    RunFileSystem::format(
+        device.clone(),
+        FormatOptions::with_fat_type(sectors, FatType::Fat32),
+    )
+    .unwrap();
+    let (dir_cluster, clusters) = {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let dir = root_dir.create("dir", FileAttributes::DIRECTORY).unwrap();
+        let file = dir.create("b.bin", FileAttributes::FILE).unwrap();
+        root_dir.create("a.txt", FileAttributes::FILE).unwrap();
+        // 每个簇的内容不同, 方便在盘上找到
+        let cluster_size = runfs.read().bpb().cluster_size();
+        let data: Vec<u8> = (0..cluster_size * 3)
+            .map(|i| 0xB0 + (i / cluster_size) as u8)
+            .collect();
+        file.write_at(0, &data).unwrap();
+        let clusters = runfs
+            .read()
+            .fat_manager_modify()
+            .all_clusters(file.first_data_cluster().unwrap() as usize)
+            .unwrap();
+        (dir.first_data_cluster().unwrap(), clusters)
+    };
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    assert!(runfs.read().scrub().unwrap().is_clean());
+    // 第二个簇坏了, 普通检查发现不了
+    flip(&disk, find_block(&disk, 0xB1));
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    assert!(runfs.read().check().unwrap().is_clean());
+    let report = runfs.read().scrub().unwrap();
+    assert_eq!(
+        report.issues,
+        vec![CheckIssue::Unreadable {
+            path: "/DIR/B.BIN".into(),
+            cluster: clusters[1] as u32,
+            error: IOError::ChecksumMismatch,
+        }]
+    );
+    assert!(report.has_errors());
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir
+        .find_vfile_byname("dir")
+        .unwrap()
+        .find_vfile_byname("b.bin")
+        .unwrap();
+    let mut buf = vec![0u8; file.size().unwrap()];
+    assert!(matches!(
+        file.read_at(0, &mut buf),
+        Err(FSError::Io(IOError::ChecksumMismatch))
+    ));
+    // 目录簇坏了, 里面的文件都找不到了
+    let bpb = *runfs.read().bpb();
+    drop(root_dir);
+    drop(file);
+    drop(runfs);
+    let first_sector = bpb.first_data_sector() as usize
+        + (dir_cluster as usize - 2) * bpb.sectors_per_cluster() as usize;
+    flip(&disk, first_sector);
+    let runfs = RunFileSystem::new(device).unwrap();
+    let report = runfs.scrub().unwrap();
+    assert!(report.issues.contains(&CheckIssue::Unreadable {
+        path: "/DIR".into(),
+        cluster: dir_cluster,
+        error: IOError::ChecksumMismatch,
+    }));
+    assert_eq!((report.files, report.directories), (1, 1));
+}
+
+#[test]
+fn test_scrub_bypasses_cache() {
+    let disk = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let device = Arc::new(ChecksumDevice::new(disk.clone()).unwrap());
+    let sectors = device.num_blocks().unwrap() as u32;
+    device.rebuild().unwrap();
+    RunFileSystem::format(
+        device.clone(),
+        FormatOptions::with_fat_type(sectors, FatType::Fat32),
+    )
+    .unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("c.bin", FileAttributes::FILE).unwrap();
+    let cluster_size = runfs.read().bpb().cluster_size();
+    file.write_at(0, &vec![0xC0; cluster_size]).unwrap();
+    let cluster = file.first_data_cluster().unwrap();
+    assert!(runfs.read().scrub().unwrap().is_clean());
+    // 根目录和文件的簇都还在缓存中, scrub 仍然要读设备
+    file.read_at(0, &mut [0u8; 16]).unwrap();
+    flip(&disk, find_block(&disk, 0xC0));
+    assert_eq!(
+        runfs.read().scrub().unwrap().issues,
+        vec![CheckIssue::Unreadable {
+            path: "/C.BIN".into(),
+            cluster,
+            error: IOError::ChecksumMismatch,
+        }]
+    );
+}
@@ -0,0 +1,58 @@
+// 各集成测试共用的格式化和挂载函数, 每个测试文件只用到其中一部分
+#![allow(dead_code)]
+use runfs::{
+    BlockDevice, FSError, FatType, FileAttributes, FormatOptions, RamDisk, RunFileSystem, VFile,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+
+/// 按 options 格式化一块内存盘
+pub fn formatted(total_sectors: u32, options: FormatOptions) -> Arc<dyn BlockDevice> {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(total_sectors as usize));
+    RunFileSystem::format(device.clone(), options).unwrap();
+    device
+}
+
+/// 格式化成指定 FAT 类型, 需要直接操作 RamDisk 时用
+pub fn formatted_disk(total_sectors: u32, fat_type: FatType) -> Arc<RamDisk> {
+    let disk = Arc::new(RamDisk::new(total_sectors as usize));
+    let options = FormatOptions::with_fat_type(total_sectors, fat_type);
+    RunFileSystem::format(disk.clone(), options).unwrap();
+    disk
+}
+
+pub fn mount(device: &Arc<dyn BlockDevice>) -> (Arc<RwLock<RunFileSystem>>, Arc<VFile>) {
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = Arc::new(runfs.read().root_vfile(&runfs));
+    (runfs, root_dir)
+}
+
+/// 绕过文件系统直接读出一个扇区
+pub fn read_sector(device: &Arc<dyn BlockDevice>, sector_id: usize) -> Vec<u8> {
+    let mut sector = vec![0u8; BLOCK_SZ];
+    device.read_block(sector_id, &mut sector).unwrap();
+    sector
+}
+
+/// 挂载后在根目录写文件, 已经存在时从头覆盖
+pub fn write_file(device: Arc<dyn BlockDevice>, name: &str, data: &[u8]) {
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = match root_dir.find_vfile_byname(name) {
+        Ok(file) => Arc::new(file),
+        Err(_) => root_dir.create(name, FileAttributes::FILE).unwrap(),
+    };
+    assert_eq!(file.write_at(0, data).unwrap(), data.len());
+}
+
+/// 挂载后读出根目录中文件的全部内容, 挂载失败或找不到文件时返回错误
+pub fn read_file(device: Arc<dyn BlockDevice>, name: &str) -> Result<Vec<u8>, FSError> {
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device)?));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname(name)?;
+    let mut buf = vec![0u8; file.size()?];
+    assert_eq!(file.read_at(0, &mut buf)?, buf.len());
+    Ok(buf)
+}
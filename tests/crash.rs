@@ -0,0 +1,209 @@
+use runfs::{
+    BlockDevice, CheckIssue, CrashOp, FSError, FatType, FaultyDevice, FileAttributes, IOError,
+    PowerCutFailure, PowerCutTest, RamDisk, RunFileSystem,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+mod common;
+use common::formatted_disk;
+
+const FAT16_SECTORS: u32 = 8 * 1024 * 2;
+const FAT32_SECTORS: u32 = 36 * 1024 * 2;
+
+fn ops() -> Vec<CrashOp> {
+    let data: Vec<u8> = (0..5000).map(|i| (i % 239) as u8).collect();
+    vec![
+        CrashOp::CreateDir("/logs".into()),
+        CrashOp::CreateFile("/logs/boot.log".into()),
+        CrashOp::Write {
+            path: "/logs/boot.log".into(),
+            offset: 0,
+            data: data.clone(),
+        },
+        CrashOp::Sync,
+        CrashOp::CreateFile("/config.txt".into()),
+        CrashOp::Write {
+            path: "/config.txt".into(),
+            offset: 0,
+            data: b"power=cut".to_vec(),
+        },
+        CrashOp::Write {
+            path: "/logs/boot.log".into(),
+            offset: data.len(),
+            data,
+        },
+        CrashOp::Delete("/config.txt".into()),
+    ]
+}
+
+#[test]
+fn test_faulty_device() {
+    let disk = Arc::new(RamDisk::new(16));
+    let device = FaultyDevice::new(disk.clone());
+    let mut buf = [0u8; 512];
+    // 第 n 次读写只失败一次
+    device.fail_read(1, IOError::MediaError);
+    device.fail_write(0, IOError::Timeout);
+    device.read_block(0, &mut buf).unwrap();
+    assert_eq!(device.read_block(0, &mut buf), Err(IOError::MediaError));
+    device.read_block(0, &mut buf).unwrap();
+    assert_eq!(device.write_block(3, &[0x33; 512]), Err(IOError::Timeout));
+    device.write_block(3, &[0x33; 512]).unwrap();
+    assert_eq!((device.reads(), device.writes()), (3, 2));
+    // 读到的数据损坏, 介质上的不变
+    device.corrupt_reads(Some(3..4));
+    device.read_block(3, &mut buf).unwrap();
+    assert_eq!(buf, [!0x33; 512]);
+    device.corrupt_reads(None);
+    device.read_block(3, &mut buf).unwrap();
+    assert_eq!(buf, [0x33; 512]);
+    // 断电后的写都丢掉
+    device.cut_power_after(3);
+    device.write_block(4, &[0x44; 512]).unwrap();
+    device.write_block(5, &[0x55; 512]).unwrap();
+    assert!(device.is_powered_off());
+    let image = disk.to_vec();
+    assert!(image[4 * 512..5 * 512].iter().all(|b| *b == 0x44));
+    assert!(image[5 * 512..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_faulty_device_write_cache() {
+    let disk = Arc::new(RamDisk::new(16));
+    let device = FaultyDevice::new(disk.clone());
+    device.enable_write_cache(7);
+    device.write_block(1, &[0x11; 512]).unwrap();
+    // 缓存中的写能读到, 但还没有到达介质
+    let mut buf = [0u8; 512];
+    device.read_block(1, &mut buf).unwrap();
+    assert_eq!(buf, [0x11; 512]);
+    assert!(disk.to_vec().iter().all(|b| *b == 0));
+    device.flush().unwrap();
+    assert!(disk.to_vec()[512..1024].iter().all(|b| *b == 0x11));
+    // 断电时没有 flush 的写只有一部分留下
+    for block_id in 2..16 {
+        device
+            .write_block(block_id, &[block_id as u8; 512])
+            .unwrap();
+    }
+    device.cut_power();
+    let image = disk.to_vec();
+    let persisted = (2..16)
+        .filter(|id| {
+            image[id * 512..(id + 1) * 512]
+                .iter()
+                .all(|b| *b == *id as u8)
+        })
+        .count();
+    assert!(persisted < 14);
+    assert!((2..16).all(|id| {
+        let block = &image[id * 512..(id + 1) * 512];
+        block.iter().all(|b| *b == 0) || block.iter().all(|b| *b == id as u8)
+    }));
+}
+
+#[test]
+fn test_check() {
+    let disk = formatted_disk(FAT32_SECTORS, FatType::Fat32);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let dir = root_dir.create("dir", FileAttributes::DIRECTORY).unwrap();
+    let a = dir.create("a.bin", FileAttributes::FILE).unwrap();
+    let b = root_dir.create("b.bin", FileAttributes::FILE).unwrap();
+    let cluster_size = runfs.read().bpb().cluster_size();
+    a.write_at(0, &vec![0xAA; cluster_size * 3]).unwrap();
+    b.write_at(0, &vec![0xBB; cluster_size * 2]).unwrap();
+    root_dir.create("empty.txt", FileAttributes::FILE).unwrap();
+    let report = runfs.read().check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.issues);
+    assert_eq!((report.files, report.directories), (3, 1));
+    // 把 b 接到 a 的第二个簇上, 再截断 a 的簇链
+    let a_first = a.first_data_cluster().unwrap() as usize;
+    let b_first = b.first_data_cluster().unwrap() as usize;
+    let a_clusters = runfs
+        .read()
+        .fat_manager_modify()
+        .all_clusters(a_first)
+        .unwrap();
+    {
+        let fs = runfs.read();
+        let mut fat_manager = fs.fat_manager_modify();
+        fat_manager
+            .set_next_cluster(b_first, a_clusters[1] as u32)
+            .unwrap();
+        fat_manager.set_free(a_clusters[2]).unwrap();
+    }
+    let issues = runfs.read().check().unwrap().issues;
+    assert!(
+        issues.contains(&CheckIssue::InvalidChain {
+            path: "/DIR/A.BIN".into(),
+            cluster: a_clusters[2] as u32,
+        }),
+        "{:?}",
+        issues
+    );
+    assert!(issues.contains(&CheckIssue::CrossLinked {
+        path: "/B.BIN".into(),
+        cluster: a_clusters[1] as u32,
+    }));
+    // b 原来的第二个簇没有人引用了
+    assert!(issues.contains(&CheckIssue::LostClusters { count: 1 }));
+}
+
+#[test]
+fn test_check_lost_clusters_are_not_errors() {
+    let disk = formatted_disk(FAT16_SECTORS, FatType::Fat16);
+    let mut runfs = RunFileSystem::new(disk).unwrap();
+    runfs.alloc_clusters(4, None).unwrap();
+    let report = runfs.check().unwrap();
+    assert_eq!(report.issues, vec![CheckIssue::LostClusters { count: 4 }]);
+    assert!(!report.has_errors());
+}
+
+#[test]
+fn test_power_cut() {
+    let disk = formatted_disk(FAT16_SECTORS, FatType::Fat16);
+    let test = PowerCutTest::new(disk.to_vec(), 512, ops());
+    let writes = test.run().unwrap();
+    assert!(writes > 0);
+}
+
+#[test]
+fn test_power_cut_finds_unordered_writes() {
+    // FAT32 的根目录在数据区, 簇缓存换出时新目录项先于 FAT 表项写回, 断电后目录指向空闲簇
+    let disk = formatted_disk(FAT32_SECTORS, FatType::Fat32);
+    let test = PowerCutTest::new(disk.to_vec(), 512, ops());
+    match test.run() {
+        Err(PowerCutFailure::Check { report, .. }) => {
+            assert!(report.issues.iter().any(
+                |issue| matches!(issue, CheckIssue::InvalidChain { path, .. } if path == "/LOGS")
+            ));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_power_cut_with_write_cache() {
+    let disk = formatted_disk(FAT16_SECTORS, FatType::Fat16);
+    let mut test = PowerCutTest::new(disk.to_vec(), 512, ops());
+    for seed in 0..4 {
+        test.write_cache = Some(seed);
+        test.run().unwrap();
+    }
+}
+
+#[test]
+fn test_power_cut_replay_error() {
+    let disk = formatted_disk(FAT16_SECTORS, FatType::Fat16);
+    let test = PowerCutTest::new(
+        disk.to_vec(),
+        512,
+        vec![CrashOp::Delete("/missing.txt".into())],
+    );
+    assert!(matches!(
+        test.run(),
+        Err(PowerCutFailure::Replay(FSError::NotFound))
+    ));
+}
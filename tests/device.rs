@@ -0,0 +1,187 @@
+use runfs::{
+    BlockDevice, FSError, FileAttributes, FormatOptions, IOError, MountOptions, RamDisk,
+    RunFileSystem,
+};
+use spin::{Mutex, RwLock};
+use std::ops::Range;
+use std::sync::Arc;
+
+mod common;
+use common::read_file;
+
+const BLOCK_SZ: usize = 512;
+
+// 报告几何参数, 记录 flush 次数和 discard 的范围
+struct ReportingBlockDevice {
+    inner: RamDisk,
+    block_size: usize,
+    num_blocks: usize,
+    flushes: Mutex<usize>,
+    discards: Mutex<Vec<Range<usize>>>,
+}
+
+impl ReportingBlockDevice {
+    fn new(num_blocks: usize) -> Self {
+        Self {
+            inner: RamDisk::new(num_blocks),
+            block_size: BLOCK_SZ,
+            num_blocks,
+            flushes: Mutex::new(0),
+            discards: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl BlockDevice for ReportingBlockDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        self.inner.read_block(block_id, buf)
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        self.inner.write_block(block_id, buf)
+    }
+    fn flush(&self) -> Result<(), IOError> {
+        *self.flushes.lock() += 1;
+        Ok(())
+    }
+    fn discard(&self, blocks: Range<usize>) -> Result<(), IOError> {
+        self.discards.lock().push(blocks);
+        Ok(())
+    }
+    fn block_size(&self) -> Option<usize> {
+        Some(self.block_size)
+    }
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.num_blocks)
+    }
+}
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+fn formatted() -> Arc<ReportingBlockDevice> {
+    let device = Arc::new(ReportingBlockDevice::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    device
+}
+
+// 只实现必需方法, 其余用默认实现
+struct MinimalBlockDevice;
+
+impl BlockDevice for MinimalBlockDevice {
+    fn read_block(&self, _block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        buf.fill(0);
+        Ok(())
+    }
+    fn write_block(&self, _block_id: usize, _buf: &[u8]) -> Result<(), IOError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_default_methods() {
+    let device = MinimalBlockDevice;
+    assert!(device.flush().is_ok());
+    assert!(device.discard(0..4).is_ok());
+    assert_eq!(device.block_size(), None);
+    assert_eq!(device.num_blocks(), None);
+    assert!(!device.is_read_only());
+}
+
+#[test]
+fn test_sync_flushes_caches_and_device() {
+    let device = formatted();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("a.txt", FileAttributes::FILE).unwrap();
+    file.write_at(0, b"synced").unwrap();
+    *device.flushes.lock() = 0;
+    runfs.read().sync().unwrap();
+    assert_eq!(*device.flushes.lock(), 1);
+    // 第一次挂载还没有卸载, 另一次挂载也能读到写回的内容
+    assert_eq!(read_file(device.clone(), "a.txt").unwrap(), b"synced");
+    let free = runfs.read().free_clusters();
+    let other = RunFileSystem::new(device).unwrap();
+    assert_eq!(other.free_clusters(), free);
+}
+
+#[test]
+fn test_unmount_flushes_device() {
+    let device = formatted();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    {
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let file = root_dir.create("b.txt", FileAttributes::FILE).unwrap();
+        file.write_at(0, b"unmounted").unwrap();
+    }
+    *device.flushes.lock() = 0;
+    let runfs = Arc::try_unwrap(runfs).ok().unwrap().into_inner();
+    runfs.unmount().unwrap();
+    assert_eq!(*device.flushes.lock(), 1);
+    assert_eq!(read_file(device, "b.txt").unwrap(), b"unmounted");
+}
+
+#[test]
+fn test_discard_freed_clusters() {
+    for discard in [false, true] {
+        let device = formatted();
+        let runfs = Arc::new(RwLock::new(
+            RunFileSystem::mount(device.clone(), 0, MountOptions { discard }).unwrap(),
+        ));
+        assert_eq!(runfs.read().mount_options().discard, discard);
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let cluster_size = runfs.read().bpb().cluster_size();
+        let file = root_dir.create("big.bin", FileAttributes::FILE).unwrap();
+        file.write_at(0, &vec![0x5A; cluster_size * 8]).unwrap();
+        let first_cluster = file.first_data_cluster().unwrap() as usize;
+        let clusters = runfs
+            .read()
+            .fat_manager_modify()
+            .all_clusters(first_cluster)
+            .unwrap();
+        file.delete().unwrap();
+        let discards = device.discards.lock().clone();
+        if !discard {
+            assert!(discards.is_empty());
+            continue;
+        }
+        // 新卷上文件的簇是连续的, 合并成一次 discard
+        let bpb = runfs.read().bpb();
+        let first_sector = bpb.first_data_sector() as usize
+            + (clusters[0] - 2) * bpb.sectors_per_cluster() as usize;
+        let sectors = clusters.len() * bpb.sectors_per_cluster() as usize;
+        assert_eq!(discards.len(), 1);
+        assert_eq!(discards[0], first_sector..first_sector + sectors);
+    }
+}
+
+// 同样的内容, 报告不同的几何参数
+fn copy_of(
+    device: &ReportingBlockDevice,
+    num_blocks: usize,
+    block_size: usize,
+) -> Arc<ReportingBlockDevice> {
+    let mut copy = ReportingBlockDevice::new(0);
+    copy.inner = RamDisk::from_vec(device.inner.to_vec(), BLOCK_SZ);
+    copy.num_blocks = num_blocks;
+    copy.block_size = block_size;
+    Arc::new(copy)
+}
+
+#[test]
+fn test_mount_checks_device_geometry() {
+    let device = formatted();
+    // 设备比文件系统小
+    let small = copy_of(&device, TOTAL_SECTORS as usize - 1, BLOCK_SZ);
+    assert!(matches!(
+        RunFileSystem::new(small),
+        Err(FSError::CorruptedFileSystem)
+    ));
+    // 块大小和扇区大小不一致
+    let mismatched = copy_of(&device, TOTAL_SECTORS as usize, 4096);
+    assert!(matches!(
+        RunFileSystem::new(mismatched),
+        Err(FSError::InvalidInput)
+    ));
+    // 分区外还有空间时可以挂载
+    let larger = copy_of(&device, TOTAL_SECTORS as usize + 8, BLOCK_SZ);
+    assert!(RunFileSystem::new(larger).is_ok());
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "aes")]
+use runfs::{
+    BlockDevice, EncryptedDevice, FSError, FatType, FileAttributes, FormatOptions, IOError,
+    RamDisk, RunFileSystem,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const TOTAL_SECTORS: u32 = 36 * 1024 * 2;
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_xts_vectors() {
+    // IEEE 1619 XTS-AES-128 向量 4 的第一个数据单元
+    let key = hex("2718281828459045235360287471352631415926535897932384626433832795");
+    let disk = Arc::new(RamDisk::new(8));
+    let device = EncryptedDevice::create(disk.clone(), &key).unwrap();
+    assert_eq!(device.num_blocks(), Some(7));
+    let plain: Vec<u8> = (0..512).map(|i| i as u8).collect();
+    device.write_block(0, &plain).unwrap();
+    let image = disk.to_vec();
+    assert_eq!(
+        image[512..544],
+        hex("27a7479befa1d476489f308cd4cfa6e2a96e4bbe3208ff25287dd3819616e89c")[..]
+    );
+    let mut buf = [0u8; 512];
+    device.read_block(0, &mut buf).unwrap();
+    assert_eq!(buf[..], plain[..]);
+    // AES-256-XTS, 第 5 块全零
+    let key: Vec<u8> = (0..64).collect();
+    let device = EncryptedDevice::create(disk.clone(), &key).unwrap();
+    device.write_block(5, &[0; 512]).unwrap();
+    assert_eq!(
+        disk.to_vec()[7 * 512 - 32..7 * 512],
+        hex("ba0ec7e9549693e4a7ce9a24cc7495b38b1ab58ee4158ba18ac613faa0ae04f4")[..]
+    );
+    // 短读只取前面一部分, 写必须是整块
+    let mut short = [0xFFu8; 16];
+    device.read_block(5, &mut short).unwrap();
+    assert_eq!(short, [0; 16]);
+    assert_eq!(
+        device.write_block(5, &[0; 16]),
+        Err(IOError::NotEnoughBuffer)
+    );
+    assert_eq!(device.write_block(7, &[0; 512]), Err(IOError::OutOfRange));
+}
+
+#[test]
+fn test_open() {
+    let key = [0x5Au8; 64];
+    let disk = Arc::new(RamDisk::new(8));
+    assert!(matches!(
+        EncryptedDevice::open(disk.clone(), &key),
+        Err(FSError::CorruptedFileSystem)
+    ));
+    assert!(matches!(
+        EncryptedDevice::create(disk.clone(), &key[..20]),
+        Err(FSError::InvalidInput)
+    ));
+    EncryptedDevice::create(disk.clone(), &key)
+        .unwrap()
+        .write_block(2, &[0x42; 512])
+        .unwrap();
+    let mut wrong = key;
+    wrong[63] ^= 1;
+    assert!(matches!(
+        EncryptedDevice::open(disk.clone(), &wrong),
+        Err(FSError::InvalidKey)
+    ));
+    assert!(matches!(
+        EncryptedDevice::open(disk.clone(), &key[..32]),
+        Err(FSError::InvalidKey)
+    ));
+    let device = EncryptedDevice::open(disk, &key).unwrap();
+    let mut buf = [0u8; 512];
+    device.read_block(2, &mut buf).unwrap();
+    assert_eq!(buf, [0x42; 512]);
+}
+
+#[test]
+fn test_encrypted_fat32() {
+    let key: Vec<u8> = (100..132).collect();
+    let disk = Arc::new(RamDisk::new(TOTAL_SECTORS as usize + 1));
+    let device: Arc<dyn BlockDevice> =
+        Arc::new(EncryptedDevice::create(disk.clone(), &key).unwrap());
+    RunFileSystem::format(
+        device.clone(),
+        FormatOptions::with_fat_type(TOTAL_SECTORS, FatType::Fat32),
+    )
+    .unwrap();
+    let secret = b"attack at dawn, bring the encrypted sandwiches";
+    let data: Vec<u8> = secret.iter().cycle().take(20000).copied().collect();
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let dir = root_dir
+            .create("secrets", FileAttributes::DIRECTORY)
+            .unwrap();
+        let file = dir.create("plan.txt", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        runfs.read().sync().unwrap();
+    }
+    // 底层设备上既没有内容也没有文件名
+    let image = disk.to_vec();
+    assert!(!image.windows(secret.len()).any(|w| w == secret));
+    assert!(!image.windows(8).any(|w| w == b"PLAN    "));
+    assert!(!image.windows(5).any(|w| w == b"FAT32"));
+    // 用同一个密钥重新打开
+    let device = Arc::new(EncryptedDevice::open(disk, &key).unwrap());
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir
+        .find_vfile_byname("secrets")
+        .unwrap()
+        .find_vfile_byname("plan.txt")
+        .unwrap();
+    let mut buf = vec![0u8; file.size().unwrap()];
+    file.read_at(0, &mut buf).unwrap();
+    assert!(buf == data);
+    assert!(runfs.read().check().unwrap().is_clean());
+}
@@ -0,0 +1,255 @@
+use runfs::{
+    BlockDevice, ExFatFileSystem, ExFatFormatOptions, ExFatVFile, FSError, FileAttributes, IOError,
+    RamDisk, ReadOnlyDevice,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+
+// 32MB, 4KB 簇
+const TOTAL_SECTORS: u64 = 32 * 1024 * 2;
+const CLUSTER_SZ: usize = 4096;
+
+fn formatted() -> Arc<dyn BlockDevice> {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let mut options = ExFatFormatOptions::new(TOTAL_SECTORS);
+    options.volume_serial = 0x1234_5678;
+    ExFatFileSystem::format(device.clone(), options).unwrap();
+    device
+}
+
+fn mount(device: &Arc<dyn BlockDevice>) -> (Arc<RwLock<ExFatFileSystem>>, Arc<ExFatVFile>) {
+    let exfat = Arc::new(RwLock::new(ExFatFileSystem::new(device.clone()).unwrap()));
+    let root_dir = Arc::new(exfat.read().root_vfile(&exfat));
+    (exfat, root_dir)
+}
+
+#[test]
+fn test_format_and_mount() {
+    let device = formatted();
+    let (exfat, root_dir) = mount(&device);
+    let exfat = exfat.read();
+    assert_eq!(exfat.volume_serial(), 0x1234_5678);
+    assert_eq!(exfat.cluster_size(), CLUSTER_SZ);
+    assert_eq!(exfat.volume_label(), "");
+    // 位图, 大写转换表, 根目录各占一个簇
+    assert_eq!(exfat.free_clusters(), exfat.total_clusters() - 3);
+    assert_eq!(root_dir.ls().unwrap().len(), 0);
+    assert_eq!(root_dir.capacity().unwrap(), CLUSTER_SZ);
+}
+
+#[test]
+fn test_boot_region_checksum() {
+    let device = formatted();
+    // 改动主启动区域的 OEM 参数扇区, 校验和不匹配时使用备份启动区域
+    let mut oem = [0u8; BLOCK_SZ];
+    oem[0] = 0x5A;
+    device.write_block(9, &oem).unwrap();
+    assert!(ExFatFileSystem::new(device.clone()).is_ok());
+    // VolumeFlags 不参与校验和
+    let mut sector = [0u8; BLOCK_SZ];
+    device.read_block(12, &mut sector).unwrap();
+    sector[106] = 0x02;
+    device.write_block(12, &sector).unwrap();
+    assert!(ExFatFileSystem::new(device.clone()).is_ok());
+    // 主备都损坏
+    device.write_block(12 + 9, &oem).unwrap();
+    assert!(ExFatFileSystem::new(device).is_err());
+}
+
+#[test]
+fn test_create_write_read() {
+    let device = formatted();
+    let buf: Vec<u8> = (0..3 * CLUSTER_SZ + 100).map(|i| (i % 251) as u8).collect();
+    let long_name = "a file name longer than fifteen characters.txt";
+    let free_before;
+    {
+        let (exfat, root_dir) = mount(&device);
+        free_before = exfat.read().free_clusters();
+        let file = root_dir.create(long_name, FileAttributes::ARCHIVE).unwrap();
+        assert_eq!(file.size().unwrap(), 0);
+        assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+        assert_eq!(file.size().unwrap(), buf.len());
+        let dir = root_dir
+            .create("Subdir", FileAttributes::DIRECTORY)
+            .unwrap();
+        let inner = dir.create("inner.txt", FileAttributes::ARCHIVE).unwrap();
+        assert_eq!(inner.write_at(0, b"wakuwaku").unwrap(), 8);
+        // 名字不区分大小写
+        assert!(root_dir.create("SUBDIR", FileAttributes::ARCHIVE).is_err());
+        assert!(root_dir
+            .create("bad:name", FileAttributes::ARCHIVE)
+            .is_err());
+    }
+    let (exfat, root_dir) = mount(&device);
+    // 文件 4 个簇, 目录 1 个簇, inner.txt 1 个簇
+    assert_eq!(exfat.read().free_clusters(), free_before - 6);
+    let ls = root_dir.ls().unwrap();
+    assert_eq!(ls.len(), 2);
+    assert_eq!(ls[0].0, long_name);
+    assert_eq!(ls[1], (String::from("Subdir"), FileAttributes::DIRECTORY));
+    let file = root_dir
+        .find_vfile_byname(&long_name.to_uppercase())
+        .unwrap();
+    let mut read_buf = vec![0u8; buf.len()];
+    assert_eq!(file.read_at(0, &mut read_buf).unwrap(), buf.len());
+    assert_eq!(read_buf, buf);
+    let inner = root_dir.find_vfile_bypath("/subdir/INNER.TXT").unwrap();
+    let mut read_buf = [0u8; 16];
+    assert_eq!(inner.read_at(0, &mut read_buf).unwrap(), 8);
+    assert_eq!(&read_buf[..8], b"wakuwaku");
+    // 删除后回收簇
+    assert_eq!(file.delete().unwrap(), 4);
+    assert!(root_dir.find_vfile_byname(long_name).is_err());
+    assert_eq!(exfat.read().free_clusters(), free_before - 2);
+}
+
+#[test]
+fn test_sparse_write() {
+    let device = formatted();
+    let (_exfat, root_dir) = mount(&device);
+    let file = root_dir.create("sparse", FileAttributes::ARCHIVE).unwrap();
+    assert_eq!(file.write_at(0, &[0xFF; 100]).unwrap(), 100);
+    // 中间的空洞读出来是 0
+    assert_eq!(file.write_at(2 * CLUSTER_SZ, b"end").unwrap(), 3);
+    assert_eq!(file.size().unwrap(), 2 * CLUSTER_SZ + 3);
+    let mut read_buf = vec![0xAAu8; 2 * CLUSTER_SZ];
+    assert_eq!(
+        file.read_at(100, &mut read_buf).unwrap(),
+        2 * CLUSTER_SZ - 97
+    );
+    assert!(read_buf[..2 * CLUSTER_SZ - 100].iter().all(|b| *b == 0));
+    assert_eq!(&read_buf[2 * CLUSTER_SZ - 100..2 * CLUSTER_SZ - 97], b"end");
+}
+
+#[test]
+fn test_fragmented_file_uses_fat_chain() {
+    let device = formatted();
+    let data_a: Vec<u8> = vec![0xA5; CLUSTER_SZ];
+    let data_b: Vec<u8> = (0..3 * CLUSTER_SZ).map(|i| (i / 7) as u8).collect();
+    {
+        let (_exfat, root_dir) = mount(&device);
+        // a 和 b 交替增长, a 的后面被 b 占用后只能改成 FAT 簇链
+        let a = root_dir.create("a.bin", FileAttributes::ARCHIVE).unwrap();
+        let b = root_dir.create("b.bin", FileAttributes::ARCHIVE).unwrap();
+        assert_eq!(a.write_at(0, &data_a).unwrap(), CLUSTER_SZ);
+        assert_eq!(b.write_at(0, &data_b).unwrap(), data_b.len());
+        assert_eq!(a.write_at(CLUSTER_SZ, &data_b).unwrap(), data_b.len());
+        assert_eq!(a.capacity().unwrap(), 4 * CLUSTER_SZ);
+    }
+    let (exfat, root_dir) = mount(&device);
+    let a = root_dir.find_vfile_byname("a.bin").unwrap();
+    let mut read_buf = vec![0u8; 4 * CLUSTER_SZ];
+    assert_eq!(a.read_at(0, &mut read_buf).unwrap(), 4 * CLUSTER_SZ);
+    assert_eq!(&read_buf[..CLUSTER_SZ], &data_a[..]);
+    assert_eq!(&read_buf[CLUSTER_SZ..], &data_b[..]);
+    let b = root_dir.find_vfile_byname("b.bin").unwrap();
+    let mut read_buf = vec![0u8; data_b.len()];
+    assert_eq!(b.read_at(0, &mut read_buf).unwrap(), data_b.len());
+    assert_eq!(read_buf, data_b);
+    let free = exfat.read().free_clusters();
+    assert_eq!(a.delete().unwrap(), 4);
+    assert_eq!(exfat.read().free_clusters(), free + 4);
+}
+
+#[test]
+fn test_directory_grows() {
+    let device = formatted();
+    {
+        let (_exfat, root_dir) = mount(&device);
+        // 每个文件 3 个目录项, 一个簇放 128 个目录项, 根目录已有 3 个
+        for i in 0..60 {
+            let name = format!("file{:02}", i);
+            assert!(root_dir.create(&name, FileAttributes::ARCHIVE).is_ok());
+        }
+        assert_eq!(root_dir.capacity().unwrap(), 2 * CLUSTER_SZ);
+        let dir = root_dir.create("dir", FileAttributes::DIRECTORY).unwrap();
+        for i in 0..50 {
+            let name = format!("inner{:02}", i);
+            assert!(dir.create(&name, FileAttributes::ARCHIVE).is_ok());
+        }
+        assert_eq!(dir.capacity().unwrap(), 2 * CLUSTER_SZ);
+    }
+    let (_exfat, root_dir) = mount(&device);
+    assert_eq!(root_dir.ls().unwrap().len(), 61);
+    let dir = root_dir.find_vfile_byname("dir").unwrap();
+    assert_eq!(dir.ls().unwrap().len(), 50);
+    assert!(root_dir.find_vfile_bypath("dir/inner49").is_ok());
+}
+
+#[test]
+fn test_corrupted_entry_set() {
+    let device = formatted();
+    let (_exfat, root_dir) = mount(&device);
+    let victim = root_dir.create("victim", FileAttributes::ARCHIVE).unwrap();
+    assert_eq!(victim.write_at(0, b"hello").unwrap(), 5);
+    let other = root_dir.create("other", FileAttributes::ARCHIVE).unwrap();
+    let data = vec![0x3C; 3 * CLUSTER_SZ];
+    assert_eq!(other.write_at(0, &data).unwrap(), data.len());
+    // 读几个簇把根目录挤出缓存, 之后从盘上重新读目录项
+    let mut read_buf = vec![0u8; data.len()];
+    assert_eq!(other.read_at(0, &mut read_buf).unwrap(), data.len());
+    let name: Vec<u8> = "victim".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let block_id = (0..TOTAL_SECTORS as usize)
+        .find(|i| {
+            let mut block = [0u8; BLOCK_SZ];
+            device.read_block(*i, &mut block).unwrap();
+            block.windows(name.len()).any(|w| w == name.as_slice())
+        })
+        .unwrap();
+    let mut block = [0u8; BLOCK_SZ];
+    device.read_block(block_id, &mut block).unwrap();
+    let pos = block
+        .windows(name.len())
+        .position(|w| w == name.as_slice())
+        .unwrap();
+    block[pos] = b'V';
+    device.write_block(block_id, &block).unwrap();
+    // 校验和不对, 不能当成根目录
+    assert!(matches!(victim.size(), Err(FSError::CorruptedFileSystem)));
+    assert!(matches!(
+        victim.read_at(0, &mut read_buf),
+        Err(FSError::CorruptedFileSystem)
+    ));
+    assert!(matches!(
+        victim.write_at(0, b"bye"),
+        Err(FSError::CorruptedFileSystem)
+    ));
+}
+
+#[test]
+fn test_sync_and_unmount() {
+    let disk = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    ExFatFileSystem::format(disk.clone(), ExFatFormatOptions::new(TOTAL_SECTORS)).unwrap();
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    let (exfat, root_dir) = mount(&device);
+    let file = root_dir.create("synced", FileAttributes::ARCHIVE).unwrap();
+    assert_eq!(file.write_at(0, b"on disk").unwrap(), 7);
+    exfat.read().sync().unwrap();
+    // 仍然挂载着, 盘上的副本已经能读到
+    let copy: Arc<dyn BlockDevice> = Arc::new(RamDisk::from_vec(disk.to_vec(), BLOCK_SZ));
+    let (_copy_fs, copy_root) = mount(&copy);
+    let mut read_buf = [0u8; 7];
+    let copied = copy_root.find_vfile_byname("synced").unwrap();
+    assert_eq!(copied.read_at(0, &mut read_buf).unwrap(), 7);
+    assert_eq!(&read_buf, b"on disk");
+    drop(file);
+    drop(root_dir);
+    let exfat = Arc::try_unwrap(exfat).ok().unwrap().into_inner();
+    exfat.unmount().unwrap();
+    // 写回失败时返回错误
+    let read_only: Arc<dyn BlockDevice> = Arc::new(ReadOnlyDevice::new(device));
+    let (exfat, root_dir) = mount(&read_only);
+    root_dir.create("lost", FileAttributes::ARCHIVE).unwrap();
+    assert!(matches!(
+        exfat.read().sync(),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+    drop(root_dir);
+    let exfat = Arc::try_unwrap(exfat).ok().unwrap().into_inner();
+    assert!(matches!(
+        exfat.unmount(),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+}
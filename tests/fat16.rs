@@ -0,0 +1,120 @@
+use runfs::{FatType, FileAttributes, FormatOptions, RamDisk, RunFileSystem};
+use std::sync::Arc;
+
+mod common;
+use common::{formatted, mount};
+
+const BLOCK_SZ: usize = 512;
+
+// 1.44MB 软盘
+const FLOPPY_SECTORS: u32 = 2880;
+// 16MB U 盘
+const STICK_SECTORS: u32 = 16 * 1024 * 2;
+
+#[test]
+fn test_fat_type_detection() {
+    let device = formatted(
+        FLOPPY_SECTORS,
+        FormatOptions::with_fat_type(FLOPPY_SECTORS, FatType::Fat12),
+    );
+    let runfs = RunFileSystem::new(device).unwrap();
+    let bpb = runfs.bpb();
+    assert_eq!(bpb.fat_type(), FatType::Fat12);
+    assert_eq!(bpb.total_sectors(), FLOPPY_SECTORS);
+    assert_eq!(bpb.root_dir_cluster(), 0);
+    assert_eq!(bpb.root_dir_size(), 512 * 32);
+    // 没有 FSInfo, 挂载时扫描 FAT 表
+    assert_eq!(runfs.free_clusters(), Some(bpb.total_clusters()));
+
+    let device = formatted(
+        STICK_SECTORS,
+        FormatOptions::with_fat_type(STICK_SECTORS, FatType::Fat16),
+    );
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert_eq!(runfs.bpb().fat_type(), FatType::Fat16);
+    assert_eq!(runfs.volume_id(), 0);
+
+    // 簇数不够 FAT16
+    let device = Arc::new(RamDisk::new(FLOPPY_SECTORS as usize));
+    let options = FormatOptions::with_fat_type(FLOPPY_SECTORS, FatType::Fat16);
+    assert!(RunFileSystem::format(device, options).is_err());
+}
+
+#[test]
+fn test_fat16_create_and_read() {
+    let mut options = FormatOptions::with_fat_type(STICK_SECTORS, FatType::Fat16);
+    options.volume_id = 0x1616_1616;
+    let device = formatted(STICK_SECTORS, options);
+    let buf: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    {
+        let (runfs, root_dir) = mount(&device);
+        assert_eq!(runfs.read().volume_id(), 0x1616_1616);
+        let file = root_dir.create("hello.txt", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+        let dir = root_dir
+            .create("subdir", FileAttributes::DIRECTORY)
+            .unwrap();
+        let inner = dir.create("inner.txt", FileAttributes::FILE).unwrap();
+        assert_eq!(inner.write_at(0, b"wakuwaku").unwrap(), 8);
+    }
+    let (_runfs, root_dir) = mount(&device);
+    let ls = root_dir.ls().unwrap();
+    assert_eq!(ls.len(), 2);
+    assert_eq!(ls[0].0, "hello.txt");
+    assert_eq!(ls[1].0, "subdir");
+    let file = root_dir.find_vfile_byname("hello.txt").unwrap();
+    let mut read_buf = vec![0u8; buf.len()];
+    assert_eq!(file.read_at(0, &mut read_buf).unwrap(), buf.len());
+    assert_eq!(read_buf, buf);
+    let inner = root_dir.find_vfile_bypath("/subdir/inner.txt").unwrap();
+    let mut read_buf = [0u8; 8];
+    assert_eq!(inner.read_at(0, &mut read_buf).unwrap(), 8);
+    assert_eq!(&read_buf, b"wakuwaku");
+}
+
+#[test]
+fn test_fat12_entries_across_sectors() {
+    let device = formatted(
+        FLOPPY_SECTORS,
+        FormatOptions::with_fat_type(FLOPPY_SECTORS, FatType::Fat12),
+    );
+    // 超过 341 个簇, 第 341 个表项跨越 FAT 表第一个扇区
+    let buf: Vec<u8> = (0..400 * BLOCK_SZ).map(|i| (i / BLOCK_SZ) as u8).collect();
+    let free_before;
+    let clusters;
+    {
+        let (runfs, root_dir) = mount(&device);
+        free_before = runfs.read().free_clusters().unwrap();
+        let file = root_dir.create("big.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+        clusters = (file.capacity().unwrap() / BLOCK_SZ) as u32;
+        assert!(clusters >= 400);
+    }
+    // 重新挂载后扫描 FAT 表得到的空闲簇数
+    let (runfs, root_dir) = mount(&device);
+    assert_eq!(runfs.read().free_clusters(), Some(free_before - clusters));
+    let file = root_dir.find_vfile_byname("big.bin").unwrap();
+    let mut read_buf = vec![0u8; buf.len()];
+    assert_eq!(file.read_at(0, &mut read_buf).unwrap(), buf.len());
+    assert_eq!(read_buf, buf);
+    file.delete().unwrap();
+    assert_eq!(runfs.read().free_clusters(), Some(free_before));
+    assert!(root_dir.find_vfile_byname("big.bin").is_err());
+}
+
+#[test]
+fn test_fixed_root_full() {
+    // 根目录区只有一个扇区, 16 个目录项
+    let mut options = FormatOptions::with_fat_type(FLOPPY_SECTORS, FatType::Fat12);
+    options.root_entries = 16;
+    let device = formatted(FLOPPY_SECTORS, options);
+    let (_runfs, root_dir) = mount(&device);
+    // 每个文件一个长目录项加一个短目录项
+    for i in 0..8 {
+        let name = format!("file{}.txt", i);
+        assert!(root_dir.create(&name, FileAttributes::FILE).is_ok());
+    }
+    assert_eq!(root_dir.capacity().unwrap(), 16 * 32);
+    assert!(root_dir.create("file8.txt", FileAttributes::FILE).is_err());
+    assert_eq!(root_dir.ls().unwrap().len(), 8);
+}
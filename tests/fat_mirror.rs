@@ -0,0 +1,144 @@
+use runfs::{
+    BiosParameterBlock, BlockDevice, FSError, FatType, FileAttributes, FormatOptions,
+    RunFileSystem, VFile,
+};
+use std::sync::Arc;
+
+mod common;
+use common::{formatted, mount};
+
+const BLOCK_SZ: usize = 512;
+
+const FAT32_SECTORS: u32 = 64 * 1024 * 2;
+const FAT16_SECTORS: u32 = 16 * 1024 * 2;
+// FAT32 BPB 中 extended_flags 的偏移
+const EXTENDED_FLAGS_OFFSET: usize = 0x28;
+
+// 直接从块设备读出第 fat 个 FAT 表
+fn read_fat(device: &Arc<dyn BlockDevice>, bpb: &BiosParameterBlock, fat: u8) -> Vec<u8> {
+    let sector_size = bpb.bytes_per_sector() as usize;
+    let mut data = vec![0u8; bpb.fats_sectors() as usize * sector_size];
+    for (i, sector) in data.chunks_mut(sector_size).enumerate() {
+        device
+            .read_block(bpb.fat_start_sector(fat) as usize + i, sector)
+            .unwrap();
+    }
+    data
+}
+
+fn create_file(root_dir: &VFile, name: &str, len: usize) {
+    let file = root_dir.create(name, FileAttributes::FILE).unwrap();
+    let buf: Vec<u8> = (0..len).map(|i| (i % 253) as u8).collect();
+    assert_eq!(file.write_at(0, &buf).unwrap(), len);
+}
+
+fn check_file(root_dir: &VFile, name: &str, len: usize) {
+    let file = root_dir.find_vfile_byname(name).unwrap();
+    let mut buf = vec![0u8; len];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), len);
+    assert!(buf.iter().enumerate().all(|(i, b)| *b == (i % 253) as u8));
+}
+
+#[test]
+fn test_mirrored_by_default() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let (runfs, root_dir) = mount(&device);
+    let bpb = runfs.read().bpb();
+    assert!(bpb.is_fat_mirrored());
+    assert_eq!(bpb.active_fat(), 0);
+    create_file(&root_dir, "hello.txt", 5000);
+    drop(root_dir);
+    drop(runfs);
+    assert_eq!(read_fat(&device, &bpb, 0), read_fat(&device, &bpb, 1));
+}
+
+#[test]
+fn test_active_fat_without_mirroring() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let (runfs, root_dir) = mount(&device);
+    create_file(&root_dir, "before.txt", 1000);
+    drop(root_dir);
+    runfs.write().set_active_fat(Some(1)).unwrap();
+    let bpb = runfs.read().bpb();
+    assert!(!bpb.is_fat_mirrored());
+    assert_eq!(bpb.active_fat(), 1);
+    assert_eq!(bpb.extended_flags(), 0x0081);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    create_file(&root_dir, "after.txt", 3000);
+    drop(root_dir);
+    drop(runfs);
+    // 只有第二个 FAT 表记录了新文件
+    assert_ne!(read_fat(&device, &bpb, 0), read_fat(&device, &bpb, 1));
+    let mut sector = [0u8; BLOCK_SZ];
+    for sector_id in [0, 6] {
+        device.read_block(sector_id, &mut sector).unwrap();
+        assert_eq!(
+            &sector[EXTENDED_FLAGS_OFFSET..EXTENDED_FLAGS_OFFSET + 2],
+            &[0x81, 0x00]
+        );
+    }
+    let (runfs, root_dir) = mount(&device);
+    assert_eq!(runfs.read().bpb().active_fat(), 1);
+    check_file(&root_dir, "before.txt", 1000);
+    check_file(&root_dir, "after.txt", 3000);
+    drop(root_dir);
+    // 重新打开镜像时以活动 FAT 表为准
+    runfs.write().set_active_fat(None).unwrap();
+    assert!(runfs.read().bpb().is_fat_mirrored());
+    drop(runfs);
+    assert_eq!(read_fat(&device, &bpb, 0), read_fat(&device, &bpb, 1));
+    let (runfs, root_dir) = mount(&device);
+    assert_eq!(runfs.read().bpb().active_fat(), 0);
+    check_file(&root_dir, "after.txt", 3000);
+}
+
+#[test]
+fn test_mirror_after_repair() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let bpb = RunFileSystem::new(device.clone()).unwrap().bpb();
+    // 破坏第二个 FAT 表
+    let garbage = [0xA5u8; BLOCK_SZ];
+    device
+        .write_block(bpb.fat_start_sector(1) as usize, &garbage)
+        .unwrap();
+    assert_ne!(read_fat(&device, &bpb, 0), read_fat(&device, &bpb, 1));
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    runfs.mirror_fats().unwrap();
+    drop(runfs);
+    assert_eq!(read_fat(&device, &bpb, 0), read_fat(&device, &bpb, 1));
+}
+
+#[test]
+fn test_single_fat() {
+    let mut options = FormatOptions::new(FAT32_SECTORS);
+    options.fats_number = 1;
+    let device = formatted(FAT32_SECTORS, options);
+    let (runfs, root_dir) = mount(&device);
+    assert_eq!(runfs.read().bpb().fats_number(), 1);
+    create_file(&root_dir, "hello.txt", 5000);
+    create_file(&root_dir, "world.txt", 700);
+    drop(root_dir);
+    assert!(matches!(
+        runfs.write().set_active_fat(Some(1)),
+        Err(FSError::InvalidInput)
+    ));
+    runfs.write().set_active_fat(Some(0)).unwrap();
+    drop(runfs);
+    let (_runfs, root_dir) = mount(&device);
+    check_file(&root_dir, "hello.txt", 5000);
+    check_file(&root_dir, "world.txt", 700);
+}
+
+#[test]
+fn test_fat16_always_mirrored() {
+    let device = formatted(
+        FAT16_SECTORS,
+        FormatOptions::with_fat_type(FAT16_SECTORS, FatType::Fat16),
+    );
+    let mut runfs = RunFileSystem::new(device).unwrap();
+    assert!(runfs.bpb().is_fat_mirrored());
+    assert!(matches!(
+        runfs.set_active_fat(Some(1)),
+        Err(FSError::InvalidInput)
+    ));
+}
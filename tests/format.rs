@@ -0,0 +1,62 @@
+use runfs::{FileAttributes, FormatOptions, RamDisk, RunFileSystem, VFile};
+use spin::RwLock;
+use std::sync::Arc;
+
+// 64MB, 默认每簇 1 个扇区
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+#[test]
+fn test_format_geometry() {
+    let device = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let mut options = FormatOptions::new(TOTAL_SECTORS);
+    options.volume_id = 0x2022_0610;
+    RunFileSystem::format(device.clone(), options).unwrap();
+    let runfs = RunFileSystem::new(device).unwrap();
+    let bpb = runfs.bpb();
+    assert_eq!(bpb.bytes_per_sector(), 512);
+    assert_eq!(bpb.sectors_per_cluster(), 1);
+    assert_eq!(bpb.total_sectors_32(), TOTAL_SECTORS);
+    assert_eq!(bpb.root_dir_cluster(), 2);
+    assert_eq!(bpb.backup_boot_sector(), 6);
+    assert_eq!(runfs.volume_id(), 0x2022_0610);
+    // FAT 表要能装下所有簇
+    let fat_entries = bpb.fats_sectors() * 512 / 4;
+    assert!(fat_entries >= bpb.total_clusters() + 2);
+    assert_eq!(runfs.free_clusters(), Some(bpb.total_clusters() - 1));
+    assert_eq!(runfs.next_free_cluster(), Some(3));
+    assert_eq!(
+        runfs.fat_manager_modify().count_free_clusters().unwrap(),
+        bpb.total_clusters() - 1
+    );
+}
+
+#[test]
+fn test_format_too_small() {
+    let device = Arc::new(RamDisk::new(4096));
+    let res = RunFileSystem::format(device, FormatOptions::new(4096));
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_format_then_create() {
+    let device = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
+    assert_eq!(root_dir.ls().unwrap().len(), 0);
+    let file = root_dir
+        .create("helloworld.txt", FileAttributes::FILE)
+        .unwrap();
+    let buf = [0x5Au8; 1500];
+    assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+    let mut read_buf = [0u8; 1500];
+    assert_eq!(file.read_at(0, &mut read_buf).unwrap(), buf.len());
+    assert_eq!(read_buf, buf);
+    root_dir
+        .create("wakuwaku", FileAttributes::DIRECTORY)
+        .unwrap();
+    let ls = root_dir.ls().unwrap();
+    assert_eq!(ls.len(), 2);
+    assert_eq!(ls[0].0, "helloworld.txt");
+    assert_eq!(ls[1].0, "wakuwaku");
+}
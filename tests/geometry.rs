@@ -0,0 +1,82 @@
+// small-cache 只能挂载 512 字节扇区和 32KB 以内的簇
+#![cfg(not(feature = "small-cache"))]
+use runfs::{BlockDevice, FatType, FileAttributes, FormatOptions, RamDisk, RunFileSystem};
+use std::sync::Arc;
+
+mod common;
+use common::mount;
+
+fn formatted(options: FormatOptions) -> Arc<dyn BlockDevice> {
+    // 块大小和卷的扇区大小一致
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::with_block_size(
+        options.total_sectors as usize,
+        usize::from(options.bytes_per_sector),
+    ));
+    RunFileSystem::format(device.clone(), options).unwrap();
+    device
+}
+
+// 写入跨越多个簇的文件和子目录, 重新挂载后读回
+fn write_and_check(device: &Arc<dyn BlockDevice>, cluster_size: usize) {
+    let buf: Vec<u8> = (0..cluster_size * 3 + 100)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    {
+        let (_runfs, root_dir) = mount(device);
+        let file = root_dir.create("big.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+        let dir = root_dir
+            .create("subdir", FileAttributes::DIRECTORY)
+            .unwrap();
+        let inner = dir.create("inner.txt", FileAttributes::FILE).unwrap();
+        assert_eq!(inner.write_at(0, b"geometry").unwrap(), 8);
+    }
+    let (runfs, root_dir) = mount(device);
+    assert_eq!(runfs.read().bpb().cluster_size(), cluster_size);
+    let file = root_dir.find_vfile_byname("big.bin").unwrap();
+    let mut read_buf = vec![0u8; buf.len()];
+    assert_eq!(file.read_at(0, &mut read_buf).unwrap(), buf.len());
+    assert_eq!(read_buf, buf);
+    let inner = root_dir.find_vfile_bypath("subdir/inner.txt").unwrap();
+    let mut read_buf = [0u8; 8];
+    assert_eq!(inner.read_at(0, &mut read_buf).unwrap(), 8);
+    assert_eq!(&read_buf, b"geometry");
+}
+
+#[test]
+fn test_fat32_4k_sectors() {
+    // 每簇一个扇区时 FAT32 至少要 65525 个簇
+    let mut options = FormatOptions::new(70000);
+    options.bytes_per_sector = 4096;
+    let device = formatted(options);
+    let runfs = RunFileSystem::new(device.clone()).unwrap();
+    assert_eq!(runfs.bpb().fat_type(), FatType::Fat32);
+    assert_eq!(runfs.bpb().bytes_per_sector(), 4096);
+    drop(runfs);
+    write_and_check(&device, 4096);
+}
+
+#[test]
+fn test_fat16_4k_sectors() {
+    let mut options = FormatOptions::with_fat_type(8192, FatType::Fat16);
+    options.bytes_per_sector = 4096;
+    let device = formatted(options);
+    write_and_check(&device, 4096);
+}
+
+#[test]
+fn test_64k_clusters() {
+    let mut options = FormatOptions::with_fat_type(64 * 1024, FatType::Fat12);
+    options.sectors_per_cluster = Some(128);
+    let device = formatted(options);
+    write_and_check(&device, 64 * 1024);
+}
+
+#[test]
+fn test_4k_sectors_64k_clusters() {
+    let mut options = FormatOptions::with_fat_type(16 * 1024, FatType::Fat12);
+    options.bytes_per_sector = 4096;
+    options.sectors_per_cluster = Some(16);
+    let device = formatted(options);
+    write_and_check(&device, 64 * 1024);
+}
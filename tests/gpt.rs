@@ -0,0 +1,209 @@
+use runfs::{
+    read_gpt_partitions, BlockDevice, FSError, FileAttributes, FormatOptions, Guid, RamDisk,
+    RunFileSystem, VFile,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+const ENTRIES: usize = 128;
+const ENTRY_SZ: usize = 128;
+const ENTRY_BLOCKS: usize = ENTRIES * ENTRY_SZ / BLOCK_SZ;
+const DATA_START: u64 = 2048;
+const DATA_SECTORS: u64 = 4096;
+const ESP_START: u64 = DATA_START + DATA_SECTORS;
+const ESP_SECTORS: u64 = 70000;
+const DISK_SECTORS: u64 = ESP_START + ESP_SECTORS + 1 + ENTRY_BLOCKS as u64;
+
+fn put_partition(
+    entries: &mut [u8],
+    index: usize,
+    type_guid: Guid,
+    start: u64,
+    sectors: u64,
+    name: &str,
+) {
+    let entry = &mut entries[index * ENTRY_SZ..(index + 1) * ENTRY_SZ];
+    entry[0..16].copy_from_slice(&type_guid.0);
+    entry[16] = index as u8 + 1;
+    entry[32..40].copy_from_slice(&start.to_le_bytes());
+    entry[40..48].copy_from_slice(&(start + sectors - 1).to_le_bytes());
+    for (i, c) in name.encode_utf16().enumerate() {
+        entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+}
+
+fn header(my_lba: u64, alternate_lba: u64, entry_lba: u64, entries_crc: u32) -> [u8; BLOCK_SZ] {
+    let mut h = [0u8; BLOCK_SZ];
+    h[0..8].copy_from_slice(b"EFI PART");
+    h[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    h[12..16].copy_from_slice(&92u32.to_le_bytes());
+    h[24..32].copy_from_slice(&my_lba.to_le_bytes());
+    h[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    h[40..48].copy_from_slice(&(2 + ENTRY_BLOCKS as u64).to_le_bytes());
+    h[48..56].copy_from_slice(&(DISK_SECTORS - 2 - ENTRY_BLOCKS as u64).to_le_bytes());
+    h[72..80].copy_from_slice(&entry_lba.to_le_bytes());
+    h[80..84].copy_from_slice(&(ENTRIES as u32).to_le_bytes());
+    h[84..88].copy_from_slice(&(ENTRY_SZ as u32).to_le_bytes());
+    h[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&h[..92]);
+    h[16..20].copy_from_slice(&crc.to_le_bytes());
+    h
+}
+
+/// 一个普通数据分区和一个 FAT32 格式的 EFI 系统分区
+fn gpt_disk() -> Arc<dyn BlockDevice> {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(DISK_SECTORS as usize));
+    let last_lba = DISK_SECTORS - 1;
+    // 保护性 MBR
+    let mut mbr = [0u8; BLOCK_SZ];
+    mbr[446 + 4] = 0xEE;
+    mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&(last_lba as u32).to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    device.write_block(0, &mbr).unwrap();
+    // 分区表项
+    let mut entries = vec![0u8; ENTRIES * ENTRY_SZ];
+    put_partition(
+        &mut entries,
+        0,
+        Guid::BASIC_DATA,
+        DATA_START,
+        DATA_SECTORS,
+        "data",
+    );
+    put_partition(
+        &mut entries,
+        1,
+        Guid::EFI_SYSTEM,
+        ESP_START,
+        ESP_SECTORS,
+        "EFI system partition",
+    );
+    write_tables(&device, &entries);
+    let mut options = FormatOptions::new(ESP_SECTORS as u32);
+    options.hidden_sectors = ESP_START as u32;
+    RunFileSystem::format_at(device.clone(), ESP_START as usize, options).unwrap();
+    device
+}
+
+// 写主备两份分区表项和 GPT 头
+fn write_tables(device: &Arc<dyn BlockDevice>, entries: &[u8]) {
+    let last_lba = DISK_SECTORS - 1;
+    let entries_crc = crc32(entries);
+    let backup_entry_lba = last_lba - ENTRY_BLOCKS as u64;
+    for (i, block) in entries.chunks(BLOCK_SZ).enumerate() {
+        device.write_block(2 + i, block).unwrap();
+        device
+            .write_block(backup_entry_lba as usize + i, block)
+            .unwrap();
+    }
+    device
+        .write_block(1, &header(1, last_lba, 2, entries_crc))
+        .unwrap();
+    device
+        .write_block(
+            last_lba as usize,
+            &header(last_lba, 1, backup_entry_lba, entries_crc),
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_read_gpt() {
+    let device = gpt_disk();
+    let partitions = read_gpt_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!(partitions[0].name, "data");
+    assert_eq!(partitions[0].type_guid, Guid::BASIC_DATA);
+    assert_eq!(partitions[0].sectors(), DATA_SECTORS);
+    assert!(partitions[1].is_efi_system());
+    assert_eq!(partitions[1].name, "EFI system partition");
+    assert_eq!(partitions[1].first_lba, ESP_START);
+}
+
+#[test]
+fn test_open_efi_system_partition() {
+    let device = gpt_disk();
+    {
+        let runfs = Arc::new(RwLock::new(
+            RunFileSystem::open_efi_system_partition(device.clone()).unwrap(),
+        ));
+        let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
+        root_dir.create("EFI", FileAttributes::DIRECTORY).unwrap();
+    }
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::open_gpt_partition(device, 1).unwrap(),
+    ));
+    assert_eq!(runfs.read().start_sector(), ESP_START as usize);
+    let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
+    assert!(root_dir.find_vfile_byname("EFI").unwrap().is_dir());
+}
+
+#[test]
+fn test_backup_gpt_header() {
+    let device = gpt_disk();
+    // 破坏主 GPT 头
+    device.write_block(1, &[0u8; BLOCK_SZ]).unwrap();
+    let partitions = read_gpt_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), 2);
+    assert!(RunFileSystem::open_efi_system_partition(device.clone()).is_ok());
+    // 主备都损坏
+    device
+        .write_block(DISK_SECTORS as usize - 1, &[0u8; BLOCK_SZ])
+        .unwrap();
+    assert!(read_gpt_partitions(&device).is_err());
+}
+
+#[test]
+fn test_corrupted_entries() {
+    let device = gpt_disk();
+    // 主分区表项被改动, CRC 不匹配时用备份
+    let mut block = [0u8; BLOCK_SZ];
+    device.read_block(2, &mut block).unwrap();
+    block[56] = b'D';
+    device.write_block(2, &block).unwrap();
+    let partitions = read_gpt_partitions(&device).unwrap();
+    assert_eq!(partitions[0].name, "data");
+}
+
+#[test]
+fn test_invalid_partition_range() {
+    let device = gpt_disk();
+    let mut entries = vec![0u8; ENTRIES * ENTRY_SZ];
+    put_partition(
+        &mut entries,
+        0,
+        Guid::BASIC_DATA,
+        DATA_START,
+        DATA_SECTORS,
+        "data",
+    );
+    // 结束扇区在起始扇区之前, 和结束扇区是 u64::MAX
+    for last_lba in [DATA_START - 1, u64::MAX] {
+        entries[40..48].copy_from_slice(&last_lba.to_le_bytes());
+        write_tables(&device, &entries);
+        assert!(matches!(
+            read_gpt_partitions(&device),
+            Err(FSError::CorruptedFileSystem)
+        ));
+        assert!(RunFileSystem::open_gpt_partition(device.clone(), 0).is_err());
+    }
+}
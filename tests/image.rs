@@ -0,0 +1,136 @@
+use runfs::{build_image, FormatOptions, ImageOptions, RamDisk, RunFileSystem, VFile};
+use spin::RwLock;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+// 测试用的主机目录, 按 files 中的顺序创建, 用完删除
+struct HostDir(PathBuf);
+
+impl HostDir {
+    fn new(tag: &str, files: &[(&str, &[u8])]) -> Self {
+        let root = std::env::temp_dir().join(format!("runfs-image-{}-{}", std::process::id(), tag));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for (path, data) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        Self(root)
+    }
+}
+
+impl Drop for HostDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn options() -> ImageOptions {
+    let mut format = FormatOptions::new(TOTAL_SECTORS);
+    format.volume_id = 0x2022_0610;
+    let mut options = ImageOptions::new(format);
+    options.timestamp = (2022, 6, 10, 12, 34, 56);
+    options
+}
+
+fn build(dir: &HostDir) -> Arc<RamDisk> {
+    let device = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    build_image(device.clone(), &dir.0, &options()).unwrap();
+    device
+}
+
+fn read_all(file: &VFile) -> Vec<u8> {
+    let mut buf = vec![0u8; file.size().unwrap()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+    buf
+}
+
+fn big_file() -> Vec<u8> {
+    (0..100_000).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn test_build_is_reproducible() {
+    let big = big_file();
+    let files: [(&str, &[u8]); 5] = [
+        ("kernel.bin", &big),
+        ("etc/passwd", b"root:x:0:0"),
+        ("etc/hosts", b"127.0.0.1 localhost"),
+        ("longname_a.txt", b"first"),
+        ("longname_b.txt", b"second"),
+    ];
+    let mut reversed = files;
+    reversed.reverse();
+    let a = HostDir::new("a", &files);
+    let b = HostDir::new("b", &reversed);
+    // 主机上的创建顺序不同也得到相同的镜像
+    assert!(build(&a).to_vec() == build(&b).to_vec());
+    assert!(build(&a).to_vec() == build(&a).to_vec());
+}
+
+#[test]
+fn test_image_contents() {
+    let big = big_file();
+    let dir = HostDir::new(
+        "contents",
+        &[
+            ("kernel.bin", &big),
+            ("etc/passwd", b"root:x:0:0"),
+            ("longname_a.txt", b"first"),
+            ("longname_b.txt", b"second"),
+            ("empty", b""),
+        ],
+    );
+    let device = build(&dir);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert_eq!(runfs.read().volume_id(), 0x2022_0610);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    // 目录项按名称排序
+    let names: Vec<String> = root_dir
+        .ls()
+        .unwrap()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "empty",
+            "etc",
+            "kernel.bin",
+            "longname_a.txt",
+            "longname_b.txt"
+        ]
+    );
+    let kernel = root_dir.find_vfile_byname("kernel.bin").unwrap();
+    assert_eq!(read_all(&kernel), big);
+    let passwd = root_dir.find_vfile_bypath("etc/passwd").unwrap();
+    assert_eq!(read_all(&passwd), b"root:x:0:0");
+    assert_eq!(
+        root_dir.find_vfile_byname("empty").unwrap().size().unwrap(),
+        0
+    );
+    // 短文件名冲突时按顺序加数字尾巴
+    let first = root_dir.find_vfile_byname("LONGNAME.TXT").unwrap();
+    assert_eq!(read_all(&first), b"first");
+    let second = root_dir.find_vfile_byname("LONGNA~1.TXT").unwrap();
+    assert_eq!(read_all(&second), b"second");
+    // 2022-06-10 12:34:56
+    let expected = ((((2022 - 1980) * 365 + 6 * 30 + 10) * 24 + 12) * 3600 + 34 * 60 + 56) as i64;
+    let (_, _, mtime, ctime, _) = kernel.stat().unwrap();
+    assert_eq!(mtime, expected);
+    assert_eq!(ctime, expected);
+}
+
+#[test]
+fn test_invalid_timestamp() {
+    let dir = HostDir::new("timestamp", &[("a.txt", b"a")]);
+    let device = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let mut options = options();
+    options.timestamp = (1979, 1, 1, 0, 0, 0);
+    assert!(build_image(device, &dir.0, &options).is_err());
+}
@@ -0,0 +1,160 @@
+use runfs::{BlockDevice, FSError, FileAttributes, FormatOptions, IOError, RamDisk, RunFileSystem};
+use spin::{Mutex, RwLock};
+use std::sync::Arc;
+
+// 可以让读或写返回指定错误的块设备
+struct FaultyBlockDevice {
+    inner: RamDisk,
+    read_error: Mutex<Option<IOError>>,
+    write_error: Mutex<Option<IOError>>,
+}
+
+impl FaultyBlockDevice {
+    fn new(blocks: usize) -> Self {
+        Self {
+            inner: RamDisk::new(blocks),
+            read_error: Mutex::new(None),
+            write_error: Mutex::new(None),
+        }
+    }
+}
+
+impl BlockDevice for FaultyBlockDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
+        match *self.read_error.lock() {
+            Some(e) => Err(e),
+            None => self.inner.read_block(block_id, buf),
+        }
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
+        match *self.write_error.lock() {
+            Some(e) => Err(e),
+            None => self.inner.write_block(block_id, buf),
+        }
+    }
+}
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+fn formatted() -> Arc<FaultyBlockDevice> {
+    let device = Arc::new(FaultyBlockDevice::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    device
+}
+
+#[test]
+fn test_mount_media_error() {
+    let device = formatted();
+    *device.read_error.lock() = Some(IOError::MediaError);
+    assert!(matches!(
+        RunFileSystem::new(device),
+        Err(FSError::Io(IOError::MediaError))
+    ));
+}
+
+#[test]
+fn test_format_write_protected() {
+    let device = Arc::new(FaultyBlockDevice::new(TOTAL_SECTORS as usize));
+    *device.write_error.lock() = Some(IOError::WriteProtected);
+    assert!(matches!(
+        RunFileSystem::format(device, FormatOptions::new(TOTAL_SECTORS)),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+}
+
+#[test]
+fn test_read_media_error() {
+    let device = formatted();
+    let data = vec![0x5Au8; 8 * 1024];
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let file = root_dir.create("data.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+    }
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname("data.bin").unwrap();
+    // 文件内容还没有读进缓存
+    *device.read_error.lock() = Some(IOError::MediaError);
+    let mut buf = vec![0u8; data.len()];
+    assert!(matches!(
+        file.read_at(0, &mut buf),
+        Err(FSError::Io(IOError::MediaError))
+    ));
+    *device.read_error.lock() = None;
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn test_sync_write_protected() {
+    let device = formatted();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    *device.write_error.lock() = Some(IOError::WriteProtected);
+    // 修改只在缓存中, 写回时才报错
+    let file = root_dir.create("a.txt", FileAttributes::FILE).unwrap();
+    assert_eq!(file.write_at(0, b"protected").unwrap(), 9);
+    assert!(matches!(
+        runfs.read().sync(),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+    // 写回失败的缓存仍然是脏的, 恢复后可以再次写回
+    *device.write_error.lock() = None;
+    runfs.read().sync().unwrap();
+    drop(file);
+    drop(root_dir);
+    let runfs = RunFileSystem::new(device).unwrap();
+    let runfs = Arc::new(RwLock::new(runfs));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname("a.txt").unwrap();
+    let mut buf = [0u8; 9];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 9);
+    assert_eq!(&buf, b"protected");
+}
+
+#[test]
+fn test_unmount_write_protected() {
+    let device = formatted();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    {
+        let root_dir = runfs.read().root_vfile(&runfs);
+        root_dir.create("b.txt", FileAttributes::FILE).unwrap();
+    }
+    *device.write_error.lock() = Some(IOError::WriteProtected);
+    let runfs = Arc::try_unwrap(runfs).ok().unwrap().into_inner();
+    assert!(matches!(
+        runfs.unmount(),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+}
+
+#[test]
+fn test_resize_write_protected() {
+    let device = formatted();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("c.txt", FileAttributes::FILE).unwrap();
+    assert_eq!(file.write_at(0, b"written").unwrap(), 7);
+    runfs.read().sync().unwrap();
+    // 只有簇缓存是脏的, 扫描 FAT 表不会碰到写错误
+    assert_eq!(file.write_at(0, b"resized").unwrap(), 7);
+    // 缩小要搬数据区, 搬之前写回缓存失败就停下, 缓存仍然是脏的
+    *device.write_error.lock() = Some(IOError::WriteProtected);
+    assert!(matches!(
+        runfs.write().resize(120_000),
+        Err(FSError::Io(IOError::WriteProtected))
+    ));
+    assert_eq!(runfs.read().bpb().total_sectors(), TOTAL_SECTORS);
+    *device.write_error.lock() = None;
+    runfs.read().sync().unwrap();
+    drop(file);
+    drop(root_dir);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname("c.txt").unwrap();
+    let mut buf = [0u8; 7];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 7);
+    assert_eq!(&buf, b"resized");
+}
@@ -0,0 +1,211 @@
+use runfs::{
+    FSError, FileAttributes, FormatOptions, Manifest, ManifestKind, ManifestSource, RamDisk,
+    RunFileSystem, VFile,
+};
+use spin::RwLock;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+fn mount() -> Arc<RwLock<RunFileSystem>> {
+    let device = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()))
+}
+
+fn read_all(file: &VFile) -> Vec<u8> {
+    let mut buf = vec![0u8; file.size().unwrap()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+    buf
+}
+
+fn clusters(runfs: &Arc<RwLock<RunFileSystem>>, file: &VFile) -> Vec<usize> {
+    let first_cluster = file.first_data_cluster().unwrap() as usize;
+    runfs
+        .read()
+        .fat_manager_modify()
+        .all_clusters(first_cluster)
+        .unwrap()
+}
+
+#[test]
+fn test_parse() {
+    let manifest = Manifest::parse(
+        "# boot files\n\
+         \n\
+         dir /boot attr=SYSTEM,HIDDEN\n\
+         file /boot/kernel.bin src=kernel.bin attr=READ_ONLY contiguous\n\
+         file \"/etc/motd file\" text=\"hello \\\"rcore\\\"\\n\" time=2022-06-10T12:34:56\n\
+         file /magic hex=7f454C46\n\
+         file /empty\n",
+    )
+    .unwrap();
+    assert_eq!(manifest.entries.len(), 5);
+    let boot = &manifest.entries[0];
+    assert_eq!(boot.path, "/boot");
+    assert_eq!(boot.kind, ManifestKind::Dir);
+    assert_eq!(
+        boot.attributes,
+        FileAttributes::SYSTEM | FileAttributes::HIDDEN
+    );
+    let kernel = &manifest.entries[1];
+    assert_eq!(
+        kernel.kind,
+        ManifestKind::File(ManifestSource::Host(PathBuf::from("kernel.bin")))
+    );
+    assert!(kernel.contiguous);
+    assert_eq!(kernel.timestamp, (1980, 1, 1, 0, 0, 0));
+    let motd = &manifest.entries[2];
+    assert_eq!(motd.path, "/etc/motd file");
+    assert_eq!(
+        motd.kind,
+        ManifestKind::File(ManifestSource::Inline(b"hello \"rcore\"\n".to_vec()))
+    );
+    assert_eq!(motd.timestamp, (2022, 6, 10, 12, 34, 56));
+    assert_eq!(
+        manifest.entries[3].kind,
+        ManifestKind::File(ManifestSource::Inline(vec![0x7f, b'E', b'L', b'F']))
+    );
+    assert_eq!(
+        manifest.entries[4].kind,
+        ManifestKind::File(ManifestSource::Inline(Vec::new()))
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    for text in [
+        "link /a",
+        "file relative",
+        "file /",
+        "file /a attr=VOLUME_ID",
+        "file /a time=1979-01-01T00:00:00",
+        "file /a time=2022-06-10",
+        "file /a hex=abc",
+        "file /a hex=zz",
+        "file /a text=\"unterminated",
+        "file /a text=\"bad \\q escape\"",
+        "file /a text=a hex=00",
+        "file /a unknown=1",
+        "dir /a contiguous",
+        "dir /a text=a",
+    ] {
+        assert!(
+            matches!(Manifest::parse(text), Err(FSError::InvalidInput)),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn test_apply() {
+    let base_dir = std::env::temp_dir().join(format!("runfs-manifest-{}", std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    let kernel: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    fs::write(base_dir.join("kernel.bin"), &kernel).unwrap();
+    let manifest = Manifest::parse(
+        "dir /boot attr=SYSTEM time=2022-06-10T12:34:56\n\
+         file /boot/kernel.bin src=kernel.bin attr=READ_ONLY contiguous\n\
+         file /etc/conf/hostname text=\"rcore\\n\" attr=HIDDEN time=2022-06-10T12:34:56\n",
+    )
+    .unwrap();
+    let runfs = mount();
+    let result = manifest.apply(&runfs, Path::new(&base_dir));
+    let _ = fs::remove_dir_all(&base_dir);
+    result.unwrap();
+
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let boot = root_dir.find_vfile_byname("boot").unwrap();
+    assert!(boot.is_dir());
+    assert!(boot.attribute().contains(FileAttributes::SYSTEM));
+    let kernel_file = root_dir.find_vfile_bypath("boot/kernel.bin").unwrap();
+    assert!(kernel_file.attribute().contains(FileAttributes::READ_ONLY));
+    assert_eq!(read_all(&kernel_file), kernel);
+    let chain = clusters(&runfs, &kernel_file);
+    assert!(chain.windows(2).all(|w| w[1] == w[0] + 1));
+    // 没有声明的父目录自动创建
+    assert!(root_dir.find_vfile_bypath("etc/conf").unwrap().is_dir());
+    let hostname = root_dir.find_vfile_bypath("etc/conf/hostname").unwrap();
+    assert!(hostname.attribute().contains(FileAttributes::HIDDEN));
+    assert_eq!(read_all(&hostname), b"rcore\n");
+    // 2022-06-10 12:34:56
+    let expected = ((((2022 - 1980) * 365 + 6 * 30 + 10) * 24 + 12) * 3600 + 34 * 60 + 56) as i64;
+    let (_, _, mtime, ctime, _) = hostname.stat().unwrap();
+    assert_eq!(mtime, expected);
+    assert_eq!(ctime, expected);
+    let (_, _, mtime, _, _) = boot.stat().unwrap();
+    assert_eq!(mtime, expected);
+
+    // 已经存在的项不会被覆盖
+    let again = Manifest::parse("file /etc/conf/hostname text=x").unwrap();
+    assert!(matches!(
+        again.apply(&runfs, Path::new(".")),
+        Err(FSError::AlreadyExists)
+    ));
+    let missing = Manifest::parse("file /missing src=does-not-exist").unwrap();
+    assert!(matches!(
+        missing.apply(&runfs, Path::new(&base_dir)),
+        Err(FSError::NotFound)
+    ));
+}
+
+#[test]
+fn test_contiguous_on_fragmented_volume() {
+    let runfs = mount();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let cluster_size = runfs.read().bpb().cluster_size();
+    // a, b, c 依次占用簇, 删除 b 后留下一个空洞
+    let a = root_dir.create("a", FileAttributes::FILE).unwrap();
+    a.write_at(0, &vec![1u8; cluster_size]).unwrap();
+    let b = root_dir.create("b", FileAttributes::FILE).unwrap();
+    b.write_at(0, &vec![2u8; cluster_size * 2]).unwrap();
+    let c = root_dir.create("c", FileAttributes::FILE).unwrap();
+    c.write_at(0, &vec![3u8; cluster_size]).unwrap();
+    let hole = clusters(&runfs, &b);
+    assert!(hole.windows(2).all(|w| w[1] == w[0] + 1));
+    b.delete().unwrap();
+
+    // 比空洞大一个簇, 只能放到 c 后面
+    let data: Vec<u8> = (0..cluster_size * (hole.len() + 1))
+        .map(|i| (i % 253) as u8)
+        .collect();
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    let free = runfs.read().free_clusters().unwrap();
+    let manifest = Manifest::parse(&format!("file /d hex={} contiguous", hex)).unwrap();
+    manifest.apply(&runfs, Path::new(".")).unwrap();
+
+    let d = root_dir.find_vfile_byname("d").unwrap();
+    assert_eq!(read_all(&d), data);
+    let chain = clusters(&runfs, &d);
+    assert_eq!(chain.len(), hole.len() + 1);
+    assert!(chain.windows(2).all(|w| w[1] == w[0] + 1));
+    assert!(!chain.contains(&hole[0]));
+    // 空闲簇计数减去整条簇链
+    assert_eq!(
+        runfs.read().free_clusters().unwrap(),
+        free - chain.len() as u32
+    );
+}
+
+#[test]
+fn test_contiguous_not_enough_space() {
+    let runfs = mount();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let cluster_size = runfs.read().bpb().cluster_size();
+    // 只留下 4 个空闲簇
+    let free = runfs.read().free_clusters().unwrap() as usize;
+    runfs.write().alloc_clusters(free - 4, None).unwrap();
+    let hex = "ab".repeat(cluster_size * 8);
+    let manifest = Manifest::parse(&format!("file /big hex={} contiguous", hex)).unwrap();
+    assert!(matches!(
+        manifest.apply(&runfs, Path::new(".")),
+        Err(FSError::NotEnoughSpace)
+    ));
+    // 创建了一半的文件仍然指向自己的簇
+    let big = root_dir.find_vfile_byname("big").unwrap();
+    assert_eq!(clusters(&runfs, &big).len(), 1);
+    assert!(!runfs.read().check().unwrap().has_errors());
+}
@@ -0,0 +1,124 @@
+use runfs::{
+    BlockDevice, FSError, FormatOptions, IOError, OverlayDevice, RamDisk, ReadOnlyDevice,
+    RunFileSystem,
+};
+use std::sync::Arc;
+
+mod common;
+use common::{read_file, write_file};
+
+const TOTAL_SECTORS: u32 = 64 * 1024 * 2;
+
+// 出厂镜像: 根目录中只有 factory.txt
+fn pristine() -> Arc<RamDisk> {
+    let disk = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    RunFileSystem::format(disk.clone(), FormatOptions::new(TOTAL_SECTORS)).unwrap();
+    write_file(disk.clone(), "factory.txt", b"factory");
+    disk
+}
+
+#[test]
+fn test_overlay_keeps_base_pristine() {
+    let disk = pristine();
+    let image = disk.to_vec();
+    let overlay = Arc::new(OverlayDevice::new(Arc::new(ReadOnlyDevice::new(
+        disk.clone(),
+    ))));
+    assert!(!overlay.is_read_only());
+    assert_eq!(overlay.num_blocks(), Some(TOTAL_SECTORS as usize));
+    write_file(overlay.clone(), "factory.txt", b"FACTORY");
+    write_file(overlay.clone(), "user.txt", b"user data");
+    assert_eq!(
+        read_file(overlay.clone(), "factory.txt").unwrap(),
+        b"FACTORY"
+    );
+    assert_eq!(
+        read_file(overlay.clone(), "user.txt").unwrap(),
+        b"user data"
+    );
+    assert!(!overlay.modified_blocks().is_empty());
+    // 基础镜像一个字节都没变, 只读包装下提交会失败
+    assert!(disk.to_vec() == image);
+    assert_eq!(overlay.commit(), Err(IOError::WriteProtected));
+    // 恢复出厂设置
+    overlay.reset();
+    assert!(overlay.modified_blocks().is_empty());
+    assert_eq!(
+        read_file(overlay.clone(), "factory.txt").unwrap(),
+        b"factory"
+    );
+    assert!(matches!(
+        read_file(overlay, "user.txt"),
+        Err(FSError::NotFound)
+    ));
+}
+
+#[test]
+fn test_overlay_commit() {
+    let disk = pristine();
+    let overlay = Arc::new(OverlayDevice::new(disk.clone()));
+    write_file(overlay.clone(), "user.txt", b"committed");
+    assert!(matches!(
+        read_file(disk.clone(), "user.txt"),
+        Err(FSError::NotFound)
+    ));
+    overlay.commit().unwrap();
+    assert!(overlay.modified_blocks().is_empty());
+    assert_eq!(read_file(disk, "user.txt").unwrap(), b"committed");
+    assert_eq!(read_file(overlay, "user.txt").unwrap(), b"committed");
+}
+
+#[test]
+fn test_overlay_delta() {
+    let disk = pristine();
+    let overlay = Arc::new(OverlayDevice::new(disk.clone()));
+    write_file(overlay.clone(), "user.txt", b"delta");
+    let delta = overlay.export().unwrap();
+    assert_eq!(
+        delta
+            .iter()
+            .map(|(block_id, _)| *block_id)
+            .collect::<Vec<_>>(),
+        overlay.modified_blocks()
+    );
+    assert!(delta.iter().all(|(_, data)| data.len() == 512));
+    // 同一个基础镜像上导入后内容相同
+    let other = Arc::new(OverlayDevice::new(disk.clone()));
+    other.import(&delta).unwrap();
+    assert_eq!(read_file(other, "user.txt").unwrap(), b"delta");
+    assert!(matches!(
+        read_file(disk, "user.txt"),
+        Err(FSError::NotFound)
+    ));
+}
+
+#[test]
+fn test_overlay_on_device() {
+    let disk = pristine();
+    let image = disk.to_vec();
+    let store = Arc::new(RamDisk::new(256));
+    let overlay = Arc::new(OverlayDevice::with_store(disk.clone(), store.clone()));
+    write_file(overlay.clone(), "user.txt", b"stored");
+    assert_eq!(read_file(overlay.clone(), "user.txt").unwrap(), b"stored");
+    assert!(disk.to_vec() == image);
+    // 覆盖层按写入顺序放在 store 的开头
+    let used = overlay.modified_blocks().len();
+    assert!(store.to_vec()[..used * 512].iter().any(|b| *b != 0));
+    assert!(store.to_vec()[used * 512..].iter().all(|b| *b == 0));
+    // store 写满
+    let small = OverlayDevice::with_store(disk.clone(), Arc::new(RamDisk::new(1)));
+    small.write_block(10, &[1; 512]).unwrap();
+    small.write_block(10, &[2; 512]).unwrap();
+    assert_eq!(small.write_block(11, &[3; 512]), Err(IOError::OutOfRange));
+    // 只接受整块写入, 越界返回 OutOfRange
+    assert_eq!(
+        overlay.write_block(0, &[0; 100]),
+        Err(IOError::NotEnoughBuffer)
+    );
+    assert_eq!(
+        overlay.write_block(TOTAL_SECTORS as usize, &[0; 512]),
+        Err(IOError::OutOfRange)
+    );
+    overlay.commit().unwrap();
+    assert_eq!(read_file(disk, "user.txt").unwrap(), b"stored");
+}
@@ -0,0 +1,130 @@
+use runfs::{
+    read_mbr_partitions, BlockDevice, FSError, FileAttributes, FormatOptions, RamDisk,
+    RunFileSystem, VFile,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const BLOCK_SZ: usize = 512;
+
+// 每个 FAT32 分区 35MB 左右
+const PART_SECTORS: u32 = 70000;
+const PRIMARY_START: u32 = 2048;
+const EXTENDED_START: u32 = PRIMARY_START + PART_SECTORS;
+const LOGICAL_OFFSET: u32 = 63; // 逻辑分区相对 EBR 的偏移
+const DISK_SECTORS: u32 = EXTENDED_START + LOGICAL_OFFSET + PART_SECTORS;
+
+fn put_entry(sector: &mut [u8], index: usize, part_type: u8, start: u32, sectors: u32) {
+    let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+    entry[4] = part_type;
+    entry[8..12].copy_from_slice(&start.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+}
+
+/// 一个 FAT32 主分区, 一个扩展分区, 扩展分区中有一个 FAT32 逻辑分区
+fn partitioned_disk() -> Arc<dyn BlockDevice> {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(DISK_SECTORS as usize));
+    let mut mbr = [0u8; BLOCK_SZ];
+    put_entry(&mut mbr, 0, 0x0C, PRIMARY_START, PART_SECTORS);
+    put_entry(
+        &mut mbr,
+        1,
+        0x0F,
+        EXTENDED_START,
+        LOGICAL_OFFSET + PART_SECTORS,
+    );
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    device.write_block(0, &mbr).unwrap();
+    let mut ebr = [0u8; BLOCK_SZ];
+    put_entry(&mut ebr, 0, 0x0C, LOGICAL_OFFSET, PART_SECTORS);
+    ebr[510] = 0x55;
+    ebr[511] = 0xAA;
+    device.write_block(EXTENDED_START as usize, &ebr).unwrap();
+    for start in [PRIMARY_START, EXTENDED_START + LOGICAL_OFFSET] {
+        let mut options = FormatOptions::new(PART_SECTORS);
+        options.hidden_sectors = start;
+        options.volume_id = start;
+        RunFileSystem::format_at(device.clone(), start as usize, options).unwrap();
+    }
+    device
+}
+
+#[test]
+fn test_read_mbr() {
+    let device = partitioned_disk();
+    let partitions = read_mbr_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), 2);
+    assert!(partitions.iter().all(|p| p.is_fat32()));
+    assert_eq!(partitions[0].start_lba, PRIMARY_START);
+    assert!(!partitions[0].logical);
+    assert_eq!(partitions[1].start_lba, EXTENDED_START + LOGICAL_OFFSET);
+    assert!(partitions[1].logical);
+}
+
+#[test]
+fn test_open_partitions() {
+    let device = partitioned_disk();
+    let primary = RunFileSystem::open_mbr_partition(device.clone(), 0).unwrap();
+    assert_eq!(primary.volume_id(), PRIMARY_START);
+    assert_eq!(primary.bpb().hidden_sectors(), PRIMARY_START);
+    let logical = RunFileSystem::open_mbr_partition(device.clone(), 1).unwrap();
+    assert_eq!(logical.volume_id(), EXTENDED_START + LOGICAL_OFFSET);
+    assert!(RunFileSystem::open_mbr_partition(device, 2).is_err());
+}
+
+#[test]
+fn test_partitions_isolated() {
+    let device = partitioned_disk();
+    {
+        let runfs = Arc::new(RwLock::new(
+            RunFileSystem::open_mbr_partition(device.clone(), 1).unwrap(),
+        ));
+        let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
+        let file = root_dir
+            .create("logical.txt", FileAttributes::FILE)
+            .unwrap();
+        assert_eq!(file.write_at(0, b"wakuwaku").unwrap(), 8);
+    }
+    let primary = Arc::new(RwLock::new(
+        RunFileSystem::open_mbr_partition(device.clone(), 0).unwrap(),
+    ));
+    let root_dir: Arc<VFile> = Arc::new(primary.read().root_vfile(&primary));
+    assert!(root_dir.find_vfile_byname("logical.txt").is_err());
+    let logical = Arc::new(RwLock::new(
+        RunFileSystem::open_mbr_partition(device, 1).unwrap(),
+    ));
+    let root_dir: Arc<VFile> = Arc::new(logical.read().root_vfile(&logical));
+    let file = root_dir.find_vfile_byname("logical.txt").unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 8);
+    assert_eq!(&buf, b"wakuwaku");
+}
+
+#[test]
+fn test_corrupted_ebr_chain() {
+    let device: Arc<dyn BlockDevice> = Arc::new(RamDisk::new(64));
+    let mut mbr = [0u8; BLOCK_SZ];
+    put_entry(&mut mbr, 0, 0x0F, 16, 32);
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    device.write_block(0, &mbr).unwrap();
+    // 逻辑分区的起始扇区加上 EBR 位置超出 u32
+    let mut ebr = [0u8; BLOCK_SZ];
+    put_entry(&mut ebr, 0, 0x0C, u32::MAX - 8, 8);
+    ebr[510] = 0x55;
+    ebr[511] = 0xAA;
+    device.write_block(16, &ebr).unwrap();
+    assert!(matches!(
+        read_mbr_partitions(&device),
+        Err(FSError::CorruptedFileSystem)
+    ));
+    // 指向下一个 EBR 的偏移溢出
+    put_entry(&mut ebr, 0, 0x0C, 1, 8);
+    put_entry(&mut ebr, 1, 0x05, u32::MAX - 4, 8);
+    device.write_block(16, &ebr).unwrap();
+    assert!(matches!(
+        read_mbr_partitions(&device),
+        Err(FSError::CorruptedFileSystem)
+    ));
+}
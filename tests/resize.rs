@@ -0,0 +1,171 @@
+use runfs::{FSError, FileAttributes, FormatOptions, RamDisk, RunFileSystem, VFile};
+use spin::RwLock;
+use std::sync::Arc;
+
+// 128MB 的设备, 每簇 1 个扇区
+const DEVICE_SECTORS: u32 = 128 * 1024 * 2;
+const SMALL_SECTORS: u32 = 64 * 1024 * 2;
+// 缩小后约 70000 个簇, 仍然是 FAT32
+const SHRUNK_SECTORS: u32 = 72000;
+
+fn create_files(root_dir: &VFile) {
+    let file = root_dir.create("hello.txt", FileAttributes::FILE).unwrap();
+    let buf: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    assert_eq!(file.write_at(0, &buf).unwrap(), buf.len());
+    let subdir = root_dir
+        .create("subdir", FileAttributes::DIRECTORY)
+        .unwrap();
+    let inner = subdir.create("inner.txt", FileAttributes::FILE).unwrap();
+    assert_eq!(inner.write_at(0, b"inner data").unwrap(), 10);
+}
+
+fn check_files(root_dir: &VFile) {
+    let file = root_dir.find_vfile_byname("hello.txt").unwrap();
+    let mut buf = [0u8; 3000];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), buf.len());
+    assert!(buf.iter().enumerate().all(|(i, b)| *b == (i % 251) as u8));
+    let inner = root_dir.find_vfile_bypath("/subdir/inner.txt").unwrap();
+    let mut buf = [0u8; 10];
+    assert_eq!(inner.read_at(0, &mut buf).unwrap(), 10);
+    assert_eq!(&buf, b"inner data");
+}
+
+fn check_free_clusters(device: Arc<RamDisk>, total_sectors: u32) {
+    let runfs = RunFileSystem::new(device).unwrap();
+    let bpb = runfs.bpb();
+    assert_eq!(bpb.total_sectors_32(), total_sectors);
+    let free = runfs.free_clusters().unwrap();
+    let mut fat_manager = runfs.fat_manager_modify();
+    let mut counted = 0;
+    for cluster_id in 2..bpb.total_clusters() as usize + 2 {
+        if fat_manager.entry(cluster_id).unwrap() == runfs::FATEntry::Free {
+            counted += 1;
+        }
+    }
+    assert_eq!(free, counted);
+}
+
+#[test]
+fn test_grow_moves_data_region() {
+    let device = Arc::new(RamDisk::new(DEVICE_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(SMALL_SECTORS)).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    create_files(&root_dir);
+    let old_bpb = runfs.read().bpb();
+    runfs.write().resize(DEVICE_SECTORS).unwrap();
+    let bpb = runfs.read().bpb();
+    assert_eq!(bpb.total_sectors_32(), DEVICE_SECTORS);
+    // FAT 表要装下翻倍的簇, 数据区后移
+    assert!(bpb.fats_sectors() > old_bpb.fats_sectors());
+    assert!(bpb.first_data_sector() > old_bpb.first_data_sector());
+    assert!(bpb.fats_sectors() * 512 / 4 >= bpb.total_clusters() + 2);
+    drop(root_dir);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_files(&root_dir);
+    drop(root_dir);
+    drop(runfs);
+    // 重新挂载后备份启动扇区和 FSInfo 也要一致
+    check_free_clusters(device.clone(), DEVICE_SECTORS);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_files(&root_dir);
+}
+
+#[test]
+fn test_shrink_relocates_clusters() {
+    let device = Arc::new(RamDisk::new(DEVICE_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(DEVICE_SECTORS)).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    // 先占住前面的簇, 让文件分配到新范围之外, 之后再释放
+    let placeholder = runfs.write().alloc_clusters(100_000, None).unwrap();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    create_files(&root_dir);
+    let inner = root_dir.find_vfile_bypath("/subdir/inner.txt").unwrap();
+    assert!(inner.first_data_cluster().unwrap() > 100_000);
+    runfs
+        .write()
+        .dealloc_clusters(placeholder as usize, None)
+        .unwrap();
+    drop(inner);
+    drop(root_dir);
+    runfs.write().resize(SHRUNK_SECTORS).unwrap();
+    let bpb = runfs.read().bpb();
+    assert_eq!(bpb.total_sectors_32(), SHRUNK_SECTORS);
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_files(&root_dir);
+    let inner = root_dir.find_vfile_bypath("/subdir/inner.txt").unwrap();
+    assert!(inner.first_data_cluster().unwrap() < bpb.total_clusters() + 2);
+    drop(inner);
+    drop(root_dir);
+    drop(runfs);
+    check_free_clusters(device.clone(), SHRUNK_SECTORS);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_files(&root_dir);
+}
+
+#[test]
+fn test_shrink_refused() {
+    let device = Arc::new(RamDisk::new(DEVICE_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(DEVICE_SECTORS)).unwrap();
+    let mut runfs = RunFileSystem::new(device).unwrap();
+    runfs.alloc_clusters(100_000, None).unwrap();
+    let free = runfs.free_clusters();
+    assert!(matches!(
+        runfs.resize(SHRUNK_SECTORS),
+        Err(FSError::NotEnoughSpace)
+    ));
+    // 簇数太少会变成 FAT16
+    assert!(matches!(runfs.resize(40000), Err(FSError::InvalidInput)));
+    assert_eq!(runfs.bpb().total_sectors_32(), DEVICE_SECTORS);
+    assert_eq!(runfs.free_clusters(), free);
+}
+
+#[test]
+fn test_grow_beyond_device() {
+    let device = Arc::new(RamDisk::new(SMALL_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(SMALL_SECTORS)).unwrap();
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    // 块设备放不下, 卷不能变
+    assert!(matches!(
+        runfs.resize(SMALL_SECTORS + 1000),
+        Err(FSError::InvalidInput)
+    ));
+    assert_eq!(runfs.bpb().total_sectors_32(), SMALL_SECTORS);
+    drop(runfs);
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert!(runfs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_shrink_with_active_second_fat() {
+    let device = Arc::new(RamDisk::new(DEVICE_SECTORS as usize));
+    RunFileSystem::format(device.clone(), FormatOptions::new(DEVICE_SECTORS)).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    // 不镜像, 只用第二个 FAT 表, 缩小时它在被搬下来的数据区覆盖范围内
+    runfs.write().set_active_fat(Some(1)).unwrap();
+    let root_dir = runfs.read().root_vfile(&runfs);
+    create_files(&root_dir);
+    let big = root_dir.create("big.bin", FileAttributes::FILE).unwrap();
+    let data: Vec<u8> = (0..400 * 1024).map(|i| (i % 253) as u8).collect();
+    assert_eq!(big.write_at(0, &data).unwrap(), data.len());
+    drop(big);
+    drop(root_dir);
+    let shrunk = 250_000;
+    let old_bpb = runfs.read().bpb();
+    runfs.write().resize(shrunk).unwrap();
+    let bpb = runfs.read().bpb();
+    assert!(bpb.first_data_sector() < old_bpb.first_data_sector());
+    assert_eq!(bpb.active_fat(), 1);
+    drop(runfs);
+    check_free_clusters(device.clone(), shrunk);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert!(runfs.read().check().unwrap().is_clean());
+    let root_dir = runfs.read().root_vfile(&runfs);
+    check_files(&root_dir);
+    let big = root_dir.find_vfile_byname("big.bin").unwrap();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(big.read_at(0, &mut buf).unwrap(), data.len());
+    assert!(buf == data);
+}
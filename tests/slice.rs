@@ -0,0 +1,95 @@
+use runfs::{
+    BlockDevice, FileAttributes, FormatOptions, IOError, RamDisk, RunFileSystem, SliceDevice,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+// flash 布局: 引导程序, FAT32 区域, 配置区
+const BOOT_BLOCKS: usize = 64;
+const FS_SECTORS: u32 = 64 * 1024 * 2;
+const CONFIG_BLOCKS: usize = 16;
+const TOTAL_BLOCKS: usize = BOOT_BLOCKS + FS_SECTORS as usize + CONFIG_BLOCKS;
+
+fn flash() -> Arc<RamDisk> {
+    let flash = Arc::new(RamDisk::new(TOTAL_BLOCKS));
+    for block_id in 0..BOOT_BLOCKS {
+        flash.write_block(block_id, &[0xB0; 512]).unwrap();
+    }
+    for block_id in TOTAL_BLOCKS - CONFIG_BLOCKS..TOTAL_BLOCKS {
+        flash.write_block(block_id, &[0xC0; 512]).unwrap();
+    }
+    flash
+}
+
+#[test]
+fn test_slice_bounds() {
+    let flash = flash();
+    assert!(matches!(
+        SliceDevice::new(flash.clone(), BOOT_BLOCKS, TOTAL_BLOCKS),
+        Err(IOError::OutOfRange)
+    ));
+    assert!(matches!(
+        SliceDevice::new(flash.clone(), usize::MAX, 2),
+        Err(IOError::OutOfRange)
+    ));
+    let slice = SliceDevice::new(flash, TOTAL_BLOCKS - CONFIG_BLOCKS, CONFIG_BLOCKS).unwrap();
+    assert_eq!(slice.num_blocks(), Some(CONFIG_BLOCKS));
+    assert_eq!(slice.block_size(), Some(512));
+    let mut buf = [0u8; 512];
+    slice.read_block(0, &mut buf).unwrap();
+    assert_eq!(buf, [0xC0; 512]);
+    assert_eq!(
+        slice.read_block(CONFIG_BLOCKS, &mut buf),
+        Err(IOError::OutOfRange)
+    );
+    assert_eq!(
+        slice.write_block(CONFIG_BLOCKS, &buf),
+        Err(IOError::OutOfRange)
+    );
+    // 多块读写整体越界时一块都不写
+    let mut blocks = vec![0u8; 2 * 512];
+    assert_eq!(
+        slice.write_blocks(CONFIG_BLOCKS - 1, 2, &blocks),
+        Err(IOError::OutOfRange)
+    );
+    slice
+        .read_blocks(CONFIG_BLOCKS - 2, 2, &mut blocks)
+        .unwrap();
+    assert!(blocks.iter().all(|b| *b == 0xC0));
+    assert_eq!(
+        slice.discard(CONFIG_BLOCKS - 1..CONFIG_BLOCKS + 1),
+        Err(IOError::OutOfRange)
+    );
+}
+
+#[test]
+fn test_fat32_in_slice() {
+    let flash = flash();
+    let slice: Arc<dyn BlockDevice> =
+        Arc::new(SliceDevice::new(flash.clone(), BOOT_BLOCKS, FS_SECTORS as usize).unwrap());
+    RunFileSystem::format(slice.clone(), FormatOptions::new(FS_SECTORS)).unwrap();
+    let data: Vec<u8> = (0..20000).map(|i| (i % 249) as u8).collect();
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(slice.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        let file = root_dir.create("config.bin", FileAttributes::FILE).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+    }
+    // 引导程序和配置区没有被碰到
+    let image = flash.to_vec();
+    assert!(image[..BOOT_BLOCKS * 512].iter().all(|b| *b == 0xB0));
+    assert!(image[(TOTAL_BLOCKS - CONFIG_BLOCKS) * 512..]
+        .iter()
+        .all(|b| *b == 0xC0));
+    // 文件系统的启动扇区在区域的第一块
+    assert_eq!(
+        &image[BOOT_BLOCKS * 512 + 510..BOOT_BLOCKS * 512 + 512],
+        &[0x55, 0xAA]
+    );
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(slice).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.find_vfile_byname("config.bin").unwrap();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), data.len());
+    assert_eq!(buf, data);
+}
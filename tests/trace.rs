@@ -0,0 +1,164 @@
+use runfs::{
+    BlockDevice, FSError, FatType, FileAttributes, FormatOptions, IOError, RamDisk, RunFileSystem,
+    Trace, TraceDevice, TraceEvent, TraceMode, TraceReplayer,
+};
+use spin::RwLock;
+use std::sync::Arc;
+
+const TOTAL_SECTORS: u32 = 8 * 1024 * 2;
+
+fn replay_to(trace: &Trace, base: &[u8], step: usize) -> Arc<RamDisk> {
+    let disk = Arc::new(RamDisk::from_vec(base.to_vec(), 512));
+    TraceReplayer::new(trace, disk.clone())
+        .run_to(step)
+        .unwrap();
+    disk
+}
+
+fn is_bad(disk: Arc<RamDisk>) -> bool {
+    match RunFileSystem::new(disk) {
+        Ok(runfs) => runfs.check().map_or(true, |report| report.has_errors()),
+        Err(_) => true,
+    }
+}
+
+#[test]
+fn test_trace_events() {
+    let disk = Arc::new(RamDisk::new(16));
+    let device = TraceDevice::new(disk.clone(), TraceMode::Metadata);
+    device.write_block(3, &[0x33; 512]).unwrap();
+    device.read_block(3, &mut [0u8; 16]).unwrap();
+    device.write_blocks(10, 2, &[0xAA; 1024]).unwrap();
+    device.flush().unwrap();
+    // 失败的请求不记
+    assert_eq!(device.write_block(16, &[0; 512]), Err(IOError::OutOfRange));
+    let mut trace = device.take_trace();
+    device.discard(5..7).unwrap();
+    device.mark("done");
+    // 分几次取出的内容拼起来
+    trace.extend(device.take_trace());
+    let parsed = Trace::parse(&trace).unwrap();
+    assert_eq!(parsed.mode, TraceMode::Metadata);
+    assert_eq!(parsed.block_size, 512);
+    assert_eq!(
+        parsed.events,
+        vec![
+            TraceEvent::Write {
+                block_id: 3,
+                count: 1,
+                len: 512,
+                checksum: None,
+                data: None,
+            },
+            TraceEvent::Read {
+                block_id: 3,
+                count: 1,
+                len: 16,
+                checksum: None,
+            },
+            TraceEvent::Write {
+                block_id: 10,
+                count: 2,
+                len: 1024,
+                checksum: None,
+                data: None,
+            },
+            TraceEvent::Flush,
+            TraceEvent::Discard(5..7),
+            TraceEvent::Mark("done".into()),
+        ]
+    );
+    assert_eq!(parsed.find_mark("done"), Some(5));
+    // 每条记录只有几个字节
+    assert!(trace.len() < 40);
+    // 末尾不完整的记录丢掉
+    let truncated = Trace::parse(&trace[..trace.len() - 2]).unwrap();
+    assert_eq!(truncated.events.len(), 5);
+    assert!(matches!(
+        Trace::parse(&trace[1..]),
+        Err(FSError::InvalidInput)
+    ));
+    // 没有数据的写不能回放
+    let mut replayer = TraceReplayer::new(&parsed, Arc::new(RamDisk::new(16)));
+    assert!(matches!(replayer.step(), Err(FSError::InvalidInput)));
+    assert_eq!(replayer.position(), 0);
+}
+
+#[test]
+fn test_trace_checksum() {
+    let disk = Arc::new(RamDisk::new(16));
+    let device = TraceDevice::new(disk.clone(), TraceMode::Checksum);
+    device.read_block(2, &mut [0u8; 512]).unwrap();
+    device.read_blocks(4, 2, &mut [0u8; 1024]).unwrap();
+    let trace = Trace::parse(&device.take_trace()).unwrap();
+    // 内容相同时读能对上, 不同时停在那一步
+    let image = Arc::new(RamDisk::new(16));
+    TraceReplayer::new(&trace, image.clone()).run().unwrap();
+    // 超出记录数或往回退
+    let mut replayer = TraceReplayer::new(&trace, image.clone());
+    assert!(matches!(replayer.run_to(3), Err(FSError::InvalidInput)));
+    replayer.run_to(2).unwrap();
+    assert!(matches!(replayer.run_to(1), Err(FSError::InvalidInput)));
+    assert_eq!(replayer.position(), 2);
+    image.write_block(5, &[1; 512]).unwrap();
+    let mut replayer = TraceReplayer::new(&trace, image);
+    assert!(replayer.step().unwrap());
+    assert!(matches!(
+        replayer.step(),
+        Err(FSError::Io(IOError::ChecksumMismatch))
+    ));
+    assert_eq!(replayer.position(), 1);
+}
+
+#[test]
+fn test_trace_bisect() {
+    let base = Arc::new(RamDisk::new(TOTAL_SECTORS as usize));
+    let options = FormatOptions::with_fat_type(TOTAL_SECTORS, FatType::Fat16);
+    RunFileSystem::format(base.clone(), options).unwrap();
+    let base = base.to_vec();
+    let disk = Arc::new(RamDisk::from_vec(base.clone(), 512));
+    let device = Arc::new(TraceDevice::new(disk.clone(), TraceMode::Full));
+    {
+        let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+        let root_dir = runfs.read().root_vfile(&runfs);
+        device.mark("create a");
+        let a = root_dir.create("a.txt", FileAttributes::FILE).unwrap();
+        a.write_at(0, &[0xAA; 5000]).unwrap();
+        runfs.read().sync().unwrap();
+        device.mark("mkdir d");
+        root_dir.create("d", FileAttributes::DIRECTORY).unwrap();
+        runfs.read().sync().unwrap();
+        // 模拟出问题的操作: 把 a 的最后一个簇标成空闲
+        device.mark("truncate a");
+        let first_cluster = a.first_data_cluster().unwrap() as usize;
+        {
+            let fs = runfs.read();
+            let mut fat_manager = fs.fat_manager_modify();
+            let clusters = fat_manager.all_clusters(first_cluster).unwrap();
+            fat_manager.set_free(*clusters.last().unwrap()).unwrap();
+        }
+        runfs.read().sync().unwrap();
+        device.mark("create b");
+        let b = root_dir.create("b.txt", FileAttributes::FILE).unwrap();
+        b.write_at(0, b"bbb").unwrap();
+        runfs.read().sync().unwrap();
+    }
+    let trace = Trace::parse(&device.take_trace()).unwrap();
+    // 完整回放得到相同的镜像
+    assert!(replay_to(&trace, &base, trace.events.len()).to_vec() == disk.to_vec());
+    // 第 k 个标记之前的状态是好的, 二分找出第一个执行完就坏了的操作
+    let marks: Vec<(usize, &str)> = trace.marks().collect();
+    let boundary = |k: usize| marks.get(k).map_or(trace.events.len(), |mark| mark.0);
+    let (mut good, mut bad) = (0, marks.len());
+    assert!(!is_bad(replay_to(&trace, &base, boundary(good))));
+    assert!(is_bad(replay_to(&trace, &base, boundary(bad))));
+    while bad - good > 1 {
+        let mid = (good + bad) / 2;
+        if is_bad(replay_to(&trace, &base, boundary(mid))) {
+            bad = mid;
+        } else {
+            good = mid;
+        }
+    }
+    assert_eq!(marks[good].1, "truncate a");
+}
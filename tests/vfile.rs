@@ -1,62 +1,18 @@
-use runfs::{BlockDevice, FileAttributes, IOError, RunFileSystem, VFile};
+use runfs::{
+    FatType, FileAttributes, FileBlockDevice, FormatOptions, RamDisk, RunFileSystem, VFile,
+};
 use spin::RwLock;
-use std::fs;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::{prelude::*, Seek, SeekFrom};
 use std::sync::Arc;
 
-struct FileEmulateBlockDevice {
-    path: String,
-}
-
-impl FileEmulateBlockDevice {
-    fn new(path: String) -> Self {
-        Self { path }
-    }
-}
-
-const BLOCK_SZ: usize = 512;
-
-impl BlockDevice for FileEmulateBlockDevice {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IOError> {
-        let _metadata = fs::metadata(self.path.as_str()).expect("Open Img Failed");
-        // let file_size: usize = metadata.len().try_into().unwrap();
-        let mut file = File::open(self.path.as_str()).expect("No Img");
-        // println!("block_id: {}", block_id);
-        let pos: usize = block_id * BLOCK_SZ;
-        // println!("pos: {}", pos);
-        // println!("file_size: {}", file_size);
-        // assert!(pos + BLOCK_SZ < file_size); // 如果是块设备就算了
-        file.seek(SeekFrom::Start(pos.try_into().unwrap()))
-            .expect("Seek Failed");
-        file.read(buf).unwrap();
-        Ok(())
-    }
-    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IOError> {
-        let _metadata = fs::metadata(self.path.as_str()).expect("Open Img Failed");
-        // let file_size: usize = metadata.len().try_into().unwrap();
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(self.path.as_str())
-            .expect("No Img");
-        let pos: usize = block_id * BLOCK_SZ;
-        // assert!(pos + BLOCK_SZ < file_size);
-        file.seek(SeekFrom::Start(pos.try_into().unwrap()))
-            .expect("Seek Failed");
-        file.write(buf).expect("Write Failed");
-        Ok(())
-    }
-}
-
 // const IMG: &str = "assets/fat32_1.img";
 const IMG: &str = "/dev/sda";
 
 #[test]
 fn test_find_file_short() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("FUCK").unwrap();
     println!("file: {:#X?}", vfile.name());
@@ -64,8 +20,10 @@ fn test_find_file_short() {
 
 #[test]
 fn test_find_file_long() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("mount").unwrap();
     println!("file: {:#X?}", vfile.name());
@@ -75,8 +33,10 @@ fn test_find_file_long() {
 
 #[test]
 fn test_directory_size() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let size = root_dir.size();
     println!("size: {:#?}", size);
@@ -84,8 +44,10 @@ fn test_directory_size() {
 
 #[test]
 fn test_find_dirents() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let offset = root_dir.find_free_dirents(3);
     println!("file offset: {:#?}", offset);
@@ -93,28 +55,34 @@ fn test_find_dirents() {
 
 #[test]
 fn test_delete_file() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("getcwd").unwrap();
     println!("file: {:#X?}", vfile.name());
-    vfile.delete();
+    vfile.delete().unwrap();
 }
 
 #[test]
 fn test_delete_dir() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("mnt").unwrap();
     println!("file: {:#X?}", vfile.name());
-    vfile.delete();
+    vfile.delete().unwrap();
 }
 
 #[test]
 fn test_create_file() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     // let file =
     root_dir
@@ -126,8 +94,10 @@ fn test_create_file() {
 
 #[test]
 fn test_create_dir() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     root_dir
         .create("wakuwaku", FileAttributes::DIRECTORY)
@@ -136,8 +106,10 @@ fn test_create_dir() {
 
 #[test]
 fn test_create_dir_in_subdir() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("wakuwaku").unwrap();
     vfile
@@ -148,8 +120,10 @@ fn test_create_dir_in_subdir() {
 
 #[test]
 fn test_create_file_in_subdir() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let vfile = root_dir.find_vfile_byname("wakuwaku").unwrap();
     let helloworld = vfile
@@ -161,13 +135,15 @@ fn test_create_file_in_subdir() {
 #[test]
 fn test_read_file() {
     use std::time::Instant;
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let text = root_dir.find_vfile_byname("open").unwrap();
     let mut buf = [0u8; 62400];
     let start = Instant::now();
-    let len = text.read_at(0, &mut buf);
+    let len = text.read_at(0, &mut buf).unwrap();
     let duration = start.elapsed();
     println!("Time elapsed is: {:?}", duration);
     // println!("buf: {:?}", buf);
@@ -178,19 +154,21 @@ fn test_read_file() {
 
 #[test]
 fn test_write_file() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let mut buf = [0x0u8; 130000];
     let text = root_dir.find_vfile_byname("user_shell").unwrap();
-    let len = text.read_at(0, &mut buf);
+    let len = text.read_at(0, &mut buf).unwrap();
     println!("text read len: {:#}", len);
     let helloworld = root_dir.find_vfile_byname("helloworld.txt").unwrap();
     println!("Here0");
-    let len = helloworld.write_at(0, &buf);
+    let len = helloworld.write_at(0, &buf).unwrap();
     println!("helloworld write len: {:#}", len);
     let mut buf1 = [0u8; 52];
-    let len = helloworld.read_at(0, &mut buf1);
+    let len = helloworld.read_at(0, &mut buf1).unwrap();
     println!("helloworld read len: {:#}", len);
     let s = String::from_utf8_lossy(&buf1);
     println!("{:#}", s);
@@ -198,8 +176,10 @@ fn test_write_file() {
 
 #[test]
 fn test_stat() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let text = root_dir.find_vfile_byname("user_shell").unwrap();
     let stat = text.stat();
@@ -209,8 +189,10 @@ fn test_stat() {
 #[test]
 fn test_dirent_info() {
     use std::time::Instant;
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let start = Instant::now();
     let info = root_dir.dirent_info(128);
@@ -222,8 +204,10 @@ fn test_dirent_info() {
 #[test]
 fn test_ls() {
     use std::time::Instant;
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let start = Instant::now();
     let ls = root_dir.ls();
@@ -234,8 +218,10 @@ fn test_ls() {
 
 #[test]
 fn test_find_file_by_path() {
-    let file_block_device: FileEmulateBlockDevice = FileEmulateBlockDevice::new(IMG.to_string());
-    let runfs = Arc::new(RwLock::new(RunFileSystem::new(Arc::new(file_block_device))));
+    let file_block_device = FileBlockDevice::open(IMG).unwrap();
+    let runfs = Arc::new(RwLock::new(
+        RunFileSystem::new(Arc::new(file_block_device)).unwrap(),
+    ));
     let root_dir: Arc<VFile> = Arc::new(runfs.read().root_vfile(&runfs));
     let path = String::from("/initproc");
     let vfile = root_dir.find_vfile_bypath(&path).unwrap();
@@ -249,3 +235,28 @@ fn test_find_file_by_path() {
     // println!("prev: {:#?}", prev);
     // println!("last: {:#?}", last);
 }
+
+#[test]
+fn test_write_keeps_size() {
+    let disk = Arc::new(RamDisk::new(8 * 1024 * 2));
+    let options = FormatOptions::with_fat_type(8 * 1024 * 2, FatType::Fat16);
+    RunFileSystem::format(disk.clone(), options).unwrap();
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(disk).unwrap()));
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let file = root_dir.create("keep.bin", FileAttributes::FILE).unwrap();
+    let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+    // 在中间写, 大小不变
+    assert_eq!(file.write_at(500, &[0xAA; 100]).unwrap(), 100);
+    assert_eq!(file.size().unwrap(), 3000);
+    let mut expected = data.clone();
+    expected[500..600].fill(0xAA);
+    // 写过末尾, 大小增长到写入的结尾
+    assert_eq!(file.write_at(2900, &[0x55; 200]).unwrap(), 200);
+    assert_eq!(file.size().unwrap(), 3100);
+    expected.truncate(2900);
+    expected.extend_from_slice(&[0x55; 200]);
+    let mut buf = vec![0u8; 3200];
+    assert_eq!(file.read_at(0, &mut buf).unwrap(), 3100);
+    assert!(buf[..3100] == expected);
+}
@@ -0,0 +1,95 @@
+use runfs::{FSError, FatType, FileAttributes, FormatOptions, RunFileSystem};
+use spin::RwLock;
+use std::sync::Arc;
+
+mod common;
+use common::formatted;
+
+const BLOCK_SZ: usize = 512;
+
+// 64MB, 刚好是 FAT32
+const FAT32_SECTORS: u32 = 64 * 1024 * 2;
+const FAT16_SECTORS: u32 = 16 * 1024 * 2;
+// FAT32 BPB 中卷名的偏移
+const LABEL_OFFSET: usize = 0x47;
+
+#[test]
+fn test_format_with_label() {
+    let mut options = FormatOptions::new(FAT32_SECTORS);
+    options.volume_label = *b"RUNFS      ";
+    let device = formatted(FAT32_SECTORS, options);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert_eq!(runfs.read().volume_label().unwrap(), "RUNFS");
+    // 卷标目录项不算文件
+    let root_dir = runfs.read().root_vfile(&runfs);
+    assert_eq!(root_dir.ls().unwrap().len(), 0);
+    assert!(root_dir.find_vfile_byname("RUNFS").is_err());
+}
+
+#[test]
+fn test_set_label_fat32() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    assert_eq!(runfs.volume_label().unwrap(), "");
+    runfs.set_volume_label("my disk").unwrap();
+    assert_eq!(runfs.volume_label().unwrap(), "MY DISK");
+    assert_eq!(&runfs.bpb().volume_label(), b"MY DISK    ");
+    drop(runfs);
+    // 启动扇区和备份启动扇区都要改
+    let mut sector = [0u8; BLOCK_SZ];
+    for sector_id in [0, 6] {
+        device.read_block(sector_id, &mut sector).unwrap();
+        assert_eq!(&sector[LABEL_OFFSET..LABEL_OFFSET + 11], b"MY DISK    ");
+    }
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device.clone()).unwrap()));
+    assert_eq!(runfs.read().volume_label().unwrap(), "MY DISK");
+    let root_dir = runfs.read().root_vfile(&runfs);
+    root_dir.create("hello.txt", FileAttributes::FILE).unwrap();
+    assert_eq!(root_dir.ls().unwrap().len(), 1);
+    drop(root_dir);
+    // 修改已有的卷标目录项, 不会再新建一个
+    runfs.write().set_volume_label("BACKUP").unwrap();
+    drop(runfs);
+    let runfs = Arc::new(RwLock::new(RunFileSystem::new(device).unwrap()));
+    assert_eq!(runfs.read().volume_label().unwrap(), "BACKUP");
+    let root_dir = runfs.read().root_vfile(&runfs);
+    let hello = root_dir.find_vfile_byname("hello.txt").unwrap();
+    assert!(hello.is_file());
+}
+
+#[test]
+fn test_set_label_fat16_and_remove() {
+    let device = formatted(
+        FAT16_SECTORS,
+        FormatOptions::with_fat_type(FAT16_SECTORS, FatType::Fat16),
+    );
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    runfs.set_volume_label("STICK").unwrap();
+    drop(runfs);
+    let mut runfs = RunFileSystem::new(device.clone()).unwrap();
+    assert_eq!(runfs.volume_label().unwrap(), "STICK");
+    assert_eq!(&runfs.bpb().volume_label(), b"STICK      ");
+    runfs.set_volume_label("").unwrap();
+    assert_eq!(runfs.volume_label().unwrap(), "");
+    drop(runfs);
+    let runfs = RunFileSystem::new(device).unwrap();
+    assert_eq!(runfs.volume_label().unwrap(), "");
+    assert_eq!(&runfs.bpb().volume_label(), b"NO NAME    ");
+}
+
+#[test]
+fn test_invalid_label() {
+    let device = formatted(FAT32_SECTORS, FormatOptions::new(FAT32_SECTORS));
+    let mut runfs = RunFileSystem::new(device).unwrap();
+    assert!(matches!(
+        runfs.set_volume_label("TOO LONG NAME"),
+        Err(FSError::InvalidFileNameLength)
+    ));
+    for label in ["A*B", " LEADING", "DOT.NAME", "TAB\tNAME", "中文"] {
+        assert!(matches!(
+            runfs.set_volume_label(label),
+            Err(FSError::UnsupportedFileNameCharacter)
+        ));
+    }
+    assert_eq!(runfs.volume_label().unwrap(), "");
+}